@@ -0,0 +1,18 @@
+use serde::{Deserialize, Serialize};
+
+/// Header a `POST /documents` is sent with so a retry of it isn't stored twice
+pub const IDEMPOTENCY_KEY: &str = "idempotency-key";
+
+/// The answer to `POST /documents`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Stored {
+    pub stored: u64,
+}
+
+/// The answer to `POST /documents/stream`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Streamed {
+    pub stored: u64,
+    /// Lines that weren't valid documents
+    pub skipped: u64,
+}
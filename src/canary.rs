@@ -0,0 +1,40 @@
+use anyhow::{bail, Result};
+use tracing::{info, warn};
+use crate::clients::llm::Embedding;
+use crate::clients::vector_store::{Filter, VectorStore};
+use crate::config::Canary;
+
+/// Runs every canary query, failing when any of them misses its expected source
+pub async fn check(canaries: &[Canary], embedder: &dyn Embedding, store: &impl VectorStore) -> Result<()> {
+    let mut failed = 0;
+
+    for canary in canaries {
+        let query = embedder.embed_query(&canary.query).await?;
+        if query.is_empty() {
+            warn!("Canary {:?} could not be embedded", canary.query);
+            failed += 1;
+            continue;
+        }
+
+        let sources = store.search(query, canary.top_k, &Filter::default()).await?
+            .into_iter()
+            .map(|hit| hit.metadata.source)
+            .collect::<Vec<_>>();
+
+        if sources.iter().any(|source| source.starts_with(&canary.expected_source)) {
+            info!("Canary {:?} passed", canary.query);
+        } else {
+            warn!(
+                "Canary {:?} expected {:?} in top {}, got {:?}",
+                canary.query, canary.expected_source, canary.top_k, sources
+            );
+            failed += 1;
+        }
+    }
+
+    if failed > 0 {
+        bail!("{failed} of {} canary queries failed", canaries.len());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,123 @@
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::Result;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::Deserialize;
+use crate::clients::Document;
+use crate::sink::Sink;
+
+/// Faults injected into ingestion runs to see how they cope, all off by default
+///
+/// Rates are the share of calls a fault hits, drawn from a generator seeded with `seed`, so
+/// a run with the same seed and inputs draws the same faults.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChaosConfig {
+    pub seed: u64,
+    /// Embedding requests failing before they are sent
+    pub embed_failure_rate: f64,
+    /// Embedding requests answered with a 503, as a llama-server without free slots does
+    pub busy_rate: f64,
+    /// Embedding replies whose body is cut short
+    pub malformed_rate: f64,
+    /// Store writes held back by `upsert_delay_ms`
+    pub upsert_delay_rate: f64,
+    pub upsert_delay_ms: u64,
+}
+
+impl Default for ChaosConfig {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            embed_failure_rate: 0.0,
+            busy_rate: 0.0,
+            malformed_rate: 0.0,
+            upsert_delay_rate: 0.0,
+            upsert_delay_ms: 100,
+        }
+    }
+}
+
+/// What happens to an embedding request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    Fail,
+    Busy,
+    Malformed,
+}
+
+/// Draws the faults of `ChaosConfig`, each one in turn
+pub struct Faults {
+    config: ChaosConfig,
+    rng: Mutex<StdRng>,
+}
+
+impl Faults {
+    pub fn new(config: &ChaosConfig) -> Self {
+        Self { config: config.clone(), rng: Mutex::new(StdRng::seed_from_u64(config.seed)) }
+    }
+
+    /// The fault of the next embedding request, if it gets one
+    pub fn embed(&self) -> Option<Fault> {
+        let mut rng = self.rng.lock().unwrap();
+        let faults = [
+            (Fault::Fail, self.config.embed_failure_rate),
+            (Fault::Busy, self.config.busy_rate),
+            (Fault::Malformed, self.config.malformed_rate),
+        ];
+
+        // One draw per request, so the rates add up instead of shadowing each other
+        let draw: f64 = rng.gen();
+        let mut below = 0.0;
+        faults.into_iter().find_map(|(fault, rate)| {
+            below += rate;
+            (draw < below).then_some(fault)
+        })
+    }
+
+    /// How long the next store write is held back
+    pub fn upsert_delay(&self) -> Option<Duration> {
+        let draw: f64 = self.rng.lock().unwrap().gen();
+        (draw < self.config.upsert_delay_rate).then(|| Duration::from_millis(self.config.upsert_delay_ms))
+    }
+}
+
+/// A sink whose writes are delayed as `[chaos]` says
+pub struct Delayed<S> {
+    sink: S,
+    faults: Faults,
+}
+
+impl<S: Sink + Send> Delayed<S> {
+    /// `config`'s seed is offset, so the delays don't repeat the embedding faults' draws
+    pub fn new(sink: S, config: &ChaosConfig) -> Self {
+        let config = ChaosConfig { seed: config.seed.wrapping_add(1), ..config.clone() };
+        Self { sink, faults: Faults::new(&config) }
+    }
+}
+
+impl<S: Sink + Send> Sink for Delayed<S> {
+    async fn push(&mut self, document: Document) -> Result<()> {
+        if let Some(delay) = self.faults.upsert_delay() {
+            tokio::time::sleep(delay).await;
+        }
+        self.sink.push(document).await
+    }
+
+    async fn flush(&mut self) -> Result<()> {
+        if let Some(delay) = self.faults.upsert_delay() {
+            tokio::time::sleep(delay).await;
+        }
+        self.sink.flush().await
+    }
+
+    fn buffered(&self) -> usize {
+        self.sink.buffered()
+    }
+
+    fn spilled(&self) -> u64 {
+        self.sink.spilled()
+    }
+}
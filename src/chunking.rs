@@ -0,0 +1,304 @@
+use anyhow::Result;
+use tracing::warn;
+use unicode_segmentation::UnicodeSegmentation;
+use crate::clients::llm::llama_cpp::LlamaCpp;
+use crate::clients::Document;
+use crate::config::{ChunkMode, Config};
+
+/// Metadata field numbering the chunks `rechunk` cut from one source, from 0
+pub const CHUNK_INDEX_FIELD: &str = "chunk_index";
+
+/// Splits `text` into pieces of at most `size` characters, each repeating the last `overlap`
+/// characters of the one before
+///
+/// A piece ends at the last whitespace in its second half when there is one, so words stay
+/// whole; pieces are trimmed and empty ones dropped.
+pub fn chunk(text: &str, size: usize, overlap: usize) -> Vec<String> {
+    let size = size.max(1);
+    let chars: Vec<(usize, char)> = text.char_indices().collect();
+    let offset = |at: usize| chars.get(at).map_or(text.len(), |(offset, _)| *offset);
+    let mut chunks = Vec::new();
+    let mut start = 0;
+
+    while start < chars.len() {
+        let mut end = (start + size).min(chars.len());
+        if end < chars.len() {
+            if let Some(space) = (start + size / 2..end).rev().find(|at| chars[*at].1.is_whitespace()) {
+                end = space;
+            }
+        }
+
+        let piece = text[offset(start)..offset(end)].trim();
+        if !piece.is_empty() {
+            chunks.push(piece.to_string());
+        }
+        if end == chars.len() {
+            break;
+        }
+        start = end.saturating_sub(overlap).max(start + 1);
+    }
+
+    chunks
+}
+
+/// Splits `text` into pieces of at most `size` tokens, each repeating about the last
+/// `overlap` tokens of the one before
+///
+/// Pieces end at sentence boundaries as Unicode places them, which includes after 。！？ with
+/// no space following; a sentence longer than `size` is cut at word boundaries instead, its
+/// words estimated at their share of its tokens. `count` is asked once per sentence.
+pub async fn chunk_tokens(
+    text: &str,
+    size: usize,
+    overlap: usize,
+    count: &mut impl AsyncFnMut(&str) -> Result<usize>,
+) -> Result<Vec<String>> {
+    let size = size.max(1);
+    let mut segments: Vec<(&str, usize)> = Vec::new();
+
+    for sentence in text.split_sentence_bounds() {
+        let tokens = count(sentence).await?;
+        if tokens <= size {
+            segments.push((sentence, tokens));
+            continue;
+        }
+
+        // Rounding where each word ends rather than each word's share keeps the sum exact
+        let chars = sentence.chars().count();
+        let mut at = 0;
+        for word in sentence.split_word_bounds() {
+            let end = at + word.chars().count();
+            segments.push((word, (tokens * end).div_ceil(chars) - (tokens * at).div_ceil(chars)));
+            at = end;
+        }
+    }
+
+    Ok(pack(&segments, size, overlap))
+}
+
+/// Joins consecutive `segments`, each a text and its tokens, into pieces of at most `size`
+/// tokens, each starting with the segments making up the last `overlap` tokens of the one
+/// before
+///
+/// A segment larger than `size` becomes a piece of its own; pieces are trimmed and empty ones
+/// dropped.
+pub fn pack(segments: &[(&str, usize)], size: usize, overlap: usize) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut start = 0;
+
+    while start < segments.len() {
+        let mut end = start;
+        let mut tokens = 0;
+        while end < segments.len() && (end == start || tokens + segments[end].1 <= size) {
+            tokens += segments[end].1;
+            end += 1;
+        }
+
+        let piece: String = segments[start..end].iter().map(|(text, _)| *text).collect();
+        let piece = piece.trim();
+        if !piece.is_empty() {
+            chunks.push(piece.to_string());
+        }
+        if end == segments.len() {
+            break;
+        }
+
+        let mut next = end;
+        let mut repeated = 0;
+        while next > start + 1 && repeated + segments[next - 1].1 <= overlap {
+            next -= 1;
+            repeated += segments[next].1;
+        }
+        start = next;
+    }
+
+    chunks
+}
+
+/// Joins consecutive documents of the same source and splits their text again with `chunk`,
+/// each piece keeping the metadata of the first document it came from along with its
+/// `CHUNK_INDEX_FIELD`
+pub fn rechunk(documents: impl IntoIterator<Item = Document>, size: usize, overlap: usize) -> Vec<Document> {
+    join(documents)
+        .into_iter()
+        .flat_map(|document| {
+            let chunks = chunk(&document.page_content, size, overlap);
+            indexed(document, chunks)
+        })
+        .collect()
+}
+
+/// Splits documents into chunks measured the way `pipeline.chunk_mode` says
+pub struct Chunker<'c> {
+    size: usize,
+    overlap: usize,
+    /// Counts tokens with `ChunkMode::Tokens`
+    tokenizer: Option<LlamaCpp<'c>>,
+}
+
+impl<'c> Chunker<'c> {
+    /// Chunks of `pipeline.chunk_size`, 0 leaving documents as they were loaded
+    pub fn from_config(config: &'c Config) -> Result<Self> {
+        Self::new(config, config.pipeline.chunk_size, config.pipeline.chunk_overlap)
+    }
+
+    /// Chunks of `size` measured as `pipeline.chunk_mode` says, whatever `pipeline.chunk_size` is
+    pub fn new(config: &'c Config, size: usize, overlap: usize) -> Result<Self> {
+        let tokenizer = match config.pipeline.chunk_mode {
+            ChunkMode::Tokens if size > 0 => Some(LlamaCpp::from_config(&config.llama)?),
+            _ => None,
+        };
+
+        Ok(Self { size, overlap, tokenizer })
+    }
+
+    /// Like `rechunk`, counting tokens with `ChunkMode::Tokens`; text that can't be tokenized
+    /// is chunked by characters instead
+    pub async fn rechunk(&self, documents: Vec<Document>) -> Vec<Document> {
+        if self.size == 0 {
+            return documents;
+        }
+        let Some(tokenizer) = &self.tokenizer else {
+            return rechunk(documents, self.size, self.overlap);
+        };
+
+        let mut chunked = Vec::new();
+        for document in join(documents) {
+            let mut count = async |text: &str| tokenizer.tokenize(text).await;
+            let chunks = match chunk_tokens(&document.page_content, self.size, self.overlap, &mut count).await {
+                Ok(chunks) => chunks,
+                Err(e) => {
+                    warn!("Chunking {} by characters, tokenizing it failed: {e:#}", document.metadata.source);
+                    chunk(&document.page_content, self.size, self.overlap)
+                }
+            };
+            chunked.extend(indexed(document, chunks));
+        }
+
+        chunked
+    }
+}
+
+/// Consecutive documents of the same source as one, their texts separated by a blank line
+fn join(documents: impl IntoIterator<Item = Document>) -> Vec<Document> {
+    let mut joined: Vec<Document> = Vec::new();
+    for document in documents {
+        match joined.last_mut() {
+            Some(last) if last.metadata.source == document.metadata.source => {
+                last.page_content.push_str("\n\n");
+                last.page_content.push_str(&document.page_content);
+            }
+            _ => joined.push(Document { embeddings: vec![], ..document }),
+        }
+    }
+
+    joined
+}
+
+/// A document for each of `chunks` with `document`'s metadata and its `CHUNK_INDEX_FIELD`
+fn indexed(document: Document, chunks: Vec<String>) -> impl Iterator<Item = Document> {
+    chunks.into_iter()
+        .enumerate()
+        .map(move |(index, page_content)| {
+            let mut metadata = document.metadata.clone();
+            metadata.extra.insert(CHUNK_INDEX_FIELD.to_string(), index.into());
+            Document { page_content, metadata, embeddings: vec![] }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHINESE: &str = include_str!("../tests/fixtures/chinese.txt");
+    const JAPANESE: &str = include_str!("../tests/fixtures/japanese.txt");
+
+    /// Counts a token per character that isn't whitespace, about what CJK tokenizers do
+    async fn per_char(text: &str) -> Result<usize> {
+        Ok(text.chars().filter(|c| !c.is_whitespace()).count())
+    }
+
+    fn tokens(text: &str) -> usize {
+        text.chars().filter(|c| !c.is_whitespace()).count()
+    }
+
+    fn dense(text: &str) -> String {
+        text.chars().filter(|c| !c.is_whitespace()).collect()
+    }
+
+    async fn chunked(text: &str, size: usize, overlap: usize) -> Vec<String> {
+        chunk_tokens(text, size, overlap, &mut async |text: &str| per_char(text).await).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn chinese_chunks_end_at_sentences() {
+        let chunks = chunked(CHINESE, 48, 0).await;
+
+        assert!(chunks.len() > 1);
+        for chunk in &chunks {
+            assert!(tokens(chunk) <= 48, "{chunk} is over 48 tokens");
+            assert!(chunk.ends_with(['。', '！', '？']), "{chunk} ends mid-sentence");
+        }
+        assert_eq!(dense(&chunks.concat()), dense(CHINESE));
+    }
+
+    #[tokio::test]
+    async fn japanese_chunks_end_at_sentences() {
+        let chunks = chunked(JAPANESE, 60, 0).await;
+
+        assert!(chunks.len() > 1);
+        for chunk in &chunks {
+            assert!(tokens(chunk) <= 60, "{chunk} is over 60 tokens");
+            assert!(chunk.ends_with(['。', '！', '？']), "{chunk} ends mid-sentence");
+        }
+        assert_eq!(dense(&chunks.concat()), dense(JAPANESE));
+    }
+
+    #[tokio::test]
+    async fn overlap_repeats_whole_sentences() {
+        let chunks = chunked(JAPANESE, 60, 30).await;
+        let mut repeated = 0;
+
+        for pair in chunks.windows(2) {
+            let last = pair[0].split_inclusive(['。', '！', '？']).next_back().unwrap().trim();
+            if tokens(last) <= 30 {
+                assert!(pair[1].starts_with(last), "{} doesn't start where {} ends", pair[1], pair[0]);
+                repeated += 1;
+            }
+        }
+        assert!(repeated > 0);
+    }
+
+    #[tokio::test]
+    async fn long_sentences_are_cut_at_words() {
+        let sentence = CHINESE.lines().next().unwrap().replace(['，', '。'], "");
+        let chunks = chunked(&sentence, 10, 0).await;
+
+        assert!(chunks.len() > 1);
+        assert!(chunks.iter().all(|chunk| tokens(chunk) <= 10));
+        assert_eq!(chunks.concat(), sentence.trim());
+    }
+
+    #[tokio::test]
+    async fn sizes_follow_the_counted_tokens() {
+        let mut count = async |text: &str| Ok(per_char(text).await? * 2);
+        let chunks = chunk_tokens(CHINESE, 48, 0, &mut count).await.unwrap();
+
+        assert!(chunks.iter().all(|chunk| tokens(chunk) * 2 <= 48));
+    }
+
+    #[test]
+    fn characters_overshoot_cjk_sentences() {
+        // Without spaces to cut at, character chunks end wherever the count runs out
+        let chunks = chunk(CHINESE, 48, 0);
+
+        assert!(chunks.iter().any(|chunk| !chunk.ends_with(['。', '！', '？'])));
+    }
+
+    #[test]
+    fn oversized_segments_stand_alone() {
+        let chunks = pack(&[("a ", 1), ("huge ", 9), ("b", 1)], 4, 0);
+
+        assert_eq!(chunks, ["a", "huge", "b"]);
+    }
+}
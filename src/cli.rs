@@ -0,0 +1,581 @@
+use std::path::PathBuf;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+use crate::config::DEFAULT_CONFIG;
+use crate::dialect::DocumentFormat;
+use crate::feedback::Vote;
+use crate::presets::Preset;
+
+pub const DEFAULT_DOCUMENTS: &str = "/home/echo/projects/llms/documents";
+pub const DEFAULT_PROBE_STORE: &str = "probes.json";
+
+#[derive(Parser)]
+#[command(version, about)]
+pub struct Cli {
+    /// TOML config file; missing files fall back to defaults
+    #[arg(long, global = true, default_value = DEFAULT_CONFIG)]
+    pub config: PathBuf,
+    /// Named `[profiles.<name>]` table merged over the base config
+    #[arg(long, global = true, env = "RAG_PROFILE")]
+    pub profile: Option<String>,
+    /// Dimensions, prefixes, normalization, chunking and distance for a known embedding
+    /// model, under whatever the config file sets
+    #[arg(long, global = true, env = "RAG_PRESET")]
+    pub preset: Option<Preset>,
+    /// Makes sampling, shuffling and generated ids repeat from run to run
+    #[arg(long, global = true, env = "RAG_SEED")]
+    pub seed: Option<u64>,
+    /// Refuses every write to the store, for querying or auditing a production collection
+    #[arg(long, global = true)]
+    pub read_only: bool,
+    /// Defaults to `ingest` when omitted
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Embeds a JSONL file of documents and stores them in Qdrant
+    Ingest(IngestArgs),
+    /// Reports value distributions for the key payload fields of a collection
+    Facets(FacetsArgs),
+    /// Compares probe embeddings from the current model against the previous run
+    Drift(DriftArgs),
+    /// Stays running and triggers ingestion on the schedules from the config
+    Daemon,
+    /// Lists past ingestion runs
+    History(HistoryArgs),
+    /// Re-embeds and stores the documents collected in the dead letter file
+    Repair(RepairArgs),
+    /// Runs the HTTP API, plus the config's schedules when there are any
+    #[cfg(feature = "server")]
+    Serve(ServeArgs),
+    /// Embeds a query and lists the nearest stored documents
+    Search(SearchArgs),
+    /// Checks the text stored with every point against the hash taken when it was ingested
+    Audit(AuditArgs),
+    /// Shows Qdrant collection and cluster telemetry next to local pipeline stats
+    Stats(StatsArgs),
+    /// Tries pipeline settings on a sample of the corpus, scored against an eval set
+    Experiment(ExperimentArgs),
+    /// Sends searches from a serve mode query log to the configured stack at a steady rate
+    Replay(ReplayArgs),
+    /// Deletes points whose source file is gone, or that the manifest no longer lists
+    Gc(GcArgs),
+    /// Shows random stored points, or one by id, with their payload, vector norm and nearest neighbours
+    Inspect(InspectArgs),
+    /// Searches Qdrant steered by pairs of texts or points to move towards and away from
+    Discover(DiscoverArgs),
+    /// Records a thumbs up or down for one search result, for tuning reranking later
+    Feedback(FeedbackArgs),
+    /// Rewrites stored payloads written by older versions into the current payload schema
+    Migrate(MigrateArgs),
+    /// Embeds the stored points that have an empty or all-zero vector, or are flagged
+    /// `needs_embedding`, from their stored text
+    Backfill(BackfillArgs),
+    /// Deletes the Qdrant points matching a payload filter, after showing how many there are
+    Delete(DeleteArgs),
+    /// Waits for a restored collection to be indexed, then searches the `serve.warmup` probes
+    /// until their latency settles
+    Warmup(WarmupArgs),
+    /// Compares each collection with its copy on `qdrant.standby`, by point counts and a
+    /// sample of points
+    CheckStandby(CheckStandbyArgs),
+    /// Checks the config, llama.cpp, the vector store and free disk space, a line per check
+    Doctor,
+    /// Prints a completion script for `shell`
+    Completions(CompletionsArgs),
+    /// Prints the man page, or writes one per command to a directory
+    Man(ManArgs),
+}
+
+impl Default for Command {
+    fn default() -> Self {
+        Command::Ingest(IngestArgs::default())
+    }
+}
+
+impl Command {
+    /// Whether the command embeds texts through llama.cpp, so `llama.supervise` applies to it
+    pub fn embeds(&self) -> bool {
+        match self {
+            Command::Ingest(_) | Command::Daemon | Command::Repair(_) | Command::Search(_) | Command::Backfill(_) | Command::Warmup(_) => true,
+            #[cfg(feature = "server")]
+            Command::Serve(_) => true,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Args)]
+pub struct IngestArgs {
+    /// Input file or http(s) URL, or a directory searched for every format a loader reads
+    #[arg(default_value = DEFAULT_DOCUMENTS)]
+    pub path: PathBuf,
+    /// Reads the config's `[sources.<SOURCE>]` instead of `path`
+    #[arg(long)]
+    pub source: Option<String>,
+    /// After the first run, keeps ingesting the source's changes until shut down
+    #[arg(long, requires = "source")]
+    pub watch: bool,
+    /// JSON dialect of the input lines
+    #[arg(long, value_enum, default_value_t)]
+    pub format: DocumentFormat,
+    /// Abort on the first malformed line instead of logging and skipping it
+    #[arg(long)]
+    pub strict: bool,
+    /// Ingest only the first N documents (after sampling)
+    #[arg(long)]
+    pub limit: Option<usize>,
+    /// Ingest a random sample of roughly this percentage of documents
+    #[arg(long, value_parser = parse_percentage)]
+    pub sample: Option<f64>,
+    /// Order documents are embedded in
+    #[arg(long, value_enum, default_value_t)]
+    pub order: Order,
+    /// Seed for `--order shuffled`; one is drawn and logged otherwise, following `--seed`
+    #[arg(long)]
+    pub shuffle_seed: Option<u64>,
+    /// Exit with a partial failure when more than this share of documents fail
+    #[arg(long)]
+    pub fail_on_error_rate: Option<f64>,
+    /// Stops the run once embedding has cost more than this, at `llama.price_per_million_tokens`
+    #[arg(long, value_parser = parse_rate)]
+    pub max_cost: Option<f64>,
+    /// Writes a JSON report of the run's outcome and counts to this path
+    #[arg(long)]
+    pub outcome: Option<PathBuf>,
+    /// Writes the documents exactly as they are sent for embedding to this JSONL file
+    #[arg(long)]
+    pub emit_chunks: Option<PathBuf>,
+    /// Logs the CPU time of each stage at the end of the run, and its allocations when built
+    /// with the `alloc-stats` feature
+    #[arg(long)]
+    pub self_profile: bool,
+    /// Waits for Qdrant to finish indexing what was stored before exiting
+    #[arg(long)]
+    pub wait_for_index: bool,
+    /// How long `--wait-for-index` waits before failing the run
+    #[arg(long, default_value_t = 600, requires = "wait_for_index")]
+    pub index_timeout_secs: u64,
+}
+
+impl Default for IngestArgs {
+    fn default() -> Self {
+        Self {
+            path: DEFAULT_DOCUMENTS.into(),
+            source: None,
+            watch: false,
+            format: DocumentFormat::Native,
+            strict: false,
+            limit: None,
+            sample: None,
+            order: Order::Original,
+            shuffle_seed: None,
+            fail_on_error_rate: None,
+            max_cost: None,
+            outcome: None,
+            emit_chunks: None,
+            self_profile: false,
+            wait_for_index: false,
+            index_timeout_secs: 600,
+        }
+    }
+}
+
+impl IngestArgs {
+    /// What the run reads, as recorded in its history: `path`, or `source:<name>`
+    pub fn input(&self) -> PathBuf {
+        match &self.source {
+            Some(source) => format!("source:{source}").into(),
+            None => self.path.clone(),
+        }
+    }
+}
+
+/// How a command prints its results
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Output {
+    /// Aligned columns, for reading in a terminal
+    #[default]
+    Text,
+    /// A JSON object per line
+    Json,
+    /// Comma separated values with a header line
+    Csv,
+    /// A Markdown table, for pasting into reports
+    Md,
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Order {
+    /// As they appear in the input file
+    #[default]
+    Original,
+    /// By source, then content, independent of how the input was assembled
+    Sorted,
+    /// Seeded random order, spreading large sources over the whole run
+    Shuffled,
+}
+
+#[derive(Args)]
+pub struct FacetsArgs {
+    /// Overrides `qdrant.url` from the config
+    #[arg(long)]
+    pub qdrant: Option<String>,
+    /// Overrides `qdrant.collection` from the config
+    #[arg(long)]
+    pub collection: Option<String>,
+    /// Maximum number of distinct values reported per field
+    #[arg(long, default_value_t = 20)]
+    pub limit: u64,
+    /// Number of leading path segments `source` is grouped by
+    #[arg(long, default_value_t = 2)]
+    pub depth: usize,
+}
+
+#[derive(Args)]
+pub struct DriftArgs {
+    /// Where probe embeddings are kept between runs
+    #[arg(long, default_value = DEFAULT_PROBE_STORE)]
+    pub store: PathBuf,
+    /// Newline separated probe texts replacing the built-in set
+    #[arg(long)]
+    pub probes: Option<PathBuf>,
+    /// Lowest cosine similarity a probe may have to its previous embedding
+    #[arg(long, default_value_t = 0.99)]
+    pub threshold: f32,
+}
+
+#[derive(Args)]
+pub struct HistoryArgs {
+    #[command(subcommand)]
+    pub action: Option<HistoryAction>,
+    /// Number of most recent runs listed
+    #[arg(long, default_value_t = 20)]
+    pub limit: usize,
+}
+
+#[derive(Subcommand)]
+pub enum HistoryAction {
+    /// Prints every recorded detail of a single run
+    Show { id: u64 },
+}
+
+#[derive(Args)]
+pub struct RepairArgs {
+    /// Exit with a partial failure when more than this share of documents fail again
+    #[arg(long)]
+    pub fail_on_error_rate: Option<f64>,
+    /// Writes a JSON report of the run's outcome and counts to this path
+    #[arg(long)]
+    pub outcome: Option<PathBuf>,
+}
+
+#[derive(Args)]
+pub struct ServeArgs {
+    /// Overrides `serve.bind` from the config
+    #[arg(long)]
+    pub bind: Option<String>,
+}
+
+#[derive(Args)]
+pub struct AuditArgs {
+    /// Points read from the store at a time
+    #[arg(long, default_value_t = 256)]
+    pub batch_size: u64,
+}
+
+#[derive(Args)]
+pub struct WarmupArgs {
+    /// Overrides `qdrant.collection` from the config, such as a restored collection not yet
+    /// behind the name searches use
+    #[arg(long)]
+    pub collection: Option<String>,
+    /// How long to wait for Qdrant to finish indexing before the probes start
+    #[arg(long, default_value_t = 600)]
+    pub index_timeout_secs: u64,
+    /// Rounds of probes to run at most before giving up on latency settling
+    #[arg(long, default_value_t = 30)]
+    pub rounds: usize,
+    /// Consecutive rounds whose latencies have to be within `--tolerance` of each other
+    #[arg(long, default_value_t = 3)]
+    pub stable_rounds: usize,
+    /// Percentage the slowest of the stable rounds may be above the fastest
+    #[arg(long, default_value_t = 10.0, value_parser = parse_percentage)]
+    pub tolerance: f64,
+}
+
+#[derive(Args)]
+pub struct StatsArgs {
+    /// Overrides `qdrant.url` from the config
+    #[arg(long)]
+    pub qdrant: Option<String>,
+    /// Overrides `qdrant.rest_url` from the config
+    #[arg(long)]
+    pub rest_url: Option<String>,
+    /// Only this collection instead of every one the config writes to
+    #[arg(long)]
+    pub collection: Option<String>,
+}
+
+#[derive(Args)]
+pub struct ExperimentArgs {
+    #[command(subcommand)]
+    pub action: ExperimentAction,
+}
+
+#[derive(Subcommand)]
+pub enum ExperimentAction {
+    /// Indexes the sample once per chunk size and reports which retrieves best
+    ChunkSize(ChunkSizeArgs),
+    /// Indexes the sample with `llama` and every one of `models` and compares them
+    Models(ModelsArgs),
+}
+
+#[derive(Args)]
+pub struct ChunkSizeArgs {
+    #[command(flatten)]
+    pub sample: ExperimentSample,
+    /// Chunk sizes tried, in characters or as `pipeline.chunk_mode` says
+    #[arg(long, value_delimiter = ',', default_values_t = [256, 512, 1024, 2048])]
+    pub sizes: Vec<usize>,
+    /// Characters each chunk repeats from the end of the one before
+    #[arg(long, default_value_t = 64)]
+    pub overlap: usize,
+}
+
+#[derive(Args)]
+pub struct ModelsArgs {
+    #[command(flatten)]
+    pub sample: ExperimentSample,
+    /// Names of `models` compared, all of them when left out; `llama` is always included
+    #[arg(long, value_delimiter = ',')]
+    pub models: Vec<String>,
+}
+
+/// The corpus sample an experiment indexes and the eval set it is scored on
+#[derive(Args)]
+pub struct ExperimentSample {
+    /// Input file or directory the sample is drawn from
+    pub path: PathBuf,
+    /// JSONL file with a `{"query": .., "relevant": [<source>, ..]}` object per line
+    #[arg(long)]
+    pub eval: PathBuf,
+    /// JSON dialect of the input lines
+    #[arg(long, value_enum, default_value_t)]
+    pub format: DocumentFormat,
+    /// Documents in the sample at most
+    #[arg(long, default_value_t = 500)]
+    pub limit: usize,
+    /// Sample roughly this percentage of documents rather than the first ones
+    #[arg(long, value_parser = parse_percentage)]
+    pub sample: Option<f64>,
+    /// Hits per eval query that recall is measured over
+    #[arg(long, default_value_t = 10)]
+    pub top_k: u64,
+    /// Leaves the temporary collections in place instead of dropping them
+    #[arg(long)]
+    pub keep: bool,
+    /// How the trials are printed
+    #[arg(long, value_enum, default_value_t)]
+    pub output: Output,
+}
+
+#[derive(Args)]
+pub struct ReplayArgs {
+    /// Query log to replay; `serve.query_log.path` from the config when left out
+    pub path: Option<PathBuf>,
+    /// Searches started per second
+    #[arg(long, default_value_t = 10.0, value_parser = parse_rate)]
+    pub rate: f64,
+    /// Embeds the logged texts with `llama` again instead of searching the logged vectors,
+    /// so the embedding backend is load-tested too
+    #[arg(long)]
+    pub reembed: bool,
+}
+
+#[derive(Args)]
+pub struct SearchArgs {
+    pub query: String,
+    /// Number of results listed
+    #[arg(long, default_value_t = 10)]
+    pub top_k: u64,
+    /// Results skipped before the first one listed
+    #[arg(long, default_value_t = 0, conflicts_with = "cursor")]
+    pub offset: u64,
+    /// Continues where an earlier search left off, as logged after its results
+    #[arg(long)]
+    pub cursor: Option<String>,
+    /// Only lists results whose payload `KEY` is `VALUE`; a key given twice takes either value
+    #[arg(long, value_name = "KEY=VALUE", value_parser = parse_condition)]
+    pub filter: Vec<(String, String)>,
+    /// Only lists results mentioning `ENTITY`, as `[extraction]` found them; given twice, either one
+    #[arg(long, value_name = "ENTITY")]
+    pub entity: Vec<String>,
+    /// Only lists results about `TAG`, one of the topics `[extraction]` found; given twice, either one
+    #[arg(long, value_name = "TAG")]
+    pub tag: Vec<String>,
+    /// Joins results that are consecutive chunks of one source into one passage
+    #[arg(long)]
+    pub merge_adjacent: bool,
+    /// How the results are printed
+    #[arg(long, value_enum, default_value_t)]
+    pub output: Output,
+    /// Adds each result's stored vector to `--output json`, `csv` and `md`
+    #[arg(long)]
+    pub with_vectors: bool,
+}
+
+#[derive(Args)]
+pub struct GcArgs {
+    /// File listing the live sources, one per line; without it a source is live while its
+    /// file exists
+    #[arg(long)]
+    pub manifest: Option<PathBuf>,
+    /// Directory relative sources are looked up in
+    #[arg(long, default_value = ".", conflicts_with = "manifest")]
+    pub root: PathBuf,
+    /// Deletes without asking first
+    #[arg(long)]
+    pub yes: bool,
+    /// Only lists what would be deleted
+    #[arg(long, conflicts_with = "yes")]
+    pub dry_run: bool,
+    /// Points read from the store at a time
+    #[arg(long, default_value_t = 256)]
+    pub batch_size: u64,
+}
+
+#[derive(Args)]
+pub struct InspectArgs {
+    /// Point to show instead of a random sample
+    pub id: Option<String>,
+    /// Random points shown without an id
+    #[arg(long, default_value_t = 5, conflicts_with = "id")]
+    pub sample: usize,
+    /// Nearest neighbours listed per point
+    #[arg(long, default_value_t = 5)]
+    pub neighbors: u64,
+    /// Points read from the store at a time while sampling
+    #[arg(long, default_value_t = 256)]
+    pub batch_size: u64,
+}
+
+#[derive(Args)]
+pub struct DiscoverArgs {
+    /// Target the results should be near; without one they only have to fit the pairs
+    pub query: Option<String>,
+    /// Text or point id the results lean towards, paired with the `--negative` at the same position
+    #[arg(long, required = true)]
+    pub positive: Vec<String>,
+    /// Text or point id the results lean away from
+    #[arg(long, required = true)]
+    pub negative: Vec<String>,
+    /// Number of results listed
+    #[arg(long, default_value_t = 10)]
+    pub top_k: u64,
+    /// Only lists results whose payload `KEY` is `VALUE`; a key given twice takes either value
+    #[arg(long, value_name = "KEY=VALUE", value_parser = parse_condition)]
+    pub filter: Vec<(String, String)>,
+    /// Only lists results mentioning `ENTITY`, as `[extraction]` found them; given twice, either one
+    #[arg(long, value_name = "ENTITY")]
+    pub entity: Vec<String>,
+    /// Only lists results about `TAG`, one of the topics `[extraction]` found; given twice, either one
+    #[arg(long, value_name = "TAG")]
+    pub tag: Vec<String>,
+}
+
+#[derive(Args)]
+pub struct FeedbackArgs {
+    /// The query the result was returned for
+    pub query: String,
+    /// Id of the result, as `search` lists it
+    pub point_id: String,
+    #[arg(value_enum)]
+    pub vote: Vote,
+}
+
+#[derive(Args)]
+pub struct MigrateArgs {
+    /// Only counts the points that would be rewritten
+    #[arg(long)]
+    pub dry_run: bool,
+    /// Points read from, and rewritten in, the store at a time
+    #[arg(long, default_value_t = 256)]
+    pub batch_size: u64,
+}
+
+#[derive(Args)]
+pub struct CheckStandbyArgs {
+    /// Points of each collection compared with their copies, picked at random
+    #[arg(long, default_value_t = 100)]
+    pub sample: usize,
+    /// Copies the sampled points the standby lacks or holds differently from the primary
+    #[arg(long)]
+    pub resync: bool,
+    /// Checks again every `SECS` until shut down, instead of once
+    #[arg(long, value_name = "SECS")]
+    pub every: Option<u64>,
+    /// Points read from the primary at a time while sampling
+    #[arg(long, default_value_t = 256)]
+    pub batch_size: u64,
+}
+
+#[derive(Args)]
+pub struct BackfillArgs {
+    /// Only counts the points that would be embedded
+    #[arg(long)]
+    pub dry_run: bool,
+    /// Points read from the store at a time
+    #[arg(long, default_value_t = 256)]
+    pub batch_size: u64,
+}
+
+#[derive(Args)]
+pub struct DeleteArgs {
+    /// Deletes points whose payload `KEY` is `VALUE`; a key given twice takes either value
+    #[arg(long, value_name = "KEY=VALUE", value_parser = parse_condition, required = true)]
+    pub filter: Vec<(String, String)>,
+    /// Deletes the points; without it they are only counted and listed
+    #[arg(long)]
+    pub yes: bool,
+}
+
+#[derive(Args)]
+pub struct CompletionsArgs {
+    #[arg(value_enum)]
+    pub shell: Shell,
+}
+
+#[derive(Args)]
+pub struct ManArgs {
+    /// Writes `rag-rs.1` and a page for every command here instead of printing `rag-rs.1`
+    #[arg(long)]
+    pub dir: Option<PathBuf>,
+}
+
+fn parse_condition(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!("{value} is not KEY=VALUE")),
+    }
+}
+
+fn parse_rate(value: &str) -> Result<f64, String> {
+    let rate: f64 = value.parse().map_err(|e| format!("{e}"))?;
+    if !rate.is_finite() || rate <= 0.0 {
+        return Err(format!("{rate} is not a rate above 0"));
+    }
+
+    Ok(rate)
+}
+
+fn parse_percentage(value: &str) -> Result<f64, String> {
+    let percentage: f64 = value.parse().map_err(|e| format!("{e}"))?;
+    if !(0.0..=100.0).contains(&percentage) {
+        return Err(format!("{percentage} is not between 0 and 100"));
+    }
+
+    Ok(percentage)
+}
@@ -0,0 +1,185 @@
+use std::fmt::{Display, Formatter};
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use reqwest::header::{AUTHORIZATION, CONTENT_TYPE};
+use reqwest::{RequestBuilder, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use crate::api::{Stored, Streamed, IDEMPOTENCY_KEY};
+use crate::clients::Document;
+use crate::clients::vector_store::Filter;
+use crate::control::State;
+use crate::dialect::DocumentFormat;
+use crate::feedback::{Feedback, Vote};
+use crate::search::Page;
+
+/// Typed calls to a `rag-rs serve` instance's REST API
+#[derive(Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    /// Without a trailing slash
+    base_url: String,
+    admin_api_key: Option<String>,
+    write_api_key: Option<String>,
+}
+
+/// A search as `GET /search` takes it
+#[derive(Debug, Clone, Default)]
+pub struct SearchRequest {
+    pub query: String,
+    /// The server's default of 10 when `None`
+    pub limit: Option<u64>,
+    pub offset: u64,
+    /// `next_cursor` of an earlier page
+    pub cursor: Option<String>,
+    pub filter: Filter,
+}
+
+impl SearchRequest {
+    pub fn new(query: impl Into<String>) -> Self {
+        Self { query: query.into(), ..Self::default() }
+    }
+}
+
+/// The error envelope of a failed call; the `anyhow::Error` downcasts to it
+#[derive(Debug, Clone, Deserialize)]
+pub struct ApiFailure {
+    #[serde(skip)]
+    pub status: u16,
+    pub code: String,
+    pub message: String,
+    /// Whether sending the same request again may work
+    pub retryable: bool,
+    pub trace_id: String,
+}
+
+impl Display for ApiFailure {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {} ({}): {}", self.status, self.code, self.trace_id, self.message)
+    }
+}
+
+impl std::error::Error for ApiFailure {}
+
+#[derive(Deserialize)]
+struct Envelope {
+    error: ApiFailure,
+}
+
+impl Client {
+    /// `base_url` like `http://127.0.0.1:8088`
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            admin_api_key: None,
+            write_api_key: None,
+        }
+    }
+
+    /// Needed by `state`, which calls an `/admin` route
+    pub fn with_admin_api_key(self, key: impl Into<String>) -> Self {
+        Self { admin_api_key: Some(key.into()), ..self }
+    }
+
+    /// Needed by `ingest`, `ingest_ndjson` and `feedback` unless the admin key is set
+    pub fn with_write_api_key(self, key: impl Into<String>) -> Self {
+        Self { write_api_key: Some(key.into()), ..self }
+    }
+
+    /// Embeds and stores `documents`, returning how many were stored
+    ///
+    /// Retrying with the same `idempotency_key` won't store them twice.
+    pub async fn ingest(&self, documents: &[Document], idempotency_key: Option<&str>) -> Result<u64> {
+        let mut request = self.writing(self.http.post(self.url("/documents")))?
+            .header(CONTENT_TYPE, "application/json")
+            .body(serde_json::to_vec(documents)?);
+        if let Some(key) = idempotency_key {
+            request = request.header(IDEMPOTENCY_KEY, key);
+        }
+
+        let stored: Stored = send(request).await?;
+        Ok(stored.stored)
+    }
+
+    /// Sends `ndjson`, a document per line in `format`, to the streaming ingest endpoint
+    pub async fn ingest_ndjson(&self, ndjson: impl Into<reqwest::Body>, format: DocumentFormat) -> Result<Streamed> {
+        let format = format.to_possible_value().expect("every format has a name");
+        let request = self.writing(self.http.post(self.url("/documents/stream")))?
+            .query(&[("format", format.get_name())])
+            .header(CONTENT_TYPE, "application/x-ndjson")
+            .body(ndjson);
+
+        send(request).await
+    }
+
+    pub async fn search(&self, search: &SearchRequest) -> Result<Page> {
+        let mut query = vec![("q", search.query.clone()), ("offset", search.offset.to_string())];
+        if let Some(limit) = search.limit {
+            query.push(("limit", limit.to_string()));
+        }
+        if let Some(cursor) = &search.cursor {
+            query.push(("cursor", cursor.clone()));
+        }
+        if !search.filter.is_empty() {
+            query.push(("filter", serde_json::to_string(&search.filter)?));
+        }
+
+        send(self.http.get(self.url("/search")).query(&query)).await
+    }
+
+    /// Records a vote on the result `point_id` of `query`
+    pub async fn feedback(&self, query: &str, point_id: &str, vote: Vote) -> Result<Feedback> {
+        let body = serde_json::json!({ "query": query, "point_id": point_id, "vote": vote });
+        let request = self.writing(self.http.post(self.url("/feedback")))?
+            .header(CONTENT_TYPE, "application/json")
+            .body(serde_json::to_vec(&body)?);
+
+        send(request).await
+    }
+
+    /// The ingestion pipeline's state: paused or not, queue depths and throughput
+    pub async fn state(&self) -> Result<State> {
+        let key = self.admin_api_key.as_deref().context("Client::state needs an admin API key")?;
+        let request = self.http.get(self.url("/admin/state"))
+            .header(AUTHORIZATION, format!("Bearer {key}"));
+
+        send(request).await
+    }
+
+    /// `request` with the key the routes storing documents and votes take
+    fn writing(&self, request: RequestBuilder) -> Result<RequestBuilder> {
+        let key = self.write_api_key.as_deref().or(self.admin_api_key.as_deref())
+            .context("Storing documents or votes needs a write or admin API key")?;
+
+        Ok(request.header(AUTHORIZATION, format!("Bearer {key}")))
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{path}", self.base_url)
+    }
+}
+
+/// Sends the request, decoding a success as `T` and a failure as an `ApiFailure`
+async fn send<T: DeserializeOwned>(request: RequestBuilder) -> Result<T> {
+    let response = request.send().await?;
+    let status = response.status();
+    let body = response.bytes().await?;
+
+    if status.is_success() {
+        return serde_json::from_slice(&body).with_context(|| format!("Unexpected answer to a {status} response"));
+    }
+
+    let failure = serde_json::from_slice::<Envelope>(&body)
+        .map(|envelope| ApiFailure { status: status.as_u16(), ..envelope.error })
+        .unwrap_or_else(|_| ApiFailure {
+            status: status.as_u16(),
+            code: "unknown".to_string(),
+            message: String::from_utf8_lossy(&body).into_owned(),
+            retryable: status == StatusCode::SERVICE_UNAVAILABLE || status == StatusCode::BAD_GATEWAY,
+            trace_id: String::new(),
+        });
+
+    Err(failure.into())
+}
@@ -1,23 +1,31 @@
-use crate::clients::{Document, EmbedRequest, EmbedResponse};
-use anyhow::Result;
-use curl::easy::{Easy, List, ReadError};
-use curl::Error;
-use serde::de::{EnumAccess, MapAccess, SeqAccess, Unexpected, Visitor};
-use serde::{Deserialize, Deserializer};
-use std::borrow::Cow;
+use crate::clients::{Document, EmbedRequest, EmbedResponse, EncodingFormat};
+use crate::config::LlamaConfig;
+use crate::secret::{redact_url, Secret};
+use anyhow::{anyhow, bail, Result};
+use curl::easy::{Easy, List};
+use serde::Deserialize;
 use std::fmt::{Display, Formatter};
-use std::io::{stdout, Read, Write};
-use std::str::FromStr;
-use std::sync::{Arc, Mutex};
-use std::time::Duration;
-use reqwest::{Client, Response};
-use tracing::field::debug;
-use tracing::{debug, error, info, warn};
-
-#[derive(PartialEq)]
+use std::path::Path;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use bytes::{BufMut, Bytes, BytesMut};
+use reqwest::Client;
+use tracing::{debug, info, warn};
+
+pub const DEFAULT_HOST: &str = "127.0.0.1";
+pub const DEFAULT_PORT: u16 = 8080;
+pub const DEFAULT_BUSY_MAX_WAIT_SECS: u64 = 300;
+/// First wait after a 503 without a Retry-After hint, doubled on every further 503
+const BUSY_INITIAL_DELAY: Duration = Duration::from_millis(250);
+const BUSY_MAX_DELAY: Duration = Duration::from_secs(8);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Status {
     Ok,
     Loading,
+    /// Every slot is processing, as reported by builds that check slots in `/health`
+    Busy,
     Error,
     Unknown,
 }
@@ -27,6 +35,7 @@ impl Display for Status {
         let string = match self {
             Status::Ok => "ok",
             Status::Loading => "loading",
+            Status::Busy => "busy",
             Status::Error => "error",
             Status::Unknown => "unknown",
         };
@@ -34,76 +43,236 @@ impl Display for Status {
         f.write_str(string)
     }
 }
-impl<'de> Deserialize<'de> for Status {
-    fn deserialize<D>(deserializer: D) -> Result<Status, D::Error>
-    where
-        D: Deserializer<'de>,
-    {
-        deserializer.deserialize_map(StatusVisitor)
+
+impl From<&str> for Status {
+    fn from(status: &str) -> Self {
+        match status {
+            "ok" => Status::Ok,
+            "loading model" | "loading" => Status::Loading,
+            "no slot available" => Status::Busy,
+            "error" => Status::Error,
+            _ => Status::Unknown,
+        }
     }
 }
 
-struct StatusVisitor;
-impl<'de> Visitor<'de> for StatusVisitor {
-    type Value = Status;
+/// What llama-server reports on `/health`, across the body shapes of old and new builds
+#[derive(Debug, Clone, Deserialize)]
+#[serde(from = "HealthBody")]
+pub struct Health {
+    pub status: Status,
+    pub slots_idle: Option<u32>,
+    pub slots_processing: Option<u32>,
+    /// Set when the server answered with an error object instead of a status
+    pub error: Option<HealthError>,
+}
 
-    fn expecting(&self, formatter: &mut Formatter) -> std::fmt::Result {
-        formatter.write_str("a slice around 16-32 bytes wide.")
-    }
+/// The OpenAI style error object newer builds answer with while unavailable
+#[derive(Debug, Clone, Deserialize)]
+pub struct HealthError {
+    pub code: Option<u16>,
+    pub message: String,
+    #[serde(rename = "type")]
+    pub kind: Option<String>,
+}
 
-    fn visit_map<A>(self, mut map: A) -> std::result::Result<Self::Value, A::Error>
-    where
-        A: MapAccess<'de>,
-    {
-        while let Ok(Some((k, v))) = map.next_entry::<&str, &str>() {
-            let stat = match v {
-                "ok" => Status::Ok,
-                "loading model" => Status::Loading,
-                "error" => Status::Error,
-                &_ => Status::Unknown,
-            };
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum HealthBody {
+    Error {
+        error: HealthError,
+    },
+    Status {
+        status: String,
+        #[serde(default)]
+        slots_idle: Option<u32>,
+        #[serde(default)]
+        slots_processing: Option<u32>,
+    },
+}
 
-            return Ok(stat);
+impl From<HealthBody> for Health {
+    fn from(body: HealthBody) -> Self {
+        match body {
+            HealthBody::Status { status, slots_idle, slots_processing } => Health {
+                status: Status::from(status.as_str()),
+                slots_idle,
+                slots_processing,
+                error: None,
+            },
+            HealthBody::Error { error } => Health {
+                status: match error.kind.as_deref() {
+                    Some("unavailable_error") if error.message.to_lowercase().contains("loading") => Status::Loading,
+                    _ => Status::Error,
+                },
+                slots_idle: None,
+                slots_processing: None,
+                error: Some(error),
+            },
         }
-
-        Ok(Status::Unknown)
     }
 }
 
-// Returns a slice of the array ending at the first zero byte found:
-// Serde doesn't appreciate strings with dangling zeros.
-fn fit_to_size(buf: &[u8]) -> &[u8] {
-    for i in 0..buf.len() {
-        if buf[i] == 0 {
-            return &buf[0..i];
+impl Display for Health {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.status)?;
+        if let (Some(idle), Some(processing)) = (self.slots_idle, self.slots_processing) {
+            write!(f, " ({idle} slots idle, {processing} processing)")?;
         }
+        if let Some(error) = &self.error {
+            write!(f, ": {}", error.message)?;
+        }
+
+        Ok(())
     }
+}
 
-    &buf
+/// An embedding request llama.cpp answered with an error status, or went on refusing as busy
+#[derive(Debug)]
+pub struct ServerError {
+    pub status: u16,
+    pub reason: String,
+}
+
+impl Display for ServerError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "llama.cpp answered {} to an embedding: {}", self.status, self.reason)
+    }
+}
+
+impl std::error::Error for ServerError {}
+
+/// An embedding request that ran past `llama.embed_timeout_secs`
+#[derive(Debug)]
+pub struct EmbedTimeout(pub Duration);
+
+impl Display for EmbedTimeout {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Embedding timed out after {:?}", self.0)
+    }
+}
+
+impl std::error::Error for EmbedTimeout {}
+
+/// Whether `e` says llama.cpp is down or struggling, rather than that the request was wrong:
+/// it couldn't be reached, didn't answer in time, or answered with a 5xx
+pub fn is_unavailable(e: &anyhow::Error) -> bool {
+    e.chain().any(|cause| {
+        if let Some(e) = cause.downcast_ref::<reqwest::Error>() {
+            return e.is_connect() || e.is_timeout();
+        }
+        if let Some(e) = cause.downcast_ref::<curl::Error>() {
+            return e.is_couldnt_connect() || e.is_couldnt_resolve_host() || e.is_operation_timedout();
+        }
+        cause.downcast_ref::<ServerError>().is_some_and(|e| e.status >= 500) || cause.is::<EmbedTimeout>()
+    })
 }
 
 pub struct LlamaCpp<'l> {
     pub(crate) https: bool,
     pub(crate) host: &'l str,
     pub(crate) port: u16,
-    pub(crate) headers: List,
+    /// Behind a lock so the client can be shared between tasks, curl lists aren't `Sync`
+    headers: Mutex<List>,
+    api_key: Option<&'l str>,
+    proxy: Option<&'l str>,
+    no_proxy: Option<&'l str>,
+    socket: Option<&'l Path>,
+    compression: bool,
+    busy_max_wait: Duration,
+    /// Also bounds the blocking curl transfer, which can't be cancelled from the async side
+    timeout: Option<Duration>,
+    body: BodyTemplate,
+    /// Used for queries when they are prefixed differently from documents
+    query_body: Option<BodyTemplate>,
+    /// Scales every vector to unit length
+    normalize: bool,
+    /// Tokens embedded so far, see `tokens_used`
+    tokens: AtomicU64,
+    price_per_million_tokens: Option<f64>,
+    #[cfg(feature = "chaos")]
+    faults: Option<crate::chaos::Faults>,
     client: Client
 }
 
+/// The JSON around a document's text, serialized once, plus a buffer reused for every request body
+struct BodyTemplate {
+    prefix: Vec<u8>,
+    suffix: Vec<u8>,
+    /// Prompt prefix put before every text
+    prompt: String,
+    buffer: Mutex<BytesMut>,
+}
+
+impl BodyTemplate {
+    const SENTINEL: &'static str = "\0";
+
+    fn new(encoding: EncodingFormat, prompt: &str) -> Self {
+        let mut request = EmbedRequest::for_text(Self::SENTINEL);
+        if encoding != EncodingFormat::Float {
+            request.encoding_format = Some(encoding);
+        }
+
+        // Splitting a real serialization keeps EmbedRequest the single source of the wire format
+        let template = serde_json::to_vec(&request).expect("EmbedRequest always serializes");
+        let sentinel = serde_json::to_vec(Self::SENTINEL).expect("str always serializes");
+        let at = template
+            .windows(sentinel.len())
+            .position(|w| w == sentinel.as_slice())
+            .expect("sentinel is part of the template");
+
+        Self {
+            prefix: template[..at].to_vec(),
+            suffix: template[at + sentinel.len()..].to_vec(),
+            prompt: prompt.to_string(),
+            buffer: Mutex::new(BytesMut::with_capacity(4096)),
+        }
+    }
+
+    /// Renders a body for `content`; the allocation is reclaimed once the previous body is dropped
+    fn render(&self, content: &str) -> Result<Bytes> {
+        let mut buffer = self.buffer.lock().unwrap();
+        buffer.clear();
+        buffer.reserve(self.prefix.len() + self.prompt.len() + content.len() + 2 + self.suffix.len());
+        buffer.extend_from_slice(&self.prefix);
+        match self.prompt.as_str() {
+            "" => serde_json::to_writer((&mut *buffer).writer(), content)?,
+            prompt => serde_json::to_writer((&mut *buffer).writer(), &format!("{prompt}{content}"))?,
+        }
+        buffer.extend_from_slice(&self.suffix);
+
+        Ok(buffer.split().freeze())
+    }
+}
+
+fn default_headers() -> List {
+    let mut l = List::new();
+    _ = l.append("Accept: application/json");
+    _ = l.append("Content-Type: application/json");
+    l
+}
+
 impl<'l> Default for LlamaCpp<'l> {
     fn default() -> Self {
-        let headers = {
-            let mut l = List::new();
-            _ = l.append("Accept: application/json");
-            _ = l.append("Content-Type: application/json");
-            l
-        };
-
         Self {
             https: false,
-            host: "127.0.0.1",
-            port: 8080,
-            headers,
+            host: DEFAULT_HOST,
+            port: DEFAULT_PORT,
+            headers: Mutex::new(default_headers()),
+            api_key: None,
+            proxy: None,
+            no_proxy: None,
+            socket: None,
+            compression: true,
+            busy_max_wait: Duration::from_secs(DEFAULT_BUSY_MAX_WAIT_SECS),
+            timeout: None,
+            body: BodyTemplate::new(EncodingFormat::Float, ""),
+            query_body: None,
+            normalize: false,
+            tokens: AtomicU64::new(0),
+            price_per_million_tokens: None,
+            #[cfg(feature = "chaos")]
+            faults: None,
             client: Client::new()
         }
     }
@@ -115,11 +284,71 @@ impl<'l> LlamaCpp<'l> {
             https,
             host,
             port,
-            headers,
+            headers: Mutex::new(headers),
+            api_key: None,
+            proxy: None,
+            no_proxy: None,
+            socket: None,
+            compression: true,
+            busy_max_wait: Duration::from_secs(DEFAULT_BUSY_MAX_WAIT_SECS),
+            timeout: None,
+            body: BodyTemplate::new(EncodingFormat::Float, ""),
+            query_body: None,
+            normalize: false,
+            tokens: AtomicU64::new(0),
+            price_per_million_tokens: None,
+            #[cfg(feature = "chaos")]
+            faults: None,
             client: reqwest::Client::new(),
         }
     }
 
+    pub fn from_config(config: &'l LlamaConfig) -> Result<Self> {
+        let mut headers = default_headers();
+        let api_key = config.api_key.as_ref().map(Secret::expose);
+        if let Some(key) = api_key {
+            _ = headers.append(&format!("Authorization: Bearer {key}"));
+        }
+
+        // Without an explicit proxy both reqwest and curl fall back to the *_PROXY variables
+        let proxy = config.proxy.as_ref().map(Secret::expose);
+        let no_proxy = config.no_proxy.as_deref();
+        let mut builder = Client::builder()
+            .gzip(config.compression)
+            .deflate(config.compression)
+            .pool_idle_timeout(Duration::from_secs(config.pool_idle_timeout_secs))
+            .pool_max_idle_per_host(config.pool_max_idle)
+            .tcp_keepalive(Duration::from_secs(config.tcp_keepalive_secs));
+        if let Some(url) = proxy {
+            let proxy = reqwest::Proxy::all(url)
+                .map_err(|e| anyhow!("Invalid llama proxy {}: {}", redact_url(url), e.without_url()))?
+                .no_proxy(no_proxy.and_then(reqwest::NoProxy::from_string));
+            builder = builder.proxy(proxy);
+        }
+
+        Ok(Self {
+            api_key,
+            proxy,
+            no_proxy,
+            socket: config.socket.as_deref(),
+            compression: config.compression,
+            busy_max_wait: Duration::from_secs(config.busy_max_wait_secs),
+            timeout: config.embed_timeout(),
+            body: BodyTemplate::new(config.encoding, &config.prompt_prefix),
+            query_body: config.query_prefix.as_deref().map(|prefix| BodyTemplate::new(config.encoding, prefix)),
+            normalize: config.normalize,
+            price_per_million_tokens: config.price_per_million_tokens,
+            client: builder.build()?,
+            ..Self::new(&config.host, config.port, headers, config.https)
+        })
+    }
+
+    /// Injects `faults` into every embedding request
+    #[cfg(feature = "chaos")]
+    pub fn with_faults(self, faults: crate::chaos::Faults) -> Self {
+        Self { faults: Some(faults), ..self }
+    }
+
     fn create_url(&self, endpoint: &str) -> String {
         let mut http = String::from("http");
         if self.https {
@@ -132,7 +361,7 @@ impl<'l> LlamaCpp<'l> {
     fn clone_headers(&self) -> Result<List> {
         let mut l = List::new();
 
-        for item in self.headers.iter() {
+        for item in self.headers.lock().unwrap().iter() {
             let s = std::str::from_utf8(item)?;
             _ = l.append(s);
         }
@@ -142,91 +371,332 @@ impl<'l> LlamaCpp<'l> {
 
 
 
-    pub fn health_check(&self) -> Result<Status> {
+    pub fn health_check(&self) -> Result<Health> {
         info!("Performing health check");
-        let buf: Arc<Mutex<[u8; 32]>> = Arc::new(Mutex::new([0u8; 32]));
-        let buf_c: Arc<Mutex<[u8; 32]>> = buf.clone();
+        let mut body = Vec::new();
         let url = self.create_url("health");
         let mut curl = Easy::new();
 
-        _ = curl.url(&url)?;
+        curl.url(&url)?;
         _ = curl.http_headers(self.clone_headers()?);
-        // curl.perform() blocks so Arc guards are just to make the compiler happy
-        _ = curl.write_function(move |dataz| {
-            let mut buf = buf_c.lock().unwrap();
-            let l = dataz.len();
-
-            if l > 32 {
-                let s = std::str::from_utf8(dataz).unwrap_or("RT: Failed to decode body via utf-8");
-                warn!("Oddly sized health message:");
-                warn!("{}", s);
-            } else {
-                _ = buf[..l].copy_from_slice(dataz);
-                debug!("Read {} bytes from remote", l);
-            }
+        if let Some(proxy) = self.proxy {
+            curl.proxy(proxy)?;
+        }
+        if let Some(no_proxy) = self.no_proxy {
+            curl.noproxy(no_proxy)?;
+        }
+        if let Some(socket) = self.socket {
+            curl.unix_socket_path(Some(socket))?;
+        }
+
+        {
+            let mut transfer = curl.transfer();
+            transfer.write_function(|chunk| {
+                body.extend_from_slice(chunk);
+                Ok(chunk.len())
+            })?;
+            transfer.perform()?;
+        }
+        debug!("Read {} bytes from remote", body.len());
 
-            Ok(l)
+        let health: Health = serde_json::from_slice(&body).map_err(|e| {
+            anyhow!("Unexpected health response {:?}: {e}", String::from_utf8_lossy(&body))
         })?;
 
-        _ = curl.perform()?;
+        info!("Llama is {health}");
 
-        let buf = buf.lock().unwrap();
-        let slice = fit_to_size(&*buf);
-        // let _strang = String::from_utf8_lossy(slice);
-        let obj: Status = serde_json::from_slice(slice)?;
+        Ok(health)
+    }
 
-        info!("Llama is {obj}");
+    pub async fn embed(&self, text: Document) -> Result<Document> {
+        let embeddings = self.embedding(&text.page_content).await?;
 
-        return Ok(obj);
+        Ok(Document { embeddings, ..text })
     }
 
-    pub async fn embed(&self, text: Document) -> Result<Document> {
-        // TODO POST via cURL - For some reason the write_function was losing bytes so reqwest it for now.
+    /// Embeds `content`, an empty vector meaning the backend answered without one
+    pub async fn embedding(&self, content: &str) -> Result<Vec<f32>> {
+        self.embed_body(&self.body, content).await
+    }
+
+    /// Embeds a search query like `embedding`, prefixed with `llama.query_prefix` when there is one
+    pub async fn query_embedding(&self, query: &str) -> Result<Vec<f32>> {
+        self.embed_body(self.query_body.as_ref().unwrap_or(&self.body), query).await
+    }
+
+    async fn embed_body(&self, template: &BodyTemplate, content: &str) -> Result<Vec<f32>> {
         let url = self.create_url("embedding");
-        let request: EmbedRequest = text.to_owned().into();
-        let req_str: String = serde_json::to_string(&request)?;
-        let res = self.client.post(url).body(req_str).send().await?;
-        let json_str = res.text().await?;
-        // Qdrant demands f32 instead of 64.. curious
-        let embedding_32 = match serde_json::from_str::<EmbedResponse>(&json_str) {
-           Ok(response) => response.embedding
-                .into_iter()
-                .map(|f| f as f32)
-                .collect::<Vec<f32>>(),
+        let body = template.render(content)?;
+        let started = Instant::now();
+        let mut delay = BUSY_INITIAL_DELAY;
+
+        // Under load llama-server refuses work with a 503 instead of queueing it; the timeout
+        // is for each attempt, so waiting for a free slot doesn't count against it
+        let json = loop {
+            let reply = match self.timeout {
+                Some(limit) => tokio::time::timeout(limit, self.post_embedding(&url, body.clone())).await
+                    .map_err(|_| EmbedTimeout(limit))??,
+                None => self.post_embedding(&url, body.clone()).await?,
+            };
+            if reply.status != 503 {
+                if reply.status >= 400 {
+                    return Err(ServerError { status: reply.status, reason: reply.reason() }.into());
+                }
+                break reply.body;
+            }
+
+            let wait = reply.retry_after.unwrap_or(delay);
+            let reason = serde_json::from_slice::<ErrorBody>(&reply.body)
+                .map(|body| body.error.message)
+                .unwrap_or_else(|_| "service unavailable".to_string());
+            if started.elapsed() + wait > self.busy_max_wait {
+                let reason = format!("still busy ({reason}) after {:?}", started.elapsed());
+                return Err(ServerError { status: reply.status, reason }.into());
+            }
+
+            debug!("llama.cpp is busy ({reason}), retrying in {wait:?}");
+            tokio::time::sleep(wait).await;
+            delay = (delay * 2).min(BUSY_MAX_DELAY);
+        };
+        debug!("Embedding response of {} bytes took {:?}", json.len(), started.elapsed());
+        let mut embedding_32 = match serde_json::from_slice::<EmbedResponse>(&json) {
+            Ok(response) => {
+                let tokens = response.usage.as_ref()
+                    .map_or_else(|| estimate_tokens(template.prompt.len() + content.len()), |usage| usage.prompt_tokens);
+                self.tokens.fetch_add(tokens, Ordering::Relaxed);
+                response.embedding.into_f32().unwrap_or_else(|e| {
+                    warn!("Undecodable embedding: {e}");
+                    vec![]
+                })
+            }
             Err(_) => vec![]
         };
+        if self.normalize {
+            let norm = embedding_32.iter().map(|x| x * x).sum::<f32>().sqrt();
+            if norm > 0.0 {
+                embedding_32.iter_mut().for_each(|x| *x /= norm);
+            }
+        }
 
-        Ok(Document {
-            page_content: text.page_content,
-            metadata: text.metadata,
-            embeddings: embedding_32,
-        })
+        Ok(embedding_32)
+    }
+
+    /// Tokens embedded through this client, as the backend reported them or estimated where
+    /// it doesn't
+    pub fn tokens_used(&self) -> u64 {
+        self.tokens.load(Ordering::Relaxed)
+    }
+
+    /// What `tokens_used` cost at `llama.price_per_million_tokens`, when there is a price
+    pub fn cost(&self) -> Option<f64> {
+        self.price_per_million_tokens.map(|price| self.tokens_used() as f64 * price / 1_000_000.0)
+    }
+
+    /// Number of tokens the model cuts `content` into, without the prompt prefix or special
+    /// tokens
+    pub async fn tokenize(&self, content: &str) -> Result<usize> {
+        let url = self.create_url("tokenize");
+        let body = serde_json::to_vec(&serde_json::json!({ "content": content }))?;
+        let reply = self.post(&url, body.into()).await?;
+        if reply.status != 200 {
+            bail!("llama.cpp answered {} to tokenize: {}", reply.status, reply.reason());
+        }
+
+        Ok(serde_json::from_slice::<TokenizeResponse>(&reply.body)?.tokens.len())
+    }
+
+    /// How relevant each of `documents` is to `query`, in their order, as scored by a
+    /// reranking model served with `--reranking`
+    pub async fn rerank(&self, query: &str, documents: &[String]) -> Result<Vec<f32>> {
+        let url = self.create_url("rerank");
+        let body = serde_json::to_vec(&serde_json::json!({ "query": query, "documents": documents }))?;
+        let reply = self.post(&url, body.into()).await?;
+        if reply.status != 200 {
+            bail!("llama.cpp answered {} to rerank: {}", reply.status, reply.reason());
+        }
+
+        let mut scores = vec![f32::NEG_INFINITY; documents.len()];
+        for result in serde_json::from_slice::<RerankResponse>(&reply.body)?.results {
+            if let Some(score) = scores.get_mut(result.index) {
+                *score = result.relevance_score;
+            }
+        }
+
+        Ok(scores)
+    }
+
+    /// The model's continuation of `prompt`, at most `max_tokens` long and sampled greedily
+    /// so the same prompt gets the same answer
+    pub async fn complete(&self, prompt: &str, max_tokens: u32) -> Result<String> {
+        self.completion(serde_json::json!({ "prompt": prompt, "n_predict": max_tokens, "temperature": 0.0 })).await
+    }
+
+    /// Like `complete`, with the answer held to JSON matching `schema` by the grammar
+    /// llama-server builds from it
+    pub async fn complete_json(&self, prompt: &str, schema: &serde_json::Value, max_tokens: u32) -> Result<serde_json::Value> {
+        let body = serde_json::json!({ "prompt": prompt, "n_predict": max_tokens, "temperature": 0.0, "json_schema": schema });
+        let content = self.completion(body).await?;
+
+        serde_json::from_str(&content).map_err(|e| anyhow!("Completion isn't the JSON asked for ({e}): {content:?}"))
+    }
+
+    async fn completion(&self, body: serde_json::Value) -> Result<String> {
+        let url = self.create_url("completion");
+        let reply = self.post(&url, serde_json::to_vec(&body)?.into()).await?;
+        if reply.status != 200 {
+            bail!("llama.cpp answered {} to a completion: {}", reply.status, reply.reason());
+        }
+
+        Ok(serde_json::from_slice::<CompletionResponse>(&reply.body)?.content)
+    }
+
+    /// `post`, unless the request draws one of the `[chaos]` faults
+    async fn post_embedding(&self, url: &str, body: Bytes) -> Result<Reply> {
+        #[cfg(feature = "chaos")]
+        if let Some(faults) = &self.faults {
+            use crate::chaos::Fault;
+            match faults.embed() {
+                Some(Fault::Fail) => bail!("Injected embedding failure"),
+                Some(Fault::Busy) => return Ok(Reply { status: 503, retry_after: None, body: Bytes::new() }),
+                Some(Fault::Malformed) => {
+                    let reply = self.post(url, body).await?;
+                    let cut = reply.body.len() / 2;
+                    return Ok(Reply { body: reply.body.slice(..cut), ..reply });
+                }
+                None => {}
+            }
+        }
+
+        self.post(url, body).await
+    }
+
+    async fn post(&self, url: &str, body: Bytes) -> Result<Reply> {
+        if let Some(socket) = self.socket {
+            return self.post_unix(url.to_string(), socket, body).await;
+        }
+
+        let mut req = self.client.post(url).body(body);
+        if let Some(key) = self.api_key {
+            req = req.bearer_auth(key);
+        }
+        // reqwest errors carry the URL, which may embed credentials
+        let res = req.send().await.map_err(|e| e.without_url())?;
+        let status = res.status().as_u16();
+        let retry_after = res.headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_retry_after);
+        let body = res.bytes().await.map_err(|e| e.without_url())?;
+
+        Ok(Reply { status, retry_after, body })
+    }
+
+    /// POSTs `body` over the llama.cpp unix socket; reqwest can't do UDS, so this goes through curl
+    async fn post_unix(&self, url: String, socket: &Path, body: Bytes) -> Result<Reply> {
+        let headers = self.clone_headers()?;
+        let socket = socket.to_path_buf();
+        let compression = self.compression;
+        let timeout = self.timeout;
+
+        tokio::task::spawn_blocking(move || {
+            let mut response = Vec::new();
+            let mut retry_after = None;
+            let mut curl = Easy::new();
+
+            curl.url(&url)?;
+            curl.unix_socket_path(Some(socket))?;
+            curl.http_headers(headers)?;
+            curl.post_fields_copy(&body)?;
+            if let Some(timeout) = timeout {
+                curl.timeout(timeout)?;
+            }
+            if compression {
+                // An empty string lets curl offer every encoding it was built with
+                curl.accept_encoding("")?;
+            }
+
+            {
+                // Bodies arrive in several chunks, each has to be appended rather than copied over the last
+                let mut transfer = curl.transfer();
+                transfer.write_function(|chunk| {
+                    response.extend_from_slice(chunk);
+                    Ok(chunk.len())
+                })?;
+                transfer.header_function(|line| {
+                    let line = String::from_utf8_lossy(line);
+                    if let Some((name, value)) = line.split_once(':') {
+                        if name.eq_ignore_ascii_case("retry-after") {
+                            retry_after = parse_retry_after(value);
+                        }
+                    }
+                    true
+                })?;
+                transfer.perform()?;
+            }
+
+            debug!(
+                "Received {} bytes over the wire for {} decoded",
+                curl.download_size().unwrap_or_default(), response.len()
+            );
+
+            Ok(Reply {
+                status: curl.response_code()? as u16,
+                retry_after,
+                body: response.into(),
+            })
+        }).await?
+    }
+}
+
+/// Status, retry hint and body of an HTTP reply, whichever transport carried it
+struct Reply {
+    status: u16,
+    retry_after: Option<Duration>,
+    body: Bytes,
+}
+
+impl Reply {
+    /// The error message of an unsuccessful reply, or its body when it isn't an error object
+    fn reason(&self) -> String {
+        serde_json::from_slice::<ErrorBody>(&self.body)
+            .map(|body| body.error.message)
+            .unwrap_or_else(|_| String::from_utf8_lossy(&self.body).into_owned())
     }
+}
+
+#[derive(Deserialize)]
+struct ErrorBody {
+    error: HealthError,
+}
+
+#[derive(Deserialize)]
+struct TokenizeResponse {
+    /// Token ids, or id and piece pairs from builds asked for pieces
+    tokens: Vec<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+struct RerankResponse {
+    results: Vec<RerankResult>,
+}
+
+#[derive(Deserialize)]
+struct RerankResult {
+    index: usize,
+    relevance_score: f32,
+}
+
+#[derive(Deserialize)]
+struct CompletionResponse {
+    content: String,
+}
+
+/// Roughly four bytes of English text to a token, for backends that don't report usage
+fn estimate_tokens(bytes: usize) -> u64 {
+    (bytes as u64).div_ceil(4)
+}
 
-    // TODO figure out why this thing's write_function loses bytes >:{
-    // let mut curl = Easy::new();
-    // _ = curl.url(&url);
-    // _ = curl.post(true);
-    // _ = curl.post_field_size(req_str.len() as u64)?;
-    // _ = curl.http_headers(headers);
-    // _ = curl.timeout(Duration::from_secs(10))?;
-    //
-    // let mut handle = curl.transfer();
-    //
-    // _ = handle.read_function(move |mut into| Ok(req_str.as_bytes().read(into).unwrap()))?;
-    // _ = handle.write_function(move |bytes| {
-    //     let mut buf = buf_c.lock().unwrap();
-    //     let l = bytes.len();
-    //
-    //     if l > 65536 {
-    //         let s = std::str::from_utf8(bytes).unwrap_or("RT: Failed to decode body via utf-8");
-    //         warn!("Oversized message: {}", s);
-    //     } else {
-    //         _ = buf[0..l].copy_from_slice(bytes);
-    //         info!("Read {} bytes from remote", l);
-    //     }
-    //
-    //     Ok(l)
-    // })?;
-    // _ = handle.perform()?;
+/// Only the delay-seconds form; llama-server doesn't send HTTP dates
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    value.trim().parse().ok().map(Duration::from_secs)
 }
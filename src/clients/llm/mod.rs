@@ -1,7 +1,79 @@
 pub mod llama_cpp;
+#[cfg(unix)]
+pub mod supervisor;
 
+use anyhow::Result;
+use futures::future::{BoxFuture, FutureExt};
+use self::llama_cpp::LlamaCpp;
 
-pub trait Embedding {
-    async fn embed_documents(text: &str);
-    async fn embed_query(text: &str);
-}
\ No newline at end of file
+/// A backend turning text into vectors, held as `&dyn Embedding` or `Box<dyn Embedding>`
+///
+/// The futures are boxed to keep the trait object safe, and `Send` so the serve mode's
+/// handlers can await them.
+pub trait Embedding: Sync {
+    /// Embeds a document's text, an empty vector meaning the backend answered without one
+    fn embed_document<'e>(&'e self, text: &'e str) -> BoxFuture<'e, Result<Vec<f32>>>;
+
+    /// Embeds a search query, which may be prefixed differently from documents
+    fn embed_query<'e>(&'e self, text: &'e str) -> BoxFuture<'e, Result<Vec<f32>>>;
+
+    /// Tokens embedded so far
+    fn tokens_used(&self) -> u64;
+
+    /// What `tokens_used` cost, when the backend has a price
+    fn cost(&self) -> Option<f64>;
+}
+
+impl<E: Embedding + ?Sized> Embedding for &E {
+    fn embed_document<'e>(&'e self, text: &'e str) -> BoxFuture<'e, Result<Vec<f32>>> {
+        (**self).embed_document(text)
+    }
+
+    fn embed_query<'e>(&'e self, text: &'e str) -> BoxFuture<'e, Result<Vec<f32>>> {
+        (**self).embed_query(text)
+    }
+
+    fn tokens_used(&self) -> u64 {
+        (**self).tokens_used()
+    }
+
+    fn cost(&self) -> Option<f64> {
+        (**self).cost()
+    }
+}
+
+impl<E: Embedding + ?Sized> Embedding for Box<E> {
+    fn embed_document<'e>(&'e self, text: &'e str) -> BoxFuture<'e, Result<Vec<f32>>> {
+        (**self).embed_document(text)
+    }
+
+    fn embed_query<'e>(&'e self, text: &'e str) -> BoxFuture<'e, Result<Vec<f32>>> {
+        (**self).embed_query(text)
+    }
+
+    fn tokens_used(&self) -> u64 {
+        (**self).tokens_used()
+    }
+
+    fn cost(&self) -> Option<f64> {
+        (**self).cost()
+    }
+}
+
+impl Embedding for LlamaCpp<'_> {
+    fn embed_document<'e>(&'e self, text: &'e str) -> BoxFuture<'e, Result<Vec<f32>>> {
+        self.embedding(text).boxed()
+    }
+
+    fn embed_query<'e>(&'e self, text: &'e str) -> BoxFuture<'e, Result<Vec<f32>>> {
+        self.query_embedding(text).boxed()
+    }
+
+    fn tokens_used(&self) -> u64 {
+        LlamaCpp::tokens_used(self)
+    }
+
+    fn cost(&self) -> Option<f64> {
+        LlamaCpp::cost(self)
+    }
+}
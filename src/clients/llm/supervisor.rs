@@ -0,0 +1,224 @@
+#[cfg(unix)]
+use std::os::unix::process::ExitStatusExt;
+use std::process::{ExitStatus, Stdio};
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::process::{ChildStderr, Command};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn};
+use crate::config::{LlamaConfig, SuperviseConfig};
+
+/// Signal the kernel's OOM killer ends a process with
+const SIGKILL: i32 = 9;
+
+/// A llama-server child kept running as `llama.supervise` says; dropping this stops it
+pub struct Supervisor {
+    stop: CancellationToken,
+    task: JoinHandle<()>,
+}
+
+/// The flags a start is given, smaller after each out of memory crash
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Sizes {
+    parallel: u32,
+    batch_size: u32,
+}
+
+impl Sizes {
+    fn halved(self, config: &SuperviseConfig) -> Self {
+        Self {
+            parallel: (self.parallel / 2).max(config.min_parallel).max(1),
+            batch_size: (self.batch_size / 2).max(config.min_batch_size).max(1),
+        }
+    }
+}
+
+/// How a start of the server ended
+struct Exited {
+    status: String,
+    out_of_memory: bool,
+}
+
+impl Supervisor {
+    /// Starts llama-server, returning once it listens, and keeps restarting it until dropped
+    ///
+    /// Running out of memory before listening counts as a crash too, so a server too big for
+    /// the GPU with the configured sizes is tried with smaller ones.
+    pub async fn start(config: &LlamaConfig) -> Result<Self> {
+        let (ready, listening) = oneshot::channel();
+        let stop = CancellationToken::new();
+        let task = tokio::spawn(supervise(config.clone(), ready, stop.clone()));
+        listening.await.map_err(|_| anyhow!("The llama-server supervisor stopped before the server listened"))??;
+
+        Ok(Self { stop, task })
+    }
+
+    /// Stops the server and waits for it to exit
+    pub async fn stop(mut self) {
+        self.stop.cancel();
+        _ = (&mut self.task).await;
+    }
+}
+
+impl Drop for Supervisor {
+    fn drop(&mut self) {
+        self.stop.cancel();
+    }
+}
+
+/// Runs the server until `stop`, telling `ready` once it first listens or why it never will
+async fn supervise(config: LlamaConfig, ready: oneshot::Sender<Result<()>>, stop: CancellationToken) {
+    let supervise = &config.supervise;
+    let mut ready = Some(ready);
+    let mut sizes = Sizes { parallel: supervise.parallel, batch_size: supervise.batch_size };
+    let mut restarts = 0;
+    loop {
+        let started = Instant::now();
+        let exited = match run_once(&config, sizes, &mut ready, &stop).await {
+            Ok(Some(exited)) => exited,
+            Ok(None) => return,
+            Err(e) => return give_up(&mut ready, e.context("Failed to run llama-server")),
+        };
+        // Crashes days apart shouldn't add up to leaving the server down
+        if started.elapsed() >= Duration::from_secs(supervise.stable_secs) {
+            restarts = 0;
+        }
+        if restarts >= supervise.max_restarts {
+            return give_up(&mut ready, anyhow!("llama-server exited ({}) after {restarts} restarts", exited.status));
+        }
+        restarts += 1;
+        if exited.out_of_memory {
+            let reduced = sizes.halved(supervise);
+            warn!(
+                "llama-server ran out of memory ({}), restarting with --parallel {} --batch-size {} instead of {} and {}",
+                exited.status, reduced.parallel, reduced.batch_size, sizes.parallel, sizes.batch_size
+            );
+            sizes = reduced;
+        } else {
+            warn!("llama-server exited ({}), restarting it", exited.status);
+        }
+
+        // A server that dies right away again shouldn't be started in a tight loop
+        tokio::select! {
+            _ = tokio::time::sleep(Duration::from_secs(restarts.into())) => {}
+            _ = stop.cancelled() => return,
+        }
+    }
+}
+
+/// Hands `e` to whoever waits for the first start, or logs it once the server had been up
+fn give_up(ready: &mut Option<oneshot::Sender<Result<()>>>, e: anyhow::Error) {
+    match ready.take() {
+        Some(ready) => _ = ready.send(Err(e)),
+        None => error!("{e:#}, leaving it down"),
+    }
+}
+
+/// Starts llama-server with `sizes` and waits for it to exit; `None` when stopped first
+async fn run_once(
+    config: &LlamaConfig,
+    sizes: Sizes,
+    ready: &mut Option<oneshot::Sender<Result<()>>>,
+    stop: &CancellationToken,
+) -> Result<Option<Exited>> {
+    let supervise = &config.supervise;
+    let address = format!("{}:{}", config.host, config.port);
+    let mut child = Command::new(&supervise.command)
+        .args(&supervise.args)
+        .args(["--host", &config.host, "--port", &config.port.to_string()])
+        .args(["--parallel", &sizes.parallel.to_string(), "--batch-size", &sizes.batch_size.to_string()])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .with_context(|| format!("Failed to start {}", supervise.command.display()))?;
+    let stderr = child.stderr.take().expect("stderr is piped");
+    let stderr = tokio::spawn(watch_stderr(stderr, supervise.oom_patterns.clone()));
+    info!("Started llama-server with --parallel {} --batch-size {}", sizes.parallel, sizes.batch_size);
+
+    let deadline = Instant::now() + Duration::from_secs(supervise.startup_timeout_secs);
+    let mut listening = false;
+    let status = loop {
+        tokio::select! {
+            status = child.wait() => break status?,
+            _ = stop.cancelled() => {
+                _ = child.kill().await;
+                return Ok(None);
+            }
+            _ = tokio::time::sleep(Duration::from_millis(250)), if !listening => {
+                if TcpStream::connect(&address).await.is_ok() {
+                    info!("llama-server listens on {address}");
+                    listening = true;
+                    if let Some(ready) = ready.take() {
+                        _ = ready.send(Ok(()));
+                    }
+                } else if Instant::now() >= deadline {
+                    _ = child.kill().await;
+                    bail!("llama-server didn't listen on {address} within {}s", supervise.startup_timeout_secs);
+                }
+            }
+        }
+    };
+    let out_of_memory = stderr.await.unwrap_or(false) || signal(status) == Some(SIGKILL);
+
+    Ok(Some(Exited { status: describe(status), out_of_memory }))
+}
+
+/// Logs the server's stderr, telling once it ends whether a line matched `patterns`
+async fn watch_stderr(stderr: ChildStderr, patterns: Vec<String>) -> bool {
+    let patterns: Vec<String> = patterns.iter().map(|pattern| pattern.to_lowercase()).collect();
+    let mut lines = BufReader::new(stderr).lines();
+    let mut out_of_memory = false;
+    while let Ok(Some(line)) = lines.next_line().await {
+        debug!(target: "llama_server", "{line}");
+        let lower = line.to_lowercase();
+        if patterns.iter().any(|pattern| lower.contains(pattern)) {
+            warn!("llama-server: {line}");
+            out_of_memory = true;
+        }
+    }
+
+    out_of_memory
+}
+
+/// The signal that ended the process, which only Unix has
+#[cfg(unix)]
+fn signal(status: ExitStatus) -> Option<i32> {
+    status.signal()
+}
+
+#[cfg(not(unix))]
+fn signal(_status: ExitStatus) -> Option<i32> {
+    None
+}
+
+fn describe(status: ExitStatus) -> String {
+    match (status.code(), signal(status)) {
+        (Some(code), _) => format!("exit code {code}"),
+        (None, Some(signal)) => format!("signal {signal}"),
+        (None, None) => status.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn halving_stops_at_the_minimums() {
+        let config = SuperviseConfig { min_parallel: 1, min_batch_size: 256, ..SuperviseConfig::default() };
+        let mut sizes = Sizes { parallel: 4, batch_size: 2048 };
+
+        sizes = sizes.halved(&config);
+        assert_eq!(sizes, Sizes { parallel: 2, batch_size: 1024 });
+        sizes = sizes.halved(&config).halved(&config).halved(&config);
+        assert_eq!(sizes, Sizes { parallel: 1, batch_size: 256 });
+    }
+}
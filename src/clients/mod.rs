@@ -1,8 +1,9 @@
 pub mod llm;
 pub mod vector_store;
 
-use std::collections::HashMap;
-use qdrant_client::qdrant::Value;
+use std::collections::BTreeMap;
+use base64::Engine;
+use base64::prelude::BASE64_STANDARD;
 use serde::{Serialize, Deserialize};
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -15,18 +16,6 @@ pub struct Document {
     pub embeddings: Vec<f32>,
 }
 
-impl Into<HashMap<String, Value>> for Metadata {
-    fn into(self) -> HashMap<String, Value> {
-        let mut map = HashMap::with_capacity(3);
-
-        map.insert("source".to_string(), Value::from( self.source));
-        map.insert("content_type".to_string(), Value::from( self.content_type));
-        map.insert("language".to_string(), Value::from( self.language));
-
-        map
-    }
-}
-
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Metadata {
@@ -34,22 +23,192 @@ pub struct Metadata {
     #[serde(rename = "content_type")]
     pub content_type: String,
     pub language: String,
+    /// Loader specific fields, stored in the payload next to the ones above
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_json::Value>,
+}
+
+impl<'d> From<&'d Document> for EmbedRequest<'d> {
+    fn from(document: &'d Document) -> EmbedRequest<'d> {
+        EmbedRequest::for_text(&document.page_content)
+    }
 }
 
-impl Into<EmbedRequest> for Document {
-    fn into(self) -> EmbedRequest {
+impl<'d> EmbedRequest<'d> {
+    pub fn for_text(content: &'d str) -> Self {
         EmbedRequest {
-            content: self.page_content
+            content,
+            encoding_format: None,
         }
     }
 }
 
+/// Borrows the document's text so serializing a request doesn't copy it
 #[derive(Serialize)]
-pub struct EmbedRequest {
-    content: String
+pub struct EmbedRequest<'d> {
+    content: &'d str,
+    /// Only sent when asking for something other than plain JSON numbers
+    #[serde(skip_serializing_if = "Option::is_none")]
+    encoding_format: Option<EncodingFormat>,
+}
+
+/// How the backend is asked to encode vectors in its responses
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EncodingFormat {
+    #[default]
+    Float,
+    /// Little-endian f32s, base64 encoded, as TEI and newer llama.cpp builds offer
+    Base64,
 }
 
 #[derive(Deserialize)]
 pub struct EmbedResponse {
-    pub embedding: Vec<f64>,
-}
\ No newline at end of file
+    pub embedding: EncodedEmbedding,
+    /// Billed tokens, as OpenAI compatible backends report them
+    #[serde(default)]
+    pub usage: Option<Usage>,
+}
+
+#[derive(Deserialize)]
+pub struct Usage {
+    pub prompt_tokens: u64,
+}
+
+/// Backends that ignore `encoding_format` still answer with numbers, so both are accepted
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum EncodedEmbedding {
+    /// Parsed straight into f32, which is what Qdrant stores anyway
+    Float(Vec<f32>),
+    Base64(String),
+}
+
+impl EncodedEmbedding {
+    pub fn into_f32(self) -> anyhow::Result<Vec<f32>> {
+        match self {
+            EncodedEmbedding::Float(floats) => Ok(floats),
+            EncodedEmbedding::Base64(encoded) => {
+                let bytes = BASE64_STANDARD.decode(encoded)?;
+                if bytes.len() % 4 != 0 {
+                    anyhow::bail!("Base64 embedding of {} bytes isn't a whole number of f32s", bytes.len());
+                }
+
+                Ok(bytes
+                    .chunks_exact(4)
+                    .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                    .collect())
+            }
+        }
+    }
+}
+/// The JSON other tools read and write: files of documents, llama-server's embedding API and
+/// the payloads in the stores, each checked against a golden file under `tests/fixtures/golden`
+///
+/// A failure means the format changed. If that's intended, rerun with `UPDATE_GOLDEN=1` to
+/// rewrite the files and review the diff like any other breaking change.
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use serde_json::{json, Value};
+    use super::*;
+    use crate::clients::vector_store;
+
+    fn path(name: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/golden").join(name)
+    }
+
+    fn golden(name: &str, actual: Value) {
+        let path = path(name);
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            std::fs::write(&path, serde_json::to_string_pretty(&actual).unwrap() + "\n").unwrap();
+            return;
+        }
+
+        let expected: Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(actual, expected, "{} changed", path.display());
+    }
+
+    fn read(name: &str) -> String {
+        std::fs::read_to_string(path(name)).unwrap()
+    }
+
+    fn document() -> Document {
+        let mut extra = BTreeMap::new();
+        extra.insert("chunk_index".to_string(), json!(2));
+        extra.insert("pageNumber".to_string(), json!(7));
+        extra.insert("entities".to_string(), json!(["service-x"]));
+
+        Document {
+            page_content: "Restart the service with `systemctl restart x`.".to_string(),
+            metadata: Metadata {
+                source: "docs/runbook.md".to_string(),
+                content_type: "text/markdown".to_string(),
+                language: "en".to_string(),
+                extra,
+            },
+            embeddings: vec![0.25, -0.5, 1.0],
+        }
+    }
+
+    #[test]
+    fn documents_keep_their_field_names() {
+        golden("document.json", serde_json::to_value(document()).unwrap());
+    }
+
+    #[test]
+    fn documents_read_back_unchanged() {
+        let read: Document = serde_json::from_str(&read("document.json")).unwrap();
+
+        assert_eq!(read, document());
+    }
+
+    #[test]
+    fn documents_without_embeddings_read() {
+        let read: Document = serde_json::from_str(&read("document_without_embeddings.json")).unwrap();
+
+        assert!(read.embeddings.is_empty());
+        assert_eq!(read.metadata, document().metadata);
+    }
+
+    #[test]
+    fn embed_requests_keep_their_field_names() {
+        let text = document().page_content;
+        golden("embed_request.json", serde_json::to_value(EmbedRequest::for_text(&text)).unwrap());
+
+        let mut request = EmbedRequest::for_text(&text);
+        request.encoding_format = Some(EncodingFormat::Base64);
+        golden("embed_request_base64.json", serde_json::to_value(request).unwrap());
+    }
+
+    #[test]
+    fn embed_responses_of_floats_read() {
+        let response: EmbedResponse = serde_json::from_str(&read("embed_response.json")).unwrap();
+
+        assert!(response.usage.is_none());
+        assert_eq!(response.embedding.into_f32().unwrap(), vec![0.25, -0.5, 1.0]);
+    }
+
+    #[test]
+    fn embed_responses_of_base64_read_with_usage() {
+        let response: EmbedResponse = serde_json::from_str(&read("embed_response_base64.json")).unwrap();
+
+        assert_eq!(response.usage.map(|usage| usage.prompt_tokens), Some(12));
+        assert_eq!(response.embedding.into_f32().unwrap(), vec![0.25, -0.5, 1.0]);
+    }
+
+    #[test]
+    fn payloads_keep_their_field_names() {
+        golden("payload.json", Value::Object(vector_store::payload(&document()).unwrap()));
+    }
+
+    #[test]
+    fn payloads_read_back_as_metadata() {
+        let Value::Object(payload) = serde_json::from_str(&read("payload.json")).unwrap() else {
+            panic!("payload.json isn't an object");
+        };
+
+        assert_eq!(vector_store::metadata(payload).unwrap(), document().metadata);
+    }
+}
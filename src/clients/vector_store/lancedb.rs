@@ -0,0 +1,446 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Context, Result};
+use futures::TryStreamExt;
+use lancedb::arrow::arrow_array::types::Float32Type;
+use lancedb::arrow::arrow_array::{Array, FixedSizeListArray, Float32Array, RecordBatch, RecordBatchIterator, StringArray};
+use lancedb::arrow::arrow_schema::{DataType, Field, Schema};
+use lancedb::query::{ExecutableQuery, QueryBase, Select};
+use lancedb::{Connection, DistanceType, Table};
+use tokio::sync::OnceCell;
+use crate::clients::Document;
+use crate::clients::vector_store::{self, Filter, Hit, Point, Scanned, VectorStore};
+use crate::config::LancedbConfig;
+use crate::sink::Sink;
+use crate::slow_log::{self, Operation};
+
+/// How many times more candidates each round of a search asks for, while filtering on
+/// fields without a column of their own
+const OVERFETCH: u64 = 4;
+/// Ids named in one delete predicate
+const DELETE_BATCH: usize = 1000;
+
+/// Columns besides `vector`; `source`, `content_type` and `language` are there to filter on
+const COLUMNS: [&str; 5] = ["id", "source", "content_type", "language", "metadata"];
+
+/// Vectors in a LanceDB directory, columnar files with no server to run
+///
+/// The table is created by the first insert, since that is when the vector size is known.
+pub struct LancedbStore {
+    path: String,
+    table: String,
+    connection: OnceCell<Connection>,
+    buffer: Vec<Document>,
+    size: usize,
+}
+
+impl LancedbStore {
+    pub fn from_config(config: &LancedbConfig) -> Result<Self> {
+        let path = config.path.to_str()
+            .ok_or_else(|| anyhow!("lancedb.path {} is not UTF-8", config.path.display()))?
+            .to_string();
+
+        Ok(Self {
+            path,
+            table: config.table.clone(),
+            connection: OnceCell::new(),
+            buffer: Vec::with_capacity(config.buffer_size),
+            size: config.buffer_size.max(1),
+        })
+    }
+
+    async fn connection(&self) -> Result<&Connection> {
+        self.connection
+            .get_or_try_init(|| async {
+                lancedb::connect(&self.path).execute().await
+                    .with_context(|| format!("Failed to open LanceDB at {}", self.path))
+            })
+            .await
+    }
+
+    /// The table, or `None` before anything was inserted
+    async fn open(&self) -> Result<Option<Table>> {
+        match self.connection().await?.open_table(&self.table).execute().await {
+            Ok(table) => Ok(Some(table)),
+            Err(lancedb::Error::TableNotFound { .. }) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn insert_buffer(&mut self) -> Result<()> {
+        let documents = std::mem::take(&mut self.buffer);
+        let operation = Operation::Upsert { points: documents.len(), wait: true };
+        slow_log::timed("lancedb", &self.table, operation, self.insert(&documents)).await
+    }
+
+    async fn insert(&self, documents: &[Document]) -> Result<()> {
+        let batch = batch(documents)?;
+
+        match self.open().await? {
+            Some(table) => {
+                // Ids come from the content, so documents stored before replace their rows
+                let schema = batch.schema();
+                let mut merge = table.merge_insert(&["id"]);
+                merge.when_matched_update_all(None).when_not_matched_insert_all();
+                merge.execute(Box::new(RecordBatchIterator::new([Ok(batch)], schema))).await.map(|_| ())?
+            }
+            None => self.connection().await?.create_table(&self.table, batch).execute().await.map(|_| ())?,
+        }
+
+        Ok(())
+    }
+
+    /// Conditions on `source`, `content_type` and `language` go to LanceDB, and the rest,
+    /// like array fields, are checked on the hits, asking for more until `limit` pass them or
+    /// the table has no more
+    async fn nearest(&self, vector: Vec<f32>, limit: u64, filter: &Filter) -> Result<Vec<Hit>> {
+        let Some(table) = self.open().await? else {
+            return Ok(vec![]);
+        };
+
+        let (columns, rest): (BTreeMap<_, _>, BTreeMap<_, _>) = filter.0.clone().into_iter()
+            .partition(|(key, _)| COLUMNS[1..4].contains(&key.as_str()));
+        let (columns, rest) = (Filter(columns), Filter(rest));
+        let mut asked = match rest.is_empty() {
+            true => limit,
+            false => limit.saturating_mul(OVERFETCH),
+        };
+        loop {
+            let mut hits = self.query(&table, &vector, asked, &columns).await?;
+            let exhausted = (hits.len() as u64) < asked;
+            hits.retain(|hit| rest.matches(&hit.metadata));
+            if rest.is_empty() || exhausted || hits.len() as u64 >= limit {
+                hits.truncate(limit as usize);
+                return Ok(hits);
+            }
+            asked = asked.saturating_mul(OVERFETCH);
+        }
+    }
+
+    /// The `limit` nearest rows to `vector` passing `filter`, which only names columns
+    async fn query(&self, table: &Table, vector: &[f32], limit: u64, filter: &Filter) -> Result<Vec<Hit>> {
+        let mut query = table.query()
+            .nearest_to(vector)?
+            .distance_type(DistanceType::Cosine)
+            .limit(limit as usize);
+        if !filter.is_empty() {
+            query = query.only_if(predicate(filter));
+        }
+        let batches: Vec<RecordBatch> = query
+            .execute()
+            .await?
+            .try_collect()
+            .await?;
+
+        let mut hits = Vec::new();
+        for batch in batches {
+            let ids = strings(&batch, "id")?;
+            let metadata = strings(&batch, "metadata")?;
+            let distances = batch.column_by_name("_distance")
+                .and_then(|column| column.as_any().downcast_ref::<Float32Array>())
+                .ok_or_else(|| anyhow!("LanceDB returned no distances"))?;
+
+            for row in 0..batch.num_rows() {
+                hits.push(Hit::from_payload(
+                    ids.value(row).to_string(),
+                    // Cosine distance is 1 - similarity, scores stay comparable with the other stores
+                    1.0 - distances.value(row),
+                    vector_store::stored_payload(ids.value(row), metadata.value(row))?,
+                )?);
+            }
+        }
+
+        Ok(hits)
+    }
+}
+
+impl Sink for LancedbStore {
+    async fn push(&mut self, document: Document) -> Result<()> {
+        self.buffer.push(document);
+
+        if self.buffer.len() < self.size {
+            return Ok(());
+        }
+
+        self.insert_buffer().await
+    }
+
+    async fn flush(&mut self) -> Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        self.insert_buffer().await
+    }
+
+    fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+}
+
+impl VectorStore for LancedbStore {
+    async fn ensure_collection(&self) -> Result<()> {
+        self.connection().await.map(|_| ())
+    }
+
+    async fn drop_collection(&self) -> Result<()> {
+        match self.connection().await?.drop_table(&self.table, &[]).await {
+            Ok(()) | Err(lancedb::Error::TableNotFound { .. }) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// The cursor is a row offset, stable as long as nothing is written meanwhile
+    async fn scan(&self, cursor: Option<String>, limit: u64) -> Result<(Vec<Point>, Option<String>)> {
+        let Some(table) = self.open().await? else {
+            return Ok((vec![], None));
+        };
+        let offset: usize = cursor.as_deref().map_or(Ok(0), str::parse).context("Malformed scan cursor")?;
+
+        let batches: Vec<RecordBatch> = table.query()
+            .select(Select::columns(&["id", "metadata"]))
+            .offset(offset)
+            .limit(limit as usize)
+            .execute()
+            .await?
+            .try_collect()
+            .await?;
+
+        let mut points = Vec::new();
+        for batch in batches {
+            let ids = strings(&batch, "id")?;
+            let payloads = strings(&batch, "metadata")?;
+            for row in 0..batch.num_rows() {
+                points.push(Point { id: ids.value(row).to_string(), payload: vector_store::stored_payload(ids.value(row), payloads.value(row))? });
+            }
+        }
+        let next = (points.len() as u64 == limit).then(|| (offset + points.len()).to_string());
+        Ok((points, next))
+    }
+
+    async fn scan_vectors(&self, cursor: Option<String>, limit: u64) -> Result<Scanned> {
+        let Some(table) = self.open().await? else {
+            return Ok((vec![], None));
+        };
+        let offset: usize = cursor.as_deref().map_or(Ok(0), str::parse).context("Malformed scan cursor")?;
+
+        let batches: Vec<RecordBatch> = table.query()
+            .select(Select::columns(&["id", "metadata", "vector"]))
+            .offset(offset)
+            .limit(limit as usize)
+            .execute()
+            .await?
+            .try_collect()
+            .await?;
+
+        let mut points = Vec::new();
+        for batch in batches {
+            let ids = strings(&batch, "id")?;
+            let payloads = strings(&batch, "metadata")?;
+            let vectors = vectors(&batch)?;
+            for row in 0..batch.num_rows() {
+                let point = Point { id: ids.value(row).to_string(), payload: vector_store::stored_payload(ids.value(row), payloads.value(row))? };
+                points.push((point, vector(vectors, row)?));
+            }
+        }
+        let next = (points.len() as u64 == limit).then(|| (offset + points.len()).to_string());
+        Ok((points, next))
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<(Point, Vec<f32>)>> {
+        let Some(table) = self.open().await? else {
+            return Ok(None);
+        };
+
+        let batches: Vec<RecordBatch> = table.query()
+            .only_if(format!("id = {}", literal(id)))
+            .limit(1)
+            .execute()
+            .await?
+            .try_collect()
+            .await?;
+        let Some(batch) = batches.into_iter().find(|batch| batch.num_rows() > 0) else {
+            return Ok(None);
+        };
+
+        let vector = vector(vectors(&batch)?, 0)?;
+        let point = Point { id: id.to_string(), payload: vector_store::stored_payload(id, strings(&batch, "metadata")?.value(0))? };
+
+        Ok(Some((point, vector)))
+    }
+
+    async fn delete(&self, ids: Vec<String>) -> Result<()> {
+        let Some(table) = self.open().await? else {
+            return Ok(());
+        };
+
+        for chunk in ids.chunks(DELETE_BATCH) {
+            let ids: Vec<String> = chunk.iter().map(|id| literal(id)).collect();
+            table.delete(format!("id IN ({})", ids.join(", ")).as_str()).await?;
+        }
+
+        Ok(())
+    }
+
+    /// An update per point, rewriting the filter columns along with the metadata
+    async fn overwrite_payloads(&self, points: Vec<Point>) -> Result<()> {
+        let Some(table) = self.open().await? else {
+            return Ok(());
+        };
+
+        for point in points {
+            let field = |name: &str| point.payload.get(name).and_then(|value| value.as_str()).unwrap_or_default();
+            let mut update = table.update().only_if(format!("id = {}", literal(&point.id)));
+            for column in &COLUMNS[1..4] {
+                update = update.column(*column, literal(field(column)));
+            }
+            update.column("metadata", literal(&serde_json::to_string(&point.payload)?)).execute().await?;
+        }
+
+        Ok(())
+    }
+
+    /// Merges the rows in by id, leaving out points the table doesn't hold
+    async fn update_vectors(&self, points: Vec<(Point, Vec<f32>)>) -> Result<()> {
+        let Some(table) = self.open().await? else {
+            return Ok(());
+        };
+        if points.is_empty() {
+            return Ok(());
+        }
+
+        let batch = rows(&points)?;
+        let schema = batch.schema();
+        let mut merge = table.merge_insert(&["id"]);
+        merge.when_matched_update_all(None);
+        merge.execute(Box::new(RecordBatchIterator::new([Ok(batch)], schema))).await?;
+
+        Ok(())
+    }
+
+    async fn search(&self, vector: Vec<f32>, limit: u64, filter: &Filter) -> Result<Vec<Hit>> {
+        let operation = Operation::Search { limit, dimensions: vector.len(), filter };
+        slow_log::timed("lancedb", &self.table, operation, self.nearest(vector, limit, filter)).await
+    }
+}
+
+/// The documents as one record batch, all vectors the size of the first one
+fn batch(documents: &[Document]) -> Result<RecordBatch> {
+    let points = documents.iter()
+        .map(|d| Ok((Point { id: vector_store::stable_id(d), payload: vector_store::payload(d)? }, d.embeddings.as_slice())))
+        .collect::<Result<Vec<_>>>()?;
+
+    rows(&points)
+}
+
+/// The points as one record batch, the filter columns taken from their payloads
+fn rows<V: AsRef<[f32]>>(points: &[(Point, V)]) -> Result<RecordBatch> {
+    let field = |point: &Point, name: &str| point.payload.get(name).and_then(|value| value.as_str()).unwrap_or_default().to_string();
+    let size = points.first().map_or(0, |(_, vector)| vector.as_ref().len());
+    if let Some((other, vector)) = points.iter().find(|(_, vector)| vector.as_ref().len() != size) {
+        return Err(anyhow!(
+            "{} has a {}-dimensional vector where the batch has {size}",
+            field(other, "source"), vector.as_ref().len()
+        ));
+    }
+
+    let mut fields: Vec<Field> = COLUMNS.iter().map(|name| Field::new(*name, DataType::Utf8, false)).collect();
+    fields.push(Field::new(
+        "vector",
+        DataType::FixedSizeList(Arc::new(Field::new("item", DataType::Float32, true)), size as i32),
+        false,
+    ));
+
+    let metadata = points.iter()
+        .map(|(point, _)| Ok(serde_json::to_string(&point.payload)?))
+        .collect::<Result<Vec<_>>>()?;
+    let vectors = FixedSizeListArray::from_iter_primitive::<Float32Type, _, _>(
+        points.iter().map(|(_, vector)| Some(vector.as_ref().iter().copied().map(Some).collect::<Vec<_>>())),
+        size as i32,
+    );
+
+    Ok(RecordBatch::try_new(Arc::new(Schema::new(fields)), vec![
+        Arc::new(StringArray::from_iter_values(points.iter().map(|(point, _)| point.id.as_str()))),
+        Arc::new(StringArray::from_iter_values(points.iter().map(|(point, _)| field(point, "source")))),
+        Arc::new(StringArray::from_iter_values(points.iter().map(|(point, _)| field(point, "content_type")))),
+        Arc::new(StringArray::from_iter_values(points.iter().map(|(point, _)| field(point, "language")))),
+        Arc::new(StringArray::from(metadata)),
+        Arc::new(vectors),
+    ])?)
+}
+
+/// `value` as a SQL string literal
+fn literal(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+/// The filter as a SQL predicate over the metadata columns, which are all it may name
+fn predicate(filter: &Filter) -> String {
+    let conditions: Vec<String> = filter.0.iter()
+        .map(|(key, condition)| {
+            let values: Vec<String> = condition.values().iter()
+                .map(|value| literal(value))
+                .collect();
+            format!("{key} IN ({})", values.join(", "))
+        })
+        .collect();
+
+    conditions.join(" AND ")
+}
+
+fn strings<'b>(batch: &'b RecordBatch, column: &str) -> Result<&'b StringArray> {
+    batch.column_by_name(column)
+        .and_then(|column| column.as_any().downcast_ref::<StringArray>())
+        .ok_or_else(|| anyhow!("LanceDB returned no {column} column"))
+}
+
+fn vectors(batch: &RecordBatch) -> Result<&FixedSizeListArray> {
+    batch.column_by_name("vector")
+        .and_then(|column| column.as_any().downcast_ref::<FixedSizeListArray>())
+        .ok_or_else(|| anyhow!("LanceDB returned no vector column"))
+}
+
+fn vector(vectors: &FixedSizeListArray, row: usize) -> Result<Vec<f32>> {
+    Ok(vectors.value(row).as_any().downcast_ref::<Float32Array>()
+        .ok_or_else(|| anyhow!("LanceDB returned a vector that isn't f32"))?
+        .values()
+        .to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::clients::Metadata;
+    use super::*;
+
+    fn document(source: &str, acl: Option<&[&str]>, vector: Vec<f32>) -> Document {
+        let mut metadata = Metadata { source: source.to_string(), ..Metadata::default() };
+        if let Some(acl) = acl {
+            metadata.extra.insert(vector_store::ACL_FIELD.to_string(), serde_json::json!(acl));
+        }
+        Document { page_content: source.to_string(), metadata, embeddings: vector }
+    }
+
+    #[tokio::test]
+    async fn fields_without_a_column_are_filtered_on_the_hits() {
+        let path = std::env::temp_dir().join(format!("rag-rs-lancedb-{}", std::process::id()));
+        _ = std::fs::remove_dir_all(&path);
+        let mut store = LancedbStore::from_config(&LancedbConfig { path: path.clone(), ..LancedbConfig::default() }).unwrap();
+        // The nearest points are ones the group may not see
+        for i in 0..10 {
+            store.push(document(&format!("hidden-{i}"), Some(&["other"]), vec![1.0, i as f32 * 0.01])).await.unwrap();
+        }
+        store.push(document("shared", Some(&["team"]), vec![0.0, 1.0])).await.unwrap();
+        store.push(document("public", None, vec![0.1, 1.0])).await.unwrap();
+        store.flush().await.unwrap();
+
+        let mut filter = Filter::default();
+        filter.within_groups(&["team".to_string()]);
+        let hits = store.search(vec![1.0, 0.0], 2, &filter).await.unwrap();
+        let mut sources: Vec<&str> = hits.iter().map(|hit| hit.metadata.source.as_str()).collect();
+        sources.sort();
+
+        assert_eq!(sources, ["public", "shared"]);
+        _ = std::fs::remove_dir_all(&path);
+    }
+}
@@ -1 +1,834 @@
-pub mod qdrant;
\ No newline at end of file
+#[cfg(feature = "lancedb")]
+pub mod lancedb;
+pub mod partition;
+pub mod qdrant;
+pub mod sqlite;
+pub mod tunnel;
+
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::future::Future;
+use std::sync::OnceLock;
+
+use anyhow::{Context, Result};
+use futures::future::{BoxFuture, FutureExt};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::time::Instant;
+use crate::chunking::CHUNK_INDEX_FIELD;
+use crate::clients::{Document, Metadata};
+use crate::config::{Config, QdrantConfig, StoreKind};
+use crate::migrations::{SCHEMA_VERSION, SCHEMA_VERSION_FIELD};
+use crate::search::merge;
+use crate::sink::Sink;
+#[cfg(feature = "lancedb")]
+use self::lancedb::LancedbStore;
+use self::partition::Partitioned;
+use self::qdrant::Qlient;
+use self::sqlite::SqliteStore;
+
+static READ_ONLY: OnceLock<bool> = OnceLock::new();
+
+/// Makes every write to a store fail from now on, for pointing the tool at a collection
+/// only to query or audit it; only the first call counts
+pub fn set_read_only(read_only: bool) {
+    _ = READ_ONLY.set(read_only);
+}
+
+pub fn is_read_only() -> bool {
+    READ_ONLY.get().copied().unwrap_or(false)
+}
+
+/// A write refused in read-only mode, naming what it would have done
+#[derive(Debug)]
+pub struct ReadOnly(pub &'static str);
+
+impl Display for ReadOnly {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Read-only mode, refusing to {}", self.0)
+    }
+}
+
+impl std::error::Error for ReadOnly {}
+
+/// A write the store turned down because the collection is locked or read-only, rather than
+/// for anything wrong with the points
+#[derive(Debug)]
+pub struct Locked(pub String);
+
+impl Display for Locked {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} doesn't take writes right now", self.0)
+    }
+}
+
+impl std::error::Error for Locked {}
+
+/// Fails with `ReadOnly` in read-only mode
+pub fn ensure_writable(action: &'static str) -> Result<()> {
+    match is_read_only() {
+        true => Err(ReadOnly(action).into()),
+        false => Ok(()),
+    }
+}
+
+/// A stored document found near a query vector
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Hit {
+    pub id: String,
+    pub score: f32,
+    /// The document's text, or the passage of several when `search::merge_adjacent` joined them
+    #[serde(default)]
+    pub text: String,
+    pub metadata: Metadata,
+}
+
+impl Hit {
+    /// The hit for a stored payload, its text taken out of the metadata
+    pub fn from_payload(id: String, score: f32, mut payload: serde_json::Map<String, serde_json::Value>) -> Result<Self> {
+        let text = match payload.remove(CONTENT_FIELD) {
+            Some(serde_json::Value::String(text)) => text,
+            _ => String::new(),
+        };
+
+        Ok(Self { id, score, text, metadata: metadata(payload)? })
+    }
+}
+
+/// Payload field holding the document's text
+pub const CONTENT_FIELD: &str = "page_content";
+/// Payload field holding the SHA-256 of the text as written, for `audit` to check it against
+pub const CONTENT_HASH_FIELD: &str = "content_sha256";
+
+/// Payload field listing the groups allowed to find a document; documents without one are
+/// visible to everyone
+pub const ACL_FIELD: &str = "acl";
+/// Stored as the ACL of documents whose ACL names no group, as Qdrant can't tell an empty
+/// array from a missing field; no caller's group is empty
+const NO_GROUP: &str = "";
+/// Payload fields of names and subjects `[extraction]` found in a document, lowercased
+pub const ENTITIES_FIELD: &str = "entities";
+pub const TOPICS_FIELD: &str = "topics";
+/// Payload flag marking a point stored without a usable vector, for `backfill` to embed
+pub const NEEDS_EMBEDDING_FIELD: &str = "needs_embedding";
+
+/// A stored point as read back by scans over the whole store, without its vector
+#[derive(Debug, Clone)]
+pub struct Point {
+    pub id: String,
+    pub payload: serde_json::Map<String, serde_json::Value>,
+}
+
+/// Id of the point holding `document`: a UUID hashed from its source, chunk index and text,
+/// so storing the same document again updates its point and ids given out with search
+/// results, like those feedback is recorded against, stay valid
+pub fn stable_id(document: &Document) -> String {
+    let bytes: [u8; 16] = identity(document)[..16].try_into().expect("SHA-256 is longer than a UUID");
+
+    uuid::Builder::from_custom_bytes(bytes).into_uuid().to_string()
+}
+
+/// Like `stable_id`, but a number, for collections that keep their ids compact
+pub fn stable_number(document: &Document) -> u64 {
+    u64::from_be_bytes(identity(document)[..8].try_into().expect("SHA-256 is longer than a u64"))
+}
+
+/// SHA-256 over a document's source, chunk index and text, which its ids are cut from
+fn identity(document: &Document) -> [u8; 32] {
+    let mut hash = Sha256::new()
+        .chain_update(document.metadata.source.as_bytes())
+        .chain_update([0]);
+    if let Some(index) = document.metadata.extra.get(CHUNK_INDEX_FIELD) {
+        hash.update(index.to_string().as_bytes());
+    }
+
+    hash.chain_update([0]).chain_update(document.page_content.as_bytes()).finalize().into()
+}
+
+/// Hex SHA-256 of a document's text
+pub fn content_hash(content: &str) -> String {
+    Sha256::digest(content.as_bytes())
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// What is stored next to a document's vector: its metadata, its text, the text's hash and
+/// the schema version of it all
+pub fn payload(document: &Document) -> Result<serde_json::Map<String, serde_json::Value>> {
+    let serde_json::Value::Object(mut payload) = serde_json::to_value(&document.metadata)? else {
+        anyhow::bail!("Metadata of {} is not a JSON object", document.metadata.source);
+    };
+    if payload.get(ACL_FIELD).is_some_and(|acl| acl.is_null() || acl.as_array().is_some_and(Vec::is_empty)) {
+        payload.insert(ACL_FIELD.to_string(), serde_json::json!([NO_GROUP]));
+    }
+    payload.insert(CONTENT_FIELD.to_string(), document.page_content.clone().into());
+    payload.insert(CONTENT_HASH_FIELD.to_string(), content_hash(&document.page_content).into());
+    payload.insert(SCHEMA_VERSION_FIELD.to_string(), SCHEMA_VERSION.into());
+
+    Ok(payload)
+}
+
+/// A payload stored as JSON text under `id`, by the stores that keep it in a column
+pub(crate) fn stored_payload(id: &str, json: &str) -> Result<serde_json::Map<String, serde_json::Value>> {
+    serde_json::from_str(json).with_context(|| format!("Stored payload of {id} is not a JSON object"))
+}
+
+/// The metadata of a stored payload, leaving out the text fields `payload` adds
+pub fn metadata(mut payload: serde_json::Map<String, serde_json::Value>) -> Result<Metadata> {
+    payload.remove(CONTENT_FIELD);
+    payload.remove(CONTENT_HASH_FIELD);
+    payload.remove(SCHEMA_VERSION_FIELD);
+    if payload.get(ACL_FIELD) == Some(&serde_json::json!([NO_GROUP])) {
+        payload.insert(ACL_FIELD.to_string(), serde_json::json!([]));
+    }
+
+    Ok(serde_json::from_value(serde_json::Value::Object(payload))?)
+}
+
+/// Payload conditions a hit has to meet, every one of them: the field equals the value, or
+/// one of the values, with array fields matching when any element does
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Filter(pub BTreeMap<String, Match>);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Match {
+    One(String),
+    Any(Vec<String>),
+    /// Like `Any`, but also matching points without the field, though not ones where it's
+    /// null or empty; only set by the server
+    #[serde(skip)]
+    AnyOrMissing(Vec<String>),
+}
+
+impl Match {
+    pub fn values(&self) -> &[String] {
+        match self {
+            Match::One(value) => std::slice::from_ref(value),
+            Match::Any(values) | Match::AnyOrMissing(values) => values,
+        }
+    }
+
+    /// Values meeting both conditions, and missing fields if both let those through
+    fn and(&self, other: &Match) -> Match {
+        let values = self.values().iter().filter(|value| other.values().contains(value)).cloned().collect();
+        match (self, other) {
+            (Match::AnyOrMissing(_), Match::AnyOrMissing(_)) => Match::AnyOrMissing(values),
+            _ => Match::Any(values),
+        }
+    }
+}
+
+impl Filter {
+    /// Conditions from `key=value` pairs, a key given more than once matching any of its values
+    pub fn from_pairs(pairs: impl IntoIterator<Item = (String, String)>) -> Self {
+        let mut conditions = BTreeMap::<String, Match>::new();
+        for (key, value) in pairs {
+            let condition = match conditions.remove(&key) {
+                None => Match::One(value),
+                Some(Match::One(first)) => Match::Any(vec![first, value]),
+                Some(Match::Any(mut values) | Match::AnyOrMissing(mut values)) => {
+                    values.push(value);
+                    Match::Any(values)
+                }
+            };
+            conditions.insert(key, condition);
+        }
+
+        Filter(conditions)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Adds the conditions of `other`, a field both name having to meet both conditions
+    pub fn and(&mut self, other: &Filter) {
+        for (key, condition) in &other.0 {
+            let merged = match self.0.remove(key) {
+                None => condition.clone(),
+                Some(mine) => mine.and(condition),
+            };
+            self.0.insert(key.clone(), merged);
+        }
+    }
+
+    /// Narrows the filter to documents `groups` may see: ones whose ACL names one of the
+    /// groups, and ones without an ACL unless the caller asked for particular ACL entries
+    pub fn within_groups(&mut self, groups: &[String]) {
+        let condition = match self.0.remove(ACL_FIELD) {
+            Some(asked) => Match::Any(asked.values().iter().filter(|value| groups.contains(value)).cloned().collect()),
+            None => Match::AnyOrMissing(groups.to_vec()),
+        };
+        self.0.insert(ACL_FIELD.to_string(), condition);
+    }
+
+    /// Narrows the filter to documents mentioning any of `entities` and tagged with any of `tags`
+    pub fn with_keywords(mut self, entities: &[String], tags: &[String]) -> Self {
+        for (field, values) in [(ENTITIES_FIELD, entities), (TOPICS_FIELD, tags)] {
+            if !values.is_empty() {
+                self.0.insert(field.to_string(), Match::Any(values.iter().map(|value| keyword(value)).collect()));
+            }
+        }
+
+        self
+    }
+
+    /// Whether `metadata` meets every condition, for stores that filter on this side
+    pub fn matches(&self, metadata: &Metadata) -> bool {
+        self.0.iter().all(|(key, condition)| {
+            let value = match key.as_str() {
+                "source" => Some(metadata.source.as_str()),
+                "content_type" => Some(metadata.content_type.as_str()),
+                "language" => Some(metadata.language.as_str()),
+                _ => None,
+            };
+            let field = metadata.extra.get(key);
+            if matches!(condition, Match::AnyOrMissing(_)) && field.is_none() {
+                return true;
+            }
+            let values: Vec<&str> = match (value, field) {
+                (Some(value), _) => vec![value],
+                (None, Some(serde_json::Value::String(value))) => vec![value],
+                (None, Some(serde_json::Value::Array(items))) => items.iter().filter_map(|item| item.as_str()).collect(),
+                _ => vec![],
+            };
+
+            values.iter().any(|value| condition.values().iter().any(|wanted| wanted == value))
+        })
+    }
+}
+
+/// `value` as it is stored in and looked up from keyword fields, so case doesn't matter
+pub fn keyword(value: &str) -> String {
+    value.trim().to_lowercase()
+}
+
+/// The distinct words of a text search, lowercased
+pub fn words(text: &str) -> Vec<String> {
+    let mut words: Vec<String> = text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect();
+    words.sort_unstable();
+    words.dedup();
+
+    words
+}
+
+/// Share of `words` that `text` contains, what text hits of stores without a ranking of
+/// their own are scored by
+pub fn text_score(words: &[String], text: &str) -> f32 {
+    if words.is_empty() {
+        return 0.0;
+    }
+    let found = self::words(text);
+    let shared = words.iter().filter(|word| found.binary_search(word).is_ok()).count();
+
+    shared as f32 / words.len() as f32
+}
+
+/// A sink that can also be searched
+pub trait VectorStore: Sink {
+    /// Creates the collection if the store doesn't have it yet
+    fn ensure_collection(&self) -> impl Future<Output = Result<()>> + Send;
+
+    /// Deletes the collection with every point in it, if there is one
+    fn drop_collection(&self) -> impl Future<Output = Result<()>> + Send;
+
+    /// Returns the `limit` nearest documents to `vector` that pass `filter`, best first
+    fn search(&self, vector: Vec<f32>, limit: u64, filter: &Filter) -> impl Future<Output = Result<Vec<Hit>>> + Send;
+
+    /// Up to `limit` stored points after `cursor`, and the cursor to continue from unless
+    /// that was the last of them
+    fn scan(&self, cursor: Option<String>, limit: u64) -> impl Future<Output = Result<(Vec<Point>, Option<String>)>> + Send;
+
+    /// Like `scan`, with the vector of each point, empty for points stored without one
+    fn scan_vectors(&self, cursor: Option<String>, limit: u64) -> impl Future<Output = Result<Scanned>> + Send;
+
+    /// The point stored under `id` with its vector, if there is one
+    fn get(&self, id: &str) -> impl Future<Output = Result<Option<(Point, Vec<f32>)>>> + Send;
+
+    /// Removes the points stored under `ids`; ids it doesn't hold are ignored
+    fn delete(&self, ids: Vec<String>) -> impl Future<Output = Result<()>> + Send;
+
+    /// Replaces the payloads of the stored `points` with the ones given, keeping their vectors
+    fn overwrite_payloads(&self, points: Vec<Point>) -> impl Future<Output = Result<()>> + Send;
+
+    /// Replaces the vectors of the stored `points`, and their payloads with the ones given,
+    /// keeping the ids they are stored under
+    fn update_vectors(&self, points: Vec<(Point, Vec<f32>)>) -> impl Future<Output = Result<()>> + Send;
+
+    /// Returns the `limit` documents whose text best matches the words of `query` that pass
+    /// `filter`, best first, for searching without an embedding
+    ///
+    /// Scores only rank a store's text hits among each other; they aren't similarities. By
+    /// default every point is read and scored by `text_score`, stores with a full-text index
+    /// of their own search that instead.
+    fn search_text(&self, query: &str, limit: u64, filter: &Filter) -> impl Future<Output = Result<Vec<Hit>>> + Send
+    where
+        Self: Sync,
+    {
+        scanned_text_search(self, query, limit, filter)
+    }
+
+    /// Like `search`, but settles for what has arrived by `deadline`; the flag tells whether
+    /// anything was left out
+    fn search_within(&self, vector: Vec<f32>, limit: u64, filter: &Filter, deadline: Instant) -> impl Future<Output = Result<(Vec<Hit>, bool)>> + Send
+    where
+        Self: Sync,
+    {
+        async move {
+            match tokio::time::timeout_at(deadline, self.search(vector, limit, filter)).await {
+                Ok(hits) => Ok((hits?, false)),
+                Err(_) => Ok((vec![], true)),
+            }
+        }
+    }
+}
+
+/// Points the default `search_text` reads at a time
+const TEXT_SCAN_PAGE: u64 = 256;
+
+/// `VectorStore::search_text` by reading every point and scoring it by `text_score`
+pub(crate) async fn scanned_text_search(store: &(impl VectorStore + Sync + ?Sized), query: &str, limit: u64, filter: &Filter) -> Result<Vec<Hit>> {
+    let words = words(query);
+    let mut hits = Vec::new();
+    let mut cursor = None;
+    loop {
+        let (points, next) = store.scan(cursor, TEXT_SCAN_PAGE).await?;
+        for point in points {
+            let hit = Hit::from_payload(point.id, 0.0, point.payload)?;
+            let score = text_score(&words, &hit.text);
+            if score > 0.0 && filter.matches(&hit.metadata) {
+                hits.push(Hit { score, ..hit });
+            }
+        }
+        // Only the best `limit` are kept between pages
+        hits = merge(vec![hits], limit as usize);
+        match next {
+            Some(next) => cursor = Some(next),
+            None => break,
+        }
+    }
+
+    Ok(hits)
+}
+
+/// What `get` finds, a point with its vector
+type Found = Option<(Point, Vec<f32>)>;
+
+/// A page of `scan_vectors`, and the cursor to continue from
+pub type Scanned = (Vec<(Point, Vec<f32>)>, Option<String>);
+
+/// `VectorStore` with boxed futures, which keeps it object safe, so stores chosen at runtime
+/// can be held as `Box<dyn DynVectorStore>`; every `VectorStore` is one, and the box is a
+/// `VectorStore` again
+pub trait DynVectorStore: Send + Sync {
+    fn push_boxed(&mut self, document: Document) -> BoxFuture<'_, Result<()>>;
+    fn flush_boxed(&mut self) -> BoxFuture<'_, Result<()>>;
+    fn buffered_boxed(&self) -> usize;
+    fn spilled_boxed(&self) -> u64;
+    fn ensure_collection_boxed(&self) -> BoxFuture<'_, Result<()>>;
+    fn drop_collection_boxed(&self) -> BoxFuture<'_, Result<()>>;
+    fn search_boxed<'s>(&'s self, vector: Vec<f32>, limit: u64, filter: &'s Filter) -> BoxFuture<'s, Result<Vec<Hit>>>;
+    fn scan_boxed(&self, cursor: Option<String>, limit: u64) -> BoxFuture<'_, Result<(Vec<Point>, Option<String>)>>;
+    fn scan_vectors_boxed(&self, cursor: Option<String>, limit: u64) -> BoxFuture<'_, Result<Scanned>>;
+    fn get_boxed<'s>(&'s self, id: &'s str) -> BoxFuture<'s, Result<Found>>;
+    fn delete_boxed(&self, ids: Vec<String>) -> BoxFuture<'_, Result<()>>;
+    fn overwrite_payloads_boxed(&self, points: Vec<Point>) -> BoxFuture<'_, Result<()>>;
+    fn update_vectors_boxed(&self, points: Vec<(Point, Vec<f32>)>) -> BoxFuture<'_, Result<()>>;
+    fn search_within_boxed<'s>(&'s self, vector: Vec<f32>, limit: u64, filter: &'s Filter, deadline: Instant) -> BoxFuture<'s, Result<(Vec<Hit>, bool)>>;
+    fn search_text_boxed<'s>(&'s self, query: &'s str, limit: u64, filter: &'s Filter) -> BoxFuture<'s, Result<Vec<Hit>>>;
+}
+
+impl<S: VectorStore + Send + Sync> DynVectorStore for S {
+    fn push_boxed(&mut self, document: Document) -> BoxFuture<'_, Result<()>> {
+        self.push(document).boxed()
+    }
+
+    fn flush_boxed(&mut self) -> BoxFuture<'_, Result<()>> {
+        self.flush().boxed()
+    }
+
+    fn buffered_boxed(&self) -> usize {
+        self.buffered()
+    }
+
+    fn spilled_boxed(&self) -> u64 {
+        self.spilled()
+    }
+
+    fn ensure_collection_boxed(&self) -> BoxFuture<'_, Result<()>> {
+        self.ensure_collection().boxed()
+    }
+
+    fn drop_collection_boxed(&self) -> BoxFuture<'_, Result<()>> {
+        self.drop_collection().boxed()
+    }
+
+    fn search_boxed<'s>(&'s self, vector: Vec<f32>, limit: u64, filter: &'s Filter) -> BoxFuture<'s, Result<Vec<Hit>>> {
+        self.search(vector, limit, filter).boxed()
+    }
+
+    fn scan_boxed(&self, cursor: Option<String>, limit: u64) -> BoxFuture<'_, Result<(Vec<Point>, Option<String>)>> {
+        self.scan(cursor, limit).boxed()
+    }
+
+    fn scan_vectors_boxed(&self, cursor: Option<String>, limit: u64) -> BoxFuture<'_, Result<Scanned>> {
+        self.scan_vectors(cursor, limit).boxed()
+    }
+
+    fn get_boxed<'s>(&'s self, id: &'s str) -> BoxFuture<'s, Result<Found>> {
+        self.get(id).boxed()
+    }
+
+    fn delete_boxed(&self, ids: Vec<String>) -> BoxFuture<'_, Result<()>> {
+        self.delete(ids).boxed()
+    }
+
+    fn overwrite_payloads_boxed(&self, points: Vec<Point>) -> BoxFuture<'_, Result<()>> {
+        self.overwrite_payloads(points).boxed()
+    }
+
+    fn update_vectors_boxed(&self, points: Vec<(Point, Vec<f32>)>) -> BoxFuture<'_, Result<()>> {
+        self.update_vectors(points).boxed()
+    }
+
+    fn search_within_boxed<'s>(&'s self, vector: Vec<f32>, limit: u64, filter: &'s Filter, deadline: Instant) -> BoxFuture<'s, Result<(Vec<Hit>, bool)>> {
+        self.search_within(vector, limit, filter, deadline).boxed()
+    }
+
+    fn search_text_boxed<'s>(&'s self, query: &'s str, limit: u64, filter: &'s Filter) -> BoxFuture<'s, Result<Vec<Hit>>> {
+        self.search_text(query, limit, filter).boxed()
+    }
+}
+
+impl Sink for Box<dyn DynVectorStore> {
+    async fn push(&mut self, document: Document) -> Result<()> {
+        self.as_mut().push_boxed(document).await
+    }
+
+    async fn flush(&mut self) -> Result<()> {
+        self.as_mut().flush_boxed().await
+    }
+
+    fn buffered(&self) -> usize {
+        self.as_ref().buffered_boxed()
+    }
+
+    fn spilled(&self) -> u64 {
+        self.as_ref().spilled_boxed()
+    }
+}
+
+impl VectorStore for Box<dyn DynVectorStore> {
+    async fn ensure_collection(&self) -> Result<()> {
+        self.as_ref().ensure_collection_boxed().await
+    }
+
+    async fn drop_collection(&self) -> Result<()> {
+        self.as_ref().drop_collection_boxed().await
+    }
+
+    async fn search(&self, vector: Vec<f32>, limit: u64, filter: &Filter) -> Result<Vec<Hit>> {
+        self.as_ref().search_boxed(vector, limit, filter).await
+    }
+
+    async fn scan(&self, cursor: Option<String>, limit: u64) -> Result<(Vec<Point>, Option<String>)> {
+        self.as_ref().scan_boxed(cursor, limit).await
+    }
+
+    async fn scan_vectors(&self, cursor: Option<String>, limit: u64) -> Result<Scanned> {
+        self.as_ref().scan_vectors_boxed(cursor, limit).await
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<(Point, Vec<f32>)>> {
+        self.as_ref().get_boxed(id).await
+    }
+
+    async fn delete(&self, ids: Vec<String>) -> Result<()> {
+        self.as_ref().delete_boxed(ids).await
+    }
+
+    async fn overwrite_payloads(&self, points: Vec<Point>) -> Result<()> {
+        self.as_ref().overwrite_payloads_boxed(points).await
+    }
+
+    async fn update_vectors(&self, points: Vec<(Point, Vec<f32>)>) -> Result<()> {
+        self.as_ref().update_vectors_boxed(points).await
+    }
+
+    /// Forwarded rather than left to the default, as stores like `Partitioned` have their own
+    async fn search_within(&self, vector: Vec<f32>, limit: u64, filter: &Filter, deadline: Instant) -> Result<(Vec<Hit>, bool)> {
+        self.as_ref().search_within_boxed(vector, limit, filter, deadline).await
+    }
+
+    async fn search_text(&self, query: &str, limit: u64, filter: &Filter) -> Result<Vec<Hit>> {
+        self.as_ref().search_text_boxed(query, limit, filter).await
+    }
+}
+
+/// The vector store the config's `store` picks
+pub enum Store {
+    Qdrant(Qlient),
+    /// `qdrant.partitions` collections, one per partition
+    Partitioned(Partitioned<Qlient>),
+    Sqlite(SqliteStore),
+    #[cfg(feature = "lancedb")]
+    Lancedb(LancedbStore),
+}
+
+impl Store {
+    pub fn from_config(config: &Config) -> Result<Self> {
+        Ok(match config.store {
+            StoreKind::Qdrant if config.qdrant.partitions > 1 => {
+                let partitions = (0..config.qdrant.partitions)
+                    .map(|partition| Qlient::from_config(&QdrantConfig {
+                        collection: format!("{}_{partition}", config.qdrant.collection),
+                        ..config.qdrant.clone()
+                    }))
+                    .collect::<Result<_>>()?;
+                Store::Partitioned(Partitioned::new(partitions, config.qdrant.buffer_size))
+            }
+            StoreKind::Qdrant => Store::Qdrant(Qlient::from_config(&config.qdrant)?),
+            StoreKind::Sqlite => Store::Sqlite(SqliteStore::from_config(&config.sqlite)?),
+            #[cfg(feature = "lancedb")]
+            StoreKind::Lancedb => Store::Lancedb(LancedbStore::from_config(&config.lancedb)?),
+            #[cfg(not(feature = "lancedb"))]
+            StoreKind::Lancedb => anyhow::bail!("store = \"lancedb\" needs a build with the lancedb feature"),
+        })
+    }
+
+    /// Whether scan cursors count rows, which makes rows rewritten during a scan move between
+    /// its pages; LanceDB appends updated rows rather than changing them in place
+    pub fn scans_by_offset(&self) -> bool {
+        #[cfg(feature = "lancedb")]
+        if let Store::Lancedb(_) = self {
+            return true;
+        }
+
+        false
+    }
+}
+
+impl Sink for Store {
+    async fn push(&mut self, document: Document) -> Result<()> {
+        ensure_writable("store documents")?;
+        match self {
+            Store::Qdrant(store) => store.push(document).await,
+            Store::Partitioned(store) => store.push(document).await,
+            Store::Sqlite(store) => store.push(document).await,
+            #[cfg(feature = "lancedb")]
+            Store::Lancedb(store) => store.push(document).await,
+        }
+    }
+
+    async fn flush(&mut self) -> Result<()> {
+        match self {
+            Store::Qdrant(store) => store.flush().await,
+            Store::Partitioned(store) => store.flush().await,
+            Store::Sqlite(store) => store.flush().await,
+            #[cfg(feature = "lancedb")]
+            Store::Lancedb(store) => store.flush().await,
+        }
+    }
+
+    fn buffered(&self) -> usize {
+        match self {
+            Store::Qdrant(store) => store.buffered(),
+            Store::Partitioned(store) => store.buffered(),
+            Store::Sqlite(store) => store.buffered(),
+            #[cfg(feature = "lancedb")]
+            Store::Lancedb(store) => store.buffered(),
+        }
+    }
+}
+
+impl VectorStore for Store {
+    async fn ensure_collection(&self) -> Result<()> {
+        ensure_writable("create the collection")?;
+        match self {
+            Store::Qdrant(store) => store.ensure_collection().await,
+            Store::Partitioned(store) => store.ensure_collection().await,
+            Store::Sqlite(store) => store.ensure_collection().await,
+            #[cfg(feature = "lancedb")]
+            Store::Lancedb(store) => store.ensure_collection().await,
+        }
+    }
+
+    async fn drop_collection(&self) -> Result<()> {
+        ensure_writable("drop the collection")?;
+        match self {
+            Store::Qdrant(store) => store.drop_collection().await,
+            Store::Partitioned(store) => store.drop_collection().await,
+            Store::Sqlite(store) => store.drop_collection().await,
+            #[cfg(feature = "lancedb")]
+            Store::Lancedb(store) => store.drop_collection().await,
+        }
+    }
+
+    async fn search(&self, vector: Vec<f32>, limit: u64, filter: &Filter) -> Result<Vec<Hit>> {
+        match self {
+            Store::Qdrant(store) => store.search(vector, limit, filter).await,
+            Store::Partitioned(store) => store.search(vector, limit, filter).await,
+            Store::Sqlite(store) => store.search(vector, limit, filter).await,
+            #[cfg(feature = "lancedb")]
+            Store::Lancedb(store) => store.search(vector, limit, filter).await,
+        }
+    }
+
+    async fn scan(&self, cursor: Option<String>, limit: u64) -> Result<(Vec<Point>, Option<String>)> {
+        match self {
+            Store::Qdrant(store) => store.scan(cursor, limit).await,
+            Store::Partitioned(store) => store.scan(cursor, limit).await,
+            Store::Sqlite(store) => store.scan(cursor, limit).await,
+            #[cfg(feature = "lancedb")]
+            Store::Lancedb(store) => store.scan(cursor, limit).await,
+        }
+    }
+
+    async fn scan_vectors(&self, cursor: Option<String>, limit: u64) -> Result<Scanned> {
+        match self {
+            Store::Qdrant(store) => store.scan_vectors(cursor, limit).await,
+            Store::Partitioned(store) => store.scan_vectors(cursor, limit).await,
+            Store::Sqlite(store) => store.scan_vectors(cursor, limit).await,
+            #[cfg(feature = "lancedb")]
+            Store::Lancedb(store) => store.scan_vectors(cursor, limit).await,
+        }
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<(Point, Vec<f32>)>> {
+        match self {
+            Store::Qdrant(store) => store.get(id).await,
+            Store::Partitioned(store) => store.get(id).await,
+            Store::Sqlite(store) => store.get(id).await,
+            #[cfg(feature = "lancedb")]
+            Store::Lancedb(store) => store.get(id).await,
+        }
+    }
+
+    async fn delete(&self, ids: Vec<String>) -> Result<()> {
+        ensure_writable("delete points")?;
+        match self {
+            Store::Qdrant(store) => store.delete(ids).await,
+            Store::Partitioned(store) => store.delete(ids).await,
+            Store::Sqlite(store) => store.delete(ids).await,
+            #[cfg(feature = "lancedb")]
+            Store::Lancedb(store) => store.delete(ids).await,
+        }
+    }
+
+    async fn overwrite_payloads(&self, points: Vec<Point>) -> Result<()> {
+        ensure_writable("rewrite payloads")?;
+        match self {
+            Store::Qdrant(store) => store.overwrite_payloads(points).await,
+            Store::Partitioned(store) => store.overwrite_payloads(points).await,
+            Store::Sqlite(store) => store.overwrite_payloads(points).await,
+            #[cfg(feature = "lancedb")]
+            Store::Lancedb(store) => store.overwrite_payloads(points).await,
+        }
+    }
+
+    async fn update_vectors(&self, points: Vec<(Point, Vec<f32>)>) -> Result<()> {
+        ensure_writable("update vectors")?;
+        match self {
+            Store::Qdrant(store) => store.update_vectors(points).await,
+            Store::Partitioned(store) => store.update_vectors(points).await,
+            Store::Sqlite(store) => store.update_vectors(points).await,
+            #[cfg(feature = "lancedb")]
+            Store::Lancedb(store) => store.update_vectors(points).await,
+        }
+    }
+
+    async fn search_within(&self, vector: Vec<f32>, limit: u64, filter: &Filter, deadline: Instant) -> Result<(Vec<Hit>, bool)> {
+        match self {
+            Store::Qdrant(store) => store.search_within(vector, limit, filter, deadline).await,
+            Store::Partitioned(store) => store.search_within(vector, limit, filter, deadline).await,
+            Store::Sqlite(store) => store.search_within(vector, limit, filter, deadline).await,
+            #[cfg(feature = "lancedb")]
+            Store::Lancedb(store) => store.search_within(vector, limit, filter, deadline).await,
+        }
+    }
+
+    async fn search_text(&self, query: &str, limit: u64, filter: &Filter) -> Result<Vec<Hit>> {
+        match self {
+            Store::Qdrant(store) => store.search_text(query, limit, filter).await,
+            Store::Partitioned(store) => store.search_text(query, limit, filter).await,
+            Store::Sqlite(store) => store.search_text(query, limit, filter).await,
+            #[cfg(feature = "lancedb")]
+            Store::Lancedb(store) => store.search_text(query, limit, filter).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use super::*;
+
+    fn with_acl(acl: Option<serde_json::Value>) -> Metadata {
+        let mut metadata = Metadata { source: "notes.md".to_string(), ..Metadata::default() };
+        if let Some(acl) = acl {
+            metadata.extra.insert(ACL_FIELD.to_string(), acl);
+        }
+
+        metadata
+    }
+
+    fn visible(groups: &[&str], acl: Option<serde_json::Value>) -> bool {
+        let mut filter = Filter::default();
+        filter.within_groups(&groups.iter().map(|group| group.to_string()).collect::<Vec<_>>());
+
+        filter.matches(&with_acl(acl))
+    }
+
+    #[test]
+    fn only_documents_without_an_acl_are_public() {
+        assert!(visible(&["staff"], None));
+        assert!(visible(&["staff"], Some(json!(["staff", "admins"]))));
+        assert!(!visible(&["staff"], Some(json!(["admins"]))));
+        assert!(!visible(&["staff"], Some(json!([]))));
+        assert!(!visible(&["staff"], Some(json!(null))));
+    }
+
+    #[test]
+    fn empty_acls_are_stored_as_naming_no_group() {
+        let document = Document { page_content: "text".to_string(), metadata: with_acl(Some(json!([]))), embeddings: Vec::new() };
+        let payload = payload(&document).unwrap();
+        assert_eq!(payload[ACL_FIELD], json!([NO_GROUP]));
+
+        let metadata = metadata(payload).unwrap();
+        assert_eq!(metadata.extra[ACL_FIELD], json!([]));
+        assert!(!visible(&["staff"], Some(json!([NO_GROUP]))));
+    }
+
+    #[test]
+    fn conditions_on_the_same_field_both_have_to_hold() {
+        let mut filter = Filter::from_pairs([("acl".to_string(), "staff".to_string()), ("acl".to_string(), "admins".to_string())]);
+        filter.and(&Filter::from_pairs([
+            ("acl".to_string(), "admins".to_string()),
+            ("language".to_string(), "en".to_string()),
+        ]));
+
+        assert_eq!(filter.0["acl"], Match::Any(vec!["admins".to_string()]));
+        assert_eq!(filter.0["language"], Match::One("en".to_string()));
+
+        let mut open = Filter(BTreeMap::from([("acl".to_string(), Match::AnyOrMissing(vec!["staff".to_string()]))]));
+        open.and(&Filter::from_pairs([("acl".to_string(), "interns".to_string())]));
+        assert_eq!(open.0["acl"], Match::Any(Vec::new()));
+    }
+
+    #[test]
+    fn text_scores_are_the_share_of_query_words_found() {
+        let query = words("Rust, rust & borrow-checker");
+        assert_eq!(query, ["borrow", "checker", "rust"]);
+
+        assert_eq!(text_score(&query, "The BORROW checker of Rust"), 1.0);
+        assert_eq!(text_score(&query, "rusty borrowing"), 0.0);
+        assert!((text_score(&query, "rust belt") - 1.0 / 3.0).abs() < 1e-6);
+        assert_eq!(text_score(&[], "rust"), 0.0);
+    }
+}
@@ -0,0 +1,495 @@
+use std::future::Future;
+
+use anyhow::{anyhow, bail, Context, Result};
+use futures::stream::{FuturesUnordered, StreamExt};
+use sha2::{Digest, Sha256};
+use tokio::time::Instant;
+use tracing::warn;
+use crate::clients::Document;
+use crate::clients::vector_store::{Filter, Hit, Point, Scanned, VectorStore};
+use crate::search::merge;
+use crate::sink::{Rejected, Sink};
+
+/// Points each partition takes on the ring; more spread the keys more evenly
+const VIRTUAL_NODES: u32 = 64;
+
+/// Consistent hash ring over `n` partitions
+///
+/// Each partition sits at `VIRTUAL_NODES` points and a key belongs to the first point at or
+/// after its hash, so growing from `n` to `n + 1` partitions only moves about `1 / (n + 1)`
+/// of the keys.
+pub struct Ring {
+    points: Vec<(u64, usize)>,
+}
+
+impl Ring {
+    pub fn new(partitions: usize) -> Self {
+        let mut points: Vec<(u64, usize)> = (0..partitions)
+            .flat_map(|partition| (0..VIRTUAL_NODES).map(move |node| (hash(&format!("{partition}/{node}")), partition)))
+            .collect();
+        points.sort_unstable();
+
+        Self { points }
+    }
+
+    /// The partition `key` belongs to
+    pub fn partition(&self, key: &str) -> usize {
+        let hash = hash(key);
+        let at = self.points.partition_point(|(point, _)| *point < hash);
+        self.points.get(at).or(self.points.first()).map_or(0, |(_, partition)| *partition)
+    }
+}
+
+/// Stable across builds and platforms, unlike std's hashers
+fn hash(key: &str) -> u64 {
+    let digest = Sha256::digest(key.as_bytes());
+    u64::from_be_bytes(digest[..8].try_into().expect("a SHA-256 digest has 32 bytes"))
+}
+
+/// Spreads documents over several stores by hashing their `source`, and searches all of them
+///
+/// Keying on the source keeps every chunk of a document in one partition. Documents are
+/// buffered here and a full buffer is written to every partition before `push` returns, so
+/// a batch settles as a whole, as it does with a single store.
+pub struct Partitioned<S> {
+    partitions: Vec<S>,
+    ring: Ring,
+    buffer: Vec<Document>,
+    size: usize,
+}
+
+impl<S: VectorStore + Send + Sync> Partitioned<S> {
+    pub fn new(partitions: Vec<S>, size: usize) -> Self {
+        let ring = Ring::new(partitions.len());
+        Self { partitions, ring, buffer: Vec::with_capacity(size), size: size.max(1) }
+    }
+
+    /// The next page of a scan going through the partitions one after the other with `scan`,
+    /// the cursor naming the partition it is in
+    async fn paged<'s, T, F>(&'s self, cursor: Option<String>, scan: impl Fn(&'s S, Option<String>) -> F) -> Result<(Vec<T>, Option<String>)>
+    where
+        F: Future<Output = Result<(Vec<T>, Option<String>)>>,
+    {
+        let (mut partition, mut inner): (usize, Option<String>) = match cursor.as_deref().map(|cursor| cursor.split_once('/')) {
+            None => (0, None),
+            Some(Some((partition, inner))) => (partition.parse().map_err(|_| anyhow!("Malformed scan cursor"))?, Some(inner.to_string())),
+            Some(None) => bail!("Malformed scan cursor"),
+        };
+
+        while partition < self.partitions.len() {
+            let (points, next) = scan(&self.partitions[partition], inner.take().filter(|inner| !inner.is_empty())).await?;
+            let cursor = match next {
+                Some(next) => Some(format!("{partition}/{next}")),
+                None => (partition + 1 < self.partitions.len()).then(|| format!("{}/", partition + 1)),
+            };
+            // An empty partition moves straight on to the next
+            if !points.is_empty() || cursor.is_none() {
+                return Ok((points, cursor));
+            }
+            partition += 1;
+        }
+
+        Ok((vec![], None))
+    }
+
+    async fn write_buffer(&mut self) -> Result<()> {
+        let mut documents = std::mem::take(&mut self.buffer).into_iter();
+        while let Some(document) = documents.next() {
+            let partition = self.ring.partition(&document.metadata.source);
+            if let Err(e) = self.partitions[partition].push(document).await {
+                // The documents not routed yet stay buffered for the next write
+                self.buffer.extend(documents);
+                return Err(e);
+            }
+        }
+
+        let mut result = Ok(());
+        for partition in &mut self.partitions {
+            if let Err(e) = partition.flush().await {
+                result = Err(e);
+            }
+        }
+
+        result
+    }
+}
+
+impl<S: VectorStore + Send + Sync> Sink for Partitioned<S> {
+    async fn push(&mut self, document: Document) -> Result<()> {
+        // Checked now, as its partition won't see it before the buffer is full
+        self.check(&document).context(Rejected)?;
+        self.buffer.push(document);
+
+        if self.buffer.len() < self.size {
+            return Ok(());
+        }
+
+        self.write_buffer().await
+    }
+
+    async fn flush(&mut self) -> Result<()> {
+        self.write_buffer().await
+    }
+
+    fn buffered(&self) -> usize {
+        self.buffer.len() + self.partitions.iter().map(S::buffered).sum::<usize>()
+    }
+
+    fn check(&self, document: &Document) -> Result<()> {
+        self.partitions[self.ring.partition(&document.metadata.source)].check(document)
+    }
+}
+
+impl<S: VectorStore + Send + Sync> VectorStore for Partitioned<S> {
+    async fn ensure_collection(&self) -> Result<()> {
+        futures::future::try_join_all(self.partitions.iter().map(S::ensure_collection)).await?;
+        Ok(())
+    }
+
+    async fn drop_collection(&self) -> Result<()> {
+        futures::future::try_join_all(self.partitions.iter().map(S::drop_collection)).await?;
+        Ok(())
+    }
+
+    async fn scan(&self, cursor: Option<String>, limit: u64) -> Result<(Vec<Point>, Option<String>)> {
+        self.paged(cursor, |partition, inner| partition.scan(inner, limit)).await
+    }
+
+    async fn scan_vectors(&self, cursor: Option<String>, limit: u64) -> Result<Scanned> {
+        self.paged(cursor, |partition, inner| partition.scan_vectors(inner, limit)).await
+    }
+
+    /// Asks each partition in turn, since the id doesn't tell which one holds the point
+    async fn get(&self, id: &str) -> Result<Option<(Point, Vec<f32>)>> {
+        for partition in &self.partitions {
+            if let Some(found) = partition.get(id).await? {
+                return Ok(Some(found));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Deletes from every partition, since the ids don't tell which one holds each point
+    async fn delete(&self, ids: Vec<String>) -> Result<()> {
+        let deletes = self.partitions.iter().map(|partition| partition.delete(ids.clone()));
+        futures::future::try_join_all(deletes).await?;
+
+        Ok(())
+    }
+
+    /// Sends each point to the partition its payload's `source` belongs to, as `push` does
+    async fn overwrite_payloads(&self, points: Vec<Point>) -> Result<()> {
+        let mut routed: Vec<Vec<Point>> = self.partitions.iter().map(|_| Vec::new()).collect();
+        for point in points {
+            let source = point.payload.get("source").and_then(|source| source.as_str()).unwrap_or_default();
+            routed[self.ring.partition(source)].push(point);
+        }
+        let overwrites = self.partitions.iter()
+            .zip(routed)
+            .map(|(partition, points)| partition.overwrite_payloads(points));
+        futures::future::try_join_all(overwrites).await?;
+
+        Ok(())
+    }
+
+    /// Routed by `source` like `overwrite_payloads`
+    async fn update_vectors(&self, points: Vec<(Point, Vec<f32>)>) -> Result<()> {
+        let mut routed: Vec<Vec<(Point, Vec<f32>)>> = self.partitions.iter().map(|_| Vec::new()).collect();
+        for (point, vector) in points {
+            let source = point.payload.get("source").and_then(|source| source.as_str()).unwrap_or_default();
+            routed[self.ring.partition(source)].push((point, vector));
+        }
+        let updates = self.partitions.iter()
+            .zip(routed)
+            .map(|(partition, points)| partition.update_vectors(points));
+        futures::future::try_join_all(updates).await?;
+
+        Ok(())
+    }
+
+    /// Asks every partition for `limit` hits and keeps the best `limit` of them all
+    async fn search(&self, vector: Vec<f32>, limit: u64, filter: &Filter) -> Result<Vec<Hit>> {
+        let searches = self.partitions.iter().map(|partition| partition.search(vector.clone(), limit, filter));
+        let hits = futures::future::try_join_all(searches).await?;
+
+        Ok(merge(hits, limit as usize))
+    }
+
+    /// Asks every partition for `limit` text hits and keeps the best `limit` of them all
+    async fn search_text(&self, query: &str, limit: u64, filter: &Filter) -> Result<Vec<Hit>> {
+        let searches = self.partitions.iter().map(|partition| partition.search_text(query, limit, filter));
+        let hits = futures::future::try_join_all(searches).await?;
+
+        Ok(merge(hits, limit as usize))
+    }
+
+    /// Merges the partitions that answered by `deadline`, so one slow partition only costs
+    /// its own share of the results
+    async fn search_within(&self, vector: Vec<f32>, limit: u64, filter: &Filter, deadline: Instant) -> Result<(Vec<Hit>, bool)> {
+        let mut searches: FuturesUnordered<_> = self.partitions.iter()
+            .map(|partition| partition.search(vector.clone(), limit, filter))
+            .collect();
+        let mut hits = Vec::with_capacity(searches.len());
+
+        while !searches.is_empty() {
+            match tokio::time::timeout_at(deadline, searches.next()).await {
+                Ok(Some(result)) => hits.push(result?),
+                Ok(None) => break,
+                Err(_) => {
+                    warn!("{} of {} partitions missed the search deadline", searches.len(), self.partitions.len());
+                    break;
+                }
+            }
+        }
+
+        Ok((merge(hits, limit as usize), !searches.is_empty()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    use crate::clients::Metadata;
+    use super::*;
+
+    /// Stores documents in memory, each write taking its whole buffer; pushing a document
+    /// reading "bad" fails, one reading "rejected" doesn't pass the check, and searches
+    /// answer after `delay` with every stored document
+    #[derive(Default)]
+    struct Memory {
+        buffer: Vec<Document>,
+        stored: Mutex<Vec<Document>>,
+        delay: Duration,
+    }
+
+    impl Memory {
+        fn texts(&self) -> Vec<String> {
+            self.stored.lock().unwrap().iter().map(|document| document.page_content.clone()).collect()
+        }
+    }
+
+    impl Sink for Memory {
+        async fn push(&mut self, document: Document) -> Result<()> {
+            if document.page_content == "bad" {
+                self.buffer.clear();
+                bail!("bad document");
+            }
+            self.buffer.push(document);
+
+            Ok(())
+        }
+
+        async fn flush(&mut self) -> Result<()> {
+            self.stored.lock().unwrap().append(&mut self.buffer);
+            Ok(())
+        }
+
+        fn buffered(&self) -> usize {
+            self.buffer.len()
+        }
+
+        fn check(&self, document: &Document) -> Result<()> {
+            match document.page_content.as_str() {
+                "rejected" => bail!("rejected document"),
+                _ => Ok(()),
+            }
+        }
+    }
+
+    impl VectorStore for Memory {
+        async fn ensure_collection(&self) -> Result<()> {
+            Ok(())
+        }
+
+        async fn drop_collection(&self) -> Result<()> {
+            Ok(())
+        }
+
+        async fn search(&self, _: Vec<f32>, limit: u64, _: &Filter) -> Result<Vec<Hit>> {
+            tokio::time::sleep(self.delay).await;
+            let stored = self.stored.lock().unwrap();
+
+            Ok(stored.iter().take(limit as usize).map(|document| Hit {
+                id: document.page_content.clone(),
+                score: document.embeddings[0],
+                text: document.page_content.clone(),
+                metadata: document.metadata.clone(),
+            }).collect())
+        }
+
+        /// Cursors are the index of the next point
+        async fn scan(&self, cursor: Option<String>, limit: u64) -> Result<(Vec<Point>, Option<String>)> {
+            let stored = self.stored.lock().unwrap();
+            let from: usize = cursor.map_or(Ok(0), |cursor| cursor.parse())?;
+            let to = (from + limit as usize).min(stored.len());
+            let points = stored[from..to].iter()
+                .map(|document| Point { id: document.page_content.clone(), payload: Default::default() })
+                .collect();
+
+            Ok((points, (to < stored.len()).then(|| to.to_string())))
+        }
+
+        async fn scan_vectors(&self, cursor: Option<String>, limit: u64) -> Result<Scanned> {
+            let (points, next) = self.scan(cursor, limit).await?;
+            let stored = self.stored.lock().unwrap();
+            let points = points.into_iter()
+                .map(|point| {
+                    let document = stored.iter().find(|document| document.page_content == point.id);
+                    (point, document.map_or_else(Vec::new, |document| document.embeddings.clone()))
+                })
+                .collect();
+
+            Ok((points, next))
+        }
+
+        async fn get(&self, _: &str) -> Result<Option<(Point, Vec<f32>)>> {
+            Ok(None)
+        }
+
+        async fn delete(&self, _: Vec<String>) -> Result<()> {
+            Ok(())
+        }
+
+        async fn overwrite_payloads(&self, _: Vec<Point>) -> Result<()> {
+            Ok(())
+        }
+
+        async fn update_vectors(&self, _: Vec<(Point, Vec<f32>)>) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    fn document(text: &str, source: &str, score: f32) -> Document {
+        Document {
+            page_content: text.to_string(),
+            metadata: Metadata { source: source.to_string(), ..Metadata::default() },
+            embeddings: vec![score],
+        }
+    }
+
+    /// A source `ring` puts in `partition`
+    fn source_in(ring: &Ring, partition: usize) -> String {
+        (0..).map(|i| format!("doc-{i}.md")).find(|source| ring.partition(source) == partition).unwrap()
+    }
+
+    #[test]
+    fn keys_keep_their_partition() {
+        let (ring, again) = (Ring::new(4), Ring::new(4));
+
+        for i in 0..1000 {
+            let key = format!("doc-{i}.md");
+            assert_eq!(ring.partition(&key), again.partition(&key));
+            assert!(ring.partition(&key) < 4);
+        }
+        // Fixed by SHA-256, whatever the build or platform
+        assert_eq!(ring.partition("notes.md"), 0);
+        assert_eq!(ring.partition("a.md"), 2);
+    }
+
+    #[test]
+    fn a_new_partition_takes_its_share_of_keys_from_the_others() {
+        let keys = 10_000;
+        for n in [1, 3, 7] {
+            let (before, after) = (Ring::new(n), Ring::new(n + 1));
+            let mut moved = 0;
+            for i in 0..keys {
+                let key = format!("doc-{i}.md");
+                let (from, to) = (before.partition(&key), after.partition(&key));
+                if from != to {
+                    assert_eq!(to, n, "{key} moved between two old partitions");
+                    moved += 1;
+                }
+            }
+
+            let share = moved as f64 / keys as f64;
+            let expected = 1.0 / (n + 1) as f64;
+            assert!((share - expected).abs() < expected / 2.0, "{share} of the keys moved going to {} partitions", n + 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn a_failed_push_keeps_the_documents_not_routed_yet() {
+        let ring = Ring::new(2);
+        let (first, second) = (source_in(&ring, 0), source_in(&ring, 1));
+        let mut partitioned = Partitioned::new(vec![Memory::default(), Memory::default()], 4);
+
+        partitioned.push(document("a", &first, 1.0)).await.unwrap();
+        partitioned.push(document("bad", &first, 1.0)).await.unwrap();
+        partitioned.push(document("c", &second, 1.0)).await.unwrap();
+        assert!(partitioned.push(document("d", &second, 1.0)).await.is_err());
+        assert_eq!(partitioned.buffered(), 2);
+
+        partitioned.flush().await.unwrap();
+        assert_eq!(partitioned.buffered(), 0);
+        assert!(partitioned.partitions[0].texts().is_empty());
+        assert_eq!(partitioned.partitions[1].texts(), ["c", "d"]);
+    }
+
+    #[tokio::test]
+    async fn documents_their_partition_turns_down_are_never_buffered() {
+        let mut partitioned = Partitioned::new(vec![Memory::default(), Memory::default()], 4);
+
+        partitioned.push(document("a", "a.md", 1.0)).await.unwrap();
+        let error = partitioned.push(document("rejected", "b.md", 1.0)).await.unwrap_err();
+        assert!(error.is::<Rejected>(), "{error:#}");
+        assert_eq!(partitioned.buffered(), 1);
+
+        partitioned.flush().await.unwrap();
+        let texts: Vec<_> = partitioned.partitions.iter().flat_map(Memory::texts).collect();
+        assert_eq!(texts, ["a"]);
+    }
+
+    async fn stored(counts: &[usize]) -> Partitioned<Memory> {
+        let ring = Ring::new(counts.len());
+        let partitions = counts.iter().map(|_| Memory::default()).collect();
+        let mut partitioned = Partitioned::new(partitions, 1);
+        for (partition, &count) in counts.iter().enumerate() {
+            let source = source_in(&ring, partition);
+            for i in 0..count {
+                partitioned.push(document(&format!("{partition}-{i}"), &source, 1.0)).await.unwrap();
+            }
+        }
+
+        partitioned
+    }
+
+    #[tokio::test]
+    async fn scans_go_through_every_partition_in_turn() {
+        let partitioned = stored(&[3, 0, 2]).await;
+
+        let mut cursor = None;
+        let mut pages = Vec::new();
+        loop {
+            let (points, next) = partitioned.scan(cursor, 2).await.unwrap();
+            pages.push(points.into_iter().map(|point| point.id).collect::<Vec<_>>());
+            cursor = next;
+            if cursor.is_none() {
+                break;
+            }
+        }
+
+        assert_eq!(pages, [vec!["0-0", "0-1"], vec!["0-2"], vec!["2-0", "2-1"]]);
+        assert!(partitioned.scan(Some("x".to_string()), 2).await.is_err());
+        assert!(partitioned.scan(Some("one/2".to_string()), 2).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn a_partition_missing_the_deadline_only_costs_its_own_hits() {
+        let mut partitioned = stored(&[1, 1]).await;
+        partitioned.partitions[1].delay = Duration::from_secs(5);
+
+        let deadline = Instant::now() + Duration::from_millis(100);
+        let (hits, partial) = partitioned.search_within(vec![1.0], 10, &Filter::default(), deadline).await.unwrap();
+        assert!(partial);
+        assert_eq!(hits.iter().map(|hit| hit.id.as_str()).collect::<Vec<_>>(), ["0-0"]);
+
+        partitioned.partitions[1].delay = Duration::ZERO;
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let (hits, partial) = partitioned.search_within(vec![1.0], 10, &Filter::default(), deadline).await.unwrap();
+        assert!(!partial);
+        assert_eq!(hits.len(), 2);
+    }
+}
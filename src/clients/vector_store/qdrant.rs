@@ -1,118 +1,768 @@
-use std::collections::VecDeque;
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::default::Default;
 use std::fmt::{Debug, Formatter};
-use anyhow::Result;
-use qdrant_client::prelude::*;
-use qdrant_client::prelude::point_id::PointIdOptions;
-use qdrant_client::qdrant::{PointId, PointsOperationResponse, Vector, Vectors, WriteOrdering};
-use qdrant_client::qdrant::shard_key::Key;
-use qdrant_client::qdrant::vectors::VectorsOptions;
-use tracing::{info, warn};
-use uuid::Uuid;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use anyhow::{anyhow, bail, Context, Result};
+use qdrant_client::{Qdrant, QdrantError};
+use qdrant_client::qdrant::{
+    self, CollectionStatus, Condition, ContextInput, ContextInputPair, CountPointsBuilder, CreateCollectionBuilder,
+    CreateFieldIndexCollectionBuilder, DeletePointsBuilder, DiscoverInput, Distance, FacetCountsBuilder, FieldType,
+    GetPointsBuilder, MultiVectorComparator, MultiVectorConfigBuilder, PointId, PointStruct, PointsSelector,
+    PointVectors, PointsUpdateOperation, Query, QueryPointsBuilder, RetrievedPoint, ScoredPoint, ScrollPointsBuilder, ShardKeySelector,
+    TextIndexParamsBuilder, TokenizerType, UpdateBatchPointsBuilder, UpdateStatus, UpsertPointsBuilder, Value, VectorInput,
+    VectorParamsBuilder, WriteOrdering,
+};
+use qdrant_client::Payload;
+use qdrant_client::qdrant::facet_value::Variant;
+use qdrant_client::qdrant::point_id::PointIdOptions;
+use qdrant_client::qdrant::points_update_operation;
+use qdrant_client::qdrant::vector_output;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, warn};
 use crate::clients::Document;
+use crate::clients::vector_store::{
+    self, Filter, Hit, Locked, Match, Point, Scanned, VectorStore, ACL_FIELD, CONTENT_FIELD, ENTITIES_FIELD, TOPICS_FIELD,
+};
+use crate::clients::vector_store::tunnel::Tunnel;
+use crate::config::{IdFormat, QdrantConfig};
+use crate::multivector;
+use crate::search::merge;
+use crate::similarity::Metric;
+use crate::secret::redact_url;
+use crate::sink::{Rejected, Sink};
+use crate::slow_log::{self, Operation};
 
 pub const DEFAULT_URI: &str = "http://localhost:6334";
 pub const DEFAULT_BUFFER_SIZE: usize = 128;
+pub const DEFAULT_COLLECTION: &str = "rust2";
+/// How often `wait_for_index` asks for the collection's status
+const INDEX_POLL: Duration = Duration::from_secs(1);
+/// Fewest points a text search reads to rank, since Qdrant returns text matches unranked
+const TEXT_CANDIDATES: u64 = 256;
+
+/// One side of a discovery context pair: a stored point, or a vector such as an embedded text
+#[derive(Debug, Clone)]
+pub enum Anchor {
+    Point(String),
+    Vector(Vec<f32>),
+}
+
+impl From<Anchor> for VectorInput {
+    fn from(anchor: Anchor) -> Self {
+        match anchor {
+            Anchor::Point(id) => VectorInput::new_id(id),
+            Anchor::Vector(vector) => VectorInput::new_dense(vector),
+        }
+    }
+}
 
 pub struct Qlient {
     buffer: VecDeque<PointStruct>,
     size: usize,
-    pub client: QdrantClient,
+    pub client: Qdrant,
     collection_name: String,
-    shard_key_selector: Option<Vec<Key>>,
+    shard_key_selector: Option<ShardKeySelector>,
     ordering: Option<WriteOrdering>,
+    ids: IdFormat,
+    dimensions: Option<u64>,
+    distance: Metric,
+    /// Points hold a multivector each, see `MultivectorConfig`; config validation makes sure
+    /// it comes with `dimensions`
+    multivector: bool,
+    /// Set once the full-text index `search_text` needs is known to be there
+    text_indexed: AtomicBool,
+    /// Kept open for as long as `client` connects through it
+    _tunnel: Option<Box<Tunnel>>,
 }
 
 impl Default for Qlient {
     fn default() -> Self {
         let buffer = VecDeque::with_capacity(DEFAULT_BUFFER_SIZE);
-        let config = QdrantClientConfig::from_url(DEFAULT_URI);
-        let client = QdrantClient::new(Some(config)).expect("failure will robinson!");
+        let client = Qdrant::from_url(DEFAULT_URI).build().expect("failure will robinson!");
 
         Self {
             buffer,
             size: DEFAULT_BUFFER_SIZE,
             client,
-            collection_name: "rust2".to_string(),
+            collection_name: DEFAULT_COLLECTION.to_string(),
             shard_key_selector: None,
-            ordering: None
+            ordering: None,
+            ids: IdFormat::Uuid,
+            dimensions: None,
+            distance: Metric::Cosine,
+            multivector: false,
+            text_indexed: AtomicBool::new(false),
+            _tunnel: None,
         }
     }
 }
 
 impl Debug for Qlient {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        f.write_str(&self.client.cfg.uri)
+        f.write_str(&redact_url(&self.client.config.uri))
     }
 }
 
 impl Qlient {
     pub fn new(
         size: usize,
-        config: Option<QdrantClientConfig>,
+        uri: &str,
         collection_name: impl ToString,
-        shard_key_selector: Option<Vec<Key>>,
+        shard_key_selector: Option<ShardKeySelector>,
         ordering: Option<WriteOrdering>,
     ) -> Self {
         let collection_name = collection_name.to_string();
-        let buffer = VecDeque::with_capacity(size.clone());
-        let client = QdrantClient::new(config)
+        let buffer = VecDeque::with_capacity(size);
+        let client = Qdrant::from_url(uri).build()
             .expect("failure will robinson!");
 
-        Self { buffer, size, client, collection_name, shard_key_selector, ordering }
+        Self {
+            buffer,
+            size,
+            client,
+            collection_name,
+            shard_key_selector,
+            ordering,
+            ids: IdFormat::Uuid,
+            dimensions: None,
+            distance: Metric::Cosine,
+            multivector: false,
+            text_indexed: AtomicBool::new(false),
+            _tunnel: None,
+        }
+    }
+
+    /// Fails on settings that can't make a client, such as a malformed `qdrant.url` or proxy
+    pub fn from_config(config: &QdrantConfig) -> Result<Self> {
+        let tunnel = Tunnel::open(config)?;
+        let mut builder = Qdrant::from_url(tunnel.as_ref().map_or(&config.url, |tunnel| &tunnel.url))
+            .api_key(config.api_key.as_ref().map(|key| key.expose().to_string()));
+        if tunnel.is_some() {
+            // The check blocks on its own connection, which the tunnel's task can't serve then
+            builder = builder.skip_compatibility_check();
+        }
+        let client = builder.build()?;
+
+        Ok(Self {
+            buffer: VecDeque::with_capacity(config.buffer_size),
+            size: config.buffer_size,
+            client,
+            collection_name: config.collection.clone(),
+            shard_key_selector: None,
+            ordering: None,
+            ids: config.ids,
+            dimensions: config.dimensions,
+            distance: config.distance,
+            multivector: config.multivector.enabled && config.dimensions.is_some(),
+            text_indexed: AtomicBool::new(false),
+            _tunnel: tunnel.map(Box::new),
+        })
+    }
+
+    /// Size of each vector of a point's multivector, if points hold one
+    fn multivector(&self) -> Option<u64> {
+        self.dimensions.filter(|_| self.multivector)
+    }
+
+    /// Creates the full-text index on the documents' text, once per client, so collections
+    /// created before there was one get it on their first text search; read-only runs have
+    /// to find it in place
+    async fn ensure_text_index(&self) -> Result<()> {
+        if self.text_indexed.load(Ordering::Relaxed) || vector_store::is_read_only() {
+            return Ok(());
+        }
+
+        self.client.create_field_index(
+            CreateFieldIndexCollectionBuilder::new(&self.collection_name, CONTENT_FIELD, FieldType::Text)
+                .field_index_params(text_index())
+                .wait(true)
+        ).await?;
+        self.text_indexed.store(true, Ordering::Relaxed);
+
+        Ok(())
+    }
+
+    pub fn collection_name(&self) -> &str {
+        &self.collection_name
+    }
+
+    async fn upsert_buffer(&mut self, wait: bool) -> Result<()> {
+        let points: Vec<PointStruct> = self.buffer.drain(0..).collect();
+        let operation = Operation::Upsert { points: points.len(), wait };
+        let mut request = UpsertPointsBuilder::new(&self.collection_name, points).wait(wait);
+        if let Some(selector) = self.shard_key_selector.clone() {
+            request = request.shard_key_selector(selector);
+        }
+        if let Some(ordering) = self.ordering {
+            request = request.ordering(ordering);
+        }
+
+        let upsert = async { Ok(self.client.upsert_points(request).await?) };
+        match slow_log::timed("qdrant", &self.collection_name, operation, upsert).await {
+            Ok(response) => match response.result.map(|r| r.status()) {
+                Some(UpdateStatus::Acknowledged) | Some(UpdateStatus::Completed) | None => Ok(()),
+                Some(status) => Err(anyhow!("Upsert finished with status {:?}", status)),
+            },
+            Err(e) if locked(&e) => Err(e.context(Locked(self.collection_name.clone()))),
+            Err(e) => {
+                warn!("{:?}", e);
+                Err(e)
+            },
+        }
+    }
+
+    /// Status, segment and point counts of the collection
+    pub async fn info(&self) -> Result<qdrant::CollectionInfo> {
+        self.client.collection_info(&self.collection_name).await?
+            .result
+            .ok_or_else(|| anyhow!("Qdrant has no info on {}", self.collection_name))
+    }
+
+    /// Polls the collection until Qdrant reports it green with nothing left in its update
+    /// queue, so searches right after don't hit unindexed segments
+    pub async fn wait_for_index(&self, deadline: Instant, cancel: &CancellationToken) -> Result<()> {
+        loop {
+            let info = self.info().await?;
+            if let Some(optimizer) = info.optimizer_status.as_ref().filter(|optimizer| !optimizer.ok) {
+                bail!("The optimizer of {} is failing: {}", self.collection_name, optimizer.error);
+            }
+            let queued = info.update_queue.as_ref().map_or(0, |queue| queue.length);
+            if info.status() == CollectionStatus::Green && queued == 0 {
+                return Ok(());
+            }
+            if Instant::now() >= deadline {
+                bail!(
+                    "{} is still {} with {queued} queued updates and {} of {} vectors indexed",
+                    self.collection_name,
+                    info.status().as_str_name(),
+                    info.indexed_vectors_count.unwrap_or_default(),
+                    info.points_count.unwrap_or_default(),
+                );
+            }
+
+            debug!("Waiting for {} to be indexed, status {}", self.collection_name, info.status().as_str_name());
+            tokio::select! {
+                _ = tokio::time::sleep(INDEX_POLL) => {}
+                _ = cancel.cancelled() => bail!("Shut down while waiting for {} to be indexed", self.collection_name),
+            }
+        }
+    }
+
+    /// Which peers hold the collection's shards
+    pub async fn cluster_info(&self) -> Result<qdrant::CollectionClusterInfoResponse> {
+        Ok(self.client.collection_cluster_info(&self.collection_name).await?)
+    }
+
+    /// Qdrant's discovery search: the points nearest `target` within the part of the space
+    /// each (positive, negative) pair points to, or without a target, the points that fit
+    /// the pairs best
+    pub async fn discover(
+        &self,
+        target: Option<Vec<f32>>,
+        pairs: Vec<(Anchor, Anchor)>,
+        limit: u64,
+        filter: &Filter,
+    ) -> Result<Vec<Hit>> {
+        let dimensions = target.as_ref().map_or(0, Vec::len);
+        let context = ContextInput {
+            pairs: pairs.into_iter()
+                .map(|(positive, negative)| ContextInputPair { positive: Some(positive.into()), negative: Some(negative.into()) })
+                .collect(),
+        };
+        let query = match target {
+            Some(target) => Query::new_discover(DiscoverInput { target: Some(target.into()), context: Some(context) }),
+            None => Query::new_context(context),
+        };
+        let mut query = QueryPointsBuilder::new(&self.collection_name)
+            .query(query)
+            .limit(limit)
+            .with_payload(true);
+        if !filter.is_empty() {
+            query = query.filter(qdrant_filter(filter));
+        }
+        let operation = Operation::Search { limit, dimensions, filter };
+        let discover = async { Ok(self.client.query(query).await?) };
+        let response = slow_log::timed("qdrant", &self.collection_name, operation, discover).await?;
+
+        response.result.into_iter().map(hit).collect()
+    }
+
+    /// Counts points per distinct value of the keyword payload field `key`
+    pub async fn facet(&self, key: &str, limit: u64) -> Result<BTreeMap<String, u64>> {
+        // Qdrant refuses to facet over fields without a payload index, which read-only
+        // runs have to find in place
+        if !vector_store::is_read_only() {
+            self.client.create_field_index(
+                CreateFieldIndexCollectionBuilder::new(&self.collection_name, key, FieldType::Keyword)
+                    .wait(true)
+            ).await?;
+        }
+
+        let response = self.client.facet(
+            FacetCountsBuilder::new(&self.collection_name, key)
+                .limit(limit)
+                .exact(true)
+        ).await?;
+
+        let counts = response.hits
+            .into_iter()
+            .filter_map(|hit| {
+                let value = match hit.value?.variant? {
+                    Variant::StringValue(s) => s,
+                    Variant::IntegerValue(i) => i.to_string(),
+                    Variant::BoolValue(b) => b.to_string(),
+                };
+                Some((value, hit.count))
+            })
+            .collect();
+
+        Ok(counts)
     }
 
-    pub async fn push(&mut self, document: Document) -> Result<()> {
+    /// Exact number of points that pass `filter`
+    pub async fn count(&self, filter: &Filter) -> Result<u64> {
+        let request = CountPointsBuilder::new(&self.collection_name)
+            .filter(qdrant_filter(filter))
+            .exact(true);
+
+        Ok(self.client.count(request).await?.result.map_or(0, |result| result.count))
+    }
+
+    /// Up to `limit` of the points that pass `filter`
+    pub async fn matching(&self, filter: &Filter, limit: u32) -> Result<Vec<Point>> {
+        let request = ScrollPointsBuilder::new(&self.collection_name)
+            .filter(qdrant_filter(filter))
+            .limit(limit)
+            .with_payload(true)
+            .with_vectors(false);
+
+        Ok(self.client.scroll(request).await?.result.into_iter()
+            .map(|point| Point { id: point_id(point.id).unwrap_or_default(), payload: json(point.payload) })
+            .collect())
+    }
+
+    /// Deletes every point that passes `filter`, waiting for Qdrant to apply it
+    pub async fn delete_matching(&self, filter: &Filter) -> Result<()> {
+        vector_store::ensure_writable("delete points")?;
+        if filter.is_empty() {
+            bail!("Refusing to delete by an empty filter, which matches every point");
+        }
+        let request = DeletePointsBuilder::new(&self.collection_name)
+            .points(qdrant_filter(filter))
+            .wait(true);
+        self.client.delete_points(request).await?;
+
+        Ok(())
+    }
+
+    /// Writes `points` with the ids, payloads and vectors they have, such as points read from
+    /// another collection, and waits for Qdrant to apply them
+    pub async fn copy(&self, points: Vec<(Point, Vec<f32>)>) -> Result<()> {
+        vector_store::ensure_writable("copy points")?;
+        if points.is_empty() {
+            return Ok(());
+        }
+        let mut copies = Vec::with_capacity(points.len());
+        for (point, vector) in points {
+            copies.push(PointStruct::new(parse_point_id(&point.id), self.vectors(vector)?, Payload::from(point.payload)));
+        }
+        self.client.upsert_points(UpsertPointsBuilder::new(&self.collection_name, copies).wait(true)).await?;
+
+        Ok(())
+    }
+
+    /// Up to `limit` points after `cursor`, with their vectors if asked for, and the cursor
+    /// to continue from
+    async fn scroll(&self, cursor: Option<String>, limit: u64, with_vectors: bool) -> Result<(Vec<RetrievedPoint>, Option<String>)> {
+        let mut request = ScrollPointsBuilder::new(&self.collection_name)
+            .limit(limit as u32)
+            .with_payload(true)
+            .with_vectors(with_vectors);
+        if let Some(cursor) = cursor {
+            request = request.offset(parse_point_id(&cursor));
+        }
+        let response = self.client.scroll(request).await?;
+
+        Ok((response.result, point_id(response.next_page_offset)))
+    }
+
+    /// A flat vector as the collection stores it, split into sub-chunks for a multivector
+    fn vectors(&self, vector: Vec<f32>) -> Result<qdrant::Vectors> {
+        match self.multivector() {
+            Some(dimensions) => Ok(qdrant::Vector::new_multi(multivector::split(&vector, dimensions as usize)).into()),
+            None => Ok(vector.into()),
+        }
+    }
+}
+
+/// gRPC status codes of refusals that are about the caller's key, not the collection
+const PERMISSION_DENIED: i32 = 7;
+const UNAUTHENTICATED: i32 = 16;
+
+/// Whether Qdrant refused a write because of a lock on its storage or read-only mode, say
+/// while a snapshot is restored or the disk is full, which passes without the points changing
+fn locked(e: &anyhow::Error) -> bool {
+    let Some(QdrantError::ResponseError { status }) = e.downcast_ref::<QdrantError>() else {
+        return false;
+    };
+
+    lock_refusal(status.code() as i32, status.message())
+}
+
+/// Whether a refusal with gRPC `code` and `message` is one of Qdrant's write locks: one set
+/// through the locks API, or storage gone read-only. A key that may only read is turned
+/// down as forbidden, which doesn't pass, so that isn't one.
+fn lock_refusal(code: i32, message: &str) -> bool {
+    if code == PERMISSION_DENIED || code == UNAUTHENTICATED {
+        return false;
+    }
+    let message = message.to_lowercase();
+
+    ["locked", "read-only", "read only", "no space left on device"].iter().any(|reason| message.contains(reason))
+}
+
+impl Sink for Qlient {
+    async fn push(&mut self, document: Document) -> Result<()> {
+        // Nothing was buffered yet, so only this document failed
+        self.check(&document).context(Rejected)?;
+        let p_struct = match self.multivector() {
+            Some(dimensions) => document_to_multivector(self.ids, dimensions as usize, document),
+            None => document_to_pointstruct(self.ids, document),
+        }.context(Rejected)?;
+        self.buffer.push_front(p_struct);
+
         if self.buffer.len() < self.size {
-            let uuid = Uuid::new_v4().to_string();
-            let p_struct = document_to_pointstruct(uuid, document);
-            self.buffer.push_front(p_struct);
             return Ok(())
         }
 
-        if self.buffer.len() >= self.size {
-            let points = self.buffer.drain(0..).collect();
-            let result = self.client.upsert_points(
-                self.collection_name.clone(),
-                self.shard_key_selector.clone(),
-                points,
-                self.ordering.clone()
-            ).await;
-
-            match result {
-                Ok(response) => {
-                    if let Some(result) = response.result {
-                        match result.status {
-                            1 => Ok(()),
-                            _ => Err(()),
-                        }
-                    }
-
-                    Ok(())
+        self.upsert_buffer(false).await
+    }
+
+    /// Upserts whatever is left in the buffer and waits for Qdrant to apply it
+    async fn flush(&mut self) -> Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(())
+        }
+
+        self.upsert_buffer(true).await
+    }
+
+    /// Points waiting for the next upsert
+    fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// A multivector embedding has to split into vectors `qdrant.dimensions` long
+    fn check(&self, document: &Document) -> Result<()> {
+        match self.multivector() {
+            Some(dimensions) if !document.embeddings.len().is_multiple_of(dimensions as usize) => {
+                bail!("{} values don't make vectors of {dimensions} dimensions", document.embeddings.len())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl VectorStore for Qlient {
+    /// Creates the collection if Qdrant doesn't know about it yet
+    async fn ensure_collection(&self) -> Result<()> {
+        if !self.client.collection_exists(&self.collection_name).await? {
+            let mut request = CreateCollectionBuilder::new(&self.collection_name);
+            match (self.multivector(), self.dimensions) {
+                (Some(dimensions), _) => request = request.vectors_config(
+                    VectorParamsBuilder::new(dimensions, distance(self.distance))
+                        .multivector_config(MultiVectorConfigBuilder::new(MultiVectorComparator::MaxSim))
+                ),
+                (None, Some(dimensions)) => {
+                    request = request.vectors_config(VectorParamsBuilder::new(dimensions, distance(self.distance)))
                 }
-                Err(e) => {
-                    warn!("{:?}", e);
-                    Err(e)
-                },
+                (None, None) => {}
             }
-        } else {
-            Ok(())
+            self.client.create_collection(request).await?;
+            // ACL filters run on every search once `serve.acl` is on, the others with every
+            // `--entity` or `--tag`
+            for field in [ACL_FIELD, ENTITIES_FIELD, TOPICS_FIELD] {
+                self.client.create_field_index(
+                    CreateFieldIndexCollectionBuilder::new(&self.collection_name, field, FieldType::Keyword)
+                ).await?;
+            }
+            // Searched when serve mode can't embed queries
+            self.client.create_field_index(
+                CreateFieldIndexCollectionBuilder::new(&self.collection_name, CONTENT_FIELD, FieldType::Text)
+                    .field_index_params(text_index())
+            ).await?;
         }
+
+        Ok(())
+    }
+
+    async fn drop_collection(&self) -> Result<()> {
+        if self.client.collection_exists(&self.collection_name).await? {
+            self.client.delete_collection(&self.collection_name).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn scan(&self, cursor: Option<String>, limit: u64) -> Result<(Vec<Point>, Option<String>)> {
+        let (points, next) = self.scroll(cursor, limit, false).await?;
+        let points = points.into_iter()
+            .map(|point| Point { id: point_id(point.id).unwrap_or_default(), payload: json(point.payload) })
+            .collect();
+
+        Ok((points, next))
+    }
+
+    async fn scan_vectors(&self, cursor: Option<String>, limit: u64) -> Result<Scanned> {
+        let (points, next) = self.scroll(cursor, limit, true).await?;
+        let points = points.into_iter()
+            .map(|point| {
+                let vector = vector(&point);
+                (Point { id: point_id(point.id).unwrap_or_default(), payload: json(point.payload) }, vector)
+            })
+            .collect();
+
+        Ok((points, next))
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<(Point, Vec<f32>)>> {
+        let request = GetPointsBuilder::new(&self.collection_name, vec![parse_point_id(id)])
+            .with_payload(true)
+            .with_vectors(true);
+        let Some(point) = self.client.get_points(request).await?.result.into_iter().next() else {
+            return Ok(None);
+        };
+
+        let vector = vector(&point);
+        let point = Point { id: point_id(point.id).unwrap_or_default(), payload: json(point.payload) };
+        Ok(Some((point, vector)))
+    }
+
+    async fn delete(&self, ids: Vec<String>) -> Result<()> {
+        if ids.is_empty() {
+            return Ok(());
+        }
+        let ids: Vec<PointId> = ids.iter().map(|id| parse_point_id(id)).collect();
+        self.client.delete_points(DeletePointsBuilder::new(&self.collection_name).points(ids).wait(true)).await?;
+
+        Ok(())
+    }
+
+    /// One batch request, with an overwrite for each point since their payloads differ
+    async fn overwrite_payloads(&self, points: Vec<Point>) -> Result<()> {
+        if points.is_empty() {
+            return Ok(());
+        }
+        let operations: Vec<PointsUpdateOperation> = points.into_iter()
+            .map(|point| PointsUpdateOperation {
+                operation: Some(points_update_operation::Operation::OverwritePayload(points_update_operation::OverwritePayload {
+                    payload: Payload::from(point.payload).into(),
+                    points_selector: Some(PointsSelector::from(vec![parse_point_id(&point.id)])),
+                    ..Default::default()
+                })),
+            })
+            .collect();
+        self.client.update_points_batch(UpdateBatchPointsBuilder::new(&self.collection_name, operations).wait(true)).await?;
+
+        Ok(())
+    }
+
+    /// One batch request updating the vectors in place, then overwriting the payloads
+    async fn update_vectors(&self, points: Vec<(Point, Vec<f32>)>) -> Result<()> {
+        if points.is_empty() {
+            return Ok(());
+        }
+        let mut vectors = Vec::with_capacity(points.len());
+        let mut overwrites = Vec::with_capacity(points.len());
+        for (point, vector) in points {
+            let id = parse_point_id(&point.id);
+            vectors.push(PointVectors { id: Some(id.clone()), vectors: Some(self.vectors(vector)?) });
+            overwrites.push(PointsUpdateOperation {
+                operation: Some(points_update_operation::Operation::OverwritePayload(points_update_operation::OverwritePayload {
+                    payload: Payload::from(point.payload).into(),
+                    points_selector: Some(PointsSelector::from(vec![id])),
+                    ..Default::default()
+                })),
+            });
+        }
+        let update = PointsUpdateOperation {
+            operation: Some(points_update_operation::Operation::UpdateVectors(points_update_operation::UpdateVectors {
+                points: vectors,
+                ..Default::default()
+            })),
+        };
+        let operations: Vec<PointsUpdateOperation> = std::iter::once(update).chain(overwrites).collect();
+        self.client.update_points_batch(UpdateBatchPointsBuilder::new(&self.collection_name, operations).wait(true)).await?;
+
+        Ok(())
+    }
+
+    async fn search(&self, vector: Vec<f32>, limit: u64, filter: &Filter) -> Result<Vec<Hit>> {
+        let dimensions = vector.len();
+        // Against a multivector the query is one of a single vector, scoring each point by
+        // the sub-chunk closest to it
+        let query_vector = match self.multivector() {
+            Some(_) => Query::new_nearest(VectorInput::new_multi(vec![vector])),
+            None => Query::new_nearest(vector),
+        };
+        let mut query = QueryPointsBuilder::new(&self.collection_name)
+            .query(query_vector)
+            .limit(limit)
+            .with_payload(true);
+        if !filter.is_empty() {
+            query = query.filter(qdrant_filter(filter));
+        }
+        let operation = Operation::Search { limit, dimensions, filter };
+        let search = async { Ok(self.client.query(query).await?) };
+        let response = slow_log::timed("qdrant", &self.collection_name, operation, search).await?;
+
+        response.result.into_iter().map(hit).collect()
+    }
+
+    /// Points holding any of the words, found through the full-text index and ranked here by
+    /// `text_score`, as Qdrant doesn't rank them
+    async fn search_text(&self, query: &str, limit: u64, filter: &Filter) -> Result<Vec<Hit>> {
+        let words = vector_store::words(query);
+        if limit == 0 || words.is_empty() {
+            return Ok(Vec::new());
+        }
+        self.ensure_text_index().await?;
+
+        let mut conditions = qdrant_filter(filter);
+        conditions.must.push(Condition::matches_text_any(CONTENT_FIELD, words.join(" ")));
+        let request = ScrollPointsBuilder::new(&self.collection_name)
+            .filter(conditions)
+            .limit(limit.max(TEXT_CANDIDATES) as u32)
+            .with_payload(true);
+        let response = self.client.scroll(request).await?;
+
+        let hits = response.result.into_iter()
+            .map(|point| {
+                let hit = Hit::from_payload(point_id(point.id).unwrap_or_default(), 0.0, json(point.payload))?;
+                Ok(Hit { score: vector_store::text_score(&words, &hit.text), ..hit })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(merge(vec![hits], limit as usize))
+    }
+}
+
+/// Words as `vector_store::words` splits them, lowercased
+fn text_index() -> TextIndexParamsBuilder {
+    TextIndexParamsBuilder::new(TokenizerType::Word).lowercase(true)
+}
+
+fn distance(metric: Metric) -> Distance {
+    match metric {
+        Metric::Cosine => Distance::Cosine,
+        Metric::Dot => Distance::Dot,
+        Metric::Euclidean => Distance::Euclid,
+    }
+}
+
+fn qdrant_filter(filter: &Filter) -> qdrant::Filter {
+    qdrant::Filter::must(filter.0.iter().map(|(key, condition)| match condition {
+        Match::One(value) => Condition::matches(key, value.clone()),
+        Match::Any(values) => Condition::matches(key, values.clone()),
+        // `is_empty` also matches null and `[]`; `payload` stores empty ACLs as `[NO_GROUP]`
+        Match::AnyOrMissing(values) => qdrant::Filter::should([
+            Condition::matches(key, values.clone()),
+            qdrant::Filter {
+                must: vec![Condition::is_empty(key)],
+                must_not: vec![Condition::is_null(key)],
+                ..qdrant::Filter::default()
+            }.into(),
+        ]).into(),
+    }))
+}
+
+/// The point with its payload read back as document metadata
+fn hit(point: ScoredPoint) -> Result<Hit> {
+    let id = point_id(point.id).unwrap_or_default();
+    Hit::from_payload(id, point.score, json(point.payload))
+}
+
+/// Numeric ids as numbers, anything else as a UUID
+fn parse_point_id(id: &str) -> PointId {
+    match id.parse::<u64>() {
+        Ok(num) => PointId::from(num),
+        Err(_) => PointId::from(id.to_string()),
+    }
+}
+
+fn point_id(id: Option<PointId>) -> Option<String> {
+    match id?.point_id_options? {
+        PointIdOptions::Uuid(uuid) => Some(uuid),
+        PointIdOptions::Num(num) => Some(num.to_string()),
+    }
+}
+
+fn json(payload: HashMap<String, Value>) -> serde_json::Map<String, serde_json::Value> {
+    match serde_json::Value::from(Payload::from(payload)) {
+        serde_json::Value::Object(object) => object,
+        _ => serde_json::Map::new(),
+    }
+}
+
+/// The vector a point came back with, empty without one; a multivector comes back the way it
+/// was pushed, its vectors one after the other
+fn vector(point: &RetrievedPoint) -> Vec<f32> {
+    match point.vectors.as_ref().and_then(|vectors| vectors.get_vector()) {
+        Some(vector_output::Vector::Dense(dense)) => dense.data,
+        Some(vector_output::Vector::MultiDense(multi)) => multi.vectors.into_iter().flat_map(|dense| dense.data).collect(),
+        _ => vec![],
     }
 }
 
 #[inline]
-fn document_to_pointstruct(uuid: impl ToString, d: Document) -> PointStruct {
-    PointStruct {
-        id: Some(PointId {
-            point_id_options: Some(PointIdOptions::Uuid(uuid.to_string()))
-        }),
-        payload: d.metadata.into(),
-        vectors: Some(Vectors {
-            vectors_options: Some(VectorsOptions::Vector(Vector {
-                data: d.embeddings,
-                indices: None
-            }))
-        })
+fn document_to_pointstruct(ids: IdFormat, d: Document) -> Result<PointStruct> {
+    let payload = vector_store::payload(&d)?;
+    let id = match ids {
+        IdFormat::Uuid => PointId::from(vector_store::stable_id(&d)),
+        IdFormat::Numeric => PointId::from(vector_store::stable_number(&d)),
+    };
+
+    Ok(PointStruct::new(id, d.embeddings, payload))
+}
+
+/// Like `document_to_pointstruct`, with the document's sub-chunk vectors as a multivector
+fn document_to_multivector(ids: IdFormat, dimensions: usize, mut d: Document) -> Result<PointStruct> {
+    let vectors = multivector::split(&std::mem::take(&mut d.embeddings), dimensions);
+    let mut point = document_to_pointstruct(ids, d)?;
+    point.vectors = Some(qdrant::Vector::new_multi(vectors).into());
+
+    Ok(point)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::secret::Secret;
+    use super::*;
+
+    #[test]
+    fn only_locks_and_read_only_storage_count_as_locked() {
+        const FAILED_PRECONDITION: i32 = 9;
+        const INTERNAL: i32 = 13;
+
+        assert!(lock_refusal(FAILED_PRECONDITION, "Write operations are locked: restoring a snapshot"));
+        assert!(lock_refusal(INTERNAL, "Service internal error: No space left on device (os error 28)"));
+        assert!(lock_refusal(FAILED_PRECONDITION, "Collection is in read-only mode"));
+        assert!(!lock_refusal(PERMISSION_DENIED, "Forbidden: write access is required"));
+        assert!(!lock_refusal(UNAUTHENTICATED, "Locked out: invalid api-key"));
+        assert!(!lock_refusal(INTERNAL, "Wrong input: Vector dimension error: expected dim: 768, got 384"));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn settings_that_make_no_client_are_an_error() {
+        let url = QdrantConfig { url: "ftp://localhost:6334".to_string(), ..QdrantConfig::default() };
+        assert!(Qlient::from_config(&url).is_err());
+
+        let proxy = QdrantConfig {
+            url: "http://qdrant.example:6334".to_string(),
+            proxy: Some(Secret::new("gopher://proxy:70")),
+            ..QdrantConfig::default()
+        };
+        assert!(Qlient::from_config(&proxy).is_err());
+    }
+}
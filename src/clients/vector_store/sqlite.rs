@@ -0,0 +1,675 @@
+use std::ffi::{c_char, c_int};
+use std::sync::Once;
+
+use anyhow::Result;
+use futures::TryStreamExt;
+use libsqlite3_sys::{sqlite3, sqlite3_api_routines, sqlite3_auto_extension};
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+use sqlx::{Row, SqliteConnection, SqlitePool};
+use crate::clients::Document;
+use crate::clients::vector_store::{self, Filter, Hit, Point, Scanned, VectorStore};
+use crate::config::SqliteConfig;
+use crate::similarity;
+use crate::sink::Sink;
+use crate::slow_log::{self, Operation};
+
+pub const DEFAULT_PATH: &str = "index.sqlite";
+pub const DEFAULT_TABLE: &str = "documents";
+pub const DEFAULT_BUFFER_SIZE: usize = 128;
+/// Ids bound to one DELETE, well under SQLite's limit on parameters
+const DELETE_BATCH: usize = 500;
+/// Most neighbours sqlite-vec returns for one query
+const MAX_K: usize = 4096;
+
+static SQLITE_VEC: Once = Once::new();
+
+type EntryPoint = unsafe extern "C" fn(*mut sqlite3, *mut *mut c_char, *const sqlite3_api_routines) -> c_int;
+
+/// A row waiting for the next insert
+struct Pending {
+    id: String,
+    metadata: String,
+    text: String,
+    vector: Vec<u8>,
+}
+
+/// Vectors kept in a single SQLite file, for machines without a Qdrant to talk to
+///
+/// Rows live in `table`, and their vectors are indexed in a sqlite-vec `vec0` table next to
+/// it, `<table>_vec`, which is created once the first vectors say how long they are. Searches
+/// ask the index for the nearest rows and only read those; filters are checked on what it
+/// returns, asking for more neighbours until enough match, and scoring every row when the
+/// nearest `MAX_K` don't hold enough. The text of every row is also in an FTS5 table,
+/// `<table>_text`, which `search_text` looks words up in.
+pub struct SqliteStore {
+    pool: SqlitePool,
+    /// Quoted for use in statements
+    table: String,
+    /// The `vec0` table, quoted too
+    index: String,
+    /// The FTS5 table, quoted too
+    text: String,
+    buffer: Vec<Pending>,
+    size: usize,
+}
+
+impl SqliteStore {
+    pub fn from_config(config: &SqliteConfig) -> Result<Self> {
+        SQLITE_VEC.call_once(|| unsafe {
+            // Every connection opened from here on has `vec0` and the `vec_*` functions
+            let init = std::mem::transmute::<*const (), EntryPoint>(sqlite_vec::sqlite3_vec_init as *const ());
+            sqlite3_auto_extension(Some(init));
+        });
+
+        let options = SqliteConnectOptions::new()
+            .filename(&config.path)
+            .create_if_missing(true);
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect_lazy_with(options);
+
+        Ok(Self {
+            pool,
+            table: quote(&config.table),
+            index: quote(&format!("{}_vec", config.table)),
+            text: quote(&format!("{}_text", config.table)),
+            buffer: Vec::with_capacity(config.buffer_size),
+            size: config.buffer_size.max(1),
+        })
+    }
+
+    async fn insert_buffer(&mut self) -> Result<()> {
+        let rows = std::mem::take(&mut self.buffer);
+        let operation = Operation::Upsert { points: rows.len(), wait: true };
+        slow_log::timed("sqlite", self.name(), operation, self.insert(rows)).await
+    }
+
+    async fn insert(&self, rows: Vec<Pending>) -> Result<()> {
+        let mut transaction = self.pool.begin().await?;
+        if let Some(row) = rows.first() {
+            self.create_index(&mut transaction, row.vector.len() / 4).await?;
+        }
+        // Ids come from the content, so documents stored before replace their rows
+        let sql = format!(
+            "INSERT INTO {} (id, metadata, vector) VALUES (?, ?, ?) \
+             ON CONFLICT(id) DO UPDATE SET metadata = excluded.metadata, vector = excluded.vector \
+             RETURNING rowid",
+            self.table
+        );
+        for row in rows {
+            let rowid: i64 = sqlx::query_scalar(&sql)
+                .bind(row.id)
+                .bind(row.metadata)
+                .bind(&row.vector)
+                .fetch_one(&mut *transaction)
+                .await?;
+            self.index_row(&mut transaction, rowid, row.vector).await?;
+            self.index_text(&mut transaction, rowid, &row.text).await?;
+        }
+
+        Ok(transaction.commit().await?)
+    }
+
+    /// Creates the index for vectors of `dimensions` if there's none yet, with every row
+    /// already in `table`
+    async fn create_index(&self, connection: &mut SqliteConnection, dimensions: usize) -> Result<()> {
+        if exists(&mut *connection, &self.index).await? {
+            return Ok(());
+        }
+
+        let sql = format!("CREATE VIRTUAL TABLE {} USING vec0(vector float[{dimensions}] distance_metric=cosine)", self.index);
+        sqlx::query(&sql).execute(&mut *connection).await?;
+        let sql = format!("INSERT INTO {} (rowid, vector) SELECT rowid, vector FROM {}", self.index, self.table);
+        sqlx::query(&sql).execute(&mut *connection).await?;
+
+        Ok(())
+    }
+
+    /// Creates the full-text table if there's none yet, with the text of every row already
+    /// in `table`
+    async fn create_text_index(&self, connection: &mut SqliteConnection) -> Result<()> {
+        if exists(&mut *connection, &self.text).await? {
+            return Ok(());
+        }
+
+        sqlx::query(&format!("CREATE VIRTUAL TABLE {} USING fts5(text)", self.text)).execute(&mut *connection).await?;
+        let sql = format!(
+            "INSERT INTO {} (rowid, text) SELECT rowid, coalesce(json_extract(metadata, '$.{}'), '') FROM {}",
+            self.text, vector_store::CONTENT_FIELD, self.table
+        );
+        sqlx::query(&sql).execute(&mut *connection).await?;
+
+        Ok(())
+    }
+
+    /// Points the index at a row's new vector; `vec0` can't update rows in place
+    async fn index_row(&self, connection: &mut SqliteConnection, rowid: i64, vector: Vec<u8>) -> Result<()> {
+        sqlx::query(&format!("DELETE FROM {} WHERE rowid = ?", self.index))
+            .bind(rowid)
+            .execute(&mut *connection)
+            .await?;
+        sqlx::query(&format!("INSERT INTO {} (rowid, vector) VALUES (?, ?)", self.index))
+            .bind(rowid)
+            .bind(vector)
+            .execute(&mut *connection)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Replaces a row's text in the full-text table
+    async fn index_text(&self, connection: &mut SqliteConnection, rowid: i64, text: &str) -> Result<()> {
+        sqlx::query(&format!("DELETE FROM {} WHERE rowid = ?", self.text))
+            .bind(rowid)
+            .execute(&mut *connection)
+            .await?;
+        sqlx::query(&format!("INSERT INTO {} (rowid, text) VALUES (?, ?)", self.text))
+            .bind(rowid)
+            .bind(text)
+            .execute(&mut *connection)
+            .await?;
+
+        Ok(())
+    }
+
+    /// The `filter` is checked here, on the neighbours the index returns, which are asked
+    /// for in growing numbers until `limit` of them match or every row has been seen
+    async fn nearest(&self, vector: Vec<f32>, limit: usize, filter: &Filter) -> Result<Vec<Hit>> {
+        let mut connection = self.pool.acquire().await?;
+        if limit == 0 || !exists(&mut connection, &self.index).await? {
+            return Ok(Vec::new());
+        }
+
+        let sql = format!(
+            "SELECT rows.id, rows.metadata, neighbours.distance \
+             FROM (SELECT rowid, distance FROM {} WHERE vector MATCH ? AND k = ?) AS neighbours \
+             JOIN {} AS rows ON rows.rowid = neighbours.rowid \
+             ORDER BY neighbours.distance",
+            self.index, self.table
+        );
+        let query = encode(&vector);
+        let mut k = limit.min(MAX_K);
+        loop {
+            let rows = sqlx::query(&sql)
+                .bind(&query)
+                .bind(k as i64)
+                .fetch_all(&mut *connection)
+                .await?;
+            let seen = rows.len();
+
+            let mut hits = Vec::with_capacity(limit);
+            for row in rows {
+                let id: String = row.get("id");
+                let distance: f64 = row.get("distance");
+                let payload = vector_store::stored_payload(&id, row.get("metadata"))?;
+                let hit = Hit::from_payload(id, 1.0 - distance as f32, payload)?;
+                if filter.matches(&hit.metadata) {
+                    hits.push(hit);
+                }
+                if hits.len() == limit {
+                    return Ok(hits);
+                }
+            }
+            // Fewer neighbours than asked for means there are no more rows
+            if seen < k {
+                return Ok(hits);
+            }
+            if k == MAX_K {
+                drop(connection);
+                return self.exhaustive(vector, limit, filter).await;
+            }
+            k = (k * 4).min(MAX_K);
+        }
+    }
+
+    /// Scores every row, for filters the nearest `MAX_K` rows don't hold enough matches for
+    async fn exhaustive(&self, vector: Vec<f32>, limit: usize, filter: &Filter) -> Result<Vec<Hit>> {
+        let sql = format!("SELECT id, metadata, vector FROM {}", self.table);
+        let mut rows = sqlx::query(&sql).fetch(&self.pool);
+        // Best first, never longer than `limit`
+        let mut best: Vec<Hit> = Vec::with_capacity(limit + 1);
+
+        while let Some(row) = rows.try_next().await? {
+            let stored = decode(row.get("vector"));
+            let score = similarity::cosine(&vector, &stored);
+            if best.len() == limit && best.last().is_none_or(|worst| score <= worst.score) {
+                continue;
+            }
+
+            let id: String = row.get("id");
+            let payload = vector_store::stored_payload(&id, row.get("metadata"))?;
+            let hit = Hit::from_payload(id, score, payload)?;
+            if !filter.matches(&hit.metadata) {
+                continue;
+            }
+
+            let at = best.partition_point(|hit| hit.score >= score);
+            best.insert(at, hit);
+            best.truncate(limit);
+        }
+
+        Ok(best)
+    }
+
+    /// The table name without its quotes
+    fn name(&self) -> &str {
+        self.table.trim_matches('"')
+    }
+}
+
+impl Sink for SqliteStore {
+    async fn push(&mut self, document: Document) -> Result<()> {
+        self.buffer.push(Pending {
+            id: vector_store::stable_id(&document),
+            metadata: serde_json::to_string(&vector_store::payload(&document)?)?,
+            text: document.page_content,
+            vector: encode(&document.embeddings),
+        });
+
+        if self.buffer.len() < self.size {
+            return Ok(());
+        }
+
+        self.insert_buffer().await
+    }
+
+    async fn flush(&mut self) -> Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        self.insert_buffer().await
+    }
+
+    fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+}
+
+impl VectorStore for SqliteStore {
+    async fn ensure_collection(&self) -> Result<()> {
+        let sql = format!(
+            // `key` makes rowids, which the index refers to rows by, survive a VACUUM
+            "CREATE TABLE IF NOT EXISTS {} \
+             (key INTEGER PRIMARY KEY, id TEXT NOT NULL UNIQUE, metadata TEXT NOT NULL, vector BLOB NOT NULL)",
+            self.table
+        );
+        let mut transaction = self.pool.begin().await?;
+        sqlx::query(&sql).execute(&mut *transaction).await?;
+        // Files written before there was an index get one with the rows they hold
+        let sql = format!("SELECT length(vector) FROM {} LIMIT 1", self.table);
+        let bytes: Option<i64> = sqlx::query_scalar(&sql).fetch_optional(&mut *transaction).await?;
+        if let Some(bytes) = bytes {
+            self.create_index(&mut transaction, bytes as usize / 4).await?;
+        }
+        self.create_text_index(&mut transaction).await?;
+
+        Ok(transaction.commit().await?)
+    }
+
+    async fn drop_collection(&self) -> Result<()> {
+        sqlx::query(&format!("DROP TABLE IF EXISTS {}", self.index)).execute(&self.pool).await?;
+        sqlx::query(&format!("DROP TABLE IF EXISTS {}", self.text)).execute(&self.pool).await?;
+        sqlx::query(&format!("DROP TABLE IF EXISTS {}", self.table)).execute(&self.pool).await?;
+
+        Ok(())
+    }
+
+    async fn scan(&self, cursor: Option<String>, limit: u64) -> Result<(Vec<Point>, Option<String>)> {
+        let sql = format!("SELECT id, metadata FROM {} WHERE id > ? ORDER BY id LIMIT ?", self.table);
+        let rows = sqlx::query(&sql)
+            .bind(cursor.unwrap_or_default())
+            .bind(limit as i64)
+            .fetch_all(&self.pool)
+            .await?;
+
+        let points = rows.iter()
+            .map(|row| {
+                let id: String = row.get("id");
+                Ok(Point { payload: vector_store::stored_payload(&id, row.get("metadata"))?, id })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let next = (points.len() as u64 == limit).then(|| points.last().map(|point| point.id.clone())).flatten();
+        Ok((points, next))
+    }
+
+    async fn scan_vectors(&self, cursor: Option<String>, limit: u64) -> Result<Scanned> {
+        let sql = format!("SELECT id, metadata, vector FROM {} WHERE id > ? ORDER BY id LIMIT ?", self.table);
+        let rows = sqlx::query(&sql)
+            .bind(cursor.unwrap_or_default())
+            .bind(limit as i64)
+            .fetch_all(&self.pool)
+            .await?;
+
+        let points = rows.iter()
+            .map(|row| {
+                let id: String = row.get("id");
+                let payload = vector_store::stored_payload(&id, row.get("metadata"))?;
+                Ok((Point { payload, id }, decode(row.get("vector"))))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let next = (points.len() as u64 == limit).then(|| points.last().map(|(point, _)| point.id.clone())).flatten();
+        Ok((points, next))
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<(Point, Vec<f32>)>> {
+        let sql = format!("SELECT metadata, vector FROM {} WHERE id = ?", self.table);
+        let Some(row) = sqlx::query(&sql).bind(id).fetch_optional(&self.pool).await? else {
+            return Ok(None);
+        };
+
+        let point = Point { id: id.to_string(), payload: vector_store::stored_payload(id, row.get("metadata"))? };
+        Ok(Some((point, decode(row.get("vector")))))
+    }
+
+    async fn delete(&self, ids: Vec<String>) -> Result<()> {
+        let mut transaction = self.pool.begin().await?;
+        let indexed = exists(&mut transaction, &self.index).await?;
+        let unindex = format!("DELETE FROM {} WHERE rowid = ?", self.index);
+        let unindex_text = format!("DELETE FROM {} WHERE rowid = ?", self.text);
+        for chunk in ids.chunks(DELETE_BATCH) {
+            let placeholders = vec!["?"; chunk.len()].join(", ");
+            let sql = format!("DELETE FROM {} WHERE id IN ({placeholders}) RETURNING rowid", self.table);
+            let mut query = sqlx::query_scalar(&sql);
+            for id in chunk {
+                query = query.bind(id);
+            }
+            let rowids: Vec<i64> = query.fetch_all(&mut *transaction).await?;
+            for rowid in rowids {
+                if indexed {
+                    sqlx::query(&unindex).bind(rowid).execute(&mut *transaction).await?;
+                }
+                sqlx::query(&unindex_text).bind(rowid).execute(&mut *transaction).await?;
+            }
+        }
+
+        Ok(transaction.commit().await?)
+    }
+
+    async fn overwrite_payloads(&self, points: Vec<Point>) -> Result<()> {
+        let mut transaction = self.pool.begin().await?;
+        let sql = format!("UPDATE {} SET metadata = ? WHERE id = ? RETURNING rowid", self.table);
+        for point in points {
+            let rowid: Option<i64> = sqlx::query_scalar(&sql)
+                .bind(serde_json::to_string(&point.payload)?)
+                .bind(point.id)
+                .fetch_optional(&mut *transaction)
+                .await?;
+            if let Some(rowid) = rowid {
+                self.index_text(&mut transaction, rowid, content(&point.payload)).await?;
+            }
+        }
+
+        Ok(transaction.commit().await?)
+    }
+
+    async fn update_vectors(&self, points: Vec<(Point, Vec<f32>)>) -> Result<()> {
+        let mut transaction = self.pool.begin().await?;
+        if let Some((_, vector)) = points.first() {
+            self.create_index(&mut transaction, vector.len()).await?;
+        }
+        let sql = format!("UPDATE {} SET metadata = ?, vector = ? WHERE id = ? RETURNING rowid", self.table);
+        for (point, vector) in points {
+            let vector = encode(&vector);
+            let text = content(&point.payload).to_string();
+            let rowid: Option<i64> = sqlx::query_scalar(&sql)
+                .bind(serde_json::to_string(&point.payload)?)
+                .bind(&vector)
+                .bind(point.id)
+                .fetch_optional(&mut *transaction)
+                .await?;
+            if let Some(rowid) = rowid {
+                self.index_row(&mut transaction, rowid, vector).await?;
+                self.index_text(&mut transaction, rowid, &text).await?;
+            }
+        }
+
+        Ok(transaction.commit().await?)
+    }
+
+    async fn search(&self, vector: Vec<f32>, limit: u64, filter: &Filter) -> Result<Vec<Hit>> {
+        let operation = Operation::Search { limit, dimensions: vector.len(), filter };
+        slow_log::timed("sqlite", self.name(), operation, self.nearest(vector, limit as usize, filter)).await
+    }
+
+    /// Rows holding any of the words, ranked by FTS5's BM25, the `filter` checked on them in
+    /// that order until `limit` match
+    async fn search_text(&self, query: &str, limit: u64, filter: &Filter) -> Result<Vec<Hit>> {
+        let words = vector_store::words(query);
+        let mut connection = self.pool.acquire().await?;
+        if limit == 0 || words.is_empty() {
+            return Ok(Vec::new());
+        }
+        // Files nothing was written to since there was a full-text table have to be read whole
+        if !exists(&mut connection, &self.text).await? {
+            drop(connection);
+            return vector_store::scanned_text_search(self, query, limit, filter).await;
+        }
+
+        let sql = format!(
+            "SELECT rows.id, rows.metadata, -bm25({text}) AS score \
+             FROM {text} JOIN {} AS rows ON rows.rowid = {text}.rowid \
+             WHERE {text} MATCH ? ORDER BY bm25({text})",
+            self.table, text = self.text
+        );
+        // Quoted, the words are searched for as they are rather than read as FTS5 syntax
+        let terms = words.iter().map(|word| format!("\"{word}\"")).collect::<Vec<_>>().join(" OR ");
+        let mut rows = sqlx::query(&sql).bind(terms).fetch(&mut *connection);
+        let mut hits = Vec::with_capacity(limit as usize);
+
+        while let Some(row) = rows.try_next().await? {
+            let id: String = row.get("id");
+            let score: f64 = row.get("score");
+            let payload = vector_store::stored_payload(&id, row.get("metadata"))?;
+            let hit = Hit::from_payload(id, score as f32, payload)?;
+            if filter.matches(&hit.metadata) {
+                hits.push(hit);
+            }
+            if hits.len() as u64 == limit {
+                break;
+            }
+        }
+
+        Ok(hits)
+    }
+}
+
+/// Whether the table or index `quoted` names exists
+async fn exists(connection: &mut SqliteConnection, quoted: &str) -> Result<bool> {
+    let exists = sqlx::query("SELECT 1 FROM sqlite_master WHERE name = ?")
+        .bind(quoted.trim_matches('"').replace("\"\"", "\""))
+        .fetch_optional(connection)
+        .await?;
+
+    Ok(exists.is_some())
+}
+
+/// The text in a stored payload
+fn content(payload: &serde_json::Map<String, serde_json::Value>) -> &str {
+    payload.get(vector_store::CONTENT_FIELD).and_then(serde_json::Value::as_str).unwrap_or_default()
+}
+
+/// `name` as an identifier
+fn quote(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// A vector as the little-endian f32s of its column
+fn encode(vector: &[f32]) -> Vec<u8> {
+    vector.iter().flat_map(|x| x.to_le_bytes()).collect()
+}
+
+/// A vector column as the little-endian f32s it was stored as
+fn decode(bytes: &[u8]) -> Vec<f32> {
+    bytes.chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::clients::Metadata;
+    use crate::clients::vector_store::{stable_id, Match};
+    use super::*;
+
+    /// A store in a file of its own, empty
+    async fn store(name: &str) -> SqliteStore {
+        let path = std::env::temp_dir().join(format!("rag-rs-sqlite-{name}-{}.sqlite", std::process::id()));
+        _ = std::fs::remove_file(&path);
+        let store = SqliteStore::from_config(&SqliteConfig { path, buffer_size: 2, ..SqliteConfig::default() }).unwrap();
+        store.ensure_collection().await.unwrap();
+
+        store
+    }
+
+    fn document(text: &str, language: &str, embeddings: Vec<f32>) -> Document {
+        Document {
+            page_content: text.to_string(),
+            metadata: Metadata { source: format!("{text}.txt"), language: language.to_string(), ..Metadata::default() },
+            embeddings,
+        }
+    }
+
+    async fn stored(store: &mut SqliteStore, documents: Vec<Document>) {
+        for document in documents {
+            store.push(document).await.unwrap();
+        }
+        store.flush().await.unwrap();
+    }
+
+    fn texts(hits: &[Hit]) -> Vec<&str> {
+        hits.iter().map(|hit| hit.text.as_str()).collect()
+    }
+
+    #[tokio::test]
+    async fn stored_documents_come_back_with_their_vectors() {
+        let mut store = store("round-trip").await;
+        let documents = vec![
+            document("a", "en", vec![1.0, 0.0]),
+            document("b", "en", vec![0.0, 1.0]),
+            document("c", "en", vec![0.5, 0.5]),
+        ];
+        stored(&mut store, documents.clone()).await;
+
+        let (points, next) = store.scan(None, 10).await.unwrap();
+        assert_eq!(points.len(), 3);
+        assert_eq!(next, None);
+        let (point, vector) = store.get(&stable_id(&documents[1])).await.unwrap().unwrap();
+        assert_eq!(point.payload["source"], "b.txt");
+        assert_eq!(vector, [0.0, 1.0]);
+        let (page, next) = store.scan_vectors(None, 2).await.unwrap();
+        assert_eq!(page.len(), 2);
+        let (rest, next) = store.scan_vectors(next, 2).await.unwrap();
+        assert_eq!(next, None);
+        let mut vectors: Vec<_> = page.into_iter().chain(rest).map(|(_, vector)| vector).collect();
+        vectors.sort_by(|a, b| a[0].total_cmp(&b[0]));
+        assert_eq!(vectors, [vec![0.0, 1.0], vec![0.5, 0.5], vec![1.0, 0.0]]);
+        assert!(store.get("missing").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn searches_return_the_nearest_rows_first() {
+        let mut store = store("nearest").await;
+        stored(&mut store, vec![
+            document("far", "en", vec![0.0, 1.0]),
+            document("near", "en", vec![0.9, 0.1]),
+            document("same", "en", vec![2.0, 0.0]),
+        ]).await;
+
+        let hits = store.search(vec![1.0, 0.0], 2, &Filter::default()).await.unwrap();
+        assert_eq!(texts(&hits), ["same", "near"]);
+        assert!((hits[0].score - 1.0).abs() < 1e-6, "{}", hits[0].score);
+        assert!(store.search(vec![1.0, 0.0], 0, &Filter::default()).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn storing_a_document_again_replaces_its_vector() {
+        let mut store = store("replace").await;
+        stored(&mut store, vec![document("a", "en", vec![1.0, 0.0]), document("b", "en", vec![0.0, 1.0])]).await;
+        stored(&mut store, vec![document("a", "en", vec![0.0, 1.0])]).await;
+
+        let hits = store.search(vec![1.0, 0.0], 10, &Filter::default()).await.unwrap();
+        assert_eq!(hits.len(), 2);
+        assert!(hits.iter().all(|hit| hit.score.abs() < 1e-6), "{hits:?}");
+
+        let (point, _) = store.get(&stable_id(&document("b", "en", Vec::new()))).await.unwrap().unwrap();
+        store.update_vectors(vec![(point, vec![1.0, 0.0])]).await.unwrap();
+        let hits = store.search(vec![1.0, 0.0], 1, &Filter::default()).await.unwrap();
+        assert_eq!(texts(&hits), ["b"]);
+    }
+
+    #[tokio::test]
+    async fn deleted_rows_leave_the_index() {
+        let mut store = store("delete").await;
+        let documents = vec![document("a", "en", vec![1.0, 0.0]), document("b", "en", vec![0.8, 0.2])];
+        stored(&mut store, documents.clone()).await;
+
+        store.delete(vec![stable_id(&documents[0])]).await.unwrap();
+
+        let hits = store.search(vec![1.0, 0.0], 10, &Filter::default()).await.unwrap();
+        assert_eq!(texts(&hits), ["b"]);
+        assert!(store.get(&stable_id(&documents[0])).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn filters_are_met_past_the_nearest_rows() {
+        let mut store = store("filter").await;
+        let mut documents: Vec<_> = (0..20)
+            .map(|i| document(&format!("en{i}"), "en", vec![1.0, i as f32 / 100.0]))
+            .collect();
+        documents.push(document("de", "de", vec![0.0, 1.0]));
+        stored(&mut store, documents).await;
+
+        let filter = Filter(BTreeMap::from([("language".to_string(), Match::One("de".to_string()))]));
+        let hits = store.search(vec![1.0, 0.0], 2, &filter).await.unwrap();
+        assert_eq!(texts(&hits), ["de"]);
+        let filter = Filter(BTreeMap::from([("language".to_string(), Match::One("fr".to_string()))]));
+        assert!(store.search(vec![1.0, 0.0], 2, &filter).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn text_searches_find_rows_by_their_words() {
+        let mut store = store("text").await;
+        let documents = vec![
+            document("rust borrow checker", "en", vec![1.0, 0.0]),
+            document("sourdough bread", "en", vec![1.0, 0.0]),
+            document("Rust auf alten Rädern", "de", vec![1.0, 0.0]),
+        ];
+        stored(&mut store, documents.clone()).await;
+
+        let hits = store.search_text("RUST checker?", 10, &Filter::default()).await.unwrap();
+        assert_eq!(texts(&hits), ["rust borrow checker", "Rust auf alten Rädern"]);
+        assert!(hits[0].score > hits[1].score, "{hits:?}");
+        let filter = Filter(BTreeMap::from([("language".to_string(), Match::One("de".to_string()))]));
+        assert_eq!(texts(&store.search_text("rust", 10, &filter).await.unwrap()), ["Rust auf alten Rädern"]);
+        assert!(store.search_text("\"*", 10, &Filter::default()).await.unwrap().is_empty());
+
+        store.delete(vec![stable_id(&documents[0])]).await.unwrap();
+        assert_eq!(texts(&store.search_text("rust", 10, &Filter::default()).await.unwrap()), ["Rust auf alten Rädern"]);
+    }
+
+    #[tokio::test]
+    async fn tables_written_before_the_index_get_indexed() {
+        let mut store = store("unindexed").await;
+        store.drop_collection().await.unwrap();
+        let old = document("old", "en", vec![1.0, 0.0]);
+        sqlx::query(&format!("CREATE TABLE {} (id TEXT PRIMARY KEY, metadata TEXT NOT NULL, vector BLOB NOT NULL)", store.table))
+            .execute(&store.pool).await.unwrap();
+        sqlx::query(&format!("INSERT INTO {} (id, metadata, vector) VALUES (?, ?, ?)", store.table))
+            .bind(stable_id(&old))
+            .bind(serde_json::to_string(&vector_store::payload(&old).unwrap()).unwrap())
+            .bind(encode(&old.embeddings))
+            .execute(&store.pool).await.unwrap();
+        assert_eq!(texts(&store.search_text("old", 10, &Filter::default()).await.unwrap()), ["old"]);
+
+        store.ensure_collection().await.unwrap();
+        stored(&mut store, vec![document("new", "en", vec![0.0, 1.0])]).await;
+
+        let hits = store.search(vec![1.0, 0.0], 10, &Filter::default()).await.unwrap();
+        assert_eq!(texts(&hits), ["old", "new"]);
+        assert_eq!(texts(&store.search_text("old", 10, &Filter::default()).await.unwrap()), ["old"]);
+    }
+}
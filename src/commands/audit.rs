@@ -0,0 +1,133 @@
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, Context, Result};
+use tracing::{info, warn};
+use crate::cli::AuditArgs;
+use crate::clients::vector_store::{content_hash, Point, Store, VectorStore, CONTENT_FIELD, CONTENT_HASH_FIELD};
+use crate::config::Config;
+use crate::outcome::Exit;
+use crate::provenance::Provenance;
+
+/// Damaged points logged one by one before only being counted
+const REPORTED: u64 = 20;
+
+#[derive(Default)]
+struct Findings {
+    checked: u64,
+    /// Stored before hashes were, so there is nothing to check them against
+    unhashed: u64,
+    missing_text: u64,
+    mismatched: u64,
+    /// Points counted by how their vectors were made
+    provenance: BTreeMap<Provenance, u64>,
+    /// Stored before provenance was recorded
+    unrecorded: u64,
+}
+
+/// Re-hashes the text stored with every point and compares it with the hash written at ingest,
+/// which catches text truncated or mangled anywhere between the loader and the store, and
+/// warns when the vectors weren't all made by the same model and settings
+pub async fn run(args: AuditArgs, config: &Config) -> Result<()> {
+    let store = Store::from_config(config).context(Exit::ConfigError)?;
+    let mut findings = Findings::default();
+    let mut cursor = None;
+
+    loop {
+        let (points, next) = store.scan(cursor, args.batch_size.max(1)).await.context(Exit::BackendUnavailable)?;
+        for point in &points {
+            check(point, &mut findings);
+        }
+        match next {
+            Some(next) => cursor = Some(next),
+            None => break,
+        }
+    }
+
+    info!(
+        "Audited {} points: {} mismatched, {} without text, {} stored without a hash",
+        findings.checked, findings.mismatched, findings.missing_text, findings.unhashed
+    );
+    report_provenance(&findings);
+    if findings.mismatched + findings.missing_text > 0 {
+        return Err(anyhow!(
+            "{} points no longer hold the text they were stored with", findings.mismatched + findings.missing_text
+        ).context(Exit::PartialFailure));
+    }
+
+    Ok(())
+}
+
+fn check(point: &Point, findings: &mut Findings) {
+    findings.checked += 1;
+    match Provenance::from_payload(&point.payload) {
+        Some(provenance) => *findings.provenance.entry(provenance).or_default() += 1,
+        None => findings.unrecorded += 1,
+    }
+    let source = point.payload.get("source").and_then(|source| source.as_str()).unwrap_or_default();
+
+    let Some(expected) = point.payload.get(CONTENT_HASH_FIELD).and_then(|hash| hash.as_str()) else {
+        findings.unhashed += 1;
+        return;
+    };
+    let Some(content) = point.payload.get(CONTENT_FIELD).and_then(|content| content.as_str()) else {
+        findings.missing_text += 1;
+        if findings.missing_text + findings.mismatched <= REPORTED {
+            warn!("Point {} ({source}) has a hash but no text", point.id);
+        }
+        return;
+    };
+
+    if content_hash(content) != expected {
+        findings.mismatched += 1;
+        if findings.missing_text + findings.mismatched <= REPORTED {
+            warn!("Point {} ({source}) holds {} bytes of text that don't match its hash", point.id, content.len());
+        }
+    }
+}
+
+fn report_provenance(findings: &Findings) {
+    if findings.unrecorded > 0 {
+        info!("{} points were stored without their provenance", findings.unrecorded);
+    }
+    if findings.provenance.len() < 2 {
+        return;
+    }
+
+    warn!("The collection holds vectors of {} different provenances:", findings.provenance.len());
+    for (provenance, points) in &findings.provenance {
+        warn!(
+            "{points:>8} points: model {}, {} dimensions, {}normalized, prompt prefix {:?}, version {}",
+            provenance.model.as_deref().unwrap_or("unknown"),
+            provenance.dimensions,
+            if provenance.normalized { "" } else { "not " },
+            provenance.prompt_prefix,
+            provenance.pipeline_version,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use super::*;
+
+    fn point(payload: serde_json::Value) -> Point {
+        Point { id: "1".to_string(), payload: payload.as_object().cloned().unwrap() }
+    }
+
+    #[test]
+    fn text_is_checked_against_its_hash() {
+        let mut findings = Findings::default();
+
+        check(&point(json!({ CONTENT_FIELD: "text", CONTENT_HASH_FIELD: content_hash("text") })), &mut findings);
+        check(&point(json!({ CONTENT_FIELD: "tex", CONTENT_HASH_FIELD: content_hash("text") })), &mut findings);
+        check(&point(json!({ CONTENT_HASH_FIELD: content_hash("text") })), &mut findings);
+        check(&point(json!({ CONTENT_FIELD: "text" })), &mut findings);
+
+        assert_eq!(findings.checked, 4);
+        assert_eq!(findings.mismatched, 1);
+        assert_eq!(findings.missing_text, 1);
+        assert_eq!(findings.unhashed, 1);
+        assert_eq!(findings.unrecorded, 4);
+    }
+}
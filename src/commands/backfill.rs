@@ -0,0 +1,204 @@
+use anyhow::{anyhow, Context, Result};
+use indicatif::{ProgressBar, ProgressStyle};
+use tracing::{info, warn};
+use crate::cli::BackfillArgs;
+use crate::clients::Document;
+use crate::clients::llm::llama_cpp::LlamaCpp;
+use crate::clients::vector_store::{self, Point, Store, VectorStore, CONTENT_FIELD, NEEDS_EMBEDDING_FIELD};
+use crate::commands::ingest::await_llama;
+use crate::config::Config;
+use crate::control::Control;
+use crate::multivector;
+use crate::outcome::Exit;
+use crate::provenance;
+
+/// Embeds the stored points left without a vector, updating them in place under the ids
+/// they're stored with, whether or not those came from their content
+///
+/// Points are scanned with their vectors a page at a time, and the ones of a page that need
+/// embedding are embedded and written before the next page is read. LanceDB's pages count
+/// rows, which its writes move, so there the scan goes through the whole store first,
+/// keeping only the points that need embedding.
+pub async fn run(args: BackfillArgs, config: &Config, control: &Control) -> Result<()> {
+    let store = Store::from_config(config).context(Exit::ConfigError)?;
+    let batch_size = args.batch_size.max(1);
+    let deferred = store.scans_by_offset() && !args.dry_run;
+
+    let progress = ProgressBar::new_spinner()
+        .with_style(ProgressStyle::with_template("{spinner} Checked {pos} points, {msg}")?);
+    let mut backfill = Backfill { store: &store, config, control, llama: None, missing: 0, embedded: 0, failed: 0 };
+    let mut later: Vec<Point> = Vec::new();
+    let mut cursor = None;
+    loop {
+        if control.shutdown.is_cancelled() {
+            break;
+        }
+        let (points, next) = store.scan_vectors(cursor, batch_size).await.context(Exit::BackendUnavailable)?;
+        progress.inc(points.len() as u64);
+        let missing: Vec<Point> = points.into_iter()
+            .filter(|(point, vector)| needs_embedding(point, vector))
+            .map(|(point, _)| point)
+            .collect();
+        backfill.missing += missing.len();
+        progress.set_message(format!("{} need embedding", backfill.missing));
+
+        if deferred {
+            later.extend(missing);
+        } else if !args.dry_run {
+            backfill.embed(missing).await?;
+        }
+        match next {
+            Some(next) => cursor = Some(next),
+            None => break,
+        }
+    }
+    for page in later.chunks(batch_size as usize) {
+        backfill.embed(page.to_vec()).await?;
+    }
+    progress.finish_and_clear();
+    let scanned = progress.position();
+    if control.shutdown.is_cancelled() {
+        warn!("Shut down after checking {scanned} points, not every point was embedded");
+    }
+
+    let Backfill { missing, embedded, failed, .. } = backfill;
+    if missing == 0 {
+        info!("All {scanned} points have a vector");
+        return Ok(());
+    }
+    if args.dry_run {
+        println!("{missing} of {scanned} points need embedding");
+        return Ok(());
+    }
+    info!("Embedded {embedded} of the {missing} of {scanned} points that needed it");
+    if failed > 0 {
+        return Err(anyhow!("{failed} of {missing} points couldn't be embedded")).context(Exit::PartialFailure);
+    }
+
+    Ok(())
+}
+
+/// What a backfill has done so far, and the llama client it connects once there's something
+/// to embed
+struct Backfill<'b> {
+    store: &'b Store,
+    config: &'b Config,
+    control: &'b Control,
+    llama: Option<LlamaCpp<'b>>,
+    missing: usize,
+    embedded: usize,
+    failed: usize,
+}
+
+impl Backfill<'_> {
+    /// Embeds `points` and writes the vectors that came back, stopping early on shutdown
+    async fn embed(&mut self, points: Vec<Point>) -> Result<()> {
+        if points.is_empty() {
+            return Ok(());
+        }
+        let llama = match &mut self.llama {
+            Some(llama) => llama,
+            llama => {
+                let client = LlamaCpp::from_config(&self.config.llama).context(Exit::ConfigError)?;
+                await_llama(&client).await.context(Exit::BackendUnavailable)?;
+                llama.insert(client)
+            }
+        };
+
+        let mut embedded: Vec<(Point, Vec<f32>)> = Vec::with_capacity(points.len());
+        for point in points {
+            if self.control.shutdown.is_cancelled() {
+                break;
+            }
+            let id = point.id.clone();
+            let Some(mut document) = document(point) else {
+                self.failed += 1;
+                continue;
+            };
+            let vector = match multivector::embed(llama, self.config, &document.page_content).await {
+                Ok(vector) if !vector.is_empty() => vector,
+                Ok(_) => {
+                    warn!("An embedding of {} came back empty again", document.metadata.source);
+                    self.failed += 1;
+                    continue;
+                }
+                Err(e) => {
+                    warn!("Failed to embed {}: {e:#}", document.metadata.source);
+                    self.failed += 1;
+                    continue;
+                }
+            };
+            provenance::stamp(&mut document, &self.config.llama, multivector::head(self.config, &vector));
+            let payload = vector_store::payload(&document)?;
+            embedded.push((Point { id, payload }, vector));
+        }
+
+        self.embedded += embedded.len();
+        self.store.update_vectors(embedded).await.context(Exit::BackendUnavailable)
+    }
+}
+
+/// Whether `point` is flagged, or stored with an empty or all-zero vector
+fn needs_embedding(point: &Point, vector: &[f32]) -> bool {
+    point.payload.get(NEEDS_EMBEDDING_FIELD).and_then(|flag| flag.as_bool()) == Some(true)
+        || vector.iter().all(|value| *value == 0.0)
+}
+
+/// The document stored as `point`, without the `needs_embedding` flag; `None`, after a
+/// warning, when its payload doesn't hold one
+fn document(point: Point) -> Option<Document> {
+    let Some(text) = point.payload.get(CONTENT_FIELD).and_then(|text| text.as_str()).map(str::to_string) else {
+        warn!("Point {} has no stored text to embed", point.id);
+        return None;
+    };
+    let mut metadata = match vector_store::metadata(point.payload) {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            warn!("Point {} has a payload that isn't document metadata: {e:#}", point.id);
+            return None;
+        }
+    };
+    metadata.extra.remove(NEEDS_EMBEDDING_FIELD);
+
+    Some(Document { page_content: text, metadata, embeddings: Vec::new() })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::clients::Metadata;
+    use super::*;
+
+    #[test]
+    fn points_under_ids_of_their_own_are_embedded_too() {
+        let mut stored = Document {
+            page_content: "text".to_string(),
+            metadata: Metadata { source: "a.txt".to_string(), ..Metadata::default() },
+            embeddings: Vec::new(),
+        };
+        stored.metadata.extra.insert(NEEDS_EMBEDDING_FIELD.to_string(), true.into());
+        let point = Point { id: uuid::Uuid::new_v4().to_string(), payload: vector_store::payload(&stored).unwrap() };
+
+        let document = document(point).unwrap();
+
+        assert_eq!(document.page_content, "text");
+        assert_eq!(document.metadata.source, "a.txt");
+        assert!(!document.metadata.extra.contains_key(NEEDS_EMBEDDING_FIELD));
+    }
+
+    #[test]
+    fn flagged_points_and_zero_vectors_need_embedding() {
+        let point = |flag: Option<bool>| {
+            let mut payload = serde_json::Map::new();
+            if let Some(flag) = flag {
+                payload.insert(NEEDS_EMBEDDING_FIELD.to_string(), flag.into());
+            }
+            Point { id: "1".to_string(), payload }
+        };
+
+        assert!(needs_embedding(&point(Some(true)), &[0.5, 0.5]));
+        assert!(needs_embedding(&point(None), &[0.0, 0.0]));
+        assert!(needs_embedding(&point(Some(false)), &[]));
+        assert!(!needs_embedding(&point(None), &[0.0, 0.5]));
+        assert!(!needs_embedding(&point(Some(false)), &[0.5]));
+    }
+}
@@ -0,0 +1,155 @@
+use std::collections::HashSet;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use sha2::{Digest, Sha256};
+use tracing::{info, warn};
+use crate::cli::CheckStandbyArgs;
+use crate::clients::vector_store::{Filter, Point, VectorStore};
+use crate::clients::vector_store::qdrant::Qlient;
+use crate::commands::inspect;
+use crate::config::{Config, QdrantConfig, StoreKind};
+use crate::control::Control;
+use crate::outcome::Exit;
+
+/// Compares every collection with its copy on `qdrant.standby`: point counts, and a sample
+/// of points by a hash of their payload and vector, copying the sampled points the standby
+/// lacks or holds differently with `--resync`
+///
+/// Each round with `--every` samples other points, even with a seed. When the counts differ
+/// both collections are scrolled to find every point the standby lacks.
+pub async fn run(args: CheckStandbyArgs, config: &Config, control: &Control) -> Result<()> {
+    if config.store != StoreKind::Qdrant {
+        return Err(anyhow!("check-standby only compares Qdrant collections").context(Exit::ConfigError));
+    }
+    let Some(standby) = config.qdrant.standby() else {
+        return Err(anyhow!("No standby to check, set qdrant.standby.url").context(Exit::ConfigError));
+    };
+    let pairs: Vec<(Qlient, Qlient)> = config.qdrant.collections().into_iter()
+        .zip(standby.collections())
+        .map(|(primary, copy)| Ok((
+            Qlient::from_config(&QdrantConfig { collection: primary, ..config.qdrant.clone() })?,
+            Qlient::from_config(&QdrantConfig { collection: copy, ..standby.clone() })?,
+        )))
+        .collect::<Result<_>>()
+        .context(Exit::ConfigError)?;
+
+    let Some(every) = args.every else {
+        return match check_all(&pairs, &args, 0).await? {
+            true => Ok(()),
+            false => Err(anyhow!("The standby diverges from the primary")).context(Exit::PartialFailure),
+        };
+    };
+    for round in 0.. {
+        match check_all(&pairs, &args, round).await {
+            Ok(true) => info!("The standby matches the primary"),
+            Ok(false) => warn!("The standby diverges from the primary"),
+            Err(e) => warn!("Checking the standby failed: {e:#}"),
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(Duration::from_secs(every)) => {}
+            _ = control.shutdown.cancelled() => break,
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks every pair, sampling from the generator stream of `round`
+async fn check_all(pairs: &[(Qlient, Qlient)], args: &CheckStandbyArgs, round: u64) -> Result<bool> {
+    let stream = format!("check-standby-{round}");
+    let mut consistent = true;
+    for (primary, standby) in pairs {
+        consistent &= check(primary, standby, args, &stream).await?;
+    }
+
+    Ok(consistent)
+}
+
+/// Prints how `standby` differs from `primary`, telling whether it holds the same points
+/// once the check is done
+async fn check(primary: &Qlient, standby: &Qlient, args: &CheckStandbyArgs, stream: &str) -> Result<bool> {
+    let batch_size = args.batch_size.max(1);
+    let primary_count = primary.count(&Filter::default()).await.context(Exit::BackendUnavailable)?;
+    let mut standby_count = standby.count(&Filter::default()).await.context(Exit::BackendUnavailable)?;
+    let ids = inspect::sample(primary, args.sample, batch_size, stream).await
+        .context(Exit::BackendUnavailable)?;
+
+    let mut stale: Vec<(Point, Vec<f32>)> = Vec::new();
+    let (mut missing, mut different) = (0, 0);
+    for id in &ids {
+        // Deleted since the scan picked it
+        let Some((point, vector)) = primary.get(id).await.context(Exit::BackendUnavailable)? else {
+            continue;
+        };
+        match standby.get(id).await.context(Exit::BackendUnavailable)? {
+            None => missing += 1,
+            Some((copy, copy_vector)) if fingerprint(&copy, &copy_vector) != fingerprint(&point, &vector) => different += 1,
+            Some(_) => continue,
+        }
+        stale.push((point, vector));
+    }
+    println!(
+        "{} -> {}: {primary_count} and {standby_count} points, of {} sampled {missing} missing and {different} different",
+        primary.collection_name(), standby.collection_name(), ids.len()
+    );
+
+    if primary_count != standby_count {
+        let lacking = lacking(primary, standby, batch_size).await.context(Exit::BackendUnavailable)?;
+        println!("{} lacks {} points of {}", standby.collection_name(), lacking.len(), primary.collection_name());
+        for id in lacking {
+            if stale.iter().any(|(point, _)| point.id == id) {
+                continue;
+            }
+            if let Some(found) = primary.get(&id).await.context(Exit::BackendUnavailable)? {
+                stale.push(found);
+            }
+        }
+    }
+
+    if !stale.is_empty() {
+        if !args.resync {
+            return Ok(false);
+        }
+        let copied = stale.len();
+        while !stale.is_empty() {
+            let rest = stale.split_off(stale.len().min(batch_size as usize));
+            standby.copy(std::mem::replace(&mut stale, rest)).await.context(Exit::BackendUnavailable)?;
+        }
+        standby_count = standby.count(&Filter::default()).await.context(Exit::BackendUnavailable)?;
+        info!("Copied {copied} points to {}, which holds {standby_count} now", standby.collection_name());
+    }
+
+    Ok(primary_count == standby_count)
+}
+
+/// Ids of the points `primary` holds and `standby` doesn't, from a scroll over each
+async fn lacking(primary: &Qlient, standby: &Qlient, batch_size: u64) -> Result<Vec<String>> {
+    let copies = ids(standby, batch_size).await?;
+
+    Ok(ids(primary, batch_size).await?.into_iter().filter(|id| !copies.contains(id)).collect())
+}
+
+async fn ids(store: &Qlient, batch_size: u64) -> Result<HashSet<String>> {
+    let mut ids = HashSet::new();
+    let mut cursor = None;
+    loop {
+        let (points, next) = store.scan(cursor, batch_size).await?;
+        ids.extend(points.into_iter().map(|point| point.id));
+        match next {
+            Some(next) => cursor = Some(next),
+            None => return Ok(ids),
+        }
+    }
+}
+
+/// SHA-256 over a point's payload and vector, equal for equal copies
+fn fingerprint(point: &Point, vector: &[f32]) -> [u8; 32] {
+    let mut hash = Sha256::new();
+    hash.update(serde_json::to_vec(&point.payload).expect("JSON values serialize"));
+    for value in vector {
+        hash.update(value.to_le_bytes());
+    }
+
+    hash.finalize().into()
+}
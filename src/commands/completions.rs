@@ -0,0 +1,17 @@
+use std::io::Write;
+
+use anyhow::Result;
+use clap::CommandFactory;
+use crate::cli::{Cli, CompletionsArgs};
+
+/// Writes the completion script for the shell to stdout, for sourcing from its rc file
+pub fn run(args: &CompletionsArgs) -> Result<()> {
+    let mut command = Cli::command();
+    let name = command.get_name().to_string();
+    // Rendered first, since the generator panics on a closed stdout rather than failing
+    let mut script = Vec::new();
+    clap_complete::generate(args.shell, &mut command, name, &mut script);
+    std::io::stdout().write_all(&script)?;
+
+    Ok(())
+}
@@ -0,0 +1,101 @@
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use cron::Schedule;
+use tracing::{error, info, warn};
+use crate::cli::IngestArgs;
+use crate::commands::ingest;
+use crate::config::{self, Config};
+use crate::control::Control;
+use crate::metrics_snapshots;
+use crate::outcome::Exit;
+
+pub async fn run(config: &Config, control: &Arc<Control>) -> Result<()> {
+    let schedules = config.schedules
+        .iter()
+        .map(|entry| {
+            let schedule = Schedule::from_str(&entry.cron)
+                .with_context(|| format!("Invalid cron expression {:?}", entry.cron))
+                .context(Exit::ConfigError)?;
+            if entry.path.as_os_str().is_empty() && entry.source.is_none() {
+                return Err(anyhow!("Schedule {:?} needs a path or a source", entry.cron).context(Exit::ConfigError));
+            }
+            Ok((schedule, entry))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    if schedules.is_empty() {
+        return Err(anyhow!("Daemon mode needs at least one [[schedules]] entry").context(Exit::ConfigError));
+    }
+
+    let snapshots = metrics_snapshots::spawn(&config.metrics_snapshots, control.clone());
+    let result = run_schedules(&schedules, config, control).await;
+    if let Some(snapshots) = snapshots {
+        snapshots.abort();
+    }
+
+    result
+}
+
+/// Runs whichever schedule fires next, over and over until shut down
+async fn run_schedules(schedules: &[(Schedule, &config::Schedule)], config: &Config, control: &Control) -> Result<()> {
+    loop {
+        let now = Utc::now();
+        let (at, entry) = next_run(schedules, now)
+            .ok_or_else(|| anyhow!("None of the schedules fire again").context(Exit::ConfigError))?;
+
+        let args = IngestArgs {
+            path: entry.path.clone(),
+            source: entry.source.clone(),
+            format: entry.format,
+            fail_on_error_rate: entry.fail_on_error_rate,
+            ..Default::default()
+        };
+        let input = args.input();
+
+        info!("Next run of {} at {at}", input.display());
+        tokio::select! {
+            _ = tokio::time::sleep((at - now).to_std().unwrap_or_default()) => {}
+            _ = control.shutdown.cancelled() => return Ok(()),
+        }
+
+        let started = Utc::now();
+
+        // Runs are awaited in place, so a slow run can never overlap with the next one
+        match ingest::run(args, config, control).await {
+            Ok(_) => info!("Run of {} finished in {}s", input.display(), (Utc::now() - started).num_seconds()),
+            Err(e) => error!("Run of {} failed ({}): {e:?}", input.display(), Exit::from_error(&e)),
+        }
+
+        if control.shutdown.is_cancelled() {
+            return Ok(());
+        }
+
+        let skipped = skipped_runs(schedules, started);
+        if skipped > 0 {
+            warn!("Skipped {skipped} scheduled run(s) that came due while the previous run was active");
+        }
+    }
+}
+
+/// The earliest upcoming firing across all schedules
+fn next_run<'c>(
+    schedules: &[(Schedule, &'c config::Schedule)],
+    now: DateTime<Utc>,
+) -> Option<(DateTime<Utc>, &'c config::Schedule)> {
+    schedules
+        .iter()
+        .filter_map(|(schedule, entry)| Some((schedule.after(&now).next()?, *entry)))
+        .min_by_key(|(at, _)| *at)
+}
+
+fn skipped_runs(schedules: &[(Schedule, &config::Schedule)], since: DateTime<Utc>) -> usize {
+    let now = Utc::now();
+
+    schedules
+        .iter()
+        .map(|(schedule, _)| schedule.after(&since).take_while(|at| *at <= now).count())
+        .sum()
+}
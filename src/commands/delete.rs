@@ -0,0 +1,62 @@
+use anyhow::{anyhow, Context, Result};
+use tracing::info;
+use crate::cli::DeleteArgs;
+use crate::clients::vector_store::Filter;
+use crate::clients::vector_store::qdrant::Qlient;
+use crate::config::{Config, QdrantConfig, StoreKind};
+use crate::outcome::Exit;
+
+/// Matching points listed per collection before the rest are only counted
+const LISTED: u32 = 10;
+
+/// Counts and lists the points matching the filter in every collection, and deletes them
+/// with `--yes`
+pub async fn run(args: DeleteArgs, config: &Config) -> Result<()> {
+    if config.store != StoreKind::Qdrant {
+        return Err(anyhow!("delete only deletes from Qdrant collections").context(Exit::ConfigError));
+    }
+    let filter = Filter::from_pairs(args.filter);
+
+    let clients: Vec<(String, Qlient)> = config.qdrant.collections().into_iter()
+        .map(|collection| {
+            let client = Qlient::from_config(&QdrantConfig { collection: collection.clone(), ..config.qdrant.clone() })?;
+            Ok((collection, client))
+        })
+        .collect::<Result<_>>()
+        .context(Exit::ConfigError)?;
+
+    let mut total = 0;
+    for (collection, client) in &clients {
+        let count = client.count(&filter).await.context(Exit::BackendUnavailable)?;
+        if count == 0 {
+            continue;
+        }
+        total += count;
+        println!("{collection}: {count} points");
+        for point in client.matching(&filter, LISTED).await.context(Exit::BackendUnavailable)? {
+            let source = point.payload.get("source").and_then(|source| source.as_str()).unwrap_or_default();
+            println!("  {}  {source}", point.id);
+        }
+        if count > u64::from(LISTED) {
+            println!("  ... and {} more", count - u64::from(LISTED));
+        }
+    }
+
+    if total == 0 {
+        info!("No points match the filter");
+        return Ok(());
+    }
+    if !args.yes {
+        println!("{total} points match, pass --yes to delete them");
+        return Ok(());
+    }
+
+    for (collection, client) in &clients {
+        client.delete_matching(&filter).await
+            .with_context(|| format!("Failed to delete from {collection}"))
+            .context(Exit::BackendUnavailable)?;
+    }
+    info!("Deleted {total} points");
+
+    Ok(())
+}
@@ -0,0 +1,66 @@
+use anyhow::{anyhow, bail, Context, Result};
+use uuid::Uuid;
+use crate::cli::DiscoverArgs;
+use crate::clients::llm::llama_cpp::LlamaCpp;
+use crate::clients::vector_store::Filter;
+use crate::clients::vector_store::qdrant::{Anchor, Qlient};
+use crate::commands::ingest::await_llama;
+use crate::config::{Config, IdFormat, StoreKind};
+use crate::outcome::Exit;
+
+/// Prints the points Qdrant's discovery search finds for the query and context pairs
+pub async fn run(args: DiscoverArgs, config: &Config) -> Result<()> {
+    if config.store != StoreKind::Qdrant {
+        return Err(anyhow!("discover only searches Qdrant collections").context(Exit::ConfigError));
+    }
+    if config.qdrant.partitions > 1 {
+        return Err(anyhow!("discover doesn't search partitioned collections").context(Exit::ConfigError));
+    }
+    if args.positive.len() != args.negative.len() {
+        return Err(anyhow!(
+            "Every --positive needs a --negative, got {} and {}", args.positive.len(), args.negative.len()
+        ).context(Exit::ConfigError));
+    }
+
+    let llama = LlamaCpp::from_config(&config.llama).context(Exit::ConfigError)?;
+    await_llama(&llama).await.context(Exit::BackendUnavailable)?;
+    let embed = async |text: &str| -> Result<Vec<f32>> {
+        let vector = llama.query_embedding(text).await.context(Exit::BackendUnavailable)?;
+        if vector.is_empty() {
+            bail!("Llama returned no embedding for {text:?}");
+        }
+        Ok(vector)
+    };
+
+    let target = match &args.query {
+        Some(query) => Some(embed(query).await?),
+        None => None,
+    };
+    let mut pairs = Vec::with_capacity(args.positive.len());
+    let ids = config.qdrant.ids;
+    for (positive, negative) in args.positive.iter().zip(&args.negative) {
+        pairs.push((anchor(positive, ids, &embed).await?, anchor(negative, ids, &embed).await?));
+    }
+
+    let client = Qlient::from_config(&config.qdrant).context(Exit::ConfigError)?;
+    let hits = client.discover(target, pairs, args.top_k, &Filter::from_pairs(args.filter).with_keywords(&args.entity, &args.tag)).await
+        .context(Exit::BackendUnavailable)?;
+    for hit in hits {
+        println!("{:.4}  {}  {}", hit.score, hit.id, hit.metadata.source);
+    }
+
+    Ok(())
+}
+
+/// A point id of the collection's format as the point itself, anything else as the
+/// embedding of the text
+async fn anchor(value: &str, ids: IdFormat, embed: &impl AsyncFn(&str) -> Result<Vec<f32>>) -> Result<Anchor> {
+    let id = match ids {
+        IdFormat::Uuid => Uuid::parse_str(value).ok().map(|id| id.to_string()),
+        IdFormat::Numeric => value.parse::<u64>().ok().map(|id| id.to_string()),
+    };
+    match id {
+        Some(id) => Ok(Anchor::Point(id)),
+        None => Ok(Anchor::Vector(embed(value).await?)),
+    }
+}
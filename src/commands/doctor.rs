@@ -0,0 +1,249 @@
+use std::collections::{BTreeMap, BTreeSet};
+#[cfg(unix)]
+use std::ffi::CString;
+#[cfg(unix)]
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Result};
+#[cfg(unix)]
+use anyhow::Context;
+#[cfg(unix)]
+use indicatif::HumanBytes;
+use crate::clients::llm::llama_cpp::{LlamaCpp, Status};
+use crate::clients::vector_store::qdrant::Qlient;
+use crate::clients::vector_store::{Store, VectorStore};
+use crate::config::{Config, QdrantConfig, StoreKind};
+use crate::multivector;
+use crate::outcome::Exit;
+use crate::presets::Preset;
+
+/// Free space below which a directory the pipeline writes to fails its check
+#[cfg(unix)]
+const MIN_FREE: u64 = 1 << 30;
+/// How long the test embedding may take without a configured timeout
+const EMBED_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Pass or fail of every check run so far
+#[derive(Default)]
+struct Checks {
+    run: usize,
+    failed: usize,
+}
+
+impl Checks {
+    fn report(&mut self, name: &str, result: Result<String>) {
+        self.run += 1;
+        match result {
+            Ok(detail) => println!("PASS  {name:<22} {detail}"),
+            Err(e) => {
+                self.failed += 1;
+                println!("FAIL  {name:<22} {e:#}");
+            }
+        }
+    }
+}
+
+/// Checks the config, the embedding backend, the store and the disks the pipeline writes
+/// to, printing a line for each; the config is loaded here so a broken one is reported
+/// like any other failed check
+pub async fn run(path: &Path, profile: Option<&str>, preset: Option<Preset>) -> Result<()> {
+    let mut checks = Checks::default();
+    let config = match Config::load(path, profile, preset) {
+        Ok(config) => {
+            let detail = match path.exists() {
+                true => format!("{} parses", path.display()),
+                false => format!("{} doesn't exist, using the defaults", path.display()),
+            };
+            checks.report("config", Ok(detail));
+            config
+        }
+        Err(e) => {
+            checks.report("config", Err(e));
+            return Err(anyhow!("The config is broken, nothing else was checked").context(Exit::ConfigError));
+        }
+    };
+
+    let dimensions = match LlamaCpp::from_config(&config.llama) {
+        Ok(llama) => {
+            checks.report("llama.cpp health", health(&llama));
+            match embedding(&llama, &config).await {
+                Ok(dimensions) => {
+                    checks.report("llama.cpp embedding", Ok(format!("returns {dimensions} dimensions")));
+                    Some(dimensions)
+                }
+                Err(e) => {
+                    checks.report("llama.cpp embedding", Err(e));
+                    None
+                }
+            }
+        }
+        Err(e) => {
+            checks.report("llama.cpp", Err(e));
+            None
+        }
+    };
+
+    if config.store == StoreKind::Qdrant {
+        checks.report("qdrant connectivity", qdrant_health(&config.qdrant).await);
+    }
+    checks.report("collection dimensions", collection_dimensions(&config, dimensions).await);
+
+    for (directory, free) in disk_space(&config) {
+        checks.report(&format!("disk {}", directory.display()), free);
+    }
+
+    println!("{} of {} checks passed", checks.run - checks.failed, checks.run);
+    if checks.failed > 0 {
+        return Err(anyhow!("{} checks failed", checks.failed).context(Exit::PartialFailure));
+    }
+
+    Ok(())
+}
+
+fn health(llama: &LlamaCpp<'_>) -> Result<String> {
+    let health = llama.health_check()?;
+    if health.status != Status::Ok {
+        bail!("llama.cpp is {health}");
+    }
+
+    Ok(format!("llama.cpp is {health}"))
+}
+
+/// Dimensions of a test embedding, failing when the backend answers without a vector or
+/// with one of another size than `qdrant.dimensions`
+async fn embedding(llama: &LlamaCpp<'_>, config: &Config) -> Result<usize> {
+    let limit = config.llama.embed_timeout().unwrap_or(EMBED_TIMEOUT);
+    let vector = tokio::time::timeout(limit, llama.embedding("doctor"))
+        .await
+        .map_err(|_| anyhow!("No answer within {limit:?}"))??;
+    if vector.is_empty() {
+        bail!("The embedding endpoint answered without a vector");
+    }
+    if let Some(expected) = config.qdrant.dimensions.filter(|expected| *expected != vector.len() as u64) {
+        bail!("returns {} dimensions, qdrant.dimensions is {expected}", vector.len());
+    }
+
+    Ok(vector.len())
+}
+
+async fn qdrant_health(config: &QdrantConfig) -> Result<String> {
+    let health = Qlient::from_config(config)?.client.health_check().await?;
+
+    Ok(format!("qdrant {} at {}", health.version, config.url))
+}
+
+/// Compares the size of a stored vector with what the backend embeds to now
+async fn collection_dimensions(config: &Config, embedded: Option<usize>) -> Result<String> {
+    match config.store {
+        StoreKind::Qdrant => {
+            let client = Qlient::from_config(&config.qdrant)?;
+            let mut exists = false;
+            for collection in config.qdrant.collections() {
+                exists |= client.client.collection_exists(collection).await?;
+            }
+            if !exists {
+                return Ok("the collection doesn't exist yet".to_string());
+            }
+        }
+        StoreKind::Sqlite if !config.sqlite.path.exists() => {
+            return Ok(format!("{} doesn't exist yet", config.sqlite.path.display()));
+        }
+        _ => {}
+    }
+
+    let store = Store::from_config(config)?;
+    let (points, _) = store.scan(None, 1).await?;
+    let Some(point) = points.first() else {
+        return Ok("the collection is empty".to_string());
+    };
+    let Some((_, vector)) = store.get(&point.id).await? else {
+        return Ok("the collection is empty".to_string());
+    };
+
+    let stored = multivector::head(config, &vector).len();
+    match embedded {
+        Some(embedded) if embedded != stored => {
+            bail!("stored vectors have {stored} dimensions, the backend embeds to {embedded}")
+        }
+        Some(_) => Ok(format!("stored vectors have {stored} dimensions, as embedded")),
+        None => bail!("stored vectors have {stored} dimensions, nothing to compare them with"),
+    }
+}
+
+/// Free space in each directory the pipeline writes its files, caches and indexes to
+fn disk_space(config: &Config) -> BTreeMap<PathBuf, Result<String>> {
+    let mut files = vec![
+        config.history.clone(),
+        config.dead_letter.clone(),
+        config.feedback.path.clone(),
+    ];
+    files.extend(config.archive.clone());
+    if config.slow_log.threshold_ms > 0 {
+        files.push(config.slow_log.path.clone());
+    }
+    if config.serve.query_log.enabled {
+        files.push(config.serve.query_log.path.clone());
+    }
+    match config.store {
+        StoreKind::Sqlite => files.push(config.sqlite.path.clone()),
+        // The directory is the database, so it is measured rather than its parent
+        StoreKind::Lancedb => files.push(config.lancedb.path.join("tables")),
+        StoreKind::Qdrant => {}
+    }
+
+    files.into_iter()
+        .map(|file| existing_dir(&file))
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .map(|directory| {
+            let free = free_space(&directory);
+            (directory, free)
+        })
+        .collect()
+}
+
+/// Free space in `directory`, failing below [`MIN_FREE`]
+#[cfg(unix)]
+fn free_space(directory: &Path) -> Result<String> {
+    match free_bytes(directory)? {
+        free if free < MIN_FREE => bail!("only {} free, less than {}", HumanBytes(free), HumanBytes(MIN_FREE)),
+        free => Ok(format!("{} free", HumanBytes(free))),
+    }
+}
+
+/// Free space is only read through statvfs, so elsewhere the check passes as skipped
+#[cfg(not(unix))]
+fn free_space(_directory: &Path) -> Result<String> {
+    Ok("skipped, free space is only checked on Unix".to_string())
+}
+
+/// The nearest directory at or above the parent of `file` that exists
+fn existing_dir(file: &Path) -> PathBuf {
+    let mut directory = file.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    while !directory.exists() {
+        match directory.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => directory = parent,
+            _ => return PathBuf::from("."),
+        }
+    }
+
+    directory.to_path_buf()
+}
+
+/// Bytes an unprivileged process may still write to the filesystem holding `directory`
+#[cfg(unix)]
+fn free_bytes(directory: &Path) -> Result<u64> {
+    let path = CString::new(directory.as_os_str().as_bytes()).context("Path holds a NUL byte")?;
+    // SAFETY: statvfs only writes the struct it is handed, and `path` is NUL terminated
+    let stat = unsafe {
+        let mut stat = std::mem::zeroed::<libc::statvfs>();
+        if libc::statvfs(path.as_ptr(), &mut stat) != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        stat
+    };
+
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
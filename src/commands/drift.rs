@@ -0,0 +1,99 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+use crate::cli::DriftArgs;
+use crate::clients::Document;
+use crate::clients::llm::llama_cpp::LlamaCpp;
+use crate::commands::ingest::await_llama;
+use crate::config::Config;
+use crate::outcome::Exit;
+use crate::similarity;
+
+/// Probes spanning prose, code and non-English text so a model swap shows up in at least one
+const DEFAULT_PROBES: [&str; 6] = [
+    "The quick brown fox jumps over the lazy dog.",
+    "fn main() { println!(\"Hello, world!\"); }",
+    "How do I configure a reverse proxy with TLS termination?",
+    "Der Vertrag tritt am ersten Januar in Kraft.",
+    "SELECT name, count(*) FROM users GROUP BY name ORDER BY 2 DESC;",
+    "Photosynthesis converts light energy into chemical energy.",
+];
+
+/// Probe embeddings persisted between runs, keyed by probe text
+#[derive(Default, Serialize, Deserialize)]
+struct ProbeStore {
+    embeddings: BTreeMap<String, Vec<f32>>,
+}
+
+pub async fn run(args: DriftArgs, config: &Config) -> Result<()> {
+    let llama = LlamaCpp::from_config(&config.llama).context(Exit::ConfigError)?;
+    let probes = read_probes(args.probes.as_deref()).await?;
+    let previous = read_store(&args.store).await?;
+
+    await_llama(&llama).await.context(Exit::BackendUnavailable)?;
+
+    let mut current = ProbeStore::default();
+    for probe in probes {
+        let document = llama.embed(Document {
+            page_content: probe.clone(),
+            ..Default::default()
+        }).await?;
+
+        if document.embeddings.is_empty() {
+            bail!("Llama returned no embedding for probe {probe:?}");
+        }
+
+        current.embeddings.insert(probe, document.embeddings);
+    }
+
+    let mut drifted = 0;
+    for (probe, embedding) in current.embeddings.iter() {
+        let Some(before) = previous.as_ref().and_then(|p| p.embeddings.get(probe)) else {
+            info!("No previous embedding for probe {probe:?}");
+            continue;
+        };
+
+        let similarity = similarity::cosine(before, embedding);
+        if similarity < args.threshold {
+            drifted += 1;
+            warn!("Probe {probe:?} drifted: similarity {similarity:.4} below {}", args.threshold);
+        } else {
+            info!("Probe {probe:?} similarity {similarity:.4}");
+        }
+    }
+
+    tokio::fs::write(&args.store, serde_json::to_vec(&current)?).await?;
+
+    if drifted > 0 {
+        bail!("{drifted} probe(s) drifted; the embedding model changed enough to warrant reindexing");
+    }
+
+    Ok(())
+}
+
+async fn read_probes(path: Option<&Path>) -> Result<Vec<String>> {
+    let Some(path) = path else {
+        return Ok(DEFAULT_PROBES.iter().map(|p| p.to_string()).collect());
+    };
+
+    let probes = tokio::fs::read_to_string(path).await?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect();
+
+    Ok(probes)
+}
+
+async fn read_store(path: &Path) -> Result<Option<ProbeStore>> {
+    if !tokio::fs::try_exists(path).await? {
+        return Ok(None);
+    }
+
+    let bytes = tokio::fs::read(path).await?;
+    Ok(Some(serde_json::from_slice(&bytes)?))
+}
@@ -0,0 +1,240 @@
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde_json::json;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+use crate::chunking::Chunker;
+use crate::cli::{ChunkSizeArgs, Output, ExperimentAction, ExperimentArgs, ExperimentSample, ModelsArgs};
+use crate::clients::Document;
+use crate::clients::llm::Embedding;
+use crate::clients::llm::llama_cpp::LlamaCpp;
+use crate::clients::vector_store::{Filter, Store, VectorStore};
+use crate::commands::ingest::await_llama;
+use crate::config::{ChunkMode, Config, LlamaConfig};
+use crate::eval::{self, EvalQuery, Scores};
+use crate::loaders::{self, Options, Selection};
+use crate::outcome::Exit;
+use crate::output::Rows;
+use crate::sink::Sink;
+
+/// How one configuration did on the eval set
+struct Trial {
+    label: String,
+    chunks: usize,
+    scores: Scores,
+    /// Mean time to embed one chunk
+    embed: Duration,
+    /// Mean time to embed and search one eval query
+    query: Duration,
+}
+
+pub async fn run(args: ExperimentArgs, config: &Config) -> Result<()> {
+    match args.action {
+        ExperimentAction::ChunkSize(args) => chunk_size(args, config).await,
+        ExperimentAction::Models(args) => models(args, config).await,
+    }
+}
+
+async fn chunk_size(args: ChunkSizeArgs, config: &Config) -> Result<()> {
+    let queries = eval::load(&args.sample.eval).await.context(Exit::ConfigError)?;
+    let documents = sample(&args.sample, config).await?;
+    let llama = LlamaCpp::from_config(&config.llama).context(Exit::ConfigError)?;
+    await_llama(&llama).await.context(Exit::BackendUnavailable)?;
+
+    let mut trials = Vec::new();
+    for size in args.sizes {
+        let chunks = Chunker::new(config, size, args.overlap).context(Exit::ConfigError)?
+            .rechunk(documents.clone())
+            .await;
+        info!("Trying chunks of {size} {}, {} of them", unit(config), chunks.len());
+        let store = Store::from_config(&temporary(config, &format!("chunk_{size}")))?;
+        trials.push(trial(size.to_string(), store, &llama, chunks, &queries, &args.sample).await?);
+    }
+
+    report("chunk size", &trials, &args.sample)?;
+    if let Some(best) = best(&trials) {
+        let best = format!("best: {} {}; set pipeline.chunk_size = {} for the full run", best.label, unit(config), best.label);
+        conclude(&best, args.sample.output);
+    }
+
+    Ok(())
+}
+
+/// What chunk sizes count
+fn unit(config: &Config) -> &'static str {
+    match config.pipeline.chunk_mode {
+        ChunkMode::Characters => "characters",
+        ChunkMode::Tokens => "tokens",
+    }
+}
+
+async fn models(args: ModelsArgs, config: &Config) -> Result<()> {
+    let mut models: Vec<(&str, &LlamaConfig)> = vec![("llama", &config.llama)];
+    if args.models.is_empty() {
+        models.extend(config.models.iter().map(|(name, model)| (name.as_str(), model)));
+    }
+    for name in &args.models {
+        let model = config.models.get(name)
+            .with_context(|| format!("No [models.{name}] in the config"))
+            .context(Exit::ConfigError)?;
+        models.push((name, model));
+    }
+    if models.len() < 2 {
+        return Err(anyhow!("Nothing to compare llama with, add [models.<name>] tables to the config").context(Exit::ConfigError));
+    }
+
+    let queries = eval::load(&args.sample.eval).await.context(Exit::ConfigError)?;
+    let documents = Chunker::from_config(config).context(Exit::ConfigError)?
+        .rechunk(sample(&args.sample, config).await?)
+        .await;
+
+    let mut trials = Vec::new();
+    for (name, model) in models {
+        let llama = LlamaCpp::from_config(model).context(Exit::ConfigError)?;
+        info!("Trying {name} at {}:{}", model.host, model.port);
+        await_llama(&llama).await.context(Exit::BackendUnavailable)?;
+        let store = Store::from_config(&temporary(config, &format!("model_{name}")))?;
+        trials.push(trial(name.to_string(), store, &llama, documents.clone(), &queries, &args.sample).await?);
+    }
+
+    report("model", &trials, &args.sample)?;
+    if let Some(best) = best(&trials) {
+        conclude(&format!("best: {}", best.label), args.sample.output);
+    }
+
+    Ok(())
+}
+
+/// Loads the documents the experiment indexes from the sample's path
+async fn sample(sample: &ExperimentSample, config: &Config) -> Result<Vec<Document>> {
+    let cancel = CancellationToken::new();
+    let options = Options { format: sample.format, strict: false, cancel: &cancel, loaders: &config.loaders };
+    let mut selection = Selection::new(sample.sample, Some(sample.limit));
+    let mut documents = Vec::new();
+
+    for file in loaders::discover(&sample.path)? {
+        if selection.is_full() {
+            break;
+        }
+        match loaders::load_file(&file, &options, &mut selection).await {
+            Ok(loaded) => documents.extend(loaded.documents),
+            Err(e) => warn!("Skipping {}: {e:#}", file.display()),
+        }
+    }
+
+    if documents.is_empty() {
+        bail!("No documents to sample under {}", sample.path.display());
+    }
+    info!("Sampled {} documents", documents.len());
+
+    Ok(documents)
+}
+
+/// `config` writing to a collection of its own for the trial named `label`
+fn temporary(config: &Config, label: &str) -> Config {
+    let mut config = config.clone();
+    config.qdrant.collection = format!("{}_experiment_{label}", config.qdrant.collection);
+    config.qdrant.partitions = 1;
+    config.sqlite.table = format!("{}_experiment_{label}", config.sqlite.table);
+    config.lancedb.table = format!("{}_experiment_{label}", config.lancedb.table);
+    config
+}
+
+/// Indexes `documents` into `store` and scores it on `queries`, dropping the collection
+/// again unless `--keep` is given
+async fn trial(
+    label: String,
+    mut store: Store,
+    embedder: &dyn Embedding,
+    documents: Vec<Document>,
+    queries: &[EvalQuery],
+    sample: &ExperimentSample,
+) -> Result<Trial> {
+    // Left behind by an earlier run with `--keep`
+    store.drop_collection().await?;
+    store.ensure_collection().await?;
+
+    let result = async {
+        let chunks = documents.len();
+        let started = Instant::now();
+        for document in documents {
+            let embeddings = embedder.embed_document(&document.page_content).await?;
+            if embeddings.is_empty() {
+                warn!("No embedding for a chunk of {}, leaving it out", document.metadata.source);
+                continue;
+            }
+            store.push(Document { embeddings, ..document }).await?;
+        }
+        store.flush().await?;
+        let embed = started.elapsed() / chunks.max(1) as u32;
+
+        let mut scores = Vec::with_capacity(queries.len());
+        let started = Instant::now();
+        for query in queries {
+            let vector = embedder.embed_query(&query.query).await?;
+            let hits = store.search(vector, sample.top_k, &Filter::default()).await?;
+            scores.push(Scores::of(query, &hits));
+        }
+        let query = started.elapsed() / queries.len().max(1) as u32;
+
+        Ok(Trial { label, chunks, scores: Scores::mean(&scores), embed, query })
+    }.await;
+
+    if !sample.keep {
+        if let Err(e) = store.drop_collection().await {
+            warn!("Failed to drop the experiment's collection: {e:#}");
+        }
+    }
+
+    result
+}
+
+/// Highest recall, ties broken by MRR and then by which was tried first
+fn best(trials: &[Trial]) -> Option<&Trial> {
+    trials.iter().rev().max_by(|a, b| {
+        a.scores.recall.total_cmp(&b.scores.recall).then(a.scores.mrr.total_cmp(&b.scores.mrr))
+    })
+}
+
+fn report(tried: &str, trials: &[Trial], sample: &ExperimentSample) -> Result<()> {
+    if sample.output != Output::Text {
+        let mut rows = Rows::new(&["trial", "chunks", "top_k", "recall", "mrr", "embed_ms_per_chunk", "query_ms"]);
+        for trial in trials {
+            rows.push(vec![
+                json!(trial.label),
+                json!(trial.chunks),
+                json!(sample.top_k),
+                json!(trial.scores.recall),
+                json!(trial.scores.mrr),
+                json!(trial.embed.as_secs_f64() * 1000.0),
+                json!(trial.query.as_secs_f64() * 1000.0),
+            ]);
+        }
+        return rows.print(sample.output);
+    }
+
+    let recall = format!("recall@{}", sample.top_k);
+    println!("{tried:<20}  {:>8}  {recall:>10}  {:>6}  {:>14}  {:>10}", "chunks", "mrr", "embed ms/chunk", "query ms");
+    for trial in trials {
+        println!(
+            "{:<20}  {:>8}  {:>10.3}  {:>6.3}  {:>14.1}  {:>10.1}",
+            trial.label,
+            trial.chunks,
+            trial.scores.recall,
+            trial.scores.mrr,
+            trial.embed.as_secs_f64() * 1000.0,
+            trial.query.as_secs_f64() * 1000.0,
+        );
+    }
+
+    Ok(())
+}
+
+/// Prints the pick below the table, or logs it so stdout holds only the rows of the other formats
+fn conclude(best: &str, output: Output) {
+    match output {
+        Output::Text => println!("{best}"),
+        _ => info!("{best}"),
+    }
+}
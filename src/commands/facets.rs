@@ -0,0 +1,78 @@
+use std::collections::BTreeMap;
+
+use anyhow::{bail, Context, Result};
+use crate::cli::FacetsArgs;
+use crate::clients::vector_store::Filter;
+use crate::clients::vector_store::qdrant::Qlient;
+use crate::config::{Config, StoreKind};
+use crate::outcome::Exit;
+
+/// Upper bound on distinct `source` values pulled before grouping them by prefix
+const SOURCE_SCAN_LIMIT: u64 = 100_000;
+
+pub async fn run(args: FacetsArgs, config: &Config) -> Result<()> {
+    if config.store != StoreKind::Qdrant {
+        bail!("facets only reads Qdrant collections");
+    }
+    if config.qdrant.partitions > 1 && args.collection.is_none() {
+        bail!("qdrant.partitions spreads points over several collections, pick one with --collection");
+    }
+    let mut qdrant = config.qdrant.clone();
+    if let Some(url) = args.qdrant {
+        qdrant.url = url;
+    }
+    if let Some(collection) = args.collection {
+        qdrant.collection = collection;
+    }
+    let client = Qlient::from_config(&qdrant).context(Exit::ConfigError)?;
+    // Facets only hold the values asked for, so shares are of every point in the collection
+    let total = client.count(&Filter::default()).await?;
+
+    for key in ["language", "content_type"] {
+        let counts = client.facet(key, args.limit).await?;
+        print_distribution(key, counts, total, args.limit as usize);
+    }
+
+    let sources = client.facet("source", SOURCE_SCAN_LIMIT).await?;
+    let prefixes = group_by_prefix(sources, args.depth);
+    print_distribution("source prefix", prefixes, total, args.limit as usize);
+
+    Ok(())
+}
+
+/// Folds exact `source` counts into counts per leading `depth` path segments
+fn group_by_prefix(sources: BTreeMap<String, u64>, depth: usize) -> BTreeMap<String, u64> {
+    let mut prefixes = BTreeMap::new();
+
+    for (source, count) in sources {
+        let prefix = source
+            .split_inclusive('/')
+            .take(depth.max(1))
+            .collect::<String>();
+        *prefixes.entry(prefix).or_insert(0) += count;
+    }
+
+    prefixes
+}
+
+/// The `limit` most common values as shares of all `total` points, the points holding
+/// none of them counted together on a last line
+fn print_distribution(field: &str, counts: BTreeMap<String, u64>, total: u64, limit: usize) {
+    let mut sorted = counts.into_iter().collect::<Vec<_>>();
+    sorted.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    sorted.truncate(limit);
+    let listed: u64 = sorted.iter().map(|(_, count)| count).sum();
+
+    println!("{field} ({total} points)");
+    for (value, count) in sorted {
+        println!("  {count:>8}  {:>5.1}%  {value}", share(count, total));
+    }
+    let rest = total.saturating_sub(listed);
+    if rest > 0 {
+        println!("  {rest:>8}  {:>5.1}%  (other or missing)", share(rest, total));
+    }
+}
+
+fn share(count: u64, total: u64) -> f64 {
+    if total == 0 { 0.0 } else { count as f64 * 100.0 / total as f64 }
+}
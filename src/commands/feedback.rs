@@ -0,0 +1,21 @@
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use tracing::info;
+use crate::cli::FeedbackArgs;
+use crate::config::Config;
+use crate::feedback::{Feedback, FeedbackLog};
+use crate::outcome::Exit;
+
+/// Records a vote on a search result in the config's feedback file
+pub async fn run(args: FeedbackArgs, config: &Config) -> Result<()> {
+    if args.query.trim().is_empty() || args.point_id.trim().is_empty() {
+        bail!("Feedback needs a query and a point id");
+    }
+
+    let feedback = Feedback::new(args.query, args.point_id, args.vote, None);
+    FeedbackLog::new(&config.feedback.path).record(&feedback).await.context(Exit::ConfigError)?;
+    let vote = feedback.vote.to_possible_value().expect("every vote has a name");
+    info!("Recorded a thumbs {} for {} in {}", vote.get_name(), feedback.point_id, config.feedback.path.display());
+
+    Ok(())
+}
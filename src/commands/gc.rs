@@ -0,0 +1,212 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{BufRead, IsTerminal, Write};
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use tracing::info;
+use crate::cli::GcArgs;
+use crate::clients::vector_store::{Store, VectorStore};
+use crate::config::{Config, Source};
+use crate::loaders::Kind;
+use crate::outcome::Exit;
+
+/// Dead sources listed by name before the rest are only counted
+const LISTED: usize = 20;
+
+/// Finds the points of sources that are gone and deletes them once confirmed
+pub async fn run(args: GcArgs, config: &Config) -> Result<()> {
+    let manifest = match &args.manifest {
+        Some(path) => Some(read_manifest(path).await.context(Exit::ConfigError)?),
+        None => None,
+    };
+    let store = Store::from_config(config).context(Exit::ConfigError)?;
+
+    let mut dead: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    // Each source is looked up once, however many chunks it has
+    let mut live: HashMap<String, bool> = HashMap::new();
+    let mut scanned = 0u64;
+    let mut cursor = None;
+    loop {
+        let (points, next) = store.scan(cursor, args.batch_size.max(1)).await.context(Exit::BackendUnavailable)?;
+        for point in points {
+            scanned += 1;
+            let Some(source) = point.payload.get("source").and_then(|source| source.as_str()) else {
+                continue;
+            };
+            let alive = match live.get(source) {
+                Some(alive) => *alive,
+                None => {
+                    let alive = is_live(source, manifest.as_ref(), &config.sources, &args.root).await;
+                    live.insert(source.to_string(), alive);
+                    alive
+                }
+            };
+            if !alive {
+                dead.entry(source.to_string()).or_default().push(point.id);
+            }
+        }
+        match next {
+            Some(next) => cursor = Some(next),
+            None => break,
+        }
+    }
+
+    let points: usize = dead.values().map(Vec::len).sum();
+    if points == 0 {
+        info!("All {scanned} points belong to live sources");
+        return Ok(());
+    }
+    for (source, ids) in dead.iter().take(LISTED) {
+        println!("{:>6}  {source}", ids.len());
+    }
+    if dead.len() > LISTED {
+        println!("   ... and {} more sources", dead.len() - LISTED);
+    }
+    println!("{points} of {scanned} points belong to {} sources that are gone", dead.len());
+
+    if args.dry_run {
+        return Ok(());
+    }
+    if !args.yes && !confirm(points).context(Exit::ConfigError)? {
+        info!("Nothing deleted");
+        return Ok(());
+    }
+
+    let ids: Vec<String> = dead.into_values().flatten().collect();
+    for batch in ids.chunks(args.batch_size.max(1) as usize) {
+        store.delete(batch.to_vec()).await.context(Exit::BackendUnavailable)?;
+    }
+    info!("Deleted {points} points");
+
+    Ok(())
+}
+
+/// Sources in the manifest, one per line
+async fn read_manifest(path: &Path) -> Result<HashSet<String>> {
+    let raw = tokio::fs::read_to_string(path).await
+        .with_context(|| format!("Failed to read the manifest {}", path.display()))?;
+
+    Ok(raw.lines().map(str::trim).filter(|line| !line.is_empty()).map(str::to_string).collect())
+}
+
+/// Whether `source` is still around; the part after a `#`, like a row or section number,
+/// doesn't count, and URLs are only checked against a manifest
+///
+/// Only what file loaders read is checked on disk: documents of a `[sources.<name>]`, named
+/// `<name>/<key>`, are always live, and an archive entry lives as long as its archive. Sources
+/// named by a `source` column or field can't be told apart from files, so need `--manifest`.
+async fn is_live(source: &str, manifest: Option<&HashSet<String>>, sources: &BTreeMap<String, Source>, root: &Path) -> bool {
+    let file = source.split_once('#').map_or(source, |(file, _)| file);
+    if let Some(manifest) = manifest {
+        return manifest.contains(source) || manifest.contains(file);
+    }
+    if file.contains("://") {
+        return true;
+    }
+    let first = file.split('/').next().unwrap_or(file);
+    if sources.contains_key(source) || sources.contains_key(first) {
+        return true;
+    }
+
+    let path = Path::new(file);
+    let file = path.ancestors()
+        .find(|ancestor| Kind::of(ancestor).is_some_and(Kind::is_archive))
+        .unwrap_or(path);
+    tokio::fs::try_exists(root.join(file)).await.unwrap_or(true)
+}
+
+/// Asks on the terminal before deleting; without one, `--yes` has to be given
+fn confirm(points: usize) -> Result<bool> {
+    if !std::io::stdin().is_terminal() {
+        return Err(anyhow!("Not deleting {points} points without a terminal to confirm on, pass --yes"));
+    }
+
+    print!("Delete {points} points? [y/N] ");
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().lock().read_line(&mut answer)?;
+
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    /// A directory holding `notes.md` and `docs.zip`
+    fn root(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("rag-rs-gc-{name}-{}", std::process::id()));
+        _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("notes.md"), "notes").unwrap();
+        std::fs::write(root.join("docs.zip"), "zip").unwrap();
+
+        root
+    }
+
+    fn sources() -> BTreeMap<String, Source> {
+        toml::from_str(r#"
+            [orders]
+            type = "sql"
+            query = "SELECT id, body FROM orders"
+            content = "body"
+            key = "id"
+
+            [tickets]
+            type = "mongodb"
+            database = "support"
+            collection = "tickets"
+            content = "text"
+
+            [events]
+            type = "redis"
+            stream = "events"
+            group = "rag"
+            content = "message"
+        "#).unwrap()
+    }
+
+    #[tokio::test]
+    async fn files_are_live_while_they_exist() {
+        let root = root("files");
+
+        assert!(is_live("notes.md", None, &sources(), &root).await);
+        assert!(is_live("notes.md#2", None, &sources(), &root).await);
+        assert!(!is_live("gone.md", None, &sources(), &root).await);
+        _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[tokio::test]
+    async fn documents_of_configured_sources_are_always_live() {
+        let root = root("sources");
+
+        assert!(is_live("orders/1042", None, &sources(), &root).await);
+        assert!(is_live("tickets/65f1c0ffee0000000000beef", None, &sources(), &root).await);
+        assert!(is_live("events/1700000000000-0", None, &sources(), &root).await);
+        assert!(is_live("orders", None, &sources(), &root).await);
+        assert!(!is_live("invoices/7", None, &sources(), &root).await);
+        _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[tokio::test]
+    async fn archive_entries_live_as_long_as_their_archive() {
+        let root = root("archives");
+
+        assert!(is_live("docs.zip/guide/intro.md", None, &sources(), &root).await);
+        assert!(!is_live("old.tar.gz/intro.md", None, &sources(), &root).await);
+        _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[tokio::test]
+    async fn a_manifest_decides_alone() {
+        let root = root("manifest");
+        let manifest = HashSet::from(["orders/1".to_string(), "gone.md".to_string()]);
+
+        assert!(is_live("gone.md#3", Some(&manifest), &sources(), &root).await);
+        assert!(!is_live("notes.md", Some(&manifest), &sources(), &root).await);
+        assert!(!is_live("orders/2", Some(&manifest), &sources(), &root).await);
+        _ = std::fs::remove_dir_all(&root);
+    }
+}
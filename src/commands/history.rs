@@ -0,0 +1,41 @@
+use anyhow::{bail, Result};
+use crate::cli::{HistoryAction, HistoryArgs};
+use crate::config::Config;
+use crate::history::History;
+
+pub async fn run(args: HistoryArgs, config: &Config) -> Result<()> {
+    let history = History::new(&config.history);
+
+    match args.action {
+        Some(HistoryAction::Show { id }) => {
+            let Some(record) = history.get(id).await? else {
+                bail!("No run with id {id} in {}", config.history.display());
+            };
+            println!("{}", serde_json::to_string_pretty(&record)?);
+        }
+        None => {
+            let records = history.list().await?;
+            let skip = records.len().saturating_sub(args.limit);
+
+            println!(
+                "{:>5}  {:<20}  {:>8}  {:<19}  {:>9}  {:>9}  {:>7}  config",
+                "id", "started", "seconds", "exit", "documents", "stored", "errors"
+            );
+            for record in records.into_iter().skip(skip) {
+                println!(
+                    "{:>5}  {:<20}  {:>8}  {:<19}  {:>9}  {:>9}  {:>6.2}%  {}",
+                    record.id,
+                    record.started_at.format("%Y-%m-%d %H:%M:%S"),
+                    (record.finished_at - record.started_at).num_seconds(),
+                    record.exit.to_string(),
+                    record.summary.documents,
+                    record.summary.stored,
+                    record.error_rate * 100.0,
+                    &record.config_hash[..record.config_hash.len().min(12)],
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,928 @@
+use std::collections::{HashMap, VecDeque};
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::Utc;
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use serde::Serialize;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use tokio::runtime::Runtime;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::{mpsc, Notify, OwnedSemaphorePermit, Semaphore};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+use crate::canary;
+use crate::chunking::Chunker;
+use crate::cli::{IngestArgs, Order};
+use crate::clients::Document;
+use crate::clients::llm::llama_cpp::{EmbedTimeout, LlamaCpp, Status};
+use crate::clients::vector_store::{self, Store, VectorStore};
+use crate::clients::vector_store::qdrant::Qlient;
+use crate::config::{Config, QdrantConfig, Source, StoreKind};
+use crate::control::{Control, Stages, Throttle};
+use crate::dead_letter::{Cause, DeadLetter};
+use crate::events::{Events, PipelineEvent};
+use crate::extraction::Extractor;
+use crate::loaders::{self, Options, Selection};
+use crate::multivector;
+use crate::history::{History, RunRecord};
+use crate::profiling::{self, Snapshot, Stage};
+use crate::provenance;
+use crate::sink::{Archive, Rejected, Sink, Spill, Tee};
+use crate::sources::{self, Changes};
+use crate::seed;
+use crate::outcome::{Exit, Report, RunSummary, SkipReason, SkippedFile};
+
+pub async fn run(args: IngestArgs, config: &Config, control: &Control) -> Result<()> {
+    vector_store::ensure_writable("ingest")?;
+    if args.watch {
+        let source = args.source.as_ref().and_then(|name| config.sources.get(name));
+        if !source.is_some_and(Source::is_watchable) {
+            return Err(anyhow!("--watch needs a --source that reports changes, like a MongoDB or Redis one").context(Exit::ConfigError));
+        }
+    }
+
+    // Streams have no backlog to load, everything arrives through the watch
+    if !args.source.as_ref().and_then(|name| config.sources.get(name)).is_some_and(Source::is_stream) {
+        run_once(&args, config, control, None).await?;
+    }
+    if args.wait_for_index {
+        wait_for_index(&args, config, control).await?;
+    }
+    if args.watch {
+        watch(&args, config, control).await?;
+    }
+
+    Ok(())
+}
+
+/// Waits until every collection the run wrote to is indexed, within `--index-timeout-secs`
+async fn wait_for_index(args: &IngestArgs, config: &Config, control: &Control) -> Result<()> {
+    if config.store != StoreKind::Qdrant {
+        info!("The {:?} store doesn't index in the background, nothing to wait for", config.store);
+        return Ok(());
+    }
+
+    let started = Instant::now();
+    let deadline = started + Duration::from_secs(args.index_timeout_secs);
+    for collection in config.qdrant.collections() {
+        let qdrant = Qlient::from_config(&QdrantConfig { collection, ..config.qdrant.clone() }).context(Exit::ConfigError)?;
+        qdrant.wait_for_index(deadline, &control.shutdown).await.context(Exit::BackendUnavailable)?;
+    }
+    info!("Indexed after waiting {:.1}s", started.elapsed().as_secs_f64());
+
+    Ok(())
+}
+
+/// Keeps ingesting batches of the source's changes until shutdown
+async fn watch(args: &IngestArgs, config: &Config, control: &Control) -> Result<()> {
+    let name = args.source.clone().unwrap_or_default();
+    let source = config.sources[&name].clone();
+    let (tx, mut rx) = mpsc::channel(1);
+    let watcher = tokio::spawn(sources::watch(name, source, control.shutdown.clone(), tx));
+
+    while let Some(Changes { documents, stored }) = rx.recv().await {
+        info!("Ingesting {} changed documents", documents.len());
+        // Each batch is a run of its own in the history, and one failing doesn't end the watch
+        let result = run_once(args, config, control, Some(documents.into())).await;
+        if let Err(e) = &result {
+            error!("Ingesting changes failed ({}): {e:?}", Exit::from_error(e));
+        }
+        if let Some(stored) = stored {
+            _ = stored.send(result.is_ok());
+        }
+        if control.shutdown.is_cancelled() {
+            break;
+        }
+    }
+
+    drop(rx);
+    watcher.await?
+}
+
+/// One run over `documents`, or over whatever `args` points at when there are none
+async fn run_once(args: &IngestArgs, config: &Config, control: &Control, documents: Option<VecDeque<Document>>) -> Result<()> {
+    let started_at = Utc::now();
+    let mut summary = RunSummary::default();
+    let result = ingest(args, config, control, &mut summary, documents).await;
+    let exit = match &result {
+        Ok(_) => Exit::Success,
+        Err(e) => Exit::from_error(e),
+    };
+
+    if let Some(path) = args.outcome.as_deref() {
+        Report::new(exit, &summary).write(path).await?;
+    }
+
+    let mut record = RunRecord {
+        id: 0,
+        started_at,
+        finished_at: Utc::now(),
+        path: args.input(),
+        exit,
+        error_rate: summary.error_rate(),
+        summary,
+        config_hash: config.hash.clone(),
+    };
+    match History::new(&config.history).append(record.clone()).await {
+        Ok(id) => record.id = id,
+        Err(e) => warn!("Failed to record run history: {e:?}"),
+    }
+    control.events.publish(PipelineEvent::RunFinished(Box::new(record)));
+
+    result
+}
+
+/// Runs the pipeline, filling `summary` as far as it gets
+async fn ingest(
+    args: &IngestArgs,
+    config: &Config,
+    control: &Control,
+    summary: &mut RunSummary,
+    documents: Option<VecDeque<Document>>,
+) -> Result<()> {
+    let (tx, rx) = mpsc::unbounded_channel::<Embedded>();
+    let llama = LlamaCpp::from_config(&config.llama).context(Exit::ConfigError)?;
+    #[cfg(feature = "chaos")]
+    let llama = llama.with_faults(crate::chaos::Faults::new(&config.chaos));
+    if args.max_cost.is_some() && config.llama.price_per_million_tokens.is_none() {
+        return Err(anyhow!("--max-cost needs llama.price_per_million_tokens").context(Exit::ConfigError));
+    }
+    // Stops every stage on shutdown or when one of them can't go on
+    let cancel = control.shutdown.child_token();
+    let resources = args.self_profile.then(Snapshot::take);
+    control.proceed().await;
+    let streamed = documents.is_none() && streams(args, config);
+    let documents = match documents {
+        Some(documents) => documents,
+        None if streamed => VecDeque::new(),
+        None => profiling::instrument(Stage::Read, read_documents(args, config, summary, &cancel)).await?,
+    };
+    if cancel.is_cancelled() {
+        bail!("Run cancelled while reading {}", args.input().display());
+    }
+
+    if !streamed {
+        info!(
+            "Read {} documents from storage, skipped {} malformed lines and {} files",
+            documents.len(), summary.skipped, summary.skipped_files.len()
+        );
+        summary.documents = documents.len() as u64;
+        for document in &documents {
+            control.events.publish(PipelineEvent::DocumentLoaded { source: document.metadata.source.clone() });
+        }
+    }
+
+    if let Some(path) = args.emit_chunks.as_deref() {
+        write_chunks(path, &documents).await?;
+        info!("Wrote {} chunks to {}", documents.len(), path.display());
+    }
+
+    tokio::select! {
+        ready = await_llama(&llama) => ready.context(Exit::BackendUnavailable)?,
+        _ = cancel.cancelled() => bail!("Run cancelled while waiting for llama.cpp"),
+    }
+
+    let store = Store::from_config(config).context(Exit::ConfigError)?;
+    store.ensure_collection().await.context(Exit::BackendUnavailable)?;
+
+    let stages = control.stages.clone();
+    stages.load.store(documents.len() as u64, Ordering::Relaxed);
+    let reporter = stages.clone().report(config.pipeline.report_interval_secs);
+    let in_flight = Arc::new(Semaphore::new(max_in_flight(config)));
+
+    // A streamed run's total is only known once reading is done
+    let qdrant_handle = vector_upsert_loop(config, documents.len() as u64, control, cancel.clone(), rx);
+
+    let mut queue: VecDeque<(Document, u32)> = documents.into_iter().map(|d| (d, 0)).collect();
+    let (reader, mut incoming) = mpsc::unbounded_channel();
+    let mut read = 0;
+    let mut over_budget = None;
+
+    let reading = async {
+        if !streamed {
+            return Ok(());
+        }
+        let result = profiling::instrument(
+            Stage::Read,
+            stream_documents(args, config, summary, control, &cancel, reader, &mut read),
+        ).await;
+        if result.is_err() {
+            // The embedder stops too, there is no telling how much of the input is left
+            cancel.cancel();
+        }
+        result
+    };
+
+    let embedding = async {
+        loop {
+            while let Ok(document) = incoming.try_recv() {
+                queue.push_back((document, 0));
+            }
+            let (mut document, attempts) = match queue.pop_front() {
+                Some(next) => next,
+                None => tokio::select! {
+                    received = incoming.recv() => match received {
+                        Some(document) => (document, 0),
+                        None => break,
+                    },
+                    _ = cancel.cancelled() => break,
+                },
+            };
+            if control.is_paused() {
+                info!("Paused before embedding, send SIGUSR2 or POST /admin/resume to resume");
+                control.proceed().await;
+            }
+            let permit = tokio::select! {
+                permit = in_flight.clone().acquire_owned() => permit?,
+                _ = cancel.cancelled() => {
+                    queue.push_front((document, attempts));
+                    break;
+                }
+            };
+            stages.load.fetch_sub(1, Ordering::Relaxed);
+            stages.embed.fetch_add(1, Ordering::Relaxed);
+            // Each request is held to `llama.embed_timeout_secs` on its own, waits for a busy
+            // server between them aren't
+            let embedding = profiling::instrument(Stage::Embed, multivector::embed(&llama, config, &document.page_content));
+            let result = tokio::select! {
+                result = embedding => result,
+                _ = cancel.cancelled() => {
+                    stages.embed.fetch_sub(1, Ordering::Relaxed);
+                    stages.load.fetch_add(1, Ordering::Relaxed);
+                    queue.push_front((document, attempts));
+                    break;
+                }
+            };
+            stages.embed.fetch_sub(1, Ordering::Relaxed);
+            if let (Some(max), Some(cost)) = (args.max_cost, llama.cost()) {
+                if cost > max {
+                    // The document that went over is still stored, it's paid for
+                    over_budget = Some((cost, max));
+                    cancel.cancel();
+                }
+            }
+
+            // A timed out document goes to the back of the queue so it can't stall the rest
+            if let Err(e) = &result {
+                if e.is::<EmbedTimeout>() && attempts + 1 < config.llama.embed_attempts {
+                    warn!("{e} for {}, requeueing (attempt {})", document.metadata.source, attempts + 1);
+                    stages.load.fetch_add(1, Ordering::Relaxed);
+                    queue.push_back((document, attempts + 1));
+                    continue;
+                }
+            }
+
+            if let Ok(vector) = &result {
+                if !vector.is_empty() {
+                    provenance::stamp(&mut document, &config.llama, multivector::head(config, vector));
+                }
+            }
+            stages.upsert.fetch_add(1, Ordering::Relaxed);
+            if tx.send(Embedded { document, result, permit }).is_err() {
+                stages.upsert.fetch_sub(1, Ordering::Relaxed);
+                break;
+            }
+        }
+
+        Ok::<_, anyhow::Error>(())
+    };
+
+    let (reading, embedding) = tokio::join!(reading, embedding);
+    embedding?;
+
+    // Read before the upsert loop finishes, which cancels the token on its way out
+    let cancelled = cancel.is_cancelled();
+    let mut left = queue.len();
+    while incoming.try_recv().is_ok() {
+        left += 1;
+    }
+    stages.load.fetch_sub(left as u64, Ordering::Relaxed);
+    drop(tx);
+
+    *summary = RunSummary {
+        skipped: summary.skipped,
+        skipped_files: std::mem::take(&mut summary.skipped_files),
+        tokens: llama.tokens_used(),
+        cost: llama.cost(),
+        ..qdrant_handle.join().map_err(|_| anyhow!("Upsert loop panicked"))?
+    };
+    if let Some(reporter) = reporter {
+        reporter.abort();
+    }
+    if streamed {
+        summary.documents = read;
+        info!(
+            "Read {read} documents while embedding, skipped {} malformed lines and {} files",
+            summary.skipped, summary.skipped_files.len()
+        );
+    }
+
+    info!(
+        "Stored {} of {} documents ({:.2}% errors)",
+        summary.stored, summary.documents, summary.error_rate() * 100.0
+    );
+    if let Some(cost) = summary.cost {
+        info!("Embedded {} tokens for {cost:.4}", summary.tokens);
+    }
+    if summary.spilled > 0 {
+        warn!(
+            "{} documents are waiting in {}, ingest it once {} takes writes again",
+            summary.spilled, config.qdrant.spill.path.display(), config.qdrant.collection,
+        );
+    }
+    if let Some(resources) = &resources {
+        resources.report();
+    }
+
+    reading?;
+    if let Some((cost, max)) = over_budget {
+        bail!("Stopped after spending {cost:.4} of --max-cost {max} with {left} documents left unembedded");
+    }
+    if cancelled {
+        bail!("Run cancelled with {left} documents left unembedded");
+    }
+
+    if let Some(rate) = args.fail_on_error_rate {
+        if summary.error_rate() > rate {
+            return Err(anyhow!(
+                "Error rate {:.4} exceeds --fail-on-error-rate {rate}", summary.error_rate()
+            ).context(Exit::PartialFailure));
+        }
+    }
+
+    if !config.canaries.is_empty() {
+        canary::check(&config.canaries, &llama, &store).await?;
+    }
+
+    Ok(())
+}
+
+/// The configured in-flight cap, raised to a full upsert batch so the buffer can always fill
+fn max_in_flight(config: &Config) -> usize {
+    let max = config.pipeline.max_in_flight.max(1);
+    if max < config.qdrant.buffer_size {
+        warn!(
+            "pipeline.max_in_flight {max} is below qdrant.buffer_size, using {}",
+            config.qdrant.buffer_size
+        );
+        return config.qdrant.buffer_size;
+    }
+
+    max
+}
+
+/// A document on its way from the embedder to the vector store
+struct Embedded {
+    document: Document,
+    result: Result<Vec<f32>>,
+    /// Held until the document's upsert has finished, one way or the other
+    permit: OwnedSemaphorePermit,
+}
+
+/// Waits for the Llama.cpp server to acknowledge a ready model
+pub(crate) async fn await_llama(llama: &LlamaCpp<'_>) -> Result<()> {
+    let mut dur = Duration::from_secs(7);
+
+    while llama.health_check()?.status != Status::Ok {
+        tokio::time::sleep(dur).await;
+        dur += Duration::from_millis(500)
+    }
+
+    Ok(())
+}
+
+/// Instantiates the event loop for handing embedded documents to the vector store, and to
+/// the `archive` file as well when one is configured
+///
+/// Documents that fail to embed, or whose batch fails to upsert, go to the dead letter file.
+fn vector_upsert_loop(
+    config: &Config,
+    total_expected: u64,
+    control: &Control,
+    cancel: CancellationToken,
+    rx: UnboundedReceiver<Embedded>,
+) -> JoinHandle<RunSummary> {
+    let stages = control.stages.clone();
+    let flush_requests = control.flush_requests();
+    let events = control.events.clone();
+    let progress = events.subscribe();
+    let config = config.clone();
+
+    std::thread::spawn(move || Runtime::new()
+        .expect("Something is very wrong")
+        .block_on(profiling::instrument(Stage::Upsert, async move {
+            // Whether the loop ends or panics, the embedder stops feeding it
+            let _stop = cancel.drop_guard();
+            let store = match Store::from_config(&config) {
+                Ok(store) => store,
+                Err(e) => {
+                    error!("Failed to open the vector store: {e:?}");
+                    return RunSummary { documents: total_expected, ..Default::default() };
+                }
+            };
+            let spill = (config.store == StoreKind::Qdrant && config.qdrant.spill.enabled).then(|| config.qdrant.spill.clone());
+            let store = Spill::new(store, spill);
+            #[cfg(feature = "chaos")]
+            let store = crate::chaos::Delayed::new(store, &config.chaos);
+            let dead_letter = DeadLetter::new(&config.dead_letter);
+            let done = CancellationToken::new();
+            let bars = tokio::spawn(show_progress(progress, total_expected, done.clone()));
+            let summary = match config.archive.clone() {
+                Some(path) => {
+                    let sink = Tee::new(store, Archive::new(path));
+                    drain(sink, &dead_letter, &stages, &events, &flush_requests, total_expected, rx).await
+                }
+                None => drain(store, &dead_letter, &stages, &events, &flush_requests, total_expected, rx).await,
+            };
+            done.cancel();
+            _ = bars.await;
+            summary
+        })))
+}
+
+/// Hands every embedded document from `rx` to `sink` until the embedder is done, publishing
+/// what becomes of them on `events`
+async fn drain(
+    mut sink: impl Sink,
+    dead_letter: &DeadLetter<'_>,
+    stages: &Stages,
+    events: &Events,
+    flush_requests: &Notify,
+    total_expected: u64,
+    mut rx: UnboundedReceiver<Embedded>,
+) -> RunSummary {
+    // Text of the documents in the sink's buffer, in case their upsert fails
+    let mut pending = Vec::new();
+    let mut permits = Vec::new();
+    // Documents count as stored once the sink acknowledged their batch, and all of them
+    // count as failed when it didn't
+    let settle = |permits: &mut Vec<OwnedSemaphorePermit>, summary: &mut RunSummary, stored: bool| {
+        let documents = permits.len() as u64;
+        stages.upsert.fetch_sub(documents, Ordering::Relaxed);
+        if stored {
+            summary.stored += documents;
+            stages.upserted.fetch_add(documents, Ordering::Relaxed);
+            events.publish(PipelineEvent::BatchUpserted { documents });
+        } else {
+            summary.failed += documents;
+        }
+        permits.clear();
+    };
+    let mut summary = RunSummary { documents: total_expected, ..Default::default() };
+
+    loop {
+        let Embedded { mut document, result, permit } = tokio::select! {
+            received = rx.recv() => match received {
+                Some(embedded) => embedded,
+                None => break,
+            },
+            _ = flush_requests.notified() => {
+                info!("Flushing {} buffered points on request", sink.buffered());
+                let flushed = sink.flush().await.is_ok();
+                if !flushed {
+                    bury(dead_letter, events, &pending, Cause::Upsert).await;
+                }
+                pending.clear();
+                settle(&mut permits, &mut summary, flushed);
+                continue;
+            }
+        };
+        permits.push(permit);
+        match result {
+            Ok(vector) if !vector.is_empty() => {
+                summary.embedded += 1;
+                stages.embedded.fetch_add(1, Ordering::Relaxed);
+                events.publish(PipelineEvent::ChunkEmbedded { source: document.metadata.source.clone(), dimensions: vector.len() });
+                pending.push(document.clone());
+                document.embeddings = vector;
+                match sink.push(document).await {
+                    Ok(_) => {
+                        if sink.buffered() == 0 {
+                            pending.clear();
+                            settle(&mut permits, &mut summary, true);
+                        }
+                    }
+                    Err(e) if e.is::<Rejected>() => {
+                        warn!("{e:#}");
+                        summary.failed += 1;
+                        bury(dead_letter, events, &pending.split_off(pending.len() - 1), Cause::Rejected).await;
+                        stages.upsert.fetch_sub(1, Ordering::Relaxed);
+                        permits.pop();
+                    }
+                    Err(_) => {
+                        bury(dead_letter, events, &pending, Cause::Upsert).await;
+                        pending.clear();
+                        settle(&mut permits, &mut summary, false);
+                    }
+                }
+            },
+            Ok(_) => {
+                summary.empty += 1;
+                bury(dead_letter, events, &[document], Cause::Empty).await;
+                stages.upsert.fetch_sub(1, Ordering::Relaxed);
+                permits.pop();
+            },
+            Err(e) => {
+                summary.failed += 1;
+                let cause = if e.is::<EmbedTimeout>() {
+                    summary.timed_out += 1;
+                    Cause::Timeout
+                } else {
+                    Cause::Embed
+                };
+                bury(dead_letter, events, &[document], cause).await;
+                stages.upsert.fetch_sub(1, Ordering::Relaxed);
+                permits.pop();
+            }
+        }
+    }
+
+    let flushed = sink.flush().await.is_ok();
+    if !flushed {
+        bury(dead_letter, events, &pending, Cause::Upsert).await;
+    }
+    settle(&mut permits, &mut summary, flushed);
+    summary.spilled = sink.spilled();
+
+    summary
+}
+
+/// Progress bars following the run's events until `done` is cancelled
+async fn show_progress(mut events: broadcast::Receiver<PipelineEvent>, total_expected: u64, done: CancellationToken) {
+    let bars = MultiProgress::new();
+    let bar = |template: &str| bars.add(progress_bar(total_expected, Some(template.to_string())).unwrap());
+    let processed = bar("{pos} processed");
+    let errors = bar("{pos} failures");
+    let embeddings = bar("{pos} embeddings generated");
+    let stored = bar("{pos} embeddings stored");
+
+    loop {
+        let event = tokio::select! {
+            event = events.recv() => event,
+            _ = done.cancelled() => break,
+        };
+        match event {
+            Ok(PipelineEvent::ChunkEmbedded { .. }) => embeddings.inc(1),
+            Ok(PipelineEvent::BatchUpserted { documents }) => {
+                stored.inc(documents);
+                processed.inc(documents);
+            }
+            Ok(PipelineEvent::Failure { documents, .. }) => {
+                errors.inc(documents);
+                processed.inc(documents);
+            }
+            // Only seen while a streamed run is still reading, which is what grows its total
+            Ok(PipelineEvent::DocumentLoaded { .. }) => {
+                for bar in [&processed, &errors, &embeddings, &stored] {
+                    bar.inc_length(1);
+                }
+            }
+            Ok(PipelineEvent::RunFinished(_)) | Err(RecvError::Lagged(_)) => {}
+            Err(RecvError::Closed) => break,
+        }
+    }
+
+    _ = bars.clear();
+}
+
+/// Publishes the failure and keeps the documents in the dead letter file
+async fn bury(dead_letter: &DeadLetter<'_>, events: &Events, documents: &[Document], cause: Cause) {
+    events.publish(PipelineEvent::Failure { cause, documents: documents.len() as u64 });
+    if let Err(e) = dead_letter.append(documents, cause).await {
+        warn!("Failed to dead-letter {} documents: {e:?}", documents.len());
+    }
+}
+
+/// Loads every input file under `args.path`, or the `--source`, into a VecDeque
+async fn read_documents(
+    args: &IngestArgs,
+    config: &Config,
+    summary: &mut RunSummary,
+    cancel: &CancellationToken,
+) -> Result<VecDeque<Document>> {
+    let mut documents = VecDeque::new();
+    load_inputs(args, config, summary, cancel, async |loaded| documents.extend(loaded)).await?;
+    order(&mut documents, args.order, args.shuffle_seed);
+
+    Ok(documents)
+}
+
+/// Whether documents are embedded while the input is still being read, as they are with a
+/// `pipeline.read_ahead` unless the whole input has to be known first
+fn streams(args: &IngestArgs, config: &Config) -> bool {
+    if config.pipeline.read_ahead == 0 {
+        return false;
+    }
+    if args.order != Order::Original || args.emit_chunks.is_some() {
+        info!("Reading every document before embedding, --order and --emit-chunks need all of them");
+        return false;
+    }
+
+    true
+}
+
+/// Loads the input like `read_documents`, sending each document on to the embedder as soon as
+/// it is read and reading slower the more of them wait to be embedded
+async fn stream_documents(
+    args: &IngestArgs,
+    config: &Config,
+    summary: &mut RunSummary,
+    control: &Control,
+    cancel: &CancellationToken,
+    tx: UnboundedSender<Document>,
+    read: &mut u64,
+) -> Result<()> {
+    let throttle = Throttle::new(control.stages.clone(), config.pipeline.read_ahead);
+
+    load_inputs(args, config, summary, cancel, async |loaded| {
+        for document in loaded {
+            control.stages.load.fetch_add(1, Ordering::Relaxed);
+            control.events.publish(PipelineEvent::DocumentLoaded { source: document.metadata.source.clone() });
+            if tx.send(document).is_err() {
+                return;
+            }
+            *read += 1;
+            throttle.pace(cancel).await;
+        }
+    }).await
+}
+
+/// Loads every input file under `args.path`, or the `--source`, handing the documents of each
+/// to `each` split to `pipeline.chunk_size`
+///
+/// With `--sample` each document is kept with that probability; `--limit` stops loading once
+/// enough are kept. Files that take longer than `pipeline.file_timeout_secs` are skipped.
+async fn load_inputs(
+    args: &IngestArgs,
+    config: &Config,
+    summary: &mut RunSummary,
+    cancel: &CancellationToken,
+    mut each: impl AsyncFnMut(Vec<Document>),
+) -> Result<()> {
+    let mut selection = Selection::new(args.sample, args.limit);
+    let options = Options { format: args.format, strict: args.strict, cancel, loaders: &config.loaders };
+    let budget = config.pipeline.file_timeout();
+    let chunker = Chunker::from_config(config).context(Exit::ConfigError)?;
+    let extractor = Extractor::from_config(config).context(Exit::ConfigError)?;
+    // Before chunking, so the model sees whole documents and every chunk gets the same answer
+    let prepare = async |documents: Vec<Document>| {
+        let documents = match &extractor {
+            Some(extractor) => extractor.extract(documents).await,
+            None => documents,
+        };
+        chunker.rechunk(documents).await
+    };
+
+    if let Some(name) = &args.source {
+        let source = config.sources.get(name)
+            .ok_or_else(|| anyhow!("No [sources.{name}] in the config").context(Exit::ConfigError))?;
+        let loaded = sources::load(name, source, &options, &mut selection).await?;
+        summary.skipped += loaded.skipped;
+        each(prepare(loaded.documents).await).await;
+        return Ok(());
+    }
+
+    for file in loaders::discover(&args.path)? {
+        if cancel.is_cancelled() || selection.is_full() {
+            break;
+        }
+
+        let kept = selection.kept();
+        let load = loaders::load_file(&file, &options, &mut selection);
+        let result = match budget {
+            Some(budget) => match tokio::time::timeout(budget, load).await {
+                Ok(result) => result,
+                Err(_) => {
+                    warn!("Skipping {}, loading took longer than {budget:?}", file.display());
+                    selection.rewind(kept);
+                    summary.skipped_files.push(SkippedFile { path: file, reason: SkipReason::Timeout });
+                    continue;
+                }
+            },
+            None => load.await,
+        };
+        let loaded = match result {
+            Ok(loaded) => loaded,
+            Err(e) if args.strict => return Err(e),
+            Err(e) => {
+                warn!("Skipping {}: {e:#}", file.display());
+                selection.rewind(kept);
+                summary.skipped_files.push(SkippedFile { path: file, reason: SkipReason::Unreadable });
+                continue;
+            }
+        };
+
+        summary.skipped += loaded.skipped;
+        each(prepare(loaded.documents).await).await;
+    }
+
+    Ok(())
+}
+
+fn order(documents: &mut VecDeque<Document>, order: Order, seed: Option<u64>) {
+    match order {
+        Order::Original => {}
+        Order::Sorted => documents.make_contiguous().sort_by(|a, b| {
+            (&a.metadata.source, &a.page_content).cmp(&(&b.metadata.source, &b.page_content))
+        }),
+        Order::Shuffled => {
+            let seed = seed.unwrap_or_else(|| seed::rng("shuffle").gen());
+            info!("Shuffling documents with seed {seed}");
+            documents.make_contiguous().shuffle(&mut StdRng::seed_from_u64(seed));
+        }
+    }
+}
+
+/// A document as emitted by `--emit-chunks`, readable again as a native document
+#[derive(Serialize)]
+struct Chunk<'c> {
+    #[serde(flatten)]
+    document: &'c Document,
+    /// Position among the chunks sharing the document's source
+    chunk_index: u64,
+}
+
+/// Writes `documents` as JSONL in the order they will be embedded
+async fn write_chunks(path: &Path, documents: &VecDeque<Document>) -> Result<()> {
+    let mut indexes = HashMap::<&str, u64>::new();
+    let mut out = Vec::new();
+
+    for document in documents {
+        let index = indexes.entry(&document.metadata.source).or_default();
+        serde_json::to_writer(&mut out, &Chunk { document, chunk_index: *index })?;
+        out.push(b'\n');
+        *index += 1;
+    }
+
+    tokio::fs::write(path, out).await
+        .with_context(|| format!("Failed to write chunks to {}", path.display()))
+}
+
+/// Creates an indicatif prog bar via `style_template`
+fn progress_bar(len: u64, style_template: Option<String>) -> Result<ProgressBar> {
+    let template = style_template
+        .unwrap_or("ETA: {eta_precise}\nElapsed: {elapsed_precise}\n{per_sec} {wide_bar} {pos}/{len}".to_string());
+    let style = ProgressStyle::with_template(&template)?;
+
+    Ok(ProgressBar::new(len).with_style(style))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::control::Stages;
+    use crate::sink::tests::{chance, step, Scheduled};
+    use super::*;
+
+    const DOCUMENTS: usize = 12;
+
+    #[test]
+    fn flush_requests_racing_the_end_of_input_store_every_document_once() {
+        shuttle::check_random(|| shuttle::future::block_on(async {
+            let dead_letter_path = std::env::temp_dir().join(format!("rag-rs-drain-{}.jsonl", std::process::id()));
+            let dead_letter = DeadLetter::new(&dead_letter_path);
+            let (stages, events, flush_requests) = (Stages::default(), Events::default(), Notify::new());
+            let mut published = events.subscribe();
+            let permits = Arc::new(Semaphore::new(DOCUMENTS));
+            let (tx, rx) = mpsc::unbounded_channel();
+
+            let embedder = async {
+                for i in 0..DOCUMENTS {
+                    step().await;
+                    if chance(0.3) {
+                        flush_requests.notify_one();
+                    }
+                    let permit = permits.clone().acquire_owned().await.unwrap();
+                    stages.upsert.fetch_add(1, Ordering::Relaxed);
+                    let document = Document { page_content: i.to_string(), ..Document::default() };
+                    tx.send(Embedded { document, result: Ok(vec![1.0]), permit }).unwrap();
+                }
+                step().await;
+                if chance(0.5) {
+                    flush_requests.notify_one();
+                }
+                drop(tx);
+            };
+            let sink = Scheduled::default();
+            let ((), summary) = tokio::join!(embedder, drain(sink, &dead_letter, &stages, &events, &flush_requests, DOCUMENTS as u64, rx));
+
+            assert_eq!(summary.stored, DOCUMENTS as u64);
+            assert_eq!(summary.failed, 0);
+            assert_eq!(permits.available_permits(), DOCUMENTS);
+            assert_eq!(stages.upsert.load(Ordering::Relaxed), 0);
+            assert_eq!(stages.upserted.load(Ordering::Relaxed), DOCUMENTS as u64);
+            let mut upserted = 0;
+            while let Ok(event) = published.try_recv() {
+                if let PipelineEvent::BatchUpserted { documents } = event {
+                    upserted += documents;
+                }
+            }
+            assert_eq!(upserted, DOCUMENTS as u64);
+        }), 300);
+    }
+
+    /// Writes batches of three, failing any batch with a document reading "bad" in it and
+    /// turning down documents reading "rejected" on their own
+    #[derive(Default)]
+    struct Batches {
+        buffer: Vec<String>,
+        stored: Vec<String>,
+    }
+
+    impl Sink for Batches {
+        async fn push(&mut self, document: Document) -> Result<()> {
+            if document.page_content == "rejected" {
+                return Err(anyhow!("unstorable").context(Rejected));
+            }
+            self.buffer.push(document.page_content);
+            match self.buffer.len() < 3 {
+                true => Ok(()),
+                false => self.flush().await,
+            }
+        }
+
+        async fn flush(&mut self) -> Result<()> {
+            let batch = std::mem::take(&mut self.buffer);
+            if batch.iter().any(|text| text == "bad") {
+                bail!("batch refused");
+            }
+            self.stored.extend(batch);
+
+            Ok(())
+        }
+
+        fn buffered(&self) -> usize {
+            self.buffer.len()
+        }
+    }
+
+    #[tokio::test]
+    async fn every_document_of_a_failed_batch_counts_as_failed() {
+        let dead_letter_path = std::env::temp_dir().join(format!("rag-rs-drain-failed-{}.jsonl", std::process::id()));
+        _ = std::fs::remove_file(&dead_letter_path);
+        let dead_letter = DeadLetter::new(&dead_letter_path);
+        let (stages, events, flush_requests) = (Stages::default(), Events::default(), Notify::new());
+        let texts = ["0", "1", "2", "3", "bad", "5", "6"];
+        let permits = Arc::new(Semaphore::new(texts.len()));
+        let (tx, rx) = mpsc::unbounded_channel();
+        for text in texts {
+            let permit = permits.clone().acquire_owned().await.unwrap();
+            stages.upsert.fetch_add(1, Ordering::Relaxed);
+            let document = Document { page_content: text.to_string(), ..Document::default() };
+            tx.send(Embedded { document, result: Ok(vec![1.0]), permit }).unwrap();
+        }
+        drop(tx);
+
+        let summary = drain(Batches::default(), &dead_letter, &stages, &events, &flush_requests, texts.len() as u64, rx).await;
+
+        // 3, bad and 5 went down together, 6 was written by the last flush
+        assert_eq!(summary.stored, 4);
+        assert_eq!(summary.failed, 3);
+        assert!((summary.error_rate() - 3.0 / 7.0).abs() < f64::EPSILON);
+        assert_eq!(stages.upserted.load(Ordering::Relaxed), 4);
+        assert_eq!(stages.upsert.load(Ordering::Relaxed), 0);
+        assert_eq!(permits.available_permits(), texts.len());
+        let buried = std::fs::read_to_string(&dead_letter_path).unwrap();
+        assert_eq!(buried.lines().count(), 3);
+        _ = std::fs::remove_file(&dead_letter_path);
+    }
+
+    #[tokio::test]
+    async fn a_rejected_document_fails_alone() {
+        let dead_letter_path = std::env::temp_dir().join(format!("rag-rs-drain-rejected-{}.jsonl", std::process::id()));
+        _ = std::fs::remove_file(&dead_letter_path);
+        let dead_letter = DeadLetter::new(&dead_letter_path);
+        let (stages, events, flush_requests) = (Stages::default(), Events::default(), Notify::new());
+        let texts = ["0", "1", "rejected", "2", "3"];
+        let permits = Arc::new(Semaphore::new(texts.len()));
+        let (tx, rx) = mpsc::unbounded_channel();
+        for text in texts {
+            let permit = permits.clone().acquire_owned().await.unwrap();
+            stages.upsert.fetch_add(1, Ordering::Relaxed);
+            let document = Document { page_content: text.to_string(), ..Document::default() };
+            tx.send(Embedded { document, result: Ok(vec![1.0]), permit }).unwrap();
+        }
+        drop(tx);
+
+        let summary = drain(Batches::default(), &dead_letter, &stages, &events, &flush_requests, texts.len() as u64, rx).await;
+
+        // 0, 1 and 2 made the first batch around it, 3 the last flush
+        assert_eq!(summary.stored, 4);
+        assert_eq!(summary.failed, 1);
+        assert_eq!(stages.upserted.load(Ordering::Relaxed), 4);
+        assert_eq!(stages.upsert.load(Ordering::Relaxed), 0);
+        assert_eq!(permits.available_permits(), texts.len());
+        let buried = std::fs::read_to_string(&dead_letter_path).unwrap();
+        assert_eq!(buried.lines().count(), 1);
+        assert!(buried.contains(r#""page_content":"rejected""#) && buried.contains(r#""cause":"rejected""#), "{buried}");
+        _ = std::fs::remove_file(&dead_letter_path);
+    }
+}
@@ -0,0 +1,97 @@
+use anyhow::{anyhow, Context, Result};
+use rand::Rng;
+use crate::cli::InspectArgs;
+use crate::clients::vector_store::{Filter, Point, Store, VectorStore, CONTENT_FIELD};
+use crate::config::Config;
+use crate::outcome::Exit;
+use crate::seed;
+
+/// Characters of the stored text shown, the payload holds all of it
+const PREVIEW_CHARS: usize = 200;
+
+/// Prints points with what's needed to tell why they show up in results: the payload, how
+/// long the vector is and which points sit closest to it
+pub async fn run(args: InspectArgs, config: &Config) -> Result<()> {
+    let store = Store::from_config(config).context(Exit::ConfigError)?;
+    let ids = match args.id {
+        Some(id) => vec![id],
+        None => sample(&store, args.sample, args.batch_size.max(1), "inspect").await.context(Exit::BackendUnavailable)?,
+    };
+    if ids.is_empty() {
+        return Err(anyhow!("The store holds no points to inspect").context(Exit::ConfigError));
+    }
+
+    for (i, id) in ids.iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        let Some((point, vector)) = store.get(id).await.context(Exit::BackendUnavailable)? else {
+            return Err(anyhow!("No point with id {id}").context(Exit::ConfigError));
+        };
+        let neighbors = match vector.is_empty() {
+            true => vec![],
+            // One more, since the point is its own nearest neighbour
+            false => store.search(vector.clone(), args.neighbors + 1, &Filter::default()).await
+                .context(Exit::BackendUnavailable)?,
+        };
+
+        print(&point, &vector)?;
+        println!("neighbors:");
+        for hit in neighbors.iter().filter(|hit| hit.id != point.id).take(args.neighbors as usize) {
+            println!("  {:.4}  {}  {}", hit.score, hit.id, hit.metadata.source);
+        }
+    }
+
+    Ok(())
+}
+
+fn print(point: &Point, vector: &[f32]) -> Result<()> {
+    let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let mut payload = point.payload.clone();
+    let text = payload.remove(CONTENT_FIELD)
+        .and_then(|text| text.as_str().map(str::to_string))
+        .unwrap_or_default();
+    let mut preview: String = text.chars().take(PREVIEW_CHARS).collect();
+    if preview.len() < text.len() {
+        preview.push('…');
+    }
+
+    println!("id: {}", point.id);
+    println!("vector: {} dimensions, norm {norm:.4}", vector.len());
+    println!("text ({} chars): {preview:?}", text.chars().count());
+    println!("payload: {}", serde_json::to_string_pretty(&payload)?);
+
+    Ok(())
+}
+
+/// Ids of `size` points picked uniformly over one scan of the store, drawn from the seeded
+/// generator `stream`
+pub(crate) async fn sample(store: &impl VectorStore, size: usize, batch_size: u64, stream: &str) -> Result<Vec<String>> {
+    let mut rng = seed::rng(stream);
+    let mut picked = Vec::with_capacity(size);
+    let mut seen = 0usize;
+    let mut cursor = None;
+
+    loop {
+        let (points, next) = store.scan(cursor, batch_size).await?;
+        for point in points {
+            // Reservoir sampling: the n-th point replaces an earlier pick with chance size/n
+            match picked.len() < size {
+                true => picked.push(point.id),
+                false => {
+                    let slot = rng.gen_range(0..=seen);
+                    if slot < size {
+                        picked[slot] = point.id;
+                    }
+                }
+            }
+            seen += 1;
+        }
+        match next {
+            Some(next) => cursor = Some(next),
+            None => break,
+        }
+    }
+
+    Ok(picked)
+}
@@ -0,0 +1,19 @@
+use anyhow::{Context, Result};
+use clap::CommandFactory;
+use clap_mangen::Man;
+use tracing::info;
+use crate::cli::{Cli, ManArgs};
+
+/// Renders man pages from the CLI definition, so they never fall behind the commands
+pub fn run(args: &ManArgs) -> Result<()> {
+    let command = Cli::command();
+    let Some(dir) = &args.dir else {
+        return Ok(Man::new(command).render(&mut std::io::stdout())?);
+    };
+
+    std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    clap_mangen::generate_to(command, dir).with_context(|| format!("Failed to write man pages to {}", dir.display()))?;
+    info!("Wrote man pages to {}", dir.display());
+
+    Ok(())
+}
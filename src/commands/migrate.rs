@@ -0,0 +1,108 @@
+use std::collections::BTreeMap;
+
+use anyhow::{Context, Result};
+use indicatif::{ProgressBar, ProgressStyle};
+use tracing::{info, warn};
+use crate::cli::MigrateArgs;
+use crate::clients::vector_store::{Point, Store, VectorStore};
+use crate::config::Config;
+use crate::migrations::{self, SCHEMA_VERSION};
+use crate::outcome::Exit;
+
+/// Brings every stored payload up to the current schema version, rewriting each scanned
+/// batch before reading the next
+///
+/// Stores that scan by offset, like LanceDB, may move rewritten rows under the cursor, so
+/// after a pass that rewrote anything the store is scanned again, until a pass finds nothing
+/// left to migrate.
+pub async fn run(args: MigrateArgs, config: &Config) -> Result<()> {
+    let store = Store::from_config(config).context(Exit::ConfigError)?;
+    let batch_size = args.batch_size.max(1);
+
+    let scanning = ProgressBar::new_spinner()
+        .with_style(ProgressStyle::with_template("{spinner} Scanned {pos} points, {msg}")?);
+    // Points each migration applies to, by version
+    let mut applied: BTreeMap<u64, (&str, u64)> = BTreeMap::new();
+    let first = pass(&store, batch_size, !args.dry_run, &mut applied, &scanning, 0).await?;
+    let mut migrated = first.migrated;
+    let mut last = first.migrated;
+    while !args.dry_run && last > 0 {
+        scanning.set_position(0);
+        last = pass(&store, batch_size, true, &mut applied, &scanning, migrated).await?.migrated;
+        migrated += last;
+    }
+    scanning.finish_and_clear();
+    let scanned = first.scanned;
+
+    if first.newer > 0 {
+        warn!("{} points were written by a newer schema than version {SCHEMA_VERSION} and are left alone", first.newer);
+    }
+    if migrated == 0 {
+        info!("{} of {scanned} points are at schema version {SCHEMA_VERSION} already", scanned - first.newer);
+        return Ok(());
+    }
+    for (version, (description, points)) in &applied {
+        println!("{version:>3}  {description}: {points} points");
+    }
+    match args.dry_run {
+        true => println!("{migrated} of {scanned} points need migrating to schema version {SCHEMA_VERSION}"),
+        false => info!("Migrated {migrated} of {scanned} points to schema version {SCHEMA_VERSION}"),
+    }
+
+    Ok(())
+}
+
+/// What one scan over the store found
+struct Pass {
+    scanned: u64,
+    newer: u64,
+    migrated: u64,
+}
+
+/// Scans the store once, migrating the payloads of each batch and rewriting them when
+/// `write`; `before` counts the points earlier passes rewrote
+async fn pass(
+    store: &Store,
+    batch_size: u64,
+    write: bool,
+    applied: &mut BTreeMap<u64, (&'static str, u64)>,
+    scanning: &ProgressBar,
+    before: u64,
+) -> Result<Pass> {
+    let mut pass = Pass { scanned: 0, newer: 0, migrated: 0 };
+    let mut cursor = None;
+    loop {
+        let (points, next) = store.scan(cursor, batch_size).await.context(Exit::BackendUnavailable)?;
+        pass.scanned += points.len() as u64;
+        scanning.inc(points.len() as u64);
+
+        let mut batch: Vec<Point> = Vec::new();
+        for mut point in points {
+            if migrations::version(&point.payload) > SCHEMA_VERSION {
+                pass.newer += 1;
+                continue;
+            }
+            let pending = migrations::migrate(&mut point.payload);
+            if pending.is_empty() {
+                continue;
+            }
+            for migration in pending {
+                applied.entry(migration.version).or_insert((migration.description, 0)).1 += 1;
+            }
+            batch.push(point);
+        }
+        let points = batch.len() as u64;
+        if write && !batch.is_empty() {
+            store.overwrite_payloads(batch).await
+                .with_context(|| format!("Migrated {} points before failing", before + pass.migrated))
+                .context(Exit::BackendUnavailable)?;
+        }
+        pass.migrated += points;
+        scanning.set_message(format!("{} to migrate", before + pass.migrated));
+
+        match next {
+            Some(next) => cursor = Some(next),
+            None => return Ok(pass),
+        }
+    }
+}
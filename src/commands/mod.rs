@@ -0,0 +1,25 @@
+pub mod audit;
+pub mod backfill;
+pub mod check_standby;
+pub mod completions;
+pub mod daemon;
+pub mod delete;
+pub mod discover;
+pub mod doctor;
+pub mod drift;
+pub mod experiment;
+pub mod facets;
+pub mod feedback;
+pub mod gc;
+pub mod history;
+pub mod ingest;
+pub mod inspect;
+pub mod man;
+pub mod migrate;
+pub mod repair;
+pub mod replay;
+pub mod search;
+#[cfg(feature = "server")]
+pub mod serve;
+pub mod stats;
+pub mod warmup;
@@ -0,0 +1,36 @@
+use anyhow::{Context, Result};
+use tracing::info;
+use crate::cli::{IngestArgs, RepairArgs};
+use crate::commands::ingest;
+use crate::config::Config;
+use crate::control::Control;
+use crate::dead_letter::DeadLetter;
+use crate::dialect::DocumentFormat;
+use crate::outcome::Exit;
+
+pub async fn run(args: RepairArgs, config: &Config, control: &Control) -> Result<()> {
+    let Some(batch) = DeadLetter::new(&config.dead_letter).claim().await? else {
+        info!("Nothing to repair in {}", config.dead_letter.display());
+        return Ok(());
+    };
+
+    info!("Retrying dead letters from {}", batch.display());
+    let result = ingest::run(IngestArgs {
+        path: batch.clone(),
+        format: DocumentFormat::Native,
+        strict: true,
+        fail_on_error_rate: args.fail_on_error_rate,
+        outcome: args.outcome,
+        ..Default::default()
+    }, config, control).await;
+
+    // Only a run that got through every document has re-queued its own failures
+    match result.as_ref().map_err(Exit::from_error) {
+        Ok(_) | Err(Exit::PartialFailure) => tokio::fs::remove_file(&batch)
+            .await
+            .with_context(|| format!("Failed to remove repaired batch {}", batch.display()))?,
+        Err(_) => info!("Keeping {} for the next repair", batch.display()),
+    }
+
+    result
+}
@@ -0,0 +1,90 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
+use futures::stream::{FuturesUnordered, StreamExt};
+use tokio::time::Instant;
+use tracing::debug;
+use crate::cli::ReplayArgs;
+use crate::clients::llm::llama_cpp::LlamaCpp;
+use crate::clients::vector_store::Store;
+use crate::commands::ingest::await_llama;
+use crate::config::Config;
+use crate::outcome::Exit;
+use crate::query_log::{self, LoggedQuery};
+use crate::retrieval::Retrieval;
+use crate::search;
+
+pub async fn run(args: ReplayArgs, config: &Config) -> Result<()> {
+    let path = args.path.unwrap_or_else(|| config.serve.query_log.path.clone());
+    let queries = query_log::read(&path).await.context(Exit::ConfigError)?;
+    if queries.is_empty() {
+        bail!("{} holds no queries to replay", path.display());
+    }
+
+    let llama = match args.reembed {
+        true if queries.iter().any(|query| query.text.is_none()) => {
+            return Err(anyhow!("--reembed needs the query texts, which are only logged with serve.query_log.texts").context(Exit::ConfigError));
+        }
+        true => {
+            let llama = LlamaCpp::from_config(&config.llama).context(Exit::ConfigError)?;
+            await_llama(&llama).await.context(Exit::BackendUnavailable)?;
+            Some(llama)
+        }
+        false => None,
+    };
+    let store = Store::from_config(config).context(Exit::ConfigError)?;
+    let retrieval = Retrieval::from_config(config).context(Exit::ConfigError)?;
+
+    let total = queries.len();
+    let mut queue = queries.into_iter();
+    let mut exhausted = false;
+    let mut running = FuturesUnordered::new();
+    let mut ticks = tokio::time::interval(Duration::from_secs_f64(1.0 / args.rate));
+    let mut latencies = Vec::with_capacity(total);
+    let mut failed = 0;
+    let started = Instant::now();
+
+    loop {
+        tokio::select! {
+            _ = ticks.tick(), if !exhausted => match queue.next() {
+                Some(query) => running.push(replay(&store, &retrieval, llama.as_ref(), query)),
+                None => exhausted = true,
+            },
+            Some(result) = running.next() => match result {
+                Ok(latency) => latencies.push(latency),
+                Err(e) => {
+                    debug!("Replayed search failed: {e:#}");
+                    failed += 1;
+                }
+            },
+            else => break,
+        }
+    }
+
+    let elapsed = started.elapsed();
+    latencies.sort_unstable();
+    println!(
+        "replayed {total} searches in {:.1}s ({:.1}/s), {failed} failed",
+        elapsed.as_secs_f64(),
+        total as f64 / elapsed.as_secs_f64(),
+    );
+    if !latencies.is_empty() {
+        let ms = |q: f64| latencies[((latencies.len() - 1) as f64 * q).round() as usize].as_secs_f64() * 1000.0;
+        println!("latency p50 {:.1} ms, p95 {:.1} ms, p99 {:.1} ms, max {:.1} ms", ms(0.5), ms(0.95), ms(0.99), ms(1.0));
+    }
+
+    Ok(())
+}
+
+/// Runs one logged search the way serve mode would, returning how long it took
+async fn replay(store: &Store, retrieval: &Retrieval<'_>, llama: Option<&LlamaCpp<'_>>, query: LoggedQuery) -> Result<Duration> {
+    let started = Instant::now();
+    let vector = match (llama, &query.text) {
+        (Some(llama), Some(text)) => Some(llama.query_embedding(text).await?),
+        // Searches answered by their words while llama.cpp was down were logged without one
+        _ => Some(query.embedding).filter(|embedding| !embedding.is_empty()),
+    };
+    search::page(store, retrieval, query.text.as_deref(), vector, query.limit, query.offset, &query.filter, None, None, None).await?;
+
+    Ok(started.elapsed())
+}
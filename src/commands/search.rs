@@ -0,0 +1,81 @@
+use anyhow::{bail, Context, Result};
+use serde_json::{json, Value};
+use tracing::info;
+use crate::cli::{Output, SearchArgs};
+use crate::clients::llm::llama_cpp::LlamaCpp;
+use crate::clients::vector_store::{Filter, Hit, Store, VectorStore};
+use crate::commands::ingest::await_llama;
+use crate::config::Config;
+use crate::feedback::Boosts;
+use crate::outcome::Exit;
+use crate::output::Rows;
+use crate::retrieval::Retrieval;
+use crate::search::{self, Cursor};
+
+/// Fields of `--output json`, `csv` and `md`, followed by `vector` with `--with-vectors`
+const FIELDS: [&str; 8] = ["rank", "score", "id", "source", "content_type", "language", "merged_chunks", "text"];
+
+/// Prints the nearest documents to the query in whichever store the config points at
+pub async fn run(args: SearchArgs, config: &Config) -> Result<()> {
+    let filter = Filter::from_pairs(args.filter).with_keywords(&args.entity, &args.tag);
+    let cursor = args.cursor.as_deref().map(Cursor::decode).transpose().context(Exit::ConfigError)?;
+    let llama = LlamaCpp::from_config(&config.llama).context(Exit::ConfigError)?;
+    let store = Store::from_config(config).context(Exit::ConfigError)?;
+    let boosts = Boosts::load(&config.feedback).context(Exit::ConfigError)?;
+    let retrieval = Retrieval::from_config(config).context(Exit::ConfigError)?;
+
+    await_llama(&llama).await.context(Exit::BackendUnavailable)?;
+    let vector = llama.query_embedding(&args.query).await.context(Exit::BackendUnavailable)?;
+    if vector.is_empty() {
+        bail!("Llama returned no embedding for the query");
+    }
+
+    let mut page = search::page(&store, &retrieval, Some(&args.query), Some(vector), args.top_k, args.offset, &filter, cursor.as_ref(), None, boosts.as_ref()).await
+        .context(Exit::BackendUnavailable)?;
+    if args.merge_adjacent || config.serve.merge_adjacent {
+        page.hits = search::merge_adjacent(page.hits, config.pipeline.chunk_overlap);
+    }
+    if args.output == Output::Text {
+        for hit in page.hits {
+            match hit.metadata.extra.get(search::MERGED_CHUNKS_FIELD) {
+                Some(chunks) => println!("{:.4}  {}  {} ({chunks} chunks)", hit.score, hit.id, hit.metadata.source),
+                None => println!("{:.4}  {}  {}", hit.score, hit.id, hit.metadata.source),
+            }
+        }
+    } else {
+        rows(&store, page.hits, args.offset, args.with_vectors).await?.print(args.output)?;
+    }
+    if let Some(cursor) = page.next_cursor {
+        info!("More results with --cursor {cursor}");
+    }
+
+    Ok(())
+}
+
+async fn rows(store: &Store, hits: Vec<Hit>, offset: u64, with_vectors: bool) -> Result<Rows> {
+    let mut fields = FIELDS.to_vec();
+    if with_vectors {
+        fields.push("vector");
+    }
+    let mut rows = Rows::new(&fields);
+    for (rank, hit) in (offset + 1..).zip(hits) {
+        let mut row = vec![
+            json!(rank),
+            json!(hit.score),
+            json!(hit.id),
+            json!(hit.metadata.source),
+            json!(hit.metadata.content_type),
+            json!(hit.metadata.language),
+            hit.metadata.extra.get(search::MERGED_CHUNKS_FIELD).cloned().unwrap_or(Value::Null),
+            json!(hit.text),
+        ];
+        if with_vectors {
+            // A merged passage is stored as its chunks, so this is the vector of the one scoring best
+            let vector = store.get(&hit.id).await.context(Exit::BackendUnavailable)?.map(|(_, vector)| vector);
+            row.push(json!(vector));
+        }
+        rows.push(row);
+    }
+
+    Ok(rows)
+}
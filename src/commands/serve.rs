@@ -0,0 +1,37 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use tokio::net::TcpListener;
+use tracing::info;
+use crate::cli::ServeArgs;
+use crate::commands::daemon;
+use crate::config::Config;
+use crate::control::Control;
+use crate::outcome::Exit;
+use crate::server;
+
+/// Serves the HTTP API, running the configured schedules alongside it
+pub async fn run(args: ServeArgs, config: &Config, control: &Arc<Control>) -> Result<()> {
+    let bind = args.bind.unwrap_or_else(|| config.serve.bind.clone());
+    let listener = TcpListener::bind(&bind)
+        .await
+        .with_context(|| format!("Failed to listen on {bind}"))
+        .context(Exit::ConfigError)?;
+    info!("Serving on {bind}");
+
+    let router = server::router(config, control.clone()).context(Exit::ConfigError)?;
+    let server = async {
+        // The peer address tells voters apart when `feedback.voter_header` isn't sent
+        axum::serve(listener, router.into_make_service_with_connect_info::<SocketAddr>())
+            .with_graceful_shutdown(control.shutdown.clone().cancelled_owned())
+            .await?;
+        Ok(())
+    };
+
+    if config.schedules.is_empty() {
+        return server.await;
+    }
+
+    tokio::try_join!(server, daemon::run(config, control)).map(|_| ())
+}
@@ -0,0 +1,165 @@
+use anyhow::{Context, Result};
+use indicatif::HumanBytes;
+use qdrant_client::qdrant::CollectionStatus;
+use reqwest::Url;
+use serde_json::Value;
+use tracing::warn;
+use crate::cli::StatsArgs;
+use crate::clients::vector_store::qdrant::Qlient;
+use crate::config::{Config, QdrantConfig, StoreKind};
+use crate::dead_letter::DeadLetter;
+use crate::history::History;
+use crate::outcome::Exit;
+
+/// Port Qdrant serves REST on next to gRPC on 6334
+const REST_PORT: u16 = 6333;
+
+pub async fn run(args: StatsArgs, config: &Config) -> Result<()> {
+    if config.store == StoreKind::Qdrant {
+        qdrant_stats(args, &config.qdrant).await?;
+    } else {
+        println!("{:?} store, no Qdrant telemetry to show", config.store);
+    }
+
+    println!();
+    pipeline_stats(config).await
+}
+
+async fn qdrant_stats(args: StatsArgs, config: &QdrantConfig) -> Result<()> {
+    let mut qdrant = config.clone();
+    if let Some(url) = args.qdrant {
+        qdrant.url = url;
+    }
+    let collections = match args.collection {
+        Some(collection) => vec![collection],
+        None => qdrant.collections(),
+    };
+
+    let health = Qlient::from_config(&qdrant).context(Exit::ConfigError)?.client.health_check().await?;
+    println!("qdrant {}", health.version);
+
+    let telemetry = match telemetry(args.rest_url.or(qdrant.rest_url.clone()), &qdrant).await {
+        Ok(telemetry) => Some(telemetry),
+        Err(e) => {
+            warn!("No REST telemetry, so no RAM and disk usage: {e:#}");
+            None
+        }
+    };
+    if let Some(resident) = telemetry.as_ref().and_then(|t| t.pointer("/result/memory/resident_bytes")).and_then(Value::as_u64) {
+        println!("  resident memory {}", HumanBytes(resident));
+    }
+
+    for collection in collections {
+        qdrant.collection = collection;
+        collection_stats(&Qlient::from_config(&qdrant).context(Exit::ConfigError)?, &qdrant.collection, telemetry.as_ref()).await?;
+    }
+
+    Ok(())
+}
+
+async fn collection_stats(client: &Qlient, name: &str, telemetry: Option<&Value>) -> Result<()> {
+    let info = client.info().await?;
+    let status = CollectionStatus::try_from(info.status).unwrap_or(CollectionStatus::UnknownCollectionStatus);
+    let optimizer = match &info.optimizer_status {
+        Some(optimizer) if !optimizer.ok => format!("optimizer failing: {}", optimizer.error),
+        _ => "optimizer ok".to_string(),
+    };
+
+    println!();
+    println!("collection {name}");
+    println!("  status          {} ({optimizer})", status.as_str_name());
+    println!("  segments        {}", info.segments_count);
+    println!("  points          {}", info.points_count.unwrap_or_default());
+    println!("  indexed vectors {}", info.indexed_vectors_count.unwrap_or_default());
+    if let Some(queue) = &info.update_queue {
+        println!("  update queue    {}", queue.length);
+    }
+
+    let mut indexes = info.payload_schema.keys().map(String::as_str).collect::<Vec<_>>();
+    indexes.sort_unstable();
+    println!("  payload indexes {}", if indexes.is_empty() { "none".to_string() } else { indexes.join(", ") });
+
+    match client.cluster_info().await {
+        Ok(cluster) => println!(
+            "  shards          {} ({} local on peer {}, {} remote, {} transfers)",
+            cluster.shard_count, cluster.local_shards.len(), cluster.peer_id, cluster.remote_shards.len(), cluster.shard_transfers.len(),
+        ),
+        Err(e) => warn!("No cluster info for {name}: {e:#}"),
+    }
+
+    if let Some((ram, disk)) = telemetry.and_then(|t| usage(t, name)) {
+        println!("  ram             {}", HumanBytes(ram));
+        println!("  disk            {}", HumanBytes(disk));
+    }
+    for warning in &info.warnings {
+        println!("  warning         {}", warning.message);
+    }
+
+    Ok(())
+}
+
+/// `GET /telemetry` with segment details, which RAM and disk usage are only reported in
+async fn telemetry(rest_url: Option<String>, config: &QdrantConfig) -> Result<Value> {
+    let mut url = match rest_url {
+        Some(url) => Url::parse(&url)?,
+        None => {
+            let mut url = Url::parse(&config.url)?;
+            _ = url.set_port(Some(REST_PORT));
+            url
+        }
+    };
+    url.set_path("/telemetry");
+    url.set_query(Some("details_level=3"));
+
+    let mut request = reqwest::Client::new().get(url.clone());
+    if let Some(key) = &config.api_key {
+        request = request.header("api-key", key.expose());
+    }
+    let response = request.send().await?.error_for_status()?;
+    let body = response.text().await?;
+
+    serde_json::from_str(&body).with_context(|| format!("{} didn't answer with JSON", url.path()))
+}
+
+/// RAM and disk bytes summed over the segments of every local shard of `collection`
+fn usage(telemetry: &Value, collection: &str) -> Option<(u64, u64)> {
+    let collections = telemetry.pointer("/result/collections/collections")?.as_array()?;
+    let collection = collections.iter().find(|c| c.get("id").and_then(Value::as_str) == Some(collection))?;
+
+    let segments = collection.get("shards")?.as_array()?
+        .iter()
+        .filter_map(|shard| shard.pointer("/local/segments")?.as_array())
+        .flatten()
+        .filter_map(|segment| segment.get("info"));
+
+    let mut totals = None;
+    for info in segments {
+        let (ram, disk) = totals.get_or_insert((0, 0));
+        *ram += info.get("ram_usage_bytes").and_then(Value::as_u64).unwrap_or_default();
+        *disk += info.get("disk_usage_bytes").and_then(Value::as_u64).unwrap_or_default();
+    }
+
+    totals
+}
+
+async fn pipeline_stats(config: &Config) -> Result<()> {
+    let records = History::new(&config.history).list().await?;
+    let stored: u64 = records.iter().map(|r| r.summary.stored).sum();
+
+    println!("pipeline");
+    println!("  runs            {} ({stored} documents stored)", records.len());
+    if let Some(last) = records.last() {
+        println!(
+            "  last run        #{} finished {}, {}, {} of {} stored, {:.2}% errors",
+            last.id,
+            last.finished_at.format("%Y-%m-%d %H:%M:%S"),
+            last.exit,
+            last.summary.stored,
+            last.summary.documents,
+            last.error_rate * 100.0,
+        );
+    }
+    println!("  dead letters    {}", DeadLetter::new(&config.dead_letter).pending().await?);
+
+    Ok(())
+}
@@ -0,0 +1,81 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
+use tokio::time::Instant;
+use tracing::info;
+use crate::cli::WarmupArgs;
+use crate::clients::llm::llama_cpp::LlamaCpp;
+use crate::clients::vector_store::qdrant::Qlient;
+use crate::clients::vector_store::{Store, VectorStore};
+use crate::commands::ingest::await_llama;
+use crate::config::{Config, QdrantConfig, StoreKind};
+use crate::control::Control;
+use crate::outcome::Exit;
+
+/// Gets a store ready for queries, say after restoring a snapshot: waits for Qdrant to index
+/// the collection, then runs rounds of the `serve.warmup` searches until the time a round
+/// takes stops changing
+pub async fn run(args: WarmupArgs, config: &Config, control: &Control) -> Result<()> {
+    if config.serve.warmup.is_empty() {
+        return Err(anyhow!("No probe searches to warm up with, add some as [[serve.warmup]]")).context(Exit::ConfigError);
+    }
+    let mut config = config.clone();
+    if let Some(collection) = args.collection {
+        config.qdrant.collection = collection;
+    }
+    let llama = LlamaCpp::from_config(&config.llama).context(Exit::ConfigError)?;
+    let store = Store::from_config(&config).context(Exit::ConfigError)?;
+
+    if config.store == StoreKind::Qdrant {
+        let started = Instant::now();
+        let deadline = started + Duration::from_secs(args.index_timeout_secs);
+        for collection in config.qdrant.collections() {
+            let qdrant = Qlient::from_config(&QdrantConfig { collection, ..config.qdrant.clone() }).context(Exit::ConfigError)?;
+            qdrant.wait_for_index(deadline, &control.shutdown).await.context(Exit::BackendUnavailable)?;
+        }
+        info!("Indexed after waiting {:.1}s", started.elapsed().as_secs_f64());
+    }
+
+    // Embedded once up front, so the rounds time the store alone
+    await_llama(&llama).await.context(Exit::BackendUnavailable)?;
+    let mut probes = Vec::with_capacity(config.serve.warmup.len());
+    for warmup in &config.serve.warmup {
+        let vector = llama.query_embedding(&warmup.query).await.context(Exit::BackendUnavailable)?;
+        probes.push((warmup, vector));
+    }
+
+    let stable_rounds = args.stable_rounds.max(1);
+    let mut latencies: Vec<Duration> = Vec::with_capacity(args.rounds);
+    for round in 1..=args.rounds {
+        if control.shutdown.is_cancelled() {
+            bail!("Shut down while warming up");
+        }
+        let started = Instant::now();
+        for (warmup, vector) in &probes {
+            store.search(vector.clone(), warmup.limit, &warmup.filter).await
+                .with_context(|| format!("Warm-up search {:?} failed", warmup.query))
+                .context(Exit::BackendUnavailable)?;
+        }
+        let latency = started.elapsed();
+        println!("round {round}  {:.1}ms", latency.as_secs_f64() * 1000.0);
+        latencies.push(latency);
+
+        if settled(&latencies, stable_rounds, args.tolerance) {
+            info!("Latency settled after {round} rounds of {} probes", probes.len());
+            return Ok(());
+        }
+    }
+
+    bail!("Latency hadn't settled within {} rounds", args.rounds)
+}
+
+/// Whether the last `rounds` latencies are all within `tolerance` percent of the fastest of them
+fn settled(latencies: &[Duration], rounds: usize, tolerance: f64) -> bool {
+    let Some(last) = latencies.len().checked_sub(rounds).map(|start| &latencies[start..]) else {
+        return false;
+    };
+    let fastest = last.iter().min().expect("rounds is at least 1");
+    let slowest = last.iter().max().expect("rounds is at least 1");
+
+    slowest.as_secs_f64() <= fastest.as_secs_f64() * (1.0 + tolerance / 100.0)
+}
@@ -0,0 +1,255 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{watch, Notify};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+use crate::events::Events;
+
+/// Runtime switches and gauges operators use to steer and watch a running pipeline
+pub struct Control {
+    paused: watch::Sender<bool>,
+    flush: Arc<Notify>,
+    pub stages: Arc<Stages>,
+    pub events: Events,
+    /// Cancelled on SIGINT/SIGTERM, or Ctrl-C off Unix; each run works on a child of it
+    pub shutdown: CancellationToken,
+    started: Instant,
+}
+
+impl Default for Control {
+    fn default() -> Self {
+        Self {
+            paused: watch::Sender::new(false),
+            flush: Arc::new(Notify::new()),
+            stages: Arc::new(Stages::default()),
+            events: Events::default(),
+            shutdown: CancellationToken::new(),
+            started: Instant::now(),
+        }
+    }
+}
+
+impl Control {
+    pub fn pause(&self) {
+        if !self.paused.send_replace(true) {
+            info!("Pipeline paused");
+        }
+    }
+
+    pub fn resume(&self) {
+        if self.paused.send_replace(false) {
+            info!("Pipeline resumed");
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        *self.paused.borrow()
+    }
+
+    /// Returns once the pipeline isn't paused, or is shutting down
+    pub async fn proceed(&self) {
+        let mut paused = self.paused.subscribe();
+        tokio::select! {
+            // The sender lives in `self`, so the channel can't close while this waits
+            _ = paused.wait_for(|paused| !paused) => {}
+            _ = self.shutdown.cancelled() => {}
+        }
+    }
+
+    /// Asks the running upsert loop to write out its buffer without waiting for it to fill
+    pub fn flush(&self) {
+        self.flush.notify_one();
+    }
+
+    /// Woken by `flush`; a request made while nobody listens is kept for the next listener
+    pub fn flush_requests(&self) -> Arc<Notify> {
+        self.flush.clone()
+    }
+
+    pub fn state(&self) -> State {
+        let uptime = self.started.elapsed().as_secs_f64();
+        let per_sec = |count: u64| if uptime > 0.0 { count as f64 / uptime } else { 0.0 };
+        let embedded = self.stages.embedded.load(Ordering::Relaxed);
+        let upserted = self.stages.upserted.load(Ordering::Relaxed);
+
+        State {
+            paused: self.is_paused(),
+            uptime_secs: uptime as u64,
+            queues: Queues {
+                load: self.stages.load.load(Ordering::Relaxed),
+                embed: self.stages.embed.load(Ordering::Relaxed),
+                upsert: self.stages.upsert.load(Ordering::Relaxed),
+            },
+            throughput: Throughput {
+                embedded,
+                upserted,
+                embed_per_sec: per_sec(embedded),
+                upsert_per_sec: per_sec(upserted),
+            },
+        }
+    }
+
+    /// Pauses on SIGUSR1, resumes on SIGUSR2 and shuts down on SIGINT/SIGTERM for the life of the process
+    ///
+    /// A second SIGINT exits immediately for runs that don't wind down fast enough.
+    #[cfg(unix)]
+    pub fn listen_for_signals(self: &Arc<Self>) -> Result<JoinHandle<()>> {
+        let mut pause = signal(SignalKind::user_defined1())?;
+        let mut resume = signal(SignalKind::user_defined2())?;
+        let mut interrupt = signal(SignalKind::interrupt())?;
+        let mut terminate = signal(SignalKind::terminate())?;
+        let control = self.clone();
+
+        Ok(tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    Some(_) = pause.recv() => control.pause(),
+                    Some(_) = resume.recv() => control.resume(),
+                    Some(_) = interrupt.recv() => control.interrupted(),
+                    Some(_) = terminate.recv() => {
+                        info!("Shutting down on SIGTERM");
+                        control.shutdown.cancel();
+                    }
+                    else => break,
+                }
+            }
+        }))
+    }
+
+    /// Shuts down on Ctrl-C for the life of the process, like SIGINT on Unix; pausing and
+    /// resuming is left to the admin API without SIGUSR1 and SIGUSR2
+    #[cfg(not(unix))]
+    pub fn listen_for_signals(self: &Arc<Self>) -> Result<JoinHandle<()>> {
+        let control = self.clone();
+
+        Ok(tokio::spawn(async move {
+            while tokio::signal::ctrl_c().await.is_ok() {
+                control.interrupted();
+            }
+        }))
+    }
+
+    /// Shuts down, or exits right away when already shutting down
+    fn interrupted(&self) {
+        if self.shutdown.is_cancelled() {
+            warn!("Interrupted again, exiting without cleanup");
+            std::process::exit(130);
+        }
+        info!("Shutting down, interrupt again to exit immediately");
+        self.shutdown.cancel();
+    }
+}
+
+/// Documents currently waiting in, or being worked on by, each stage, plus running totals
+#[derive(Default)]
+pub struct Stages {
+    pub load: AtomicU64,
+    pub embed: AtomicU64,
+    pub upsert: AtomicU64,
+    pub embedded: AtomicU64,
+    pub upserted: AtomicU64,
+}
+
+impl Stages {
+    /// Logs the stage counts every `interval_secs` until the returned task is aborted
+    pub fn report(self: Arc<Self>, interval_secs: u64) -> Option<JoinHandle<()>> {
+        if interval_secs == 0 {
+            return None;
+        }
+
+        Some(tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(interval_secs));
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                info!(
+                    "In flight: {} load, {} embed, {} upsert",
+                    self.load.load(Ordering::Relaxed),
+                    self.embed.load(Ordering::Relaxed),
+                    self.upsert.load(Ordering::Relaxed),
+                );
+            }
+        }))
+    }
+}
+
+/// Longest pause between two documents read while the pipeline is backed up
+const MAX_READ_DELAY: Duration = Duration::from_millis(100);
+
+/// Slows reading down as documents pile up between it and the store, so a fast parser
+/// can't fill memory with documents the embedder is nowhere near
+pub struct Throttle {
+    stages: Arc<Stages>,
+    /// Backlog reads start slowing down at
+    low: u64,
+    /// Backlog reads wait at until it drains below again
+    high: u64,
+}
+
+impl Throttle {
+    pub fn new(stages: Arc<Stages>, read_ahead: usize) -> Self {
+        let high = read_ahead.max(2) as u64;
+        Self { stages, low: high / 2, high }
+    }
+
+    /// Documents read but not embedded yet
+    ///
+    /// A slow store shows up here too: documents wait to be embedded while `max_in_flight`
+    /// is used up. Those in the upsert stage itself don't count, they may be waiting in the
+    /// store's buffer for the reads that would fill it.
+    fn backlog(&self) -> u64 {
+        self.stages.load.load(Ordering::Relaxed) + self.stages.embed.load(Ordering::Relaxed)
+    }
+
+    /// Waits before the next read, the longer the fuller the pipeline is
+    pub async fn pace(&self, cancel: &CancellationToken) {
+        let mut waited = false;
+        loop {
+            let backlog = self.backlog();
+            if backlog < self.low || cancel.is_cancelled() {
+                break;
+            }
+            let pressure = (backlog - self.low) as f64 / (self.high - self.low) as f64;
+            tokio::time::sleep(MAX_READ_DELAY.mul_f64(pressure.min(1.0))).await;
+            if backlog < self.high {
+                break;
+            }
+            if !waited {
+                debug!("{backlog} documents waiting to be embedded, holding reads back");
+                waited = true;
+            }
+        }
+    }
+}
+
+/// Snapshot served by `/admin/state`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct State {
+    pub paused: bool,
+    pub uptime_secs: u64,
+    pub queues: Queues,
+    pub throughput: Throughput,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Queues {
+    pub load: u64,
+    pub embed: u64,
+    pub upsert: u64,
+}
+
+/// Totals since startup and their average rates
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Throughput {
+    pub embedded: u64,
+    pub upserted: u64,
+    pub embed_per_sec: f64,
+    pub upsert_per_sec: f64,
+}
@@ -0,0 +1,100 @@
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use serde::Serialize;
+use tokio::io::AsyncWriteExt;
+use crate::clients::Document;
+
+/// Why a document ended up in the dead letter file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Cause {
+    Embed,
+    /// The backend answered without a vector
+    Empty,
+    /// Every attempt ran past `llama.embed_timeout_secs`
+    Timeout,
+    Upsert,
+    /// The store turned the document itself down, not the batch it was in
+    Rejected,
+}
+
+/// A dead letter line; `cause` is ignored when the line is read back as a native document
+#[derive(Serialize)]
+struct Entry<'e> {
+    #[serde(flatten)]
+    document: &'e Document,
+    cause: Cause,
+}
+
+/// JSONL file of documents that failed to embed or upsert, kept for `repair`
+pub struct DeadLetter<'d> {
+    path: &'d Path,
+}
+
+impl<'d> DeadLetter<'d> {
+    pub fn new(path: &'d Path) -> Self {
+        Self { path }
+    }
+
+    /// Appends `documents` as native JSONL, without their embeddings
+    pub async fn append(&self, documents: &[Document], cause: Cause) -> Result<()> {
+        if documents.is_empty() {
+            return Ok(());
+        }
+
+        let mut out = Vec::new();
+        for document in documents {
+            let document = Document { embeddings: vec![], ..document.clone() };
+            serde_json::to_writer(&mut out, &Entry { document: &document, cause })?;
+            out.push(b'\n');
+        }
+
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.path)
+            .await?;
+        file.write_all(&out).await?;
+        // Tokio finishes the write in the background otherwise, after this returns
+        file.flush().await?;
+
+        Ok(())
+    }
+
+    /// Moves the current dead letters aside so a repair run can re-queue its own failures
+    ///
+    /// A batch left behind by an interrupted repair is returned before any new one is claimed.
+    pub async fn claim(&self) -> Result<Option<PathBuf>> {
+        let claimed = self.claimed_path();
+        if tokio::fs::try_exists(&claimed).await? {
+            return Ok(Some(claimed));
+        }
+        if !tokio::fs::try_exists(self.path).await? {
+            return Ok(None);
+        }
+
+        tokio::fs::rename(self.path, &claimed).await?;
+        Ok(Some(claimed))
+    }
+
+    /// Documents waiting for `repair`, a batch an interrupted repair left behind included
+    pub async fn pending(&self) -> Result<u64> {
+        let mut count = 0;
+        for path in [self.path.to_path_buf(), self.claimed_path()] {
+            if tokio::fs::try_exists(&path).await? {
+                let raw = tokio::fs::read_to_string(&path).await?;
+                count += raw.lines().filter(|line| !line.trim().is_empty()).count() as u64;
+            }
+        }
+
+        Ok(count)
+    }
+
+    fn claimed_path(&self) -> PathBuf {
+        let mut name = OsString::from(self.path.as_os_str());
+        name.push(".repairing");
+        name.into()
+    }
+}
@@ -0,0 +1,85 @@
+use clap::ValueEnum;
+use serde::Deserialize;
+use serde::de::Error;
+use serde_json::{Map, Value};
+use crate::clients::{Document, Metadata};
+
+/// JSON shapes documents are accepted in, as exported by common Python tooling
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DocumentFormat {
+    /// This crate's own `Document` JSON
+    #[default]
+    Native,
+    /// LangChain `Document`s, plain or in their `lc` constructor serialization
+    Langchain,
+    /// LlamaIndex `TextNode`/`Document` JSON
+    Llamaindex,
+    /// Tries every known field name for text and metadata
+    Auto,
+}
+
+const TEXT_KEYS: [&str; 3] = ["page_content", "text", "content"];
+const SOURCE_KEYS: [&str; 5] = ["source", "file_path", "file_name", "url", "filename"];
+const CONTENT_TYPE_KEYS: [&str; 4] = ["content_type", "mime_type", "file_type", "type"];
+const LANGUAGE_KEYS: [&str; 2] = ["language", "lang"];
+
+pub fn parse_document(line: &str, format: DocumentFormat) -> serde_json::Result<Document> {
+    if format == DocumentFormat::Native {
+        return serde_json::from_str(line);
+    }
+
+    let value = serde_json::from_str::<Value>(line)?;
+    let Value::Object(mut object) = value else {
+        return Err(serde_json::Error::custom("expected a JSON object"));
+    };
+
+    // LangChain's `dumpd` wraps the document's fields in `kwargs`
+    if format != DocumentFormat::Llamaindex {
+        if let Some(Value::Object(kwargs)) = object.remove("kwargs") {
+            object = kwargs;
+        }
+    }
+
+    let text_keys: &[&str] = match format {
+        DocumentFormat::Langchain => &TEXT_KEYS[..1],
+        DocumentFormat::Llamaindex => &TEXT_KEYS[1..2],
+        _ => &TEXT_KEYS,
+    };
+    let page_content = text_keys
+        .iter()
+        .find_map(|key| object.get(*key)?.as_str())
+        .ok_or_else(|| serde_json::Error::custom(format!("missing text field, expected one of {text_keys:?}")))?
+        .to_string();
+
+    let metadata = match object.remove("metadata") {
+        Some(Value::Object(map)) => map,
+        Some(Value::Null) | None => Map::new(),
+        Some(_) => return Err(serde_json::Error::custom("`metadata` must be an object")),
+    };
+
+    Ok(Document {
+        page_content,
+        metadata: Metadata {
+            source: lookup(&metadata, &SOURCE_KEYS),
+            content_type: lookup(&metadata, &CONTENT_TYPE_KEYS),
+            language: lookup(&metadata, &LANGUAGE_KEYS),
+            ..Default::default()
+        },
+        embeddings: vec![],
+    })
+}
+
+/// First matching key at the top level of `metadata`, then one nested object deep
+fn lookup(metadata: &Map<String, Value>, keys: &[&str]) -> String {
+    let nested = metadata.values().filter_map(Value::as_object);
+
+    std::iter::once(metadata)
+        .chain(nested)
+        .find_map(|map| keys.iter().find_map(|key| map.get(*key)))
+        .map(|value| match value {
+            Value::String(s) => s.clone(),
+            other => other.to_string(),
+        })
+        .unwrap_or_default()
+}
@@ -0,0 +1,67 @@
+use std::collections::HashSet;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use crate::clients::vector_store::Hit;
+
+/// A query of the eval set and the sources a good answer to it comes from
+#[derive(Debug, Clone, Deserialize)]
+pub struct EvalQuery {
+    pub query: String,
+    pub relevant: Vec<String>,
+}
+
+/// Reads an eval set, a JSON object like `{"query": .., "relevant": [..]}` per line
+pub async fn load(path: &Path) -> Result<Vec<EvalQuery>> {
+    let raw = tokio::fs::read_to_string(path).await
+        .with_context(|| format!("Failed to read the eval set {}", path.display()))?;
+
+    raw.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(n, line)| serde_json::from_str(line).with_context(|| format!("Line {} of {} is not an eval query", n + 1, path.display())))
+        .collect()
+}
+
+/// Retrieval quality averaged over an eval set
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Scores {
+    /// Share of relevant sources among the top hits
+    pub recall: f64,
+    /// Mean of 1 / rank of the first relevant hit, 0 when there is none
+    pub mrr: f64,
+}
+
+impl Scores {
+    /// How well `hits`, best first, answer `query`
+    pub fn of(query: &EvalQuery, hits: &[Hit]) -> Self {
+        let relevant: HashSet<&str> = query.relevant.iter().map(String::as_str).collect();
+        if relevant.is_empty() {
+            return Self::default();
+        }
+
+        let found: HashSet<&str> = hits.iter()
+            .map(|hit| hit.metadata.source.as_str())
+            .filter(|source| relevant.contains(source))
+            .collect();
+        let first = hits.iter().position(|hit| relevant.contains(hit.metadata.source.as_str()));
+
+        Self {
+            recall: found.len() as f64 / relevant.len() as f64,
+            mrr: first.map_or(0.0, |rank| 1.0 / (rank + 1) as f64),
+        }
+    }
+
+    pub fn mean(scores: &[Scores]) -> Self {
+        if scores.is_empty() {
+            return Self::default();
+        }
+
+        let n = scores.len() as f64;
+        Self {
+            recall: scores.iter().map(|s| s.recall).sum::<f64>() / n,
+            mrr: scores.iter().map(|s| s.mrr).sum::<f64>() / n,
+        }
+    }
+}
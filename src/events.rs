@@ -0,0 +1,76 @@
+use std::fmt::{Display, Formatter};
+
+use serde::Serialize;
+use tokio::sync::broadcast;
+use crate::dead_letter::Cause;
+use crate::history::RunRecord;
+
+/// Events a subscriber that falls this far behind starts missing
+const CAPACITY: usize = 4096;
+
+/// What an ingestion run reports as it goes
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum PipelineEvent {
+    DocumentLoaded { source: String },
+    ChunkEmbedded { source: String, dimensions: usize },
+    /// Documents the store has taken, settled as one batch
+    BatchUpserted { documents: u64 },
+    /// Documents that went to the dead letter file
+    Failure { cause: Cause, documents: u64 },
+    /// A run is over, as recorded in the history; its id is 0 when that failed
+    RunFinished(Box<RunRecord>),
+}
+
+impl Display for PipelineEvent {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PipelineEvent::DocumentLoaded { source } => write!(f, "Loaded {source}"),
+            PipelineEvent::ChunkEmbedded { source, dimensions } => write!(f, "Embedded a chunk of {source} ({dimensions} dimensions)"),
+            PipelineEvent::BatchUpserted { documents } => write!(f, "Stored a batch of {documents} documents"),
+            PipelineEvent::Failure { cause, documents } => write!(f, "Dead-lettered {documents} documents ({cause:?})"),
+            PipelineEvent::RunFinished(record) => write!(f, "Run {} finished with {}", record.id, record.exit),
+        }
+    }
+}
+
+/// Broadcasts pipeline events to every subscriber, such as the progress bars and notifiers
+///
+/// Publishing never waits: a subscriber that can't keep up misses events rather than
+/// holding the pipeline back, so nothing that has to add up, like the throughput totals
+/// of `/admin/state`, is counted from them.
+#[derive(Clone)]
+pub struct Events {
+    tx: broadcast::Sender<PipelineEvent>,
+}
+
+impl Default for Events {
+    fn default() -> Self {
+        Self { tx: broadcast::Sender::new(CAPACITY) }
+    }
+}
+
+impl Events {
+    pub fn publish(&self, event: PipelineEvent) {
+        // Nobody listening is fine
+        _ = self.tx.send(event);
+    }
+
+    /// Events published from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<PipelineEvent> {
+        self.tx.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_serialize_tagged_with_their_kind() {
+        let event = PipelineEvent::Failure { cause: Cause::Timeout, documents: 3 };
+
+        assert_eq!(serde_json::to_value(&event).unwrap(), serde_json::json!({ "event": "failure", "cause": "timeout", "documents": 3 }));
+        assert_eq!(event.to_string(), "Dead-lettered 3 documents (Timeout)");
+    }
+}
@@ -0,0 +1,105 @@
+use anyhow::{anyhow, Result};
+use serde_json::{json, Map, Value};
+use tracing::{info, warn};
+use crate::clients::Document;
+use crate::clients::llm::llama_cpp::LlamaCpp;
+use crate::clients::vector_store::keyword;
+use crate::config::{Config, ExtractedField, ExtractionConfig};
+
+/// Entries of each list the model may answer with
+const MAX_ITEMS: usize = 10;
+
+/// Fills in `[extraction]` metadata for documents as they are loaded
+pub struct Extractor<'e> {
+    llama: LlamaCpp<'e>,
+    config: &'e ExtractionConfig,
+    schema: Value,
+}
+
+impl<'e> Extractor<'e> {
+    /// None unless `extraction.enabled` is set
+    pub fn from_config(config: &'e Config) -> Result<Option<Self>> {
+        let extraction = &config.extraction;
+        if !extraction.enabled || extraction.fields.is_empty() {
+            return Ok(None);
+        }
+        let llama = LlamaCpp::from_config(config.model(extraction.model.as_deref())?)?;
+
+        Ok(Some(Self { llama, config: extraction, schema: schema(&extraction.fields) }))
+    }
+
+    /// Adds what the model makes of each document to its payload; documents it can't be
+    /// asked about go on without
+    pub async fn extract(&self, mut documents: Vec<Document>) -> Vec<Document> {
+        let mut failed = 0;
+        for document in &mut documents {
+            if let Err(e) = self.extract_one(document).await {
+                warn!("No metadata extracted from {}: {e:#}", document.metadata.source);
+                failed += 1;
+            }
+        }
+        if failed > 0 {
+            info!("Extracted metadata from {} of {} documents", documents.len() - failed, documents.len());
+        }
+
+        documents
+    }
+
+    async fn extract_one(&self, document: &mut Document) -> Result<()> {
+        let text: String = document.page_content.chars().take(self.config.max_chars).collect();
+        let prompt = self.config.prompt.replace("{text}", &text);
+        let answer = self.llama.complete_json(&prompt, &self.schema, self.config.max_tokens).await?;
+        let Value::Object(mut answer) = answer else {
+            return Err(anyhow!("Expected a JSON object, got {answer}"));
+        };
+
+        for field in &self.config.fields {
+            let Some(mut value) = answer.remove(field.name()) else { continue };
+            if matches!(field, ExtractedField::Topics | ExtractedField::Entities) {
+                value = keywords(value);
+            }
+            let empty = match &value {
+                Value::String(text) => text.trim().is_empty(),
+                Value::Array(items) => items.is_empty(),
+                _ => true,
+            };
+            if !empty {
+                document.metadata.extra.entry(field.name().to_string()).or_insert(value);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A list of strings as keywords, each once
+fn keywords(value: Value) -> Value {
+    let Value::Array(items) = value else { return value };
+    let mut keywords: Vec<String> = items.iter()
+        .filter_map(Value::as_str)
+        .map(keyword)
+        .filter(|keyword| !keyword.is_empty())
+        .collect();
+    keywords.sort();
+    keywords.dedup();
+
+    json!(keywords)
+}
+
+/// The JSON schema answers are held to, an object with every one of `fields`
+fn schema(fields: &[ExtractedField]) -> Value {
+    let list = |items: Value| json!({ "type": "array", "items": items, "maxItems": MAX_ITEMS });
+    let properties: Map<String, Value> = fields.iter()
+        .map(|field| {
+            let property = match field {
+                ExtractedField::Title => json!({ "type": "string" }),
+                ExtractedField::Topics | ExtractedField::Entities => list(json!({ "type": "string" })),
+                ExtractedField::Dates => list(json!({ "type": "string", "format": "date" })),
+            };
+            (field.name().to_string(), property)
+        })
+        .collect();
+    let required: Vec<&str> = fields.iter().map(|field| field.name()).collect();
+
+    json!({ "type": "object", "properties": properties, "required": required, "additionalProperties": false })
+}
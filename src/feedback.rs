@@ -0,0 +1,207 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use crate::clients::vector_store::Hit;
+use crate::config::FeedbackConfig;
+
+const SECONDS_PER_DAY: f64 = 24.0 * 60.0 * 60.0;
+
+/// The votes counted for each point, by point id, oldest first
+type Votes = HashMap<String, Vec<Cast>>;
+
+/// A vote as `Boosts` counts it
+struct Cast {
+    at: DateTime<Utc>,
+    vote: Vote,
+    voter: Option<String>,
+}
+
+/// Whether a search result was what the query was after
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Vote {
+    Up,
+    Down,
+}
+
+/// A vote on one result of one query, kept to tune reranking with later
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Feedback {
+    pub at: DateTime<Utc>,
+    pub query: String,
+    /// Id of the point as search results carry it
+    pub point_id: String,
+    pub vote: Vote,
+    /// Who voted, as far as the server could tell; a voter's later vote on a point replaces
+    /// their earlier one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub voter: Option<String>,
+}
+
+impl Feedback {
+    pub fn new(query: String, point_id: String, vote: Vote, voter: Option<String>) -> Self {
+        Self { at: Utc::now(), query, point_id, vote, voter }
+    }
+}
+
+/// Append-only JSONL file of the votes cast on search results
+pub struct FeedbackLog {
+    /// Guards appends, so concurrent votes don't interleave their lines
+    path: Mutex<PathBuf>,
+}
+
+impl FeedbackLog {
+    pub fn new(path: &Path) -> Self {
+        Self { path: Mutex::new(path.to_path_buf()) }
+    }
+
+    pub async fn record(&self, feedback: &Feedback) -> Result<()> {
+        let mut line = serde_json::to_vec(feedback)?;
+        line.push(b'\n');
+
+        let path = self.path.lock().await;
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&*path)
+            .await
+            .with_context(|| format!("Failed to open the feedback file {}", path.display()))?;
+        file.write_all(&line).await?;
+
+        Ok(())
+    }
+}
+
+/// Score adjustments from the votes each point got, decaying with their age
+pub struct Boosts {
+    config: FeedbackConfig,
+    votes: RwLock<Votes>,
+}
+
+impl Boosts {
+    pub fn new(config: &FeedbackConfig, feedback: Vec<Feedback>) -> Self {
+        let boosts = Self { config: config.clone(), votes: RwLock::default() };
+        for feedback in &feedback {
+            boosts.add(feedback);
+        }
+        boosts
+    }
+
+    /// The votes in the feedback file, or `None` with `feedback.scoring` off
+    pub fn load(config: &FeedbackConfig) -> Result<Option<Self>> {
+        if !config.scoring {
+            return Ok(None);
+        }
+
+        Ok(Some(Self::new(config, read(&config.path)?)))
+    }
+
+    /// Counts `feedback`, in place of the voter's earlier vote on the point, dropping the
+    /// point's oldest votes past `feedback.max_votes_per_point`
+    pub fn add(&self, feedback: &Feedback) {
+        let mut votes = self.votes.write().expect("boosts lock poisoned");
+        let votes = votes.entry(feedback.point_id.clone()).or_default();
+        if feedback.voter.is_some() {
+            votes.retain(|cast| cast.voter != feedback.voter);
+        }
+        votes.push(Cast { at: feedback.at, vote: feedback.vote, voter: feedback.voter.clone() });
+        let excess = votes.len().saturating_sub(self.config.max_votes_per_point.max(1));
+        votes.drain(..excess);
+    }
+
+    /// What the votes on the point add to its score `now`, within `feedback.max_boost`
+    pub fn boost(&self, point_id: &str, now: DateTime<Utc>) -> f32 {
+        let votes = self.votes.read().expect("boosts lock poisoned");
+        let Some(votes) = votes.get(point_id) else {
+            return 0.0;
+        };
+
+        let net: f64 = votes.iter()
+            .map(|cast| {
+                let weight = self.weight(now - cast.at);
+                match cast.vote {
+                    Vote::Up => weight,
+                    Vote::Down => -weight,
+                }
+            })
+            .sum();
+        (net as f32 * self.config.boost).clamp(-self.config.max_boost, self.config.max_boost)
+    }
+
+    /// Adds each hit's boost to its score; the hits need sorting again afterwards
+    pub fn apply(&self, hits: &mut [Hit]) {
+        let now = Utc::now();
+        for hit in hits {
+            hit.score += self.boost(&hit.id, now);
+        }
+    }
+
+    /// Halves every `feedback.half_life_days`
+    fn weight(&self, age: chrono::Duration) -> f64 {
+        if self.config.half_life_days <= 0.0 {
+            return 1.0;
+        }
+        let days = age.num_seconds().max(0) as f64 / SECONDS_PER_DAY;
+        0.5f64.powf(days / self.config.half_life_days)
+    }
+}
+
+/// Every vote in the file at `path`, oldest first; none when there is no file yet
+pub fn read(path: &Path) -> Result<Vec<Feedback>> {
+    let raw = match std::fs::read_to_string(path) {
+        Ok(raw) => raw,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read the feedback file {}", path.display())),
+    };
+
+    raw.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(n, line)| serde_json::from_str(line).with_context(|| format!("Line {} of {} is not a vote", n + 1, path.display())))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vote(point_id: &str, vote: Vote, voter: Option<&str>) -> Feedback {
+        Feedback::new("query".to_string(), point_id.to_string(), vote, voter.map(str::to_string))
+    }
+
+    fn boosts(config: FeedbackConfig, feedback: Vec<Feedback>) -> Boosts {
+        Boosts::new(&FeedbackConfig { boost: 1.0, max_boost: 100.0, half_life_days: 0.0, ..config }, feedback)
+    }
+
+    #[test]
+    fn a_voter_counts_once_per_point() {
+        let boosts = boosts(FeedbackConfig::default(), vec![
+            vote("a", Vote::Up, Some("ana")),
+            vote("a", Vote::Up, Some("ana")),
+            vote("a", Vote::Down, Some("ana")),
+            vote("a", Vote::Up, Some("ben")),
+            vote("b", Vote::Up, Some("ana")),
+        ]);
+
+        assert_eq!(boosts.boost("a", Utc::now()), 0.0);
+        assert_eq!(boosts.boost("b", Utc::now()), 1.0);
+    }
+
+    #[test]
+    fn a_point_keeps_its_latest_votes_only() {
+        let config = FeedbackConfig { max_votes_per_point: 3, ..FeedbackConfig::default() };
+        let mut feedback = vec![vote("a", Vote::Down, None); 5];
+        feedback.extend([vote("a", Vote::Up, None), vote("a", Vote::Up, None)]);
+        let boosts = boosts(config, feedback);
+
+        assert_eq!(boosts.boost("a", Utc::now()), 1.0);
+        assert_eq!(boosts.votes.read().unwrap()["a"].len(), 3);
+    }
+}
@@ -0,0 +1,67 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use crate::outcome::{Exit, RunSummary};
+
+/// One finished ingestion run as persisted in the history file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunRecord {
+    pub id: u64,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    pub path: PathBuf,
+    pub exit: Exit,
+    pub error_rate: f64,
+    pub summary: RunSummary,
+    pub config_hash: String,
+}
+
+/// Append-only JSONL log of run records
+pub struct History<'h> {
+    path: &'h Path,
+}
+
+impl<'h> History<'h> {
+    pub fn new(path: &'h Path) -> Self {
+        Self { path }
+    }
+
+    /// Appends `record` under the next free id, returning that id
+    pub async fn append(&self, mut record: RunRecord) -> Result<u64> {
+        record.id = self.list().await?.last().map_or(1, |r| r.id + 1);
+
+        let mut line = serde_json::to_vec(&record)?;
+        line.push(b'\n');
+
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.path)
+            .await?;
+        file.write_all(&line).await?;
+
+        Ok(record.id)
+    }
+
+    /// Every readable record, oldest first
+    pub async fn list(&self) -> Result<Vec<RunRecord>> {
+        if !tokio::fs::try_exists(self.path).await? {
+            return Ok(vec![]);
+        }
+
+        let raw = tokio::fs::read_to_string(self.path).await?;
+        let records = raw
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect();
+
+        Ok(records)
+    }
+
+    pub async fn get(&self, id: u64) -> Result<Option<RunRecord>> {
+        Ok(self.list().await?.into_iter().find(|r| r.id == id))
+    }
+}
@@ -0,0 +1,42 @@
+pub mod api;
+pub mod canary;
+#[cfg(feature = "chaos")]
+pub mod chaos;
+pub mod chunking;
+pub mod cli;
+#[cfg(feature = "client")]
+pub mod client;
+pub mod clients;
+pub mod commands;
+pub mod config;
+pub mod control;
+pub mod dead_letter;
+pub mod dialect;
+pub mod eval;
+pub mod events;
+pub mod extraction;
+pub mod feedback;
+pub mod history;
+pub mod loaders;
+pub mod metrics_snapshots;
+pub mod migrations;
+pub mod multivector;
+pub mod notify;
+pub mod outcome;
+pub mod pipeline;
+pub mod output;
+pub mod presets;
+pub mod profiling;
+pub mod provenance;
+pub mod query_log;
+pub mod retrieval;
+pub mod secret;
+pub mod seed;
+pub mod search;
+#[cfg(feature = "server")]
+pub mod server;
+pub mod similarity;
+pub mod sink;
+pub mod slow_log;
+pub mod sources;
+pub mod tls;
@@ -0,0 +1,242 @@
+use std::io::{Cursor, Read};
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use flate2::read::GzDecoder;
+use tokio::sync::mpsc;
+use tracing::{debug, warn};
+use crate::config::ArchiveConfig;
+use crate::loaders::{self, Kind, Loaded, Options, Selection};
+
+/// Most bytes reserved for an entry up front, since the size in its header may be made up
+const MAX_PREALLOCATION: u64 = 1 << 20;
+
+/// Routes every supported entry of a zip or tar archive to its loader, without unpacking to disk
+///
+/// Entries are decompressed one at a time on a blocking thread, which stops once the run is
+/// cancelled or has all the documents it wants, within `loaders.archive`'s limits.
+pub async fn load(path: &Path, kind: Kind, bytes: Vec<u8>, options: &Options<'_>, selection: &mut Selection) -> Result<Loaded> {
+    let (tx, mut entries) = mpsc::channel(1);
+    let mut reader = Reader { entries: tx, limits: options.loaders.archive.clone(), total: 0 };
+    let reading = tokio::task::spawn_blocking(move || match kind {
+        Kind::Zip => reader.zip(bytes),
+        Kind::TarGz => reader.tar(GzDecoder::new(Cursor::new(bytes))),
+        _ => reader.tar(Cursor::new(bytes)),
+    });
+
+    let mut loaded = Loaded::default();
+    while let Some((entry, bytes)) = entries.recv().await {
+        if options.cancel.is_cancelled() || selection.is_full() {
+            break;
+        }
+        let Some(kind) = Kind::of(&entry) else {
+            debug!("Ignoring {} in {}", entry.display(), path.display());
+            continue;
+        };
+        if kind.is_archive() {
+            warn!("Skipping nested archive {} in {}", entry.display(), path.display());
+            continue;
+        }
+
+        // `archive.zip/docs/a.jsonl` reads naturally in messages and stays unique per entry
+        let name = path.join(&entry);
+        loaded.extend(loaders::load_bytes(&name, kind, &bytes, options, selection).await?);
+    }
+    // Ends the reader at its next entry if the loop stopped early
+    drop(entries);
+
+    reading.await?
+        .with_context(|| format!("Failed to read archive {}", path.display()))?;
+
+    Ok(loaded)
+}
+
+/// Decompresses the file entries of an archive in order, handing each to `entries`
+struct Reader {
+    entries: mpsc::Sender<(PathBuf, Vec<u8>)>,
+    limits: ArchiveConfig,
+    /// Bytes decompressed so far
+    total: u64,
+}
+
+impl Reader {
+    fn zip(&mut self, bytes: Vec<u8>) -> Result<()> {
+        let mut archive = zip::ZipArchive::new(Cursor::new(bytes))?;
+
+        for index in 0..archive.len() {
+            let file = archive.by_index(index)?;
+            if file.is_dir() {
+                continue;
+            }
+            // Entries escaping the archive root are skipped rather than trusted
+            let Some(name) = file.enclosed_name() else {
+                warn!("Skipping {}, it points outside the archive", file.name());
+                continue;
+            };
+
+            let claimed = file.size();
+            if !self.read(name, claimed, file)? {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn tar(&mut self, reader: impl Read) -> Result<()> {
+        let mut archive = tar::Archive::new(reader);
+
+        for entry in archive.entries()? {
+            let entry = entry?;
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+            let raw = entry.path()?.into_owned();
+            let Some(name) = enclosed(&raw) else {
+                warn!("Skipping {}, it points outside the archive", raw.display());
+                continue;
+            };
+
+            let claimed = entry.size();
+            if !self.read(name, claimed, entry)? {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Decompresses an entry whose header claims `claimed` bytes and hands it on, skipping it
+    /// when it's past `max_entry_bytes`; false once nothing takes entries anymore
+    fn read(&mut self, name: PathBuf, claimed: u64, entry: impl Read) -> Result<bool> {
+        let room = self.limits.max_total_bytes.saturating_sub(self.total);
+        let limit = self.limits.max_entry_bytes.min(room);
+        let mut contents = Vec::with_capacity(claimed.min(limit).min(MAX_PREALLOCATION) as usize);
+        // One byte past the limit tells an entry that fits from one that doesn't
+        entry.take(limit + 1).read_to_end(&mut contents)?;
+        self.total += contents.len() as u64;
+
+        if contents.len() as u64 > limit {
+            if room <= self.limits.max_entry_bytes {
+                bail!("It decompresses to more than loaders.archive.max_total_bytes ({})", self.limits.max_total_bytes);
+            }
+            warn!(
+                "Skipping {}, it decompresses to more than loaders.archive.max_entry_bytes ({})",
+                name.display(), self.limits.max_entry_bytes,
+            );
+            return Ok(true);
+        }
+
+        Ok(self.entries.blocking_send((name, contents)).is_ok())
+    }
+}
+
+/// `path` if it stays below the archive root, as zip's `enclosed_name` decides for zips
+fn enclosed(path: &Path) -> Option<PathBuf> {
+    let mut depth = 0usize;
+    for component in path.components() {
+        match component {
+            Component::Normal(_) => depth += 1,
+            Component::CurDir => {}
+            Component::ParentDir => depth = depth.checked_sub(1)?,
+            Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+
+    (depth > 0).then(|| path.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use tokio_util::sync::CancellationToken;
+    use crate::config::LoadersConfig;
+    use super::*;
+
+    fn zip(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, contents) in entries {
+            writer.start_file(*name, zip::write::SimpleFileOptions::default()).unwrap();
+            writer.write_all(contents).unwrap();
+        }
+
+        writer.finish().unwrap().into_inner()
+    }
+
+    /// A gzipped tar; names are written into the header as they are, `..` included
+    fn tar_gz(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+        for (name, contents) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append(&header, *contents).unwrap();
+        }
+
+        builder.into_inner().unwrap().finish().unwrap()
+    }
+
+    async fn load_with(name: &str, kind: Kind, bytes: Vec<u8>, loaders: &LoadersConfig) -> Result<Vec<String>> {
+        let cancel = CancellationToken::new();
+        let options = Options { format: Default::default(), strict: false, cancel: &cancel, loaders };
+        let loaded = load(Path::new(name), kind, bytes, &options, &mut Selection::new(None, None)).await?;
+
+        Ok(loaded.documents.into_iter().map(|document| document.metadata.source).collect())
+    }
+
+    fn limits(max_entry_bytes: u64, max_total_bytes: u64) -> LoadersConfig {
+        LoadersConfig { archive: ArchiveConfig { max_entry_bytes, max_total_bytes }, ..LoadersConfig::default() }
+    }
+
+    #[tokio::test]
+    async fn routes_zip_entries_by_name() {
+        let bytes = zip(&[("docs/a.md", b"# A\nalpha"), ("notes.txt", b"ignored"), ("inner.zip", b"nested")]);
+
+        let sources = load_with("export.zip", Kind::Zip, bytes, &LoadersConfig::default()).await.unwrap();
+        assert_eq!(sources, ["export.zip/docs/a.md"]);
+    }
+
+    #[tokio::test]
+    async fn routes_tar_gz_entries_by_name() {
+        let bytes = tar_gz(&[("a.md", b"alpha"), ("b/c.md", b"gamma"), ("d.bin", b"\0")]);
+
+        let sources = load_with("export.tar.gz", Kind::TarGz, bytes, &LoadersConfig::default()).await.unwrap();
+        assert_eq!(sources, ["export.tar.gz/a.md", "export.tar.gz/b/c.md"]);
+    }
+
+    #[tokio::test]
+    async fn entries_escaping_the_root_are_skipped() {
+        let entries: [(&str, &[u8]); 3] = [("../evil.md", b"evil"), ("/etc/evil.md", b"evil"), ("ok/../fine.md", b"fine")];
+
+        let sources = load_with("export.zip", Kind::Zip, zip(&entries), &LoadersConfig::default()).await.unwrap();
+        assert_eq!(sources, ["export.zip/ok/../fine.md"]);
+        let sources = load_with("export.tgz", Kind::TarGz, tar_gz(&entries), &LoadersConfig::default()).await.unwrap();
+        assert_eq!(sources, ["export.tgz/ok/../fine.md"]);
+    }
+
+    #[tokio::test]
+    async fn oversized_entries_are_skipped() {
+        let big = vec![b'x'; 100];
+        let entries: [(&str, &[u8]); 3] = [("small.md", b"small"), ("big.md", &big), ("last.md", b"last")];
+
+        let sources = load_with("export.zip", Kind::Zip, zip(&entries), &limits(50, 1000)).await.unwrap();
+        assert_eq!(sources, ["export.zip/small.md", "export.zip/last.md"]);
+        let sources = load_with("export.tgz", Kind::TarGz, tar_gz(&entries), &limits(50, 1000)).await.unwrap();
+        assert_eq!(sources, ["export.tgz/small.md", "export.tgz/last.md"]);
+    }
+
+    #[tokio::test]
+    async fn archives_past_the_total_are_abandoned() {
+        let chunk = vec![b'x'; 40];
+        let entries: [(&str, &[u8]); 3] = [("a.md", &chunk), ("b.md", &chunk), ("c.md", &chunk)];
+
+        assert!(load_with("export.zip", Kind::Zip, zip(&entries), &limits(50, 100)).await.is_err());
+        assert!(load_with("export.tgz", Kind::TarGz, tar_gz(&entries), &limits(50, 100)).await.is_err());
+        assert!(load_with("export.zip", Kind::Zip, zip(&entries), &limits(50, 120)).await.is_ok());
+    }
+}
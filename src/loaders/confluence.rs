@@ -0,0 +1,218 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use quick_xml::escape::resolve_predefined_entity;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use scraper::{Html, Selector};
+use crate::clients::Document;
+use crate::loaders::{html, page_metadata, Loaded, Selection};
+
+const CONTENT_TYPE: &str = "text/html";
+
+/// One document per HTML page, with the breadcrumb trail of a Confluence HTML export when present
+pub fn load_html(name: &Path, bytes: &[u8], selection: &mut Selection) -> Result<Loaded> {
+    if selection.is_full() {
+        return Ok(Loaded::default());
+    }
+
+    let page = Html::parse_document(&String::from_utf8_lossy(bytes));
+    let select = |css: &str| Selector::parse(css).ok()
+        .and_then(|selector| page.select(&selector).next());
+
+    // Exported pages title themselves "Space : Page", the space already leading the breadcrumbs
+    let title = select("#title-text").or_else(|| select("title"))
+        .map(html::text)
+        .unwrap_or_default();
+    let title = title.split_once(" : ").map_or(title.as_str(), |(_, page)| page).to_string();
+
+    let breadcrumb = Selector::parse("#breadcrumbs li").map(|selector| page.select(&selector)
+        .map(html::text)
+        .filter(|crumb| !crumb.is_empty())
+        .collect())
+        .unwrap_or_default();
+
+    let text = select("#main-content").or_else(|| select("body"))
+        .map(html::text)
+        .unwrap_or_default();
+    if text.is_empty() || !selection.admit() {
+        return Ok(Loaded::default());
+    }
+
+    let metadata = page_metadata(name.display().to_string(), CONTENT_TYPE, &title, breadcrumb);
+    Ok(Loaded { documents: vec![Document { page_content: text, metadata, embeddings: vec![] }], skipped: 0 })
+}
+
+/// A top level `<object>` of `entities.xml`, properties holding either text or the id they refer to
+#[derive(Default)]
+struct Object {
+    class: String,
+    id: String,
+    properties: HashMap<String, String>,
+}
+
+impl Object {
+    fn get(&self, property: &str) -> Option<&str> {
+        self.properties.get(property).map(String::as_str)
+    }
+}
+
+struct Page {
+    title: String,
+    parent: Option<String>,
+    space: Option<String>,
+}
+
+/// One document per current page or blog post of a Confluence XML space export
+///
+/// Historical versions, drafts and deleted pages are left out; the breadcrumb is the space
+/// name followed by the page's ancestors.
+pub fn load_xml(name: &Path, bytes: &[u8], selection: &mut Selection) -> Result<Loaded> {
+    let xml = std::str::from_utf8(bytes)
+        .with_context(|| format!("{} is not UTF-8", name.display()))?;
+    let objects = objects(xml)
+        .with_context(|| format!("{} is not a Confluence XML export", name.display()))?;
+
+    let mut spaces = HashMap::new();
+    let mut pages = HashMap::new();
+    let mut bodies = HashMap::new();
+    let mut order = Vec::new();
+
+    for object in objects {
+        match object.class.as_str() {
+            "Space" => {
+                spaces.insert(object.id.clone(), object.get("name").unwrap_or_default().to_string());
+            }
+            "Page" | "BlogPost" => {
+                if object.get("originalVersion").is_some() || object.get("contentStatus").is_some_and(|s| s != "current") {
+                    continue;
+                }
+                order.push(object.id.clone());
+                pages.insert(object.id.clone(), Page {
+                    title: object.get("title").unwrap_or_default().to_string(),
+                    parent: object.get("parent").map(str::to_string),
+                    space: object.get("space").map(str::to_string),
+                });
+            }
+            "BodyContent" => {
+                if let (Some(content), Some(body)) = (object.get("content"), object.get("body")) {
+                    bodies.insert(content.to_string(), body.to_string());
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut documents = Vec::new();
+    for id in order {
+        if selection.is_full() {
+            break;
+        }
+        let (page, Some(body)) = (&pages[&id], bodies.get(&id)) else {
+            continue;
+        };
+
+        let text = html::text(Html::parse_fragment(body).root_element());
+        if text.is_empty() || !selection.admit() {
+            continue;
+        }
+
+        let mut breadcrumb = Vec::new();
+        let mut parent = page.parent.as_ref();
+        // Bounded by the page count, so a corrupt export with a parent cycle still terminates
+        while let Some(ancestor) = parent.and_then(|id| pages.get(id)) {
+            if breadcrumb.len() == pages.len() {
+                break;
+            }
+            breadcrumb.push(ancestor.title.clone());
+            parent = ancestor.parent.as_ref();
+        }
+        if let Some(space) = page.space.as_ref().and_then(|id| spaces.get(id)) {
+            breadcrumb.push(space.clone());
+        }
+        breadcrumb.reverse();
+
+        let source = format!("{}#{id}", name.display());
+        let metadata = page_metadata(source, CONTENT_TYPE, &page.title, breadcrumb);
+        documents.push(Document { page_content: text, metadata, embeddings: vec![] });
+    }
+
+    Ok(Loaded { documents, skipped: 0 })
+}
+
+/// Flattens the Hibernate dump into its objects, collections left out
+fn objects(xml: &str) -> Result<Vec<Object>> {
+    let mut reader = Reader::from_str(xml);
+    let mut objects = Vec::new();
+    let mut object: Option<Object> = None;
+    // Depth below the current object, and the property being read at depth one
+    let mut depth = 0;
+    let mut property: Option<(String, String)> = None;
+    let mut id: Option<String> = None;
+
+    loop {
+        match reader.read_event()? {
+            Event::Start(start) => {
+                let attribute = |key: &str| start.attributes().flatten()
+                    .find(|a| a.key.as_ref() == key)
+                    .map(|a| a.value.to_string())
+                    .unwrap_or_default();
+                match (&object, start.local_name().as_ref()) {
+                    (None, "object") => {
+                        object = Some(Object { class: attribute("class"), ..Default::default() });
+                        depth = 0;
+                    }
+                    (Some(_), tag) => {
+                        depth += 1;
+                        if depth == 1 && tag == "property" {
+                            property = Some((attribute("name"), String::new()));
+                        } else if depth == 1 && tag == "id" {
+                            id = Some(String::new());
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            Event::End(end) => match &mut object {
+                Some(current) if depth == 0 && end.local_name().as_ref() == "object" => {
+                    objects.push(std::mem::take(current));
+                    object = None;
+                }
+                Some(current) => {
+                    if depth == 1 {
+                        if let Some((name, value)) = property.take() {
+                            current.properties.insert(name, value.trim().to_string());
+                        }
+                        if let Some(value) = id.take() {
+                            current.id = value;
+                        }
+                    }
+                    depth -= 1;
+                }
+                None => {}
+            },
+            Event::Text(text) => push(&mut property, &mut id, &text.xml10_content()),
+            Event::CData(data) => push(&mut property, &mut id, &data.xml10_content()),
+            Event::GeneralRef(entity) => {
+                let resolved = match entity.resolve_char_ref()? {
+                    Some(c) => c.to_string(),
+                    None => resolve_predefined_entity(&entity.xml10_content()).unwrap_or_default().to_string(),
+                };
+                push(&mut property, &mut id, &resolved);
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    Ok(objects)
+}
+
+fn push(property: &mut Option<(String, String)>, id: &mut Option<String>, text: &str) {
+    if let Some((_, value)) = property {
+        value.push_str(text);
+    } else if let Some(value) = id {
+        value.push_str(text.trim());
+    }
+}
@@ -0,0 +1,165 @@
+use std::path::Path;
+
+use chrono::DateTime;
+use scraper::Html;
+use crate::clients::{Document, Metadata};
+use crate::loaders::{html, Loaded, Selection};
+use crate::loaders::xml::Element;
+
+/// One document per RSS item or Atom entry, with `title`, `link`, `published` and `feed` in the payload
+///
+/// The item's link is its source, so the same post is recognisable across feed refreshes.
+pub fn load(name: &Path, root: &Element, selection: &mut Selection) -> Loaded {
+    let atom = root.name == "feed";
+    let channel = if atom { Some(root) } else { root.descendants().into_iter().find(|element| element.name == "channel") };
+    let feed_title = channel.map(|channel| child(channel, "title")).unwrap_or_default();
+    let content_type = if atom { "application/atom+xml" } else { "application/rss+xml" };
+    let item_name = if atom { "entry" } else { "item" };
+    let items = root.descendants().into_iter().filter(|element| element.name == item_name);
+
+    let mut documents = Vec::new();
+    for (index, item) in items.enumerate() {
+        if selection.is_full() {
+            break;
+        }
+
+        // Full content when the feed has it, the summary otherwise; both usually hold HTML
+        let body = ["encoded", "content", "description", "summary"].iter()
+            .map(|tag| child(item, tag))
+            .find(|body| !body.is_empty())
+            .unwrap_or_default();
+        let title = child(item, "title");
+        let text = [title.clone(), html::text(Html::parse_fragment(&body).root_element())]
+            .into_iter()
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n");
+        if text.is_empty() || !selection.admit() {
+            continue;
+        }
+
+        let link = if atom { atom_link(item) } else { child(item, "link") };
+        let published = ["pubDate", "published", "updated", "date"].iter()
+            .map(|tag| child(item, tag))
+            .find(|date| !date.is_empty())
+            .map(normalise_date);
+
+        let mut metadata = Metadata {
+            source: if link.is_empty() { format!("{}#{}", name.display(), index + 1) } else { link.clone() },
+            content_type: content_type.to_string(),
+            ..Default::default()
+        };
+        metadata.extra.insert("title".to_string(), title.into());
+        metadata.extra.insert("link".to_string(), link.into());
+        metadata.extra.insert("feed".to_string(), feed_title.clone().into());
+        if let Some(published) = published {
+            metadata.extra.insert("published".to_string(), published.into());
+        }
+
+        documents.push(Document { page_content: text, metadata, embeddings: vec![] });
+    }
+
+    Loaded { documents, skipped: 0 }
+}
+
+/// Raw text of the first child named `tag`, in any namespace, so `content:encoded` matches `encoded`
+fn child(element: &Element, tag: &str) -> String {
+    element.elements()
+        .find(|child| child.name == tag)
+        .map(|child| child.raw_text().trim().to_string())
+        .unwrap_or_default()
+}
+
+/// The `alternate` link of an Atom entry, which is what a reader would open
+fn atom_link(entry: &Element) -> String {
+    entry.elements()
+        .filter(|child| child.name == "link")
+        .find(|link| link.attribute("rel").is_none_or(|rel| rel == "alternate"))
+        .and_then(|link| link.attribute("href"))
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// RFC 3339 for RSS's RFC 2822 dates and Atom's own, anything unparseable kept as written
+fn normalise_date(date: String) -> String {
+    DateTime::parse_from_rfc2822(&date)
+        .or_else(|_| DateTime::parse_from_rfc3339(&date))
+        .map(|date| date.to_rfc3339())
+        .unwrap_or(date)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load_feed(xml: &str) -> Vec<Document> {
+        let document = Element::parse(xml).unwrap();
+        let root = document.elements().next().unwrap();
+
+        load(Path::new("feed.xml"), root, &mut Selection::new(None, None)).documents
+    }
+
+    #[test]
+    fn rss_items_keep_their_title_link_and_date() {
+        let documents = load_feed(r#"<?xml version="1.0"?>
+            <rss version="2.0" xmlns:content="http://purl.org/rss/1.0/modules/content/">
+              <channel>
+                <title>Example blog</title>
+                <item>
+                  <title>First post</title>
+                  <link>https://example.com/first</link>
+                  <pubDate>Tue, 10 Jun 2025 04:00:00 GMT</pubDate>
+                  <description>Short</description>
+                  <content:encoded><![CDATA[<p>Full <b>text</b></p>]]></content:encoded>
+                </item>
+                <item>
+                  <title>Undated</title>
+                  <description>&lt;p&gt;Escaped markup&lt;/p&gt;</description>
+                </item>
+              </channel>
+            </rss>"#);
+
+        assert_eq!(documents.len(), 2);
+        let first = &documents[0];
+        assert_eq!(first.page_content, "First post\nFull text");
+        assert_eq!(first.metadata.source, "https://example.com/first");
+        assert_eq!(first.metadata.content_type, "application/rss+xml");
+        assert_eq!(first.metadata.extra["title"], "First post");
+        assert_eq!(first.metadata.extra["link"], "https://example.com/first");
+        assert_eq!(first.metadata.extra["feed"], "Example blog");
+        assert_eq!(first.metadata.extra["published"], "2025-06-10T04:00:00+00:00");
+
+        let second = &documents[1];
+        assert_eq!(second.page_content, "Undated\nEscaped markup");
+        assert_eq!(second.metadata.source, "feed.xml#2");
+        assert!(!second.metadata.extra.contains_key("published"));
+    }
+
+    #[test]
+    fn atom_entries_link_to_their_alternate() {
+        let documents = load_feed(r#"<feed xmlns="http://www.w3.org/2005/Atom">
+              <title>Example feed</title>
+              <entry>
+                <title>An entry</title>
+                <link rel="edit" href="https://example.com/edit/1"/>
+                <link rel="alternate" href="https://example.com/1"/>
+                <published>2025-06-10T04:00:00Z</published>
+                <summary>Summary only</summary>
+              </entry>
+              <entry>
+                <title>Plain link</title>
+                <link href="https://example.com/2"/>
+                <updated>not a date</updated>
+              </entry>
+            </feed>"#);
+
+        assert_eq!(documents.len(), 2);
+        assert_eq!(documents[0].page_content, "An entry\nSummary only");
+        assert_eq!(documents[0].metadata.source, "https://example.com/1");
+        assert_eq!(documents[0].metadata.content_type, "application/atom+xml");
+        assert_eq!(documents[0].metadata.extra["feed"], "Example feed");
+        assert_eq!(documents[0].metadata.extra["published"], "2025-06-10T04:00:00+00:00");
+        assert_eq!(documents[1].metadata.extra["link"], "https://example.com/2");
+        assert_eq!(documents[1].metadata.extra["published"], "not a date");
+    }
+}
@@ -0,0 +1,50 @@
+use ego_tree::NodeRef;
+use scraper::{ElementRef, Node};
+
+/// Elements whose contents never read as text
+const HIDDEN: &[&str] = &["script", "style", "head", "template", "noscript", "ac:parameter"];
+
+/// Elements that start on a line of their own
+const BLOCKS: &[&str] = &[
+    "address", "article", "aside", "blockquote", "br", "dd", "div", "dl", "dt", "figcaption",
+    "figure", "footer", "form", "h1", "h2", "h3", "h4", "h5", "h6", "header", "hr", "li", "main",
+    "nav", "ol", "p", "pre", "section", "table", "tr", "ul",
+];
+
+/// Readable text of an element, one line per block and whitespace collapsed within lines
+pub fn text(element: ElementRef) -> String {
+    let mut raw = String::new();
+    collect(*element, &mut raw);
+
+    raw.lines()
+        .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn collect(node: NodeRef<Node>, out: &mut String) {
+    for child in node.children() {
+        match child.value() {
+            Node::Text(text) => out.push_str(text),
+            Node::Element(element) => {
+                let name = element.name();
+                if HIDDEN.contains(&name) {
+                    continue;
+                }
+                let block = BLOCKS.contains(&name);
+                if block {
+                    out.push('\n');
+                }
+                if matches!(name, "td" | "th") {
+                    out.push(' ');
+                }
+                collect(child, out);
+                if block {
+                    out.push('\n');
+                }
+            }
+            _ => {}
+        }
+    }
+}
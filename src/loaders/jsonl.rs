@@ -0,0 +1,59 @@
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt};
+use tracing::warn;
+use crate::dialect::parse_document;
+use crate::loaders::{Loaded, Options, Selection};
+
+/// Reads one document per line of `reader`, `name` only appearing in messages
+///
+/// Malformed lines are logged and counted, or abort the read when `strict`.
+pub async fn load(
+    name: &Path,
+    reader: impl AsyncBufRead + Unpin,
+    options: &Options<'_>,
+    selection: &mut Selection,
+) -> Result<Loaded> {
+    let mut documents = Vec::new();
+    let mut skipped = 0;
+    let mut lines = reader.lines();
+    let mut number = 0;
+
+    while let Some(k) = lines.next_line().await
+        .with_context(|| format!("Failed reading {} after line {number}", name.display()))?
+    {
+        number += 1;
+        if options.cancel.is_cancelled() || selection.is_full() {
+            break;
+        }
+        if k.trim().is_empty() {
+            continue;
+        }
+
+        match parse_document(&k, options.format) {
+            Ok(doc) => if selection.admit() {
+                documents.push(doc);
+            },
+            Err(e) if options.strict => {
+                bail!("{}:{number}: {e} in {:?}", name.display(), preview(&k));
+            }
+            Err(e) => {
+                warn!("Skipping {}:{number}: {e} in {:?}", name.display(), preview(&k));
+                skipped += 1;
+            }
+        }
+    }
+
+    Ok(Loaded { documents, skipped })
+}
+
+/// The start of a line, cut on a char boundary, for log messages
+fn preview(line: &str) -> String {
+    const PREVIEW_CHARS: usize = 80;
+
+    match line.char_indices().nth(PREVIEW_CHARS) {
+        Some((end, _)) => format!("{}…", &line[..end]),
+        None => line.to_string(),
+    }
+}
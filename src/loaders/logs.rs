@@ -0,0 +1,194 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use chrono::{Duration, NaiveDateTime};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt};
+use crate::clients::{Document, Metadata};
+use crate::loaders::{Loaded, Options, Selection};
+
+const CONTENT_TYPE: &str = "text/x-log";
+
+/// Layouts a line's timestamp is recognised in, ISO 8601 style first
+const FORMATS: &[&str] = &[
+    "%Y-%m-%dT%H:%M:%S%.f",
+    "%Y-%m-%d %H:%M:%S%.f",
+    "%Y-%m-%dT%H:%M:%S",
+    "%Y-%m-%d %H:%M:%S",
+    // Common log format, as written by web servers
+    "%d/%b/%Y:%H:%M:%S",
+];
+
+/// Lines of one document, with the first and last timestamps seen among them
+struct Block {
+    lines: Vec<String>,
+    first_line: usize,
+    last_line: usize,
+    start: Option<NaiveDateTime>,
+    end: Option<NaiveDateTime>,
+}
+
+/// Streams `reader` into documents of consecutive lines
+///
+/// A document ends when a timestamped line falls more than `loaders.logs.window_secs`
+/// after the first timestamp in it, or at the first entry past `loaders.logs.max_lines` lines. Lines without
+/// a timestamp, such as stack traces, stay with the line before them.
+pub async fn load(
+    name: &Path,
+    mut reader: impl AsyncBufRead + Unpin,
+    options: &Options<'_>,
+    selection: &mut Selection,
+) -> Result<Loaded> {
+    let settings = &options.loaders.logs;
+    let window = (settings.window_secs > 0).then(|| Duration::seconds(settings.window_secs as i64));
+    let max_lines = settings.max_lines.max(1);
+
+    let mut documents = Vec::new();
+    let mut block: Option<Block> = None;
+    let mut buffer = Vec::new();
+    let mut number = 0;
+
+    loop {
+        buffer.clear();
+        let read = reader.read_until(b'\n', &mut buffer).await
+            .with_context(|| format!("Failed reading {} after line {number}", name.display()))?;
+        if options.cancel.is_cancelled() || selection.is_full() {
+            return Ok(Loaded { documents, skipped: 0 });
+        }
+        if read == 0 {
+            break;
+        }
+        number += 1;
+
+        // Logs are often mostly but not entirely UTF-8, and one bad byte shouldn't lose the file
+        let line = String::from_utf8_lossy(&buffer).trim_end().to_string();
+        if line.is_empty() {
+            continue;
+        }
+        let timestamp = timestamp(&line);
+
+        let ends_block = block.as_ref().is_some_and(|block| {
+            let outside = match (window, block.start, timestamp) {
+                (Some(window), Some(start), Some(at)) => at - start > window,
+                _ => false,
+            };
+            // Continuation lines are kept with their entry unless the log has no timestamps at all
+            let full = block.lines.len() >= max_lines && (timestamp.is_some() || block.start.is_none());
+            outside || full
+        });
+        if ends_block {
+            if let Some(document) = block.take().and_then(|block| finish(name, block, selection)) {
+                documents.push(document);
+            }
+        }
+
+        let block = block.get_or_insert_with(|| Block { lines: vec![], first_line: number, last_line: number, start: None, end: None });
+        block.lines.push(line);
+        block.last_line = number;
+        if timestamp.is_some() {
+            block.start = block.start.or(timestamp);
+            block.end = timestamp;
+        }
+    }
+
+    if let Some(document) = block.and_then(|block| finish(name, block, selection)) {
+        documents.push(document);
+    }
+
+    Ok(Loaded { documents, skipped: 0 })
+}
+
+/// The block as a document, with `first_line`, `last_line` and any `start` and `end` in the payload
+fn finish(name: &Path, block: Block, selection: &mut Selection) -> Option<Document> {
+    if !selection.admit() {
+        return None;
+    }
+
+    let mut metadata = Metadata {
+        source: name.display().to_string(),
+        content_type: CONTENT_TYPE.to_string(),
+        ..Default::default()
+    };
+    metadata.extra.insert("first_line".to_string(), block.first_line.into());
+    metadata.extra.insert("last_line".to_string(), block.last_line.into());
+    if let (Some(start), Some(end)) = (block.start, block.end) {
+        metadata.extra.insert("start".to_string(), start.format("%Y-%m-%dT%H:%M:%S%.f").to_string().into());
+        metadata.extra.insert("end".to_string(), end.format("%Y-%m-%dT%H:%M:%S%.f").to_string().into());
+    }
+
+    Some(Document { page_content: block.lines.join("\n"), metadata, embeddings: vec![] })
+}
+
+/// The time a line was logged, from its start or, for web server logs, its first bracket
+fn timestamp(line: &str) -> Option<NaiveDateTime> {
+    let start = line.trim_start_matches('[');
+    let bracketed = line.split_once('[').map(|(_, rest)| rest);
+
+    [Some(start), bracketed].into_iter().flatten()
+        .find_map(|text| FORMATS.iter()
+            .find_map(|format| NaiveDateTime::parse_and_remainder(text, format).ok())
+            .map(|(timestamp, _)| timestamp))
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio_util::sync::CancellationToken;
+    use crate::config::{LoadersConfig, LogsConfig};
+    use super::*;
+
+    async fn load_with(log: &str, window_secs: u64, max_lines: usize) -> Vec<Document> {
+        let cancel = CancellationToken::new();
+        let loaders = LoadersConfig { logs: LogsConfig { window_secs, max_lines }, ..LoadersConfig::default() };
+        let options = Options { format: Default::default(), strict: false, cancel: &cancel, loaders: &loaders };
+
+        load(Path::new("app.log"), log.as_bytes(), &options, &mut Selection::new(None, None)).await.unwrap().documents
+    }
+
+    fn texts(documents: &[Document]) -> Vec<&str> {
+        documents.iter().map(|document| document.page_content.as_str()).collect()
+    }
+
+    #[tokio::test]
+    async fn blocks_end_past_the_window() {
+        let log = "2024-05-01T10:00:00 start\n2024-05-01 10:00:30.5 within\n2024-05-01T10:01:01 outside\n";
+
+        let documents = load_with(log, 60, 100).await;
+        assert_eq!(texts(&documents), [
+            "2024-05-01T10:00:00 start\n2024-05-01 10:00:30.5 within",
+            "2024-05-01T10:01:01 outside",
+        ]);
+        assert_eq!(documents[0].metadata.extra["start"], "2024-05-01T10:00:00");
+        assert_eq!(documents[0].metadata.extra["end"], "2024-05-01T10:00:30.500");
+        assert_eq!(documents[1].metadata.extra["first_line"], 3);
+    }
+
+    #[tokio::test]
+    async fn blocks_end_at_max_lines_but_keep_their_continuation_lines() {
+        let log = "2024-05-01T10:00:00 one\n2024-05-01T10:00:01 two\n  at frame\n\n  at caller\n2024-05-01T10:00:02 three\n";
+
+        let documents = load_with(log, 0, 2).await;
+        assert_eq!(texts(&documents), [
+            "2024-05-01T10:00:00 one\n2024-05-01T10:00:01 two\n  at frame\n  at caller",
+            "2024-05-01T10:00:02 three",
+        ]);
+        assert_eq!(documents[0].metadata.extra["last_line"], 5);
+        assert_eq!(documents[1].metadata.extra["first_line"], 6);
+    }
+
+    #[tokio::test]
+    async fn logs_without_timestamps_are_cut_by_line_count() {
+        let documents = load_with("a\nb\nc\nd\ne\n", 300, 2).await;
+
+        assert_eq!(texts(&documents), ["a\nb", "c\nd", "e"]);
+        assert!(!documents[0].metadata.extra.contains_key("start"));
+    }
+
+    #[test]
+    fn bracketed_common_log_timestamps_are_recognised() {
+        let line = r#"127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] "GET /a.gif HTTP/1.0" 200 2326"#;
+        let expected = NaiveDateTime::parse_from_str("2000-10-10 13:55:36", "%Y-%m-%d %H:%M:%S").unwrap();
+
+        assert_eq!(timestamp(line), Some(expected));
+        assert_eq!(timestamp("[2024-05-01 10:00:00] INFO up").map(|at| at.to_string()).as_deref(), Some("2024-05-01 10:00:00"));
+        assert_eq!(timestamp("no time here [nor here]"), None);
+    }
+}
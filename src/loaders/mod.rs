@@ -0,0 +1,257 @@
+pub mod archive;
+pub mod confluence;
+pub mod feed;
+pub mod html;
+pub mod jsonl;
+pub mod logs;
+pub mod notebook;
+pub mod notion;
+pub mod openapi;
+pub mod subtitles;
+pub mod xml;
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use rand::rngs::StdRng;
+use rand::Rng;
+use tokio::fs::File;
+use tokio::io::BufReader;
+use tokio_util::sync::CancellationToken;
+use crate::clients::{Document, Metadata};
+use crate::config::LoadersConfig;
+use crate::dialect::DocumentFormat;
+use crate::seed;
+
+/// Settings every loader sees
+pub struct Options<'o> {
+    pub format: DocumentFormat,
+    pub strict: bool,
+    pub cancel: &'o CancellationToken,
+    pub loaders: &'o LoadersConfig,
+}
+
+/// Documents read from one input, and the number of malformed records skipped
+#[derive(Default)]
+pub struct Loaded {
+    pub documents: Vec<Document>,
+    pub skipped: u64,
+}
+
+impl Loaded {
+    pub fn extend(&mut self, other: Loaded) {
+        self.documents.extend(other.documents);
+        self.skipped += other.skipped;
+    }
+}
+
+/// The loader a file is routed to, decided by its name
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Jsonl,
+    Notebook,
+    Markdown,
+    Csv,
+    Html,
+    /// `entities.xml` of a Confluence XML space export
+    ConfluenceXml,
+    /// JSON or YAML named like `openapi.yaml` or `petstore.swagger.json`
+    OpenApi,
+    Subtitles,
+    Log,
+    /// XML in general, RSS and Atom feeds included
+    Xml,
+    Zip,
+    TarGz,
+    Tar,
+}
+
+impl Kind {
+    pub fn of(path: &Path) -> Option<Kind> {
+        let name = path.file_name()?.to_str()?.to_lowercase();
+        if name == "entities.xml" {
+            return Some(Kind::ConfluenceXml);
+        }
+        // Rotated logs such as `app.log.1` keep their extension before the counter
+        if name.rsplit_once(".log.").is_some_and(|(_, n)| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit())) {
+            return Some(Kind::Log);
+        }
+        let (stem, extension) = name.rsplit_once('.')?;
+        if matches!(extension, "json" | "yaml" | "yml") && (stem.contains("openapi") || stem.contains("swagger")) {
+            return Some(Kind::OpenApi);
+        }
+        let kind = match extension {
+            "jsonl" | "ndjson" => Kind::Jsonl,
+            "ipynb" => Kind::Notebook,
+            "md" | "markdown" => Kind::Markdown,
+            "csv" => Kind::Csv,
+            "html" | "htm" => Kind::Html,
+            "srt" | "vtt" => Kind::Subtitles,
+            "log" => Kind::Log,
+            "xml" | "rss" | "atom" => Kind::Xml,
+            "zip" => Kind::Zip,
+            "tgz" => Kind::TarGz,
+            "gz" if name.ends_with(".tar.gz") => Kind::TarGz,
+            "tar" => Kind::Tar,
+            _ => return None,
+        };
+
+        Some(kind)
+    }
+
+    pub fn is_archive(self) -> bool {
+        matches!(self, Kind::Zip | Kind::TarGz | Kind::Tar)
+    }
+}
+
+/// Loads one input file with the loader its name calls for, JSONL when nothing matches
+///
+/// An `http(s)://` path is downloaded first, so schedules can index feeds.
+pub async fn load_file(path: &Path, options: &Options<'_>, selection: &mut Selection) -> Result<Loaded> {
+    if let Some(url) = path.to_str().filter(|p| p.starts_with("http://") || p.starts_with("https://")) {
+        let bytes = fetch(url).await?;
+        let kind = Kind::of(path).unwrap_or_else(|| sniff(&bytes));
+        if kind.is_archive() {
+            return archive::load(path, kind, bytes, options, selection).await;
+        }
+        return load_bytes(path, kind, &bytes, options, selection).await;
+    }
+
+    match Kind::of(path).unwrap_or(Kind::Jsonl) {
+        Kind::Jsonl => {
+            let file = File::open(path).await
+                .with_context(|| format!("Failed to open {}", path.display()))?;
+            jsonl::load(path, BufReader::new(file), options, selection).await
+        }
+        Kind::Log => {
+            let file = File::open(path).await
+                .with_context(|| format!("Failed to open {}", path.display()))?;
+            logs::load(path, BufReader::new(file), options, selection).await
+        }
+        kind => {
+            let bytes = tokio::fs::read(path).await
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            if kind.is_archive() {
+                return archive::load(path, kind, bytes, options, selection).await;
+            }
+            load_bytes(path, kind, &bytes, options, selection).await
+        }
+    }
+}
+
+/// Loads an input already in memory, such as an archive entry named `name`
+pub async fn load_bytes(name: &Path, kind: Kind, bytes: &[u8], options: &Options<'_>, selection: &mut Selection) -> Result<Loaded> {
+    match kind {
+        Kind::Jsonl => jsonl::load(name, bytes, options, selection).await,
+        Kind::Notebook => notebook::load(name, bytes, selection),
+        Kind::Markdown => notion::load_markdown(name, bytes, selection),
+        Kind::Csv => notion::load_csv(name, bytes, selection),
+        Kind::Html => confluence::load_html(name, bytes, selection),
+        Kind::ConfluenceXml => confluence::load_xml(name, bytes, selection),
+        Kind::OpenApi => openapi::load(name, bytes, selection),
+        Kind::Subtitles => subtitles::load(name, bytes, selection),
+        Kind::Log => logs::load(name, bytes, options, selection).await,
+        Kind::Xml => xml::load(name, bytes, options, selection),
+        _ => bail!("{} is an archive inside an archive, which isn't supported", name.display()),
+    }
+}
+
+async fn fetch(url: &str) -> Result<Vec<u8>> {
+    let client = reqwest::Client::builder()
+        .user_agent(concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION")))
+        .build()?;
+    let response = client.get(url).send().await
+        .and_then(|response| response.error_for_status())
+        .with_context(|| format!("Failed to download {url}"))?;
+
+    Ok(response.bytes().await?.to_vec())
+}
+
+/// Markup for URLs like `/feed` that don't name their format, JSONL otherwise
+fn sniff(bytes: &[u8]) -> Kind {
+    let bytes = bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(bytes);
+    match bytes.iter().find(|b| !b.is_ascii_whitespace()) {
+        Some(b'<') => Kind::Xml,
+        _ => Kind::Jsonl,
+    }
+}
+
+/// Input files under `path`, in path order; a file path is returned as is
+pub fn discover(path: &Path) -> Result<Vec<PathBuf>> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+
+    let mut files = Vec::new();
+    let mut dirs = vec![path.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let entries = std::fs::read_dir(&dir)
+            .with_context(|| format!("Failed to list {}", dir.display()))?;
+        for entry in entries {
+            let path = entry?.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if is_supported(&path) {
+                files.push(path);
+            }
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+/// Whether some loader reads files like `path` when walking a directory
+fn is_supported(path: &Path) -> bool {
+    Kind::of(path).is_some()
+}
+
+/// Metadata of a knowledge-base page, `breadcrumb` naming its ancestors outermost first
+fn page_metadata(source: String, content_type: &str, title: &str, breadcrumb: Vec<String>) -> Metadata {
+    let mut metadata = Metadata {
+        source,
+        content_type: content_type.to_string(),
+        ..Default::default()
+    };
+    metadata.extra.insert("title".to_string(), title.into());
+    metadata.extra.insert("breadcrumb".to_string(), breadcrumb.into());
+    metadata
+}
+
+/// Which documents a run keeps, shared by every file it loads
+pub struct Selection {
+    sample: Option<f64>,
+    limit: Option<usize>,
+    kept: usize,
+    rng: StdRng,
+}
+
+impl Selection {
+    pub fn new(sample: Option<f64>, limit: Option<usize>) -> Self {
+        Self { sample, limit, kept: 0, rng: seed::rng("sample") }
+    }
+
+    /// Whether the limit has been reached and loading can stop
+    pub fn is_full(&self) -> bool {
+        self.limit.is_some_and(|limit| self.kept >= limit)
+    }
+
+    /// Decides on the next document, counting it when kept
+    pub fn admit(&mut self) -> bool {
+        if self.is_full() || self.sample.is_some_and(|p| !self.rng.gen_bool(p / 100.0)) {
+            return false;
+        }
+
+        self.kept += 1;
+        true
+    }
+
+    pub fn kept(&self) -> usize {
+        self.kept
+    }
+
+    /// Rolls the count back to `kept`, for documents of a file that was abandoned
+    pub fn rewind(&mut self, kept: usize) {
+        self.kept = kept;
+    }
+}
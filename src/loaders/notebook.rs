@@ -0,0 +1,97 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use serde_json::Value;
+use crate::clients::{Document, Metadata};
+use crate::loaders::{Loaded, Selection};
+
+const CONTENT_TYPE: &str = "application/x-ipynb+json";
+
+#[derive(Deserialize)]
+struct Notebook {
+    #[serde(default)]
+    cells: Vec<Cell>,
+    #[serde(default)]
+    metadata: NotebookMetadata,
+}
+
+#[derive(Default, Deserialize)]
+struct NotebookMetadata {
+    #[serde(default)]
+    kernelspec: Option<Value>,
+    #[serde(default)]
+    language_info: Option<Value>,
+}
+
+impl NotebookMetadata {
+    fn language(&self) -> String {
+        self.language_info.as_ref().and_then(|info| info.get("name"))
+            .or_else(|| self.kernelspec.as_ref().and_then(|spec| spec.get("language")))
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string()
+    }
+}
+
+#[derive(Deserialize)]
+struct Cell {
+    cell_type: String,
+    /// nbformat allows a single string or a list of lines
+    #[serde(default)]
+    source: Source,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(untagged)]
+enum Source {
+    #[default]
+    Empty,
+    Text(String),
+    Lines(Vec<String>),
+}
+
+impl Source {
+    fn into_text(self) -> String {
+        match self {
+            Source::Empty => String::new(),
+            Source::Text(text) => text,
+            Source::Lines(lines) => lines.concat(),
+        }
+    }
+}
+
+/// One document per non-empty markdown or code cell, with `cell_type` and `cell_index` in the payload
+pub fn load(name: &Path, bytes: &[u8], selection: &mut Selection) -> Result<Loaded> {
+    let notebook: Notebook = serde_json::from_slice(bytes)
+        .with_context(|| format!("{} is not a Jupyter notebook", name.display()))?;
+    let language = notebook.metadata.language();
+    let mut documents = Vec::new();
+
+    for (index, cell) in notebook.cells.into_iter().enumerate() {
+        if selection.is_full() {
+            break;
+        }
+        if cell.cell_type != "markdown" && cell.cell_type != "code" {
+            continue;
+        }
+
+        let text = cell.source.into_text();
+        if text.trim().is_empty() || !selection.admit() {
+            continue;
+        }
+
+        let mut metadata = Metadata {
+            source: name.display().to_string(),
+            content_type: CONTENT_TYPE.to_string(),
+            language: if cell.cell_type == "code" { language.clone() } else { String::new() },
+            ..Default::default()
+        };
+        metadata.extra.insert("cell_type".to_string(), cell.cell_type.into());
+        metadata.extra.insert("cell_index".to_string(), index.into());
+
+        documents.push(Document { page_content: text, metadata, embeddings: vec![] });
+    }
+
+    Ok(Loaded { documents, skipped: 0 })
+}
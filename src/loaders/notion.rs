@@ -0,0 +1,99 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use crate::clients::Document;
+use crate::loaders::{page_metadata, Loaded, Selection};
+
+const MARKDOWN: &str = "text/markdown";
+const CSV: &str = "text/csv";
+
+/// Length of the hex id Notion appends to every exported page and database name
+const ID_LEN: usize = 32;
+
+/// One document per markdown page, its Notion ancestors as the breadcrumb
+pub fn load_markdown(name: &Path, bytes: &[u8], selection: &mut Selection) -> Result<Loaded> {
+    let text = String::from_utf8_lossy(bytes).trim().to_string();
+    if text.is_empty() || !selection.admit() {
+        return Ok(Loaded::default());
+    }
+
+    // Notion starts each page with its title as a heading, which survives renamed files
+    let title = text.lines().next()
+        .and_then(|line| line.strip_prefix("# "))
+        .map(str::to_string)
+        .unwrap_or_else(|| page_title(name));
+
+    let metadata = page_metadata(name.display().to_string(), MARKDOWN, &title, breadcrumb(name));
+    Ok(Loaded { documents: vec![Document { page_content: text, metadata, embeddings: vec![] }], skipped: 0 })
+}
+
+/// One document per row of a database export, its properties as `Name: value` lines
+///
+/// The row is titled by its first column, Notion's title property, and the database
+/// itself ends the breadcrumb.
+pub fn load_csv(name: &Path, bytes: &[u8], selection: &mut Selection) -> Result<Loaded> {
+    // Exports carry a byte order mark that would otherwise stick to the first header
+    let bytes = bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(bytes);
+    let mut reader = csv::ReaderBuilder::new().flexible(true).from_reader(bytes);
+    let headers = reader.headers()
+        .with_context(|| format!("{} has no header row", name.display()))?
+        .clone();
+
+    let mut trail = breadcrumb(name);
+    trail.push(page_title(name));
+
+    let mut documents = Vec::new();
+    let mut skipped = 0;
+    for (row, record) in reader.records().enumerate() {
+        if selection.is_full() {
+            break;
+        }
+        let Ok(record) = record else {
+            skipped += 1;
+            continue;
+        };
+
+        let text = headers.iter().zip(record.iter())
+            .filter(|(_, value)| !value.trim().is_empty())
+            .map(|(header, value)| format!("{header}: {}", value.trim()))
+            .collect::<Vec<_>>()
+            .join("\n");
+        if text.is_empty() || !selection.admit() {
+            continue;
+        }
+
+        let title = record.get(0).unwrap_or_default().trim();
+        let source = format!("{}#{}", name.display(), row + 1);
+        let metadata = page_metadata(source, CSV, title, trail.clone());
+        documents.push(Document { page_content: text, metadata, embeddings: vec![] });
+    }
+
+    Ok(Loaded { documents, skipped })
+}
+
+/// Titles of the enclosing Notion pages, outermost first
+///
+/// Child pages are exported into a directory named like their parent, so the trail is every
+/// directory above `name` carrying a Notion id, up to the first one that doesn't.
+fn breadcrumb(name: &Path) -> Vec<String> {
+    let mut breadcrumb: Vec<String> = name.parent().into_iter()
+        .flat_map(Path::ancestors)
+        .map_while(|dir| dir.file_name()?.to_str().and_then(strip_id))
+        .map(str::to_string)
+        .collect();
+    breadcrumb.reverse();
+    breadcrumb
+}
+
+/// The file name without its extension or Notion id
+fn page_title(name: &Path) -> String {
+    let stem = name.file_stem().and_then(|stem| stem.to_str()).unwrap_or_default();
+    let stem = stem.strip_suffix("_all").unwrap_or(stem);
+    strip_id(stem).unwrap_or(stem).to_string()
+}
+
+/// `Title 0123…` with the trailing 32 hex digit id removed, when there is one
+fn strip_id(name: &str) -> Option<&str> {
+    let (title, id) = name.rsplit_once(' ')?;
+    (id.len() == ID_LEN && id.bytes().all(|b| b.is_ascii_hexdigit())).then_some(title)
+}
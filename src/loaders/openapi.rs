@@ -0,0 +1,170 @@
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use serde_json::{Map, Value};
+use crate::clients::{Document, Metadata};
+use crate::loaders::{Loaded, Selection};
+
+const CONTENT_TYPE: &str = "application/vnd.oai.openapi";
+
+const METHODS: &[&str] = &["get", "put", "post", "delete", "options", "head", "patch", "trace"];
+
+/// One document per operation and per schema of an OpenAPI 3 or Swagger 2 spec, JSON or YAML
+///
+/// Operations carry `method` and `path` in the payload, schemas carry `schema`; both carry
+/// the spec's `api` title.
+pub fn load(name: &Path, bytes: &[u8], selection: &mut Selection) -> Result<Loaded> {
+    // YAML is a superset of JSON, so one parser reads both
+    let spec: Value = serde_yaml::from_slice(bytes)
+        .with_context(|| format!("{} is not valid JSON or YAML", name.display()))?;
+    if spec.get("openapi").is_none() && spec.get("swagger").is_none() {
+        bail!("{} is not an OpenAPI or Swagger spec", name.display());
+    }
+
+    let api = text(&spec, &["info", "title"]);
+    let mut documents = Vec::new();
+
+    for (path, item) in spec.get("paths").and_then(Value::as_object).into_iter().flatten() {
+        let shared = item.get("parameters");
+        for method in METHODS {
+            let Some(operation) = item.get(*method) else {
+                continue;
+            };
+            if selection.is_full() {
+                return Ok(Loaded { documents, skipped: 0 });
+            }
+            if !selection.admit() {
+                continue;
+            }
+
+            let method = method.to_uppercase();
+            let mut metadata = metadata(format!("{}#{method} {path}", name.display()), &api);
+            metadata.extra.insert("method".to_string(), method.clone().into());
+            metadata.extra.insert("path".to_string(), path.clone().into());
+            if let Some(id) = operation.get("operationId") {
+                metadata.extra.insert("operation_id".to_string(), id.clone());
+            }
+            if let Some(tags) = operation.get("tags") {
+                metadata.extra.insert("tags".to_string(), tags.clone());
+            }
+
+            let page_content = describe_operation(&method, path, operation, shared);
+            documents.push(Document { page_content, metadata, embeddings: vec![] });
+        }
+    }
+
+    // Swagger 2 keeps schemas under `definitions`
+    let schemas = spec.pointer("/components/schemas").or_else(|| spec.get("definitions"));
+    for (schema, body) in schemas.and_then(Value::as_object).into_iter().flatten() {
+        if selection.is_full() {
+            break;
+        }
+        if !selection.admit() {
+            continue;
+        }
+
+        let mut metadata = metadata(format!("{}#schema {schema}", name.display()), &api);
+        metadata.extra.insert("schema".to_string(), schema.clone().into());
+
+        let page_content = describe_schema(schema, body);
+        documents.push(Document { page_content, metadata, embeddings: vec![] });
+    }
+
+    Ok(Loaded { documents, skipped: 0 })
+}
+
+fn metadata(source: String, api: &str) -> Metadata {
+    let mut metadata = Metadata {
+        source,
+        content_type: CONTENT_TYPE.to_string(),
+        ..Default::default()
+    };
+    metadata.extra.insert("api".to_string(), api.into());
+    metadata
+}
+
+/// `GET /pets` followed by the summary, description, parameters, body and responses
+fn describe_operation(method: &str, path: &str, operation: &Value, shared: Option<&Value>) -> String {
+    let mut lines = vec![format!("{method} {path}")];
+    lines.extend(["summary", "description"].iter().map(|key| text(operation, &[key])).filter(|s| !s.is_empty()));
+
+    let parameters = shared.and_then(Value::as_array).into_iter().flatten()
+        .chain(operation.get("parameters").and_then(Value::as_array).into_iter().flatten());
+    for parameter in parameters {
+        let required = if parameter.get("required").and_then(Value::as_bool).unwrap_or(false) { ", required" } else { "" };
+        lines.push(described(format!("Parameter {} ({}{required})", text_or_ref(parameter, "name"), text(parameter, &["in"])), parameter));
+    }
+
+    if let Some(body) = operation.get("requestBody") {
+        let types = body.get("content").and_then(Value::as_object)
+            .map(|content| content.iter()
+                .map(|(media, content)| format!("{media} {}", type_of(content.get("schema").unwrap_or(&Value::Null))))
+                .collect::<Vec<_>>()
+                .join(", "))
+            .unwrap_or_default();
+        lines.push(described(format!("Request body: {types}"), body));
+    }
+
+    for (status, response) in operation.get("responses").and_then(Value::as_object).into_iter().flatten() {
+        lines.push(format!("Response {status}: {}", text_or_ref(response, "description")));
+    }
+
+    lines.join("\n")
+}
+
+/// The schema name, its description and one line per property
+fn describe_schema(name: &str, schema: &Value) -> String {
+    let mut lines = vec![format!("Schema {name}")];
+    let description = text(schema, &["description"]);
+    if !description.is_empty() {
+        lines.push(description);
+    }
+
+    let required: Vec<&str> = schema.get("required").and_then(Value::as_array).into_iter().flatten()
+        .filter_map(Value::as_str)
+        .collect();
+    let empty = Map::new();
+    for (property, body) in schema.get("properties").and_then(Value::as_object).unwrap_or(&empty) {
+        let flag = if required.contains(&property.as_str()) { ", required" } else { "" };
+        lines.push(described(format!("Property {property} ({}{flag})", type_of(body)), body));
+    }
+
+    lines.join("\n")
+}
+
+/// A short type name: the target of a `$ref`, `array of X` or the declared `type`
+fn type_of(schema: &Value) -> String {
+    if let Some(target) = schema.get("$ref").and_then(Value::as_str) {
+        return target.rsplit('/').next().unwrap_or(target).to_string();
+    }
+    match schema.get("type").and_then(Value::as_str) {
+        Some("array") => format!("array of {}", type_of(schema.get("items").unwrap_or(&Value::Null))),
+        Some(kind) => kind.to_string(),
+        None => "object".to_string(),
+    }
+}
+
+/// The string at `path` below `value`, empty when missing
+fn text(value: &Value, path: &[&str]) -> String {
+    path.iter().try_fold(value, |value, key| value.get(key))
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .trim()
+        .to_string()
+}
+
+/// `label: description`, or just the label when `value` has no description
+fn described(label: String, value: &Value) -> String {
+    match text(value, &["description"]) {
+        description if description.is_empty() => label,
+        description => format!("{label}: {description}"),
+    }
+}
+
+/// The string under `key`, or the component name when `value` is a `$ref` to one
+fn text_or_ref(value: &Value, key: &str) -> String {
+    match text(value, &[key]) {
+        text if text.is_empty() && value.get("$ref").is_some() => type_of(value),
+        text => text,
+    }
+}
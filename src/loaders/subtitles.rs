@@ -0,0 +1,182 @@
+use std::path::Path;
+
+use anyhow::{bail, Result};
+use crate::clients::{Document, Metadata};
+use crate::loaders::{Loaded, Selection};
+
+/// Longest stretch of a video one document covers
+const WINDOW_SECS: f64 = 30.0;
+
+struct Cue {
+    start: f64,
+    end: f64,
+    text: String,
+}
+
+/// Documents of consecutive SRT or WebVTT cues spanning up to `WINDOW_SECS` each
+///
+/// `start` and `end` in the payload are seconds into the video.
+pub fn load(name: &Path, bytes: &[u8], selection: &mut Selection) -> Result<Loaded> {
+    let content_type = match name.extension().and_then(|e| e.to_str()).map(str::to_lowercase).as_deref() {
+        Some("vtt") => "text/vtt",
+        _ => "application/x-subrip",
+    };
+    let cues = cues(&String::from_utf8_lossy(bytes));
+    if cues.is_empty() {
+        bail!("{} has no subtitle cues", name.display());
+    }
+
+    let mut documents = Vec::new();
+    let mut window: Vec<&Cue> = Vec::new();
+    for cue in cues.iter().map(Some).chain([None]) {
+        if let (Some(first), Some(cue)) = (window.first(), cue) {
+            if cue.end - first.start <= WINDOW_SECS {
+                window.push(cue);
+                continue;
+            }
+        }
+
+        if let (Some(first), Some(last)) = (window.first(), window.last()) {
+            if selection.is_full() {
+                break;
+            }
+            if selection.admit() {
+                let mut metadata = Metadata {
+                    source: name.display().to_string(),
+                    content_type: content_type.to_string(),
+                    ..Default::default()
+                };
+                metadata.extra.insert("start".to_string(), first.start.into());
+                metadata.extra.insert("end".to_string(), last.end.into());
+
+                let mut lines: Vec<&str> = window.iter().map(|cue| cue.text.as_str()).collect();
+                // Rolling captions repeat the previous line before adding a new one
+                lines.dedup();
+                let page_content = lines.join("\n");
+                documents.push(Document { page_content, metadata, embeddings: vec![] });
+            }
+        }
+        window = cue.into_iter().collect();
+    }
+
+    Ok(Loaded { documents, skipped: 0 })
+}
+
+/// Cues with text, in file order; header, `NOTE` and `STYLE` blocks have no timing line and drop out
+fn cues(text: &str) -> Vec<Cue> {
+    let text = text.replace("\r\n", "\n");
+    let mut cues = Vec::new();
+
+    for block in text.split("\n\n") {
+        let mut lines = block.lines().skip_while(|line| !line.contains("-->"));
+        let Some(timing) = lines.next() else {
+            continue;
+        };
+        let Some((start, end)) = timing.split_once("-->") else {
+            continue;
+        };
+        // VTT cue settings such as `align:start` follow the end time
+        let end = end.split_whitespace().next().unwrap_or_default();
+        let (Some(start), Some(end)) = (timestamp(start.trim()), timestamp(end)) else {
+            continue;
+        };
+
+        let text = lines.map(strip_markup)
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<_>>()
+            .join(" ");
+        if !text.is_empty() {
+            cues.push(Cue { start, end, text });
+        }
+    }
+
+    cues
+}
+
+/// Seconds from `HH:MM:SS,mmm` (SRT) or `[HH:]MM:SS.mmm` (VTT)
+fn timestamp(value: &str) -> Option<f64> {
+    let value = value.replace(',', ".");
+    let mut parts = value.rsplit(':');
+    let seconds: f64 = parts.next()?.parse().ok()?;
+    let minutes: f64 = parts.next()?.parse().ok()?;
+    let hours: f64 = parts.next().map_or(Some(0.0), |hours| hours.parse().ok())?;
+
+    Some(hours * 3600.0 + minutes * 60.0 + seconds)
+}
+
+/// A cue line without `<i>`, `<v Speaker>` or `{\an8}` style markup
+fn strip_markup(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut closing = None;
+
+    for c in line.chars() {
+        match (closing, c) {
+            (None, '<') => closing = Some('>'),
+            (None, '{') => closing = Some('}'),
+            (Some(end), c) if c == end => closing = None,
+            (None, c) => out.push(c),
+            _ => {}
+        }
+    }
+
+    out.trim().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load_text(name: &str, text: &str) -> Vec<Document> {
+        load(Path::new(name), text.as_bytes(), &mut Selection::new(None, None)).unwrap().documents
+    }
+
+    #[test]
+    fn timestamps_read_either_separator_with_or_without_hours() {
+        assert_eq!(timestamp("01:02:03,500"), Some(3723.5));
+        assert_eq!(timestamp("01:02:03.500"), Some(3723.5));
+        assert_eq!(timestamp("02:03.250"), Some(123.25));
+        assert_eq!(timestamp("3.5"), None);
+        assert_eq!(timestamp("aa:03.250"), None);
+    }
+
+    #[test]
+    fn markup_is_stripped_from_cue_lines() {
+        assert_eq!(strip_markup("<i>Hello</i> there"), "Hello there");
+        assert_eq!(strip_markup("<v Roger Bingham>We are in New York"), "We are in New York");
+        assert_eq!(strip_markup("{\\an8} Up top "), "Up top");
+        assert_eq!(strip_markup("<b></b>"), "");
+    }
+
+    #[test]
+    fn srt_cues_are_grouped_into_windows() {
+        let srt = "1\r\n00:00:01,000 --> 00:00:04,000\r\nFirst\r\n\r\n\
+            2\r\n00:00:10,000 --> 00:00:31,000\r\n<i>Second</i>\r\n\r\n\
+            3\r\n00:00:30,000 --> 00:00:35,000\r\nThird\r\n";
+
+        let documents = load_text("talk.srt", srt);
+        let texts: Vec<_> = documents.iter().map(|document| document.page_content.as_str()).collect();
+        assert_eq!(texts, ["First\nSecond", "Third"]);
+        assert_eq!(documents[0].metadata.content_type, "application/x-subrip");
+        assert_eq!(documents[0].metadata.extra["start"], 1.0);
+        assert_eq!(documents[0].metadata.extra["end"], 31.0);
+        assert_eq!(documents[1].metadata.extra["start"], 30.0);
+    }
+
+    #[test]
+    fn rolling_vtt_captions_are_not_repeated() {
+        let vtt = "WEBVTT\n\nNOTE a comment\n\n\
+            00:01.000 --> 00:02.000 align:start\nwe rolled\n\n\
+            00:02.000 --> 00:03.000\nwe rolled\n\n\
+            00:03.000 --> 00:04.000\n<v Ann>along</v>\n";
+
+        let documents = load_text("talk.vtt", vtt);
+        assert_eq!(documents.len(), 1);
+        assert_eq!(documents[0].page_content, "we rolled\nalong");
+        assert_eq!(documents[0].metadata.content_type, "text/vtt");
+    }
+
+    #[test]
+    fn files_without_cues_are_an_error() {
+        assert!(load(Path::new("empty.vtt"), b"WEBVTT\n\nNOTE nothing here\n", &mut Selection::new(None, None)).is_err());
+    }
+}
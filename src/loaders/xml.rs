@@ -0,0 +1,306 @@
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use quick_xml::escape::resolve_predefined_entity;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::{Reader, XmlVersion};
+use crate::clients::{Document, Metadata};
+use crate::config::XmlMapping;
+use crate::loaders::{feed, Loaded, Options, Selection};
+
+const CONTENT_TYPE: &str = "application/xml";
+
+/// Reads RSS and Atom feeds as such, and other XML through the first `[[loaders.xml]]` mapping
+/// that selects any records
+pub fn load(name: &Path, bytes: &[u8], options: &Options<'_>, selection: &mut Selection) -> Result<Loaded> {
+    let text = std::str::from_utf8(bytes)
+        .with_context(|| format!("{} is not UTF-8", name.display()))?;
+    let document = Element::parse(text)
+        .with_context(|| format!("{} is not well-formed XML", name.display()))?;
+    let Some(root) = document.elements().next() else {
+        bail!("{} has no root element", name.display());
+    };
+
+    if matches!(root.name.as_str(), "rss" | "RDF" | "feed") {
+        return Ok(feed::load(name, root, selection));
+    }
+
+    for mapping in &options.loaders.xml {
+        let records = select(&document, &mapping.records);
+        if !records.is_empty() {
+            return Ok(map(name, mapping, records, selection));
+        }
+    }
+
+    bail!("{} is neither a feed nor matched by any [[loaders.xml]] mapping", name.display())
+}
+
+/// An element with what's inside it; names are local ones, without a namespace prefix, so
+/// `content:encoded` is `encoded`
+#[derive(Debug, Default)]
+pub struct Element {
+    pub name: String,
+    attributes: Vec<(String, String)>,
+    children: Vec<Node>,
+}
+
+#[derive(Debug)]
+enum Node {
+    Element(Element),
+    Text(String),
+}
+
+impl Element {
+    /// The document as a nameless element holding the root one
+    ///
+    /// DOCTYPEs, which feeds in the wild still carry, are skipped, and entities they declare
+    /// read as nothing.
+    pub fn parse(xml: &str) -> Result<Self> {
+        let mut reader = Reader::from_str(xml);
+        let mut open = vec![Element::default()];
+
+        loop {
+            match reader.read_event()? {
+                Event::Start(start) => open.push(element(&start)?),
+                Event::Empty(start) => {
+                    let empty = element(&start)?;
+                    innermost(&mut open).children.push(Node::Element(empty));
+                }
+                Event::End(_) => {
+                    let closed = open.pop().filter(|_| !open.is_empty()).context("An end tag closes nothing")?;
+                    innermost(&mut open).children.push(Node::Element(closed));
+                }
+                Event::Text(text) => innermost(&mut open).push_text(&text.xml10_content()),
+                Event::CData(data) => innermost(&mut open).push_text(&data.xml10_content()),
+                Event::GeneralRef(entity) => {
+                    let resolved = match entity.resolve_char_ref()? {
+                        Some(c) => c.to_string(),
+                        None => resolve_predefined_entity(&entity.xml10_content()).unwrap_or_default().to_string(),
+                    };
+                    innermost(&mut open).push_text(&resolved);
+                }
+                Event::Eof => break,
+                _ => {}
+            }
+        }
+
+        if open.len() > 1 {
+            bail!("<{}> is never closed", innermost(&mut open).name);
+        }
+        Ok(open.pop().unwrap_or_default())
+    }
+
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+    }
+
+    /// Child elements, in document order
+    pub fn elements(&self) -> impl Iterator<Item = &Element> {
+        self.children.iter().filter_map(|child| match child {
+            Node::Element(element) => Some(element),
+            Node::Text(_) => None,
+        })
+    }
+
+    /// Every element below this one, depth first
+    pub fn descendants(&self) -> Vec<&Element> {
+        let mut found = Vec::new();
+        for element in self.elements() {
+            found.push(element);
+            found.extend(element.descendants());
+        }
+
+        found
+    }
+
+    /// The text below this element, as written
+    pub fn raw_text(&self) -> String {
+        let mut text = String::new();
+        for child in &self.children {
+            match child {
+                Node::Text(part) => text.push_str(part),
+                Node::Element(element) => text.push_str(&element.raw_text()),
+            }
+        }
+
+        text
+    }
+
+    /// The text below this element, whitespace collapsed
+    pub fn text(&self) -> String {
+        self.raw_text().split_whitespace().collect::<Vec<_>>().join(" ")
+    }
+
+    /// Adds to the text node it ends with, since entities split text into several events
+    fn push_text(&mut self, text: &str) {
+        match self.children.last_mut() {
+            Some(Node::Text(last)) => last.push_str(text),
+            _ => self.children.push(Node::Text(text.to_string())),
+        }
+    }
+}
+
+fn element(start: &BytesStart) -> Result<Element> {
+    let name = start.local_name().as_ref().to_string();
+    let attributes = start.attributes()
+        .map(|attribute| {
+            let attribute = attribute?;
+            let key = attribute.key.local_name().as_ref().to_string();
+            Ok((key, attribute.normalized_value(XmlVersion::Implicit1_0)?.into_owned()))
+        })
+        .collect::<Result<_>>()?;
+
+    Ok(Element { name, attributes, children: Vec::new() })
+}
+
+fn innermost(open: &mut [Element]) -> &mut Element {
+    open.last_mut().expect("the document element is never closed")
+}
+
+fn map(name: &Path, mapping: &XmlMapping, records: Vec<&Element>, selection: &mut Selection) -> Loaded {
+    let mut documents = Vec::new();
+
+    for (index, record) in records.into_iter().enumerate() {
+        if selection.is_full() {
+            break;
+        }
+
+        let text = values(record, &mapping.text).join("\n");
+        if text.is_empty() || !selection.admit() {
+            continue;
+        }
+
+        let mut metadata = Metadata {
+            source: format!("{}#{}", name.display(), index + 1),
+            content_type: CONTENT_TYPE.to_string(),
+            ..Default::default()
+        };
+        for (field, path) in &mapping.fields {
+            if let Some(value) = values(record, path).into_iter().next() {
+                metadata.extra.insert(field.clone(), value.into());
+            }
+        }
+
+        documents.push(Document { page_content: text, metadata, embeddings: vec![] });
+    }
+
+    Loaded { documents, skipped: 0 }
+}
+
+/// Elements `path` selects from the document: `/a/b` from the root element, `//b/c` from any
+/// `b` element, `*` matching any name
+fn select<'e>(document: &'e Element, path: &str) -> Vec<&'e Element> {
+    let (anywhere, path) = match path.strip_prefix("//") {
+        Some(path) => (true, path),
+        None => (false, path.trim_start_matches('/')),
+    };
+    let mut steps = path.split('/').filter(|step| !step.is_empty());
+    let Some(first) = steps.next() else {
+        return vec![];
+    };
+
+    let mut elements: Vec<&Element> = if anywhere {
+        document.descendants().into_iter().filter(|element| matches(element, first)).collect()
+    } else {
+        document.elements().filter(|element| matches(element, first)).collect()
+    };
+    for step in steps {
+        elements = elements.iter().flat_map(|element| element.elements()).filter(|element| matches(element, step)).collect();
+    }
+
+    elements
+}
+
+/// Trimmed, non-empty values `path` reads below `element`: element text, or `@name` for an
+/// attribute
+fn values(element: &Element, path: &str) -> Vec<String> {
+    let (path, attribute) = match path.rsplit_once('@') {
+        Some((path, attribute)) => (path.trim_end_matches('/'), Some(attribute)),
+        None => (path, None),
+    };
+
+    let mut elements = vec![element];
+    for step in path.split('/').filter(|step| !step.is_empty() && *step != ".") {
+        elements = elements.iter().flat_map(|element| element.elements()).filter(|element| matches(element, step)).collect();
+    }
+
+    elements.into_iter()
+        .map(|element| match attribute {
+            Some(attribute) => element.attribute(attribute).unwrap_or_default().trim().to_string(),
+            None => element.text(),
+        })
+        .filter(|value| !value.is_empty())
+        .collect()
+}
+
+fn matches(element: &Element, step: &str) -> bool {
+    step == "*" || element.name == step
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use tokio_util::sync::CancellationToken;
+    use crate::config::LoadersConfig;
+    use super::*;
+
+    fn load_with(xml: &str, mappings: Vec<XmlMapping>) -> Result<Vec<Document>> {
+        let cancel = CancellationToken::new();
+        let loaders = LoadersConfig { xml: mappings, ..LoadersConfig::default() };
+        let options = Options { format: Default::default(), strict: false, cancel: &cancel, loaders: &loaders };
+
+        Ok(load(Path::new("books.xml"), xml.as_bytes(), &options, &mut Selection::new(None, None))?.documents)
+    }
+
+    fn mapping(records: &str, text: &str, fields: &[(&str, &str)]) -> XmlMapping {
+        XmlMapping {
+            records: records.to_string(),
+            text: text.to_string(),
+            fields: fields.iter().map(|(field, path)| (field.to_string(), path.to_string())).collect::<BTreeMap<_, _>>(),
+        }
+    }
+
+    const BOOKS: &str = r#"<?xml version="1.0"?>
+        <!DOCTYPE library>
+        <lib:library xmlns:lib="urn:library">
+            <shelf>
+                <book id="b1" lang="en"><title>Dune</title><blurb>Sand &amp;
+                    <em>spice</em></blurb></book>
+                <book id="b2"><title>Empty</title><blurb>  </blurb></book>
+                <book id="b3"><title><![CDATA[<Solaris>]]></title><blurb>Ocean &#8212; planet</blurb></book>
+            </shelf>
+        </lib:library>"#;
+
+    #[test]
+    fn records_and_fields_are_read_through_their_paths() {
+        let documents = load_with(BOOKS, vec![
+            mapping("/missing/book", "title", &[]),
+            mapping("/library/shelf/book", "blurb", &[("title", "title"), ("id", "@id"), ("lang", "./@lang")]),
+        ]).unwrap();
+
+        let texts: Vec<_> = documents.iter().map(|document| document.page_content.as_str()).collect();
+        assert_eq!(texts, ["Sand & spice", "Ocean \u{2014} planet"]);
+        assert_eq!(documents[0].metadata.source, "books.xml#1");
+        assert_eq!(documents[1].metadata.source, "books.xml#3");
+        assert_eq!(documents[0].metadata.extra["title"], "Dune");
+        assert_eq!(documents[0].metadata.extra["id"], "b1");
+        assert_eq!(documents[0].metadata.extra["lang"], "en");
+        assert_eq!(documents[1].metadata.extra["title"], "<Solaris>");
+        assert!(!documents[1].metadata.extra.contains_key("lang"));
+    }
+
+    #[test]
+    fn double_slashes_find_records_anywhere() {
+        let documents = load_with(BOOKS, vec![mapping("//shelf/*", "title", &[])]).unwrap();
+
+        assert_eq!(documents.len(), 3);
+    }
+
+    #[test]
+    fn unmatched_and_malformed_xml_is_an_error() {
+        assert!(load_with(BOOKS, vec![mapping("/library/book", "title", &[])]).is_err());
+        assert!(load_with("<library><book></library>", vec![mapping("//book", ".", &[])]).is_err());
+        assert!(load_with("<library><book>", vec![mapping("//book", ".", &[])]).is_err());
+    }
+}
@@ -0,0 +1,86 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
+use crate::config::MetricsSnapshotsConfig;
+use crate::control::{Control, State};
+use crate::profiling::Usage;
+
+/// Snapshot file names start with this, followed by the time they were taken at
+const PREFIX: &str = "metrics-";
+
+/// One snapshot file
+#[derive(Serialize)]
+struct Snapshot {
+    at: DateTime<Utc>,
+    pid: u32,
+    /// The `/admin/state` numbers: pause, queues and throughput
+    state: State,
+    usage: Usage,
+}
+
+/// Writes a snapshot into `config.dir` every `config.interval_secs` until the returned task
+/// is aborted, keeping the latest `config.retain` of them
+pub fn spawn(config: &MetricsSnapshotsConfig, control: Arc<Control>) -> Option<JoinHandle<()>> {
+    if config.interval_secs == 0 {
+        return None;
+    }
+
+    let config = config.clone();
+    Some(tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(config.interval_secs));
+        loop {
+            interval.tick().await;
+            if let Err(e) = write(&config, &control).await {
+                warn!("Couldn't write a metrics snapshot to {}: {e:?}", config.dir.display());
+            }
+        }
+    }))
+}
+
+async fn write(config: &MetricsSnapshotsConfig, control: &Control) -> Result<()> {
+    let snapshot = Snapshot {
+        at: Utc::now(),
+        pid: std::process::id(),
+        state: control.state(),
+        usage: Usage::now(),
+    };
+    tokio::fs::create_dir_all(&config.dir).await?;
+
+    // Timestamps with a fixed width sort by name in the order they were taken
+    let name = format!("{PREFIX}{}.json", snapshot.at.format("%Y%m%dT%H%M%S%.3fZ"));
+    let path = config.dir.join(name);
+    // Written aside and renamed, so a crash never leaves half a snapshot behind
+    let partial = path.with_extension("json.partial");
+    tokio::fs::write(&partial, serde_json::to_vec_pretty(&snapshot)?).await?;
+    tokio::fs::rename(&partial, &path).await.with_context(|| format!("Couldn't move {} into place", partial.display()))?;
+    debug!("Wrote metrics snapshot {}", path.display());
+
+    prune(&config.dir, config.retain).await
+}
+
+/// Deletes all but the newest `retain` snapshots in `dir`
+async fn prune(dir: &Path, retain: usize) -> Result<()> {
+    let mut snapshots: Vec<PathBuf> = Vec::new();
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if name.starts_with(PREFIX) && name.ends_with(".json") {
+            snapshots.push(entry.path());
+        }
+    }
+    snapshots.sort();
+
+    let excess = snapshots.len().saturating_sub(retain.max(1));
+    for path in &snapshots[..excess] {
+        tokio::fs::remove_file(path).await.with_context(|| format!("Couldn't delete {}", path.display()))?;
+    }
+
+    Ok(())
+}
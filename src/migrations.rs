@@ -0,0 +1,95 @@
+use crate::clients::vector_store::{content_hash, CONTENT_FIELD, CONTENT_HASH_FIELD};
+
+/// Payload field holding the schema version a point's payload was written in; payloads
+/// without one predate versioning and count as version 0
+pub const SCHEMA_VERSION_FIELD: &str = "schema_version";
+
+type Payload = serde_json::Map<String, serde_json::Value>;
+
+/// One change to the payload schema, bringing a payload from `version - 1` to `version`
+pub struct Migration {
+    pub version: u64,
+    pub description: &'static str,
+    apply: fn(&mut Payload),
+}
+
+/// Every schema change so far, oldest first; a new one goes at the end with the next version
+pub const MIGRATIONS: &[Migration] = &[
+    Migration { version: 1, description: "Hash the text of points stored before hashes were", apply: hash_content },
+];
+
+/// Version of the payloads this build writes
+pub const SCHEMA_VERSION: u64 = MIGRATIONS.len() as u64;
+
+/// The schema version `payload` was written in
+pub fn version(payload: &Payload) -> u64 {
+    payload.get(SCHEMA_VERSION_FIELD).and_then(|version| version.as_u64()).unwrap_or(0)
+}
+
+/// Brings `payload` up to `SCHEMA_VERSION`, returning the migrations that were applied
+pub fn migrate(payload: &mut Payload) -> Vec<&'static Migration> {
+    let from = version(payload);
+    let pending: Vec<&Migration> = MIGRATIONS.iter().filter(|migration| migration.version > from).collect();
+    for migration in &pending {
+        (migration.apply)(payload);
+        payload.insert(SCHEMA_VERSION_FIELD.to_string(), migration.version.into());
+    }
+
+    pending
+}
+
+fn hash_content(payload: &mut Payload) {
+    if payload.contains_key(CONTENT_HASH_FIELD) {
+        return;
+    }
+    if let Some(content) = payload.get(CONTENT_FIELD).and_then(|content| content.as_str()) {
+        let hash = content_hash(content);
+        payload.insert(CONTENT_HASH_FIELD.to_string(), hash.into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use super::*;
+
+    fn payload(value: serde_json::Value) -> Payload {
+        value.as_object().cloned().unwrap()
+    }
+
+    #[test]
+    fn payloads_without_a_version_predate_versioning() {
+        assert_eq!(version(&payload(json!({ CONTENT_FIELD: "text" }))), 0);
+        assert_eq!(version(&payload(json!({ SCHEMA_VERSION_FIELD: 1 }))), 1);
+    }
+
+    #[test]
+    fn migrating_hashes_the_text_and_stamps_the_version() {
+        let mut migrated = payload(json!({ CONTENT_FIELD: "text" }));
+
+        let applied = migrate(&mut migrated);
+
+        assert_eq!(applied.iter().map(|migration| migration.version).collect::<Vec<_>>(), [1]);
+        assert_eq!(migrated[CONTENT_HASH_FIELD], content_hash("text"));
+        assert_eq!(version(&migrated), SCHEMA_VERSION);
+        assert!(migrate(&mut migrated).is_empty());
+    }
+
+    #[test]
+    fn an_existing_hash_is_kept() {
+        let mut migrated = payload(json!({ CONTENT_FIELD: "text", CONTENT_HASH_FIELD: "kept" }));
+
+        migrate(&mut migrated);
+
+        assert_eq!(migrated[CONTENT_HASH_FIELD], "kept");
+    }
+
+    #[test]
+    fn payloads_of_a_newer_schema_are_left_alone() {
+        let newer = payload(json!({ CONTENT_FIELD: "text", SCHEMA_VERSION_FIELD: SCHEMA_VERSION + 1 }));
+        let mut migrated = newer.clone();
+
+        assert!(migrate(&mut migrated).is_empty());
+        assert_eq!(migrated, newer);
+    }
+}
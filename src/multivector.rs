@@ -0,0 +1,65 @@
+use anyhow::{anyhow, bail, Result};
+use crate::chunking;
+use crate::clients::llm::Embedding;
+use crate::config::{Config, MultivectorConfig, StoreKind};
+
+/// Embeds a document's text, with `qdrant.multivector` as the vectors of its sub-chunks one
+/// after the other, each `qdrant.dimensions` long; an empty vector means there is no
+/// embedding, as with `Embedding::embed_document`
+pub async fn embed(embedder: &dyn Embedding, config: &Config, text: &str) -> Result<Vec<f32>> {
+    if !enabled(config) {
+        return embedder.embed_document(text).await;
+    }
+    let dimensions = dimensions(config)?;
+
+    let mut vectors = Vec::new();
+    for piece in pieces(&config.qdrant.multivector, text) {
+        let vector = embedder.embed_document(&piece).await?;
+        if vector.is_empty() {
+            return Ok(vec![]);
+        }
+        if vector.len() != dimensions {
+            bail!("A sub-chunk embedded to {} dimensions, qdrant.dimensions is {dimensions}", vector.len());
+        }
+        vectors.extend(vector);
+    }
+
+    Ok(vectors)
+}
+
+/// Whether documents are embedded as multivectors, which only Qdrant stores
+pub fn enabled(config: &Config) -> bool {
+    config.qdrant.multivector.enabled && config.store == StoreKind::Qdrant
+}
+
+/// Size of each vector of a multivector embedding
+pub fn dimensions(config: &Config) -> Result<usize> {
+    config.qdrant.dimensions
+        .map(|dimensions| dimensions as usize)
+        .ok_or_else(|| anyhow!("qdrant.multivector needs qdrant.dimensions"))
+}
+
+/// The vectors of a multivector embedding, one per sub-chunk
+pub fn split(vectors: &[f32], dimensions: usize) -> Vec<Vec<f32>> {
+    vectors.chunks(dimensions.max(1)).map(<[f32]>::to_vec).collect()
+}
+
+/// The vector that stands for a whole embedding where only one fits, like in its provenance:
+/// the first sub-chunk's of a multivector one
+pub fn head<'v>(config: &Config, vectors: &'v [f32]) -> &'v [f32] {
+    match dimensions(config) {
+        Ok(dimensions) if enabled(config) => &vectors[..dimensions.min(vectors.len())],
+        _ => vectors,
+    }
+}
+
+fn pieces(config: &MultivectorConfig, text: &str) -> Vec<String> {
+    let mut pieces = chunking::chunk(text, config.sub_chunk_size, config.sub_chunk_overlap);
+    pieces.truncate(config.max_sub_chunks.max(1));
+    // Blank text still gets a vector, like it would without sub-chunks
+    if pieces.is_empty() {
+        pieces.push(text.to_string());
+    }
+
+    pieces
+}
@@ -0,0 +1,179 @@
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+use base64::Engine;
+use base64::prelude::BASE64_STANDARD;
+use chrono::Utc;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::TlsConnector;
+use crate::config::{Email, SmtpTls, Trigger};
+use crate::history::RunRecord;
+use crate::notify;
+use crate::secret::Secret;
+use crate::tls;
+
+/// Either side of a STARTTLS upgrade
+trait Connection: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<C: AsyncRead + AsyncWrite + Unpin + Send> Connection for C {}
+
+/// An SMTP session with the relay
+struct Session {
+    stream: BufReader<Box<dyn Connection>>,
+}
+
+/// Mails `record` to every recipient of `email`
+///
+/// Speaks just enough SMTP for an internal relay: STARTTLS or implicit TLS, and AUTH PLAIN
+/// only, which the config refuses without TLS.
+pub async fn send(email: &Email, record: &RunRecord, reasons: &[Trigger]) -> Result<()> {
+    let stream: Box<dyn Connection> = Box::new(TcpStream::connect(&email.smtp).await
+        .with_context(|| format!("Failed to connect to {}", email.smtp))?);
+    let mut session = match email.tls {
+        SmtpTls::Implicit => Session::new(secure(email, stream).await?),
+        _ => Session::new(stream),
+    };
+
+    session.reply('2').await?;
+    session.command("EHLO rag-rs", '2').await?;
+    if email.tls == SmtpTls::Starttls {
+        session.command("STARTTLS", '2').await?;
+        // Anything the relay sent past its reply is dropped with the buffer, as it came in the clear
+        session = Session::new(secure(email, session.stream.into_inner()).await?);
+        session.command("EHLO rag-rs", '2').await?;
+    }
+    if let Some(username) = &email.username {
+        let password = email.password.as_ref().map_or("", Secret::expose);
+        let token = BASE64_STANDARD.encode(format!("\0{username}\0{password}"));
+        session.command(&format!("AUTH PLAIN {token}"), '2').await?;
+    }
+    session.command(&format!("MAIL FROM:<{}>", email.from), '2').await?;
+    for to in &email.to {
+        session.command(&format!("RCPT TO:<{to}>"), '2').await?;
+    }
+    session.command("DATA", '3').await?;
+    session.stream.write_all(mail(email, record, reasons)?.as_bytes()).await?;
+    session.command(".", '2').await?;
+    // The mail is accepted, the relay hanging up early doesn't matter anymore
+    _ = session.command("QUIT", '2').await;
+
+    Ok(())
+}
+
+/// `stream` after a TLS handshake with the relay, its certificate checked for the host in `smtp`
+async fn secure(email: &Email, stream: Box<dyn Connection>) -> Result<Box<dyn Connection>> {
+    let config = tls::client_config()?;
+
+    let host = email.smtp.rsplit_once(':').map_or(email.smtp.as_str(), |(host, _)| host);
+    let name = ServerName::try_from(host.trim_start_matches('[').trim_end_matches(']').to_string())
+        .with_context(|| format!("{host} isn't a name a certificate can be checked for"))?;
+    let stream = TlsConnector::from(Arc::new(config)).connect(name, stream).await
+        .with_context(|| format!("TLS handshake with {} failed", email.smtp))?;
+
+    Ok(Box::new(stream))
+}
+
+impl Session {
+    fn new(stream: Box<dyn Connection>) -> Self {
+        Self { stream: BufReader::new(stream) }
+    }
+
+    async fn command(&mut self, line: &str, expected: char) -> Result<()> {
+        self.stream.write_all(format!("{line}\r\n").as_bytes()).await?;
+        // TLS holds writes back until flushed
+        self.stream.flush().await?;
+        self.reply(expected).await
+    }
+
+    /// Reads a possibly multi-line reply, failing unless its code starts with `expected`
+    async fn reply(&mut self, expected: char) -> Result<()> {
+        loop {
+            let mut line = String::new();
+            if self.stream.read_line(&mut line).await? == 0 {
+                bail!("The SMTP relay hung up");
+            }
+            if !line.starts_with(expected) {
+                bail!("The SMTP relay answered {:?}", line.trim_end());
+            }
+            // "250-..." continues, "250 ..." ends the reply
+            if line.as_bytes().get(3) != Some(&b'-') {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Headers and body of the mail, dot-stuffed with CRLF line endings and ready for DATA
+fn mail(email: &Email, record: &RunRecord, reasons: &[Trigger]) -> Result<String> {
+    let body = format!(
+        "{}\n\n{}",
+        notify::message(record, reasons),
+        serde_json::to_string_pretty(record)?,
+    );
+    let mut mail = format!(
+        "From: {}\r\nTo: {}\r\nSubject: Ingestion run {}: {}\r\nDate: {}\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n",
+        email.from,
+        email.to.join(", "),
+        record.id,
+        record.exit,
+        Utc::now().to_rfc2822(),
+    );
+    for line in body.lines() {
+        if line.starts_with('.') {
+            mail.push('.');
+        }
+        mail.push_str(line);
+        mail.push_str("\r\n");
+    }
+
+    Ok(mail)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::outcome::{Exit, RunSummary};
+    use super::*;
+
+    fn email() -> Email {
+        toml::from_str("smtp = \"relay:25\"\nfrom = \"rag@example.com\"\nto = [\"a@example.com\", \"b@example.com\"]").unwrap()
+    }
+
+    fn record(path: &str) -> RunRecord {
+        RunRecord {
+            id: 7,
+            started_at: Utc::now(),
+            finished_at: Utc::now(),
+            path: path.into(),
+            exit: Exit::Success,
+            error_rate: 0.0,
+            summary: RunSummary::default(),
+            config_hash: String::new(),
+        }
+    }
+
+    #[test]
+    fn every_line_ends_in_crlf() {
+        let mail = mail(&email(), &record("docs"), &[Trigger::Completed]).unwrap();
+
+        assert!(mail.ends_with("\r\n"));
+        assert!(!mail.replace("\r\n", "").contains(['\r', '\n']), "{mail:?} has a bare line ending");
+        let (headers, body) = mail.split_once("\r\n\r\n").unwrap();
+        assert!(headers.contains("To: a@example.com, b@example.com\r\n"));
+        assert!(headers.contains("Subject: Ingestion run 7: "));
+        assert!(body.starts_with("Ingestion run 7 of docs ended in "));
+    }
+
+    #[test]
+    fn lines_starting_with_a_dot_are_stuffed() {
+        // A lone dot would otherwise end the mail early
+        let mail = mail(&email(), &record("docs\n.\n.hidden\r\nend"), &[Trigger::Completed]).unwrap();
+
+        let lines: Vec<&str> = mail.split("\r\n").collect();
+        assert!(lines.contains(&".."));
+        assert!(lines.contains(&"..hidden"));
+        assert!(!lines.contains(&"."));
+        assert!(!mail.replace("\r\n", "").contains(['\r', '\n']));
+    }
+}
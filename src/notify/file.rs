@@ -0,0 +1,22 @@
+use anyhow::Result;
+use tokio::io::AsyncWriteExt;
+use crate::config::{NotificationFile, Trigger};
+use crate::history::RunRecord;
+use crate::notify::Payload;
+
+/// Appends `record` to the notification file as one JSON line
+pub async fn append(file: &NotificationFile, record: &RunRecord, reasons: &[Trigger]) -> Result<()> {
+    let mut line = serde_json::to_vec(&Payload { reasons, run: record })?;
+    line.push(b'\n');
+
+    let mut out = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&file.path)
+        .await?;
+    out.write_all(&line).await?;
+    // Tokio writes in the background; the line is only down once flushed
+    out.flush().await?;
+
+    Ok(())
+}
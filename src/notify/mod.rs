@@ -0,0 +1,261 @@
+use std::future::Future;
+use std::time::Duration;
+
+use anyhow::Result;
+use serde::Serialize;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, warn};
+use crate::config::{Config, Email, NotificationFile, PushgatewayConfig, Trigger, Webhook};
+use crate::events::{Events, PipelineEvent};
+use crate::history::RunRecord;
+use crate::outcome::Exit;
+
+pub mod email;
+pub mod file;
+pub mod pushgateway;
+pub mod webhook;
+
+/// How long one delivery may take
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// Deliveries of a notification before it is given up on
+const ATTEMPTS: u32 = 3;
+
+/// A finished run as webhooks and notification files get it
+#[derive(Serialize)]
+pub struct Payload<'p> {
+    pub reasons: &'p [Trigger],
+    pub run: &'p RunRecord,
+}
+
+/// Where the outcomes of runs go
+#[derive(Clone)]
+struct Sinks {
+    webhooks: Vec<Webhook>,
+    emails: Vec<Email>,
+    files: Vec<NotificationFile>,
+    pushgateway: PushgatewayConfig,
+    profile: Option<String>,
+}
+
+/// Tells the configured webhooks, mailboxes, files and pushgateway how every run of the
+/// process ended
+pub struct Notifier {
+    done: CancellationToken,
+    task: JoinHandle<()>,
+}
+
+impl Notifier {
+    /// Subscribes to `events`, or does nothing without any notifications configured
+    pub fn spawn(config: &Config, events: &Events) -> Result<Option<Self>> {
+        let configured = !config.webhooks.is_empty()
+            || !config.emails.is_empty()
+            || !config.notification_files.is_empty()
+            || config.pushgateway.url.is_some();
+        if !configured {
+            return Ok(None);
+        }
+
+        let mut events = events.subscribe();
+        let sinks = Sinks {
+            webhooks: config.webhooks.clone(),
+            emails: config.emails.clone(),
+            files: config.notification_files.clone(),
+            pushgateway: config.pushgateway.clone(),
+            profile: config.profile.clone(),
+        };
+        let http = reqwest::Client::builder().timeout(TIMEOUT).build()?;
+        let done = CancellationToken::new();
+        let stop = done.clone();
+
+        let task = tokio::spawn(async move {
+            loop {
+                let event = tokio::select! {
+                    biased;
+                    event = events.recv() => event,
+                    // What was published before `finish` still goes out
+                    _ = stop.cancelled() => match events.try_recv() {
+                        Ok(event) => Ok(event),
+                        Err(_) => break,
+                    },
+                };
+                match event {
+                    Ok(PipelineEvent::RunFinished(record)) => sinks.notify(&http, &record).await,
+                    Ok(_) => {}
+                    Err(RecvError::Lagged(missed)) => warn!("Notifications missed {missed} pipeline events"),
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        });
+
+        Ok(Some(Self { done, task }))
+    }
+
+    /// Waits for the notifications of runs that already finished to be sent
+    pub async fn finish(self) {
+        self.done.cancel();
+        if let Err(e) = self.task.await {
+            warn!("Notifications stopped early: {e}");
+        }
+    }
+}
+
+impl Sinks {
+    async fn notify(&self, http: &reqwest::Client, record: &RunRecord) {
+        for webhook in &self.webhooks {
+            let reasons = reasons(&webhook.on, webhook.error_rate, record);
+            if !reasons.is_empty() {
+                deliver("webhook", record, || webhook::send(http, webhook, record, &reasons)).await;
+            }
+        }
+        for email in &self.emails {
+            let reasons = reasons(&email.on, email.error_rate, record);
+            if !reasons.is_empty() {
+                deliver("email", record, || email::send(email, record, &reasons)).await;
+            }
+        }
+        for file in &self.files {
+            let reasons = reasons(&file.on, file.error_rate, record);
+            if !reasons.is_empty() {
+                deliver("notification file", record, || file::append(file, record, &reasons)).await;
+            }
+        }
+        if self.pushgateway.url.is_some() {
+            let profile = self.profile.as_deref();
+            deliver("pushgateway", record, || pushgateway::push(http, &self.pushgateway, profile, record)).await;
+        }
+    }
+}
+
+/// Triggers in `on` that `record` sets off; a partial failure counts as failed
+pub fn reasons(on: &[Trigger], error_rate: f64, record: &RunRecord) -> Vec<Trigger> {
+    on.iter()
+        .copied()
+        .filter(|trigger| match trigger {
+            Trigger::Completed => record.exit == Exit::Success,
+            Trigger::Failed => record.exit != Exit::Success,
+            Trigger::ErrorRate => record.error_rate > error_rate,
+        })
+        .collect()
+}
+
+/// One line summing up the run, for chat channels and subject lines
+pub fn message(record: &RunRecord, reasons: &[Trigger]) -> String {
+    let summary = &record.summary;
+    let mut message = format!(
+        "Ingestion run {} of {} ended in {}: {} of {} documents stored, {} failed, {:.1}% error rate",
+        record.id,
+        record.path.display(),
+        record.exit,
+        summary.stored,
+        summary.documents,
+        summary.failed,
+        record.error_rate * 100.0,
+    );
+    if reasons.contains(&Trigger::ErrorRate) {
+        message.push_str(" (over the threshold)");
+    }
+
+    message
+}
+
+/// Calls `send` until it succeeds, each call given `TIMEOUT`, warning when all `ATTEMPTS` failed
+async fn deliver<F, Fut>(sink: &str, record: &RunRecord, send: F)
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    for attempt in 1..=ATTEMPTS {
+        let result = match tokio::time::timeout(TIMEOUT, send()).await {
+            Ok(result) => result,
+            Err(_) => Err(anyhow::anyhow!("Timed out after {TIMEOUT:?}")),
+        };
+        match result {
+            Ok(()) => return debug!("Notified {sink} of run {}", record.id),
+            Err(e) if attempt < ATTEMPTS => {
+                debug!("Notifying {sink}, attempt {attempt} of {ATTEMPTS} failed: {e:#}");
+                tokio::time::sleep(Duration::from_secs(attempt as u64)).await;
+            }
+            Err(e) => warn!("Failed to notify {sink} of run {}: {e:#}", record.id),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+    use crate::outcome::RunSummary;
+    use super::*;
+
+    const EVERY: [Trigger; 3] = [Trigger::Completed, Trigger::Failed, Trigger::ErrorRate];
+
+    fn record(exit: Exit, error_rate: f64) -> RunRecord {
+        RunRecord {
+            id: 3,
+            started_at: Utc::now(),
+            finished_at: Utc::now(),
+            path: "docs".into(),
+            exit,
+            error_rate,
+            summary: RunSummary { documents: 10, stored: 9, failed: 1, ..RunSummary::default() },
+            config_hash: String::new(),
+        }
+    }
+
+    #[test]
+    fn completed_runs_fire_completed_only() {
+        assert_eq!(reasons(&EVERY, 0.05, &record(Exit::Success, 0.0)), [Trigger::Completed]);
+        assert_eq!(reasons(&[Trigger::Failed], 0.05, &record(Exit::Success, 0.0)), []);
+    }
+
+    #[test]
+    fn failed_and_partly_failed_runs_fire_failed() {
+        assert_eq!(reasons(&EVERY, 0.05, &record(Exit::Failure, 0.0)), [Trigger::Failed]);
+        assert_eq!(reasons(&EVERY, 0.05, &record(Exit::PartialFailure, 0.05)), [Trigger::Failed]);
+        assert_eq!(reasons(&[Trigger::Completed], 0.05, &record(Exit::BackendUnavailable, 0.0)), []);
+    }
+
+    #[test]
+    fn runs_over_the_threshold_fire_error_rate_whatever_their_exit() {
+        assert_eq!(reasons(&EVERY, 0.05, &record(Exit::Success, 0.1)), [Trigger::Completed, Trigger::ErrorRate]);
+        assert_eq!(reasons(&[Trigger::ErrorRate], 0.05, &record(Exit::PartialFailure, 0.1)), [Trigger::ErrorRate]);
+        assert_eq!(reasons(&[Trigger::ErrorRate], 0.2, &record(Exit::PartialFailure, 0.1)), []);
+    }
+
+    #[test]
+    fn messages_say_when_the_threshold_was_crossed() {
+        let record = record(Exit::PartialFailure, 0.1);
+
+        let failed = message(&record, &[Trigger::Failed]);
+        assert!(failed.starts_with("Ingestion run 3 of docs ended in "), "{failed}");
+        assert!(failed.ends_with(": 9 of 10 documents stored, 1 failed, 10.0% error rate"), "{failed}");
+        let over = message(&record, &[Trigger::Failed, Trigger::ErrorRate]);
+        assert!(over.ends_with("10.0% error rate (over the threshold)"), "{over}");
+    }
+
+    #[tokio::test]
+    async fn finishing_still_delivers_a_run_published_just_before() {
+        let path = std::env::temp_dir().join(format!("rag-rs-notify-{}.jsonl", std::process::id()));
+        _ = std::fs::remove_file(&path);
+        let file = NotificationFile { path: path.clone(), on: EVERY.to_vec(), error_rate: 0.05 };
+        let config = Config { notification_files: vec![file], ..Config::default() };
+        let events = Events::default();
+
+        let notifier = Notifier::spawn(&config, &events).unwrap().unwrap();
+        events.publish(PipelineEvent::RunFinished(Box::new(record(Exit::Success, 0.0))));
+        notifier.finish().await;
+
+        let written = std::fs::read_to_string(&path).unwrap();
+        _ = std::fs::remove_file(&path);
+        let line: serde_json::Value = serde_json::from_str(written.trim_end()).unwrap();
+        assert_eq!(written.lines().count(), 1);
+        assert_eq!(line["reasons"], serde_json::json!(["completed"]));
+        assert_eq!(line["run"]["id"], 3);
+    }
+
+    #[test]
+    fn nothing_is_spawned_without_notifications() {
+        assert!(Notifier::spawn(&Config::default(), &Events::default()).unwrap().is_none());
+    }
+}
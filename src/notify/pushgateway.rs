@@ -0,0 +1,66 @@
+use std::fmt::Write;
+
+use anyhow::{bail, Result};
+use base64::Engine;
+use base64::prelude::BASE64_URL_SAFE_NO_PAD;
+use crate::config::PushgatewayConfig;
+use crate::history::RunRecord;
+
+/// Replaces the metrics group of `record`'s run on the pushgateway with its final metrics
+pub async fn push(http: &reqwest::Client, config: &PushgatewayConfig, profile: Option<&str>, record: &RunRecord) -> Result<()> {
+    let Some(url) = &config.url else { return Ok(()) };
+    let url = format!(
+        "{}/metrics{}{}{}",
+        url.trim_end_matches('/'),
+        label("job", &config.job),
+        label("run_id", &record.id.to_string()),
+        // Empty without a profile, which the pushgateway takes as no label at all
+        label("profile", profile.unwrap_or_default()),
+    );
+
+    let response = http.put(url)
+        .header(reqwest::header::CONTENT_TYPE, "text/plain; version=0.0.4")
+        .body(exposition(record))
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        bail!("The pushgateway answered {}", response.status());
+    }
+
+    Ok(())
+}
+
+/// A grouping label as URL path segments, base64 encoded so any value is safe in them
+fn label(name: &str, value: &str) -> String {
+    match value {
+        "" => format!("/{name}@base64/="),
+        value => format!("/{name}@base64/{}", BASE64_URL_SAFE_NO_PAD.encode(value)),
+    }
+}
+
+/// `record` in the Prometheus text format
+fn exposition(record: &RunRecord) -> String {
+    let summary = &record.summary;
+    let duration = (record.finished_at - record.started_at).num_milliseconds() as f64 / 1000.0;
+    let metrics: [(&str, &str, f64); 12] = [
+        ("rag_run_documents", "Documents the run read", summary.documents as f64),
+        ("rag_run_skipped", "Input lines that weren't valid documents", summary.skipped as f64),
+        ("rag_run_embedded", "Documents embedded", summary.embedded as f64),
+        ("rag_run_empty", "Documents the embedding backend answered without a vector", summary.empty as f64),
+        ("rag_run_stored", "Documents stored", summary.stored as f64),
+        ("rag_run_failed", "Documents that failed to embed or store", summary.failed as f64),
+        ("rag_run_timed_out", "Failed documents whose every embedding attempt timed out", summary.timed_out as f64),
+        ("rag_run_skipped_files", "Input files left out entirely", summary.skipped_files.len() as f64),
+        ("rag_run_error_rate", "Share of documents that failed or came back empty", record.error_rate),
+        ("rag_run_exit_code", "Exit code of the run", record.exit.code() as f64),
+        ("rag_run_duration_seconds", "How long the run took", duration),
+        ("rag_run_finished_timestamp_seconds", "When the run finished", record.finished_at.timestamp() as f64),
+    ];
+
+    let mut text = String::new();
+    for (name, help, value) in metrics {
+        _ = write!(text, "# HELP {name} {help}\n# TYPE {name} gauge\n{name} {value}\n");
+    }
+
+    text
+}
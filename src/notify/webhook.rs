@@ -0,0 +1,26 @@
+use anyhow::{bail, Result};
+use serde_json::json;
+use crate::config::{Trigger, Webhook, WebhookFormat};
+use crate::history::RunRecord;
+use crate::notify::{self, Payload};
+
+/// Posts `record` to `webhook` in its format
+pub async fn send(http: &reqwest::Client, webhook: &Webhook, record: &RunRecord, reasons: &[Trigger]) -> Result<()> {
+    // Resolved with the config, which refuses webhooks without one
+    let Some(url) = &webhook.url else { return Ok(()) };
+    let body = match webhook.format {
+        WebhookFormat::Json => serde_json::to_string(&Payload { reasons, run: record })?,
+        WebhookFormat::Slack => serde_json::to_string(&json!({ "text": notify::message(record, reasons) }))?,
+    };
+
+    let response = http.post(url.expose())
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(body)
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        bail!("{} answered {}", response.url().host_str().unwrap_or("webhook"), response.status());
+    }
+
+    Ok(())
+}
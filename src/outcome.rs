@@ -0,0 +1,128 @@
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use crate::clients::vector_store::ReadOnly;
+
+/// Process outcomes with stable exit codes for schedulers and CI
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Exit {
+    Success,
+    Failure,
+    PartialFailure,
+    BackendUnavailable,
+    ConfigError,
+}
+
+impl Exit {
+    pub fn code(self) -> u8 {
+        match self {
+            Exit::Success => 0,
+            Exit::Failure => 1,
+            Exit::PartialFailure => 2,
+            Exit::BackendUnavailable => 3,
+            Exit::ConfigError => 4,
+        }
+    }
+
+    /// Finds the outcome attached to an error via `.context(Exit::..)`, defaulting to `Failure`;
+    /// a write refused in read-only mode is a configuration error wherever it came from
+    pub fn from_error(error: &anyhow::Error) -> Self {
+        if error.is::<ReadOnly>() {
+            return Exit::ConfigError;
+        }
+        error.downcast_ref::<Exit>()
+            .copied()
+            .unwrap_or(Exit::Failure)
+    }
+}
+
+impl Display for Exit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let string = match self {
+            Exit::Success => "success",
+            Exit::Failure => "failure",
+            Exit::PartialFailure => "partial failure",
+            Exit::BackendUnavailable => "backend unavailable",
+            Exit::ConfigError => "configuration error",
+        };
+
+        f.write_str(string)
+    }
+}
+
+impl std::error::Error for Exit {}
+
+/// Document counts for a single ingestion run
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RunSummary {
+    pub documents: u64,
+    /// Input lines that weren't valid documents
+    pub skipped: u64,
+    pub embedded: u64,
+    /// Documents the embedding backend answered without a vector
+    pub empty: u64,
+    pub stored: u64,
+    pub failed: u64,
+    /// Failed documents whose every embedding attempt timed out
+    pub timed_out: u64,
+    /// Stored documents left in `qdrant.spill.path` because the collection didn't take writes
+    pub spilled: u64,
+    /// Tokens embedded, reported by the backend or estimated
+    pub tokens: u64,
+    /// What embedding cost at `llama.price_per_million_tokens`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cost: Option<f64>,
+    /// Input files left out entirely
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub skipped_files: Vec<SkippedFile>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SkippedFile {
+    pub path: PathBuf,
+    pub reason: SkipReason,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipReason {
+    /// Loading ran past `pipeline.file_timeout_secs`
+    Timeout,
+    /// The file couldn't be read or wasn't in the format its name suggests
+    Unreadable,
+}
+
+impl RunSummary {
+    /// Share of documents that never made it into the vector store as intended
+    pub fn error_rate(&self) -> f64 {
+        if self.documents == 0 {
+            return 0.0;
+        }
+
+        (self.failed + self.empty) as f64 / self.documents as f64
+    }
+}
+
+/// The machine-readable result of a run written via `--outcome`
+#[derive(Debug, Serialize)]
+pub struct Report<'r> {
+    pub exit: Exit,
+    pub code: u8,
+    pub error_rate: f64,
+    pub summary: &'r RunSummary,
+}
+
+impl<'r> Report<'r> {
+    pub fn new(exit: Exit, summary: &'r RunSummary) -> Self {
+        Self { exit, code: exit.code(), error_rate: summary.error_rate(), summary }
+    }
+
+    pub async fn write(&self, path: &Path) -> Result<()> {
+        tokio::fs::write(path, serde_json::to_vec_pretty(self)?).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,90 @@
+use std::io::Write;
+
+use anyhow::Result;
+use serde_json::Value;
+use crate::cli::Output;
+
+/// Results as rows of named fields, for the machine readable `--output` formats; fields come
+/// out in the order they were named in, whatever the format
+pub struct Rows {
+    fields: Vec<&'static str>,
+    rows: Vec<Vec<Value>>,
+}
+
+impl Rows {
+    pub fn new(fields: &[&'static str]) -> Self {
+        Self { fields: fields.to_vec(), rows: Vec::new() }
+    }
+
+    /// Adds a row holding a value for each field, in the same order
+    pub fn push(&mut self, row: Vec<Value>) {
+        debug_assert_eq!(row.len(), self.fields.len());
+        self.rows.push(row);
+    }
+
+    /// Writes the rows to stdout; `Output::Text` is left to the command, which lays them out
+    /// for reading
+    pub fn print(&self, output: Output) -> Result<()> {
+        let mut rendered = Vec::new();
+        match output {
+            Output::Text => {}
+            Output::Json => self.json(&mut rendered)?,
+            Output::Csv => self.csv(&mut rendered)?,
+            Output::Md => self.markdown(&mut rendered)?,
+        }
+        std::io::stdout().lock().write_all(&rendered)?;
+
+        Ok(())
+    }
+
+    fn json(&self, out: &mut Vec<u8>) -> Result<()> {
+        // Written field by field, since a JSON map would reorder them
+        for row in &self.rows {
+            out.push(b'{');
+            for (i, (field, value)) in self.fields.iter().zip(row).enumerate() {
+                if i > 0 {
+                    out.push(b',');
+                }
+                serde_json::to_writer(&mut *out, field)?;
+                out.push(b':');
+                serde_json::to_writer(&mut *out, value)?;
+            }
+            out.extend_from_slice(b"}\n");
+        }
+
+        Ok(())
+    }
+
+    fn csv(&self, out: &mut Vec<u8>) -> Result<()> {
+        let mut writer = csv::Writer::from_writer(out);
+        writer.write_record(&self.fields)?;
+        for row in &self.rows {
+            writer.write_record(row.iter().map(plain))?;
+        }
+        writer.flush()?;
+
+        Ok(())
+    }
+
+    fn markdown(&self, out: &mut Vec<u8>) -> Result<()> {
+        writeln!(out, "| {} |", self.fields.join(" | "))?;
+        writeln!(out, "|{}", "---|".repeat(self.fields.len()))?;
+        for row in &self.rows {
+            let cells: Vec<String> = row.iter()
+                .map(|value| plain(value).replace('|', "\\|").replace(['\r', '\n'], " "))
+                .collect();
+            writeln!(out, "| {} |", cells.join(" | "))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A value as a table cell: strings without quotes, nothing for null, anything else as JSON
+fn plain(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        Value::Null => String::new(),
+        value => value.to_string(),
+    }
+}
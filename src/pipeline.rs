@@ -0,0 +1,340 @@
+use anyhow::Result;
+use futures::{Stream, StreamExt};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+use crate::clients::Document;
+use crate::clients::llm::Embedding;
+use crate::outcome::RunSummary;
+use crate::sink::Sink;
+
+/// A stage a `PipelineBuilder` hasn't been given yet
+pub struct Missing;
+
+/// Turns one document into any number of them, none to drop it
+pub type Transform = Box<dyn FnMut(Document) -> Vec<Document>>;
+
+/// Puts a `Pipeline` together from a document source, transforms, an embedder and a sink
+///
+/// Each stage is a type parameter that stays `Missing` until it's set, and `build` only exists
+/// once none are, so a pipeline without a source, embedder or sink doesn't compile.
+pub struct PipelineBuilder<R, E, S> {
+    source: R,
+    transforms: Vec<Transform>,
+    embedder: E,
+    sink: S,
+}
+
+impl PipelineBuilder<Missing, Missing, Missing> {
+    pub fn new() -> Self {
+        Self { source: Missing, transforms: Vec::new(), embedder: Missing, sink: Missing }
+    }
+}
+
+impl Default for PipelineBuilder<Missing, Missing, Missing> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<R, E, S> PipelineBuilder<R, E, S> {
+    /// Adds a transform, run on every document in the order they were added
+    pub fn transform(mut self, transform: impl FnMut(Document) -> Vec<Document> + 'static) -> Self {
+        self.transforms.push(Box::new(transform));
+        self
+    }
+}
+
+impl<E, S> PipelineBuilder<Missing, E, S> {
+    /// Where documents come from, a source error skipping the document rather than the run
+    pub fn source<R: Stream<Item = Result<Document>> + Unpin>(self, source: R) -> PipelineBuilder<R, E, S> {
+        PipelineBuilder { source, transforms: self.transforms, embedder: self.embedder, sink: self.sink }
+    }
+}
+
+impl<R, S> PipelineBuilder<R, Missing, S> {
+    pub fn embedder<E: Embedding>(self, embedder: E) -> PipelineBuilder<R, E, S> {
+        PipelineBuilder { source: self.source, transforms: self.transforms, embedder, sink: self.sink }
+    }
+}
+
+impl<R, E> PipelineBuilder<R, E, Missing> {
+    pub fn sink<S: Sink>(self, sink: S) -> PipelineBuilder<R, E, S> {
+        PipelineBuilder { source: self.source, transforms: self.transforms, embedder: self.embedder, sink }
+    }
+}
+
+impl<R, E, S> PipelineBuilder<R, E, S>
+where
+    R: Stream<Item = Result<Document>> + Unpin,
+    E: Embedding,
+    S: Sink,
+{
+    pub fn build(self) -> Pipeline<R, E, S> {
+        Pipeline { source: self.source, transforms: self.transforms, embedder: self.embedder, sink: self.sink }
+    }
+}
+
+/// Reads, transforms, embeds and stores documents one at a time, see `PipelineBuilder`
+pub struct Pipeline<R, E, S> {
+    source: R,
+    transforms: Vec<Transform>,
+    embedder: E,
+    sink: S,
+}
+
+impl<R, E, S> Pipeline<R, E, S>
+where
+    R: Stream<Item = Result<Document>> + Unpin,
+    E: Embedding,
+    S: Sink,
+{
+    /// Runs until the source is done or `cancel`, then flushes the sink and hands it back
+    ///
+    /// Documents failing to embed are counted and left out; a failed write ends the run.
+    pub async fn run(mut self, cancel: CancellationToken) -> Result<(RunSummary, S)> {
+        let mut summary = RunSummary::default();
+
+        'reading: loop {
+            let next = tokio::select! {
+                next = self.source.next() => next,
+                _ = cancel.cancelled() => break,
+            };
+            let document = match next {
+                Some(Ok(document)) => document,
+                Some(Err(e)) => {
+                    warn!("Skipped a document the source couldn't read: {e:#}");
+                    summary.skipped += 1;
+                    continue;
+                }
+                None => break,
+            };
+
+            let mut documents = vec![document];
+            for transform in &mut self.transforms {
+                documents = documents.into_iter().flat_map(&mut *transform).collect();
+            }
+            summary.documents += documents.len() as u64;
+
+            for mut document in documents {
+                let embedding = tokio::select! {
+                    embedding = self.embedder.embed_document(&document.page_content) => embedding,
+                    _ = cancel.cancelled() => break 'reading,
+                };
+                match embedding {
+                    Ok(embedding) if embedding.is_empty() => summary.empty += 1,
+                    Ok(embedding) => {
+                        document.embeddings = embedding;
+                        summary.embedded += 1;
+                        self.sink.push(document).await?;
+                    }
+                    Err(e) => {
+                        warn!("Failed to embed a document of {}: {e:#}", document.metadata.source);
+                        summary.failed += 1;
+                    }
+                }
+            }
+        }
+
+        self.sink.flush().await?;
+        summary.stored = summary.embedded;
+        summary.spilled = self.sink.spilled();
+        summary.tokens = self.embedder.tokens_used();
+        summary.cost = self.embedder.cost();
+        info!("Stored {} of {} documents", summary.stored, summary.documents);
+
+        Ok((summary, self.sink))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::{anyhow, bail};
+    use futures::future::{BoxFuture, FutureExt};
+    use futures::stream;
+    use crate::clients::Metadata;
+    use crate::clients::vector_store::{stable_id, DynVectorStore, VectorStore};
+    use crate::clients::vector_store::sqlite::SqliteStore;
+    use crate::config::SqliteConfig;
+    use super::*;
+
+    /// Embeds a text as its length, failing on texts reading "bad" and answering "empty" without a vector
+    struct Lengths;
+
+    impl Embedding for Lengths {
+        fn embed_document<'e>(&'e self, text: &'e str) -> BoxFuture<'e, Result<Vec<f32>>> {
+            async move {
+                match text {
+                    "bad" => bail!("unembeddable"),
+                    "empty" => Ok(Vec::new()),
+                    _ => Ok(vec![text.len() as f32]),
+                }
+            }.boxed()
+        }
+
+        fn embed_query<'e>(&'e self, text: &'e str) -> BoxFuture<'e, Result<Vec<f32>>> {
+            self.embed_document(text)
+        }
+
+        fn tokens_used(&self) -> u64 {
+            0
+        }
+
+        fn cost(&self) -> Option<f64> {
+            None
+        }
+    }
+
+    /// Twice what the embedder it wraps answers, borrowing it to show `&E` embeds too
+    struct Doubled<E>(E);
+
+    impl<E: Embedding> Embedding for Doubled<E> {
+        fn embed_document<'e>(&'e self, text: &'e str) -> BoxFuture<'e, Result<Vec<f32>>> {
+            let inner = &self.0;
+            async move {
+                let vector = Embedding::embed_document(&inner, text).await?;
+                Ok(vector.into_iter().map(|value| value * 2.0).collect())
+            }.boxed()
+        }
+
+        fn embed_query<'e>(&'e self, text: &'e str) -> BoxFuture<'e, Result<Vec<f32>>> {
+            self.embed_document(text)
+        }
+
+        fn tokens_used(&self) -> u64 {
+            self.0.tokens_used()
+        }
+
+        fn cost(&self) -> Option<f64> {
+            self.0.cost()
+        }
+    }
+
+    /// Keeps what it's given, counting flushes
+    #[derive(Default)]
+    struct Collected {
+        documents: Vec<Document>,
+        flushes: usize,
+    }
+
+    impl Sink for Collected {
+        async fn push(&mut self, document: Document) -> Result<()> {
+            self.documents.push(document);
+            Ok(())
+        }
+
+        async fn flush(&mut self) -> Result<()> {
+            self.flushes += 1;
+            Ok(())
+        }
+
+        fn buffered(&self) -> usize {
+            0
+        }
+    }
+
+    fn document(text: &str) -> Document {
+        Document {
+            page_content: text.to_string(),
+            metadata: Metadata { source: format!("{text}.txt"), ..Metadata::default() },
+            embeddings: Vec::new(),
+        }
+    }
+
+    fn texts(documents: &[Document]) -> Vec<&str> {
+        documents.iter().map(|document| document.page_content.as_str()).collect()
+    }
+
+    #[tokio::test]
+    async fn embeds_and_stores_every_document() {
+        let source = stream::iter(["a", "bb", "ccc"].map(|text| Ok(document(text))));
+        let pipeline = PipelineBuilder::new().source(source).embedder(Lengths).sink(Collected::default()).build();
+
+        let (summary, sink) = pipeline.run(CancellationToken::new()).await.unwrap();
+
+        assert_eq!(summary.documents, 3);
+        assert_eq!(summary.stored, 3);
+        assert_eq!(texts(&sink.documents), ["a", "bb", "ccc"]);
+        assert_eq!(sink.documents[2].embeddings, [3.0]);
+        assert_eq!(sink.flushes, 1);
+    }
+
+    #[tokio::test]
+    async fn transforms_run_in_order_and_may_split_or_drop_documents() {
+        let source = stream::iter(["a b", "drop", "c"].map(|text| Ok(document(text))));
+        let pipeline = PipelineBuilder::new()
+            .transform(|input| match input.page_content.as_str() {
+                "drop" => Vec::new(),
+                text => text.split(' ').map(document).collect(),
+            })
+            .source(source)
+            .transform(|mut document| {
+                document.page_content = document.page_content.to_uppercase();
+                vec![document]
+            })
+            .sink(Collected::default())
+            .embedder(Lengths)
+            .build();
+
+        let (summary, sink) = pipeline.run(CancellationToken::new()).await.unwrap();
+
+        assert_eq!(summary.documents, 3);
+        assert_eq!(texts(&sink.documents), ["A", "B", "C"]);
+    }
+
+    #[tokio::test]
+    async fn unreadable_and_unembeddable_documents_are_counted_and_left_out() {
+        let source = stream::iter([Ok(document("a")), Err(anyhow!("truncated")), Ok(document("bad")), Ok(document("empty"))]);
+        let pipeline = PipelineBuilder::new().source(source).embedder(Lengths).sink(Collected::default()).build();
+
+        let (summary, sink) = pipeline.run(CancellationToken::new()).await.unwrap();
+
+        assert_eq!(summary.skipped, 1);
+        assert_eq!(summary.documents, 3);
+        assert_eq!(summary.failed, 1);
+        assert_eq!(summary.empty, 1);
+        assert_eq!(summary.stored, 1);
+        assert_eq!(texts(&sink.documents), ["a"]);
+    }
+
+    #[tokio::test]
+    async fn a_cancelled_run_stops_reading_and_still_flushes() {
+        let cancel = CancellationToken::new();
+        let source = stream::iter([Ok(document("a"))]).chain(stream::pending());
+        let pipeline = PipelineBuilder::new().source(source).embedder(Lengths).sink(Collected::default()).build();
+
+        let cancelled = cancel.clone();
+        let running = pipeline.run(cancel);
+        let (result, _) = tokio::join!(running, async move {
+            tokio::task::yield_now().await;
+            cancelled.cancel();
+        });
+        let (summary, sink) = result.unwrap();
+
+        assert_eq!(summary.stored, 1);
+        assert_eq!(sink.flushes, 1);
+    }
+
+    #[tokio::test]
+    async fn stages_can_be_chosen_at_runtime() {
+        let path = std::env::temp_dir().join(format!("rag-rs-pipeline-{}.sqlite", std::process::id()));
+        _ = std::fs::remove_file(&path);
+        let config = SqliteConfig { path: path.clone(), ..SqliteConfig::default() };
+        let embedder: Box<dyn Embedding> = match config.table.is_empty() {
+            true => Box::new(Lengths),
+            false => Box::new(Doubled(Lengths)),
+        };
+        let store: Box<dyn DynVectorStore> = Box::new(SqliteStore::from_config(&config).unwrap());
+        store.ensure_collection().await.unwrap();
+
+        let source = stream::iter(["a", "bb"].map(|text| Ok(document(text))));
+        let pipeline = PipelineBuilder::new().source(source).embedder(embedder).sink(store).build();
+        let (summary, store) = pipeline.run(CancellationToken::new()).await.unwrap();
+
+        assert_eq!(summary.stored, 2);
+        let (points, _) = store.scan(None, 10).await.unwrap();
+        assert_eq!(points.len(), 2);
+        let (_, vector) = store.get(&stable_id(&document("bb"))).await.unwrap().unwrap();
+        assert_eq!(vector, [4.0]);
+        _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,84 @@
+use clap::ValueEnum;
+use toml::Table;
+
+/// Settings that suit a well-known embedding model, for `--preset`
+///
+/// A preset only fills in what the config file leaves out, so anything the file or a
+/// profile sets still wins.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Preset {
+    /// BAAI bge-small-en-v1.5: 384 dimensions, queries prefixed, 512 token window
+    BgeSmall,
+    /// Nomic nomic-embed-text-v1.5: 768 dimensions, documents and queries prefixed, 8192 token window
+    NomicEmbed,
+    /// intfloat e5-large-v2: 1024 dimensions, documents and queries prefixed, 512 token window
+    E5Large,
+}
+
+impl Preset {
+    pub fn name(self) -> &'static str {
+        match self {
+            Preset::BgeSmall => "bge-small",
+            Preset::NomicEmbed => "nomic-embed",
+            Preset::E5Large => "e5-large",
+        }
+    }
+
+    /// The preset as a config table
+    pub fn table(self) -> Table {
+        toml::from_str(self.settings()).expect("Presets are valid TOML")
+    }
+
+    fn settings(self) -> &'static str {
+        // Chunks stay a little under the model's window, which the prefix takes from too
+        match self {
+            Preset::BgeSmall => r#"
+                [llama]
+                model = "bge-small-en-v1.5"
+                query_prefix = "Represent this sentence for searching relevant passages: "
+                normalize = true
+
+                [qdrant]
+                dimensions = 384
+                distance = "cosine"
+
+                [pipeline]
+                chunk_mode = "tokens"
+                chunk_size = 480
+                chunk_overlap = 48
+            "#,
+            Preset::NomicEmbed => r#"
+                [llama]
+                model = "nomic-embed-text-v1.5"
+                prompt_prefix = "search_document: "
+                query_prefix = "search_query: "
+                normalize = true
+
+                [qdrant]
+                dimensions = 768
+                distance = "cosine"
+
+                [pipeline]
+                chunk_mode = "tokens"
+                chunk_size = 1024
+                chunk_overlap = 128
+            "#,
+            Preset::E5Large => r#"
+                [llama]
+                model = "e5-large-v2"
+                prompt_prefix = "passage: "
+                query_prefix = "query: "
+                normalize = true
+
+                [qdrant]
+                dimensions = 1024
+                distance = "cosine"
+
+                [pipeline]
+                chunk_mode = "tokens"
+                chunk_size = 480
+                chunk_overlap = 48
+            "#,
+        }
+    }
+}
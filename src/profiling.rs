@@ -0,0 +1,297 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use indicatif::HumanBytes;
+use serde::Serialize;
+use tracing::info;
+
+/// Parts of an ingestion run whose resource usage is told apart
+#[derive(Debug, Clone, Copy)]
+pub enum Stage {
+    /// Reading and parsing the input into documents
+    Read,
+    /// Embedding requests and decoding their JSON answers
+    Embed,
+    /// Handing vectors to the store
+    Upsert,
+}
+
+const STAGES: [Stage; 3] = [Stage::Read, Stage::Embed, Stage::Upsert];
+
+impl Stage {
+    fn name(self) -> &'static str {
+        match self {
+            Stage::Read => "read",
+            Stage::Embed => "embed",
+            Stage::Upsert => "upsert",
+        }
+    }
+}
+
+/// What the process spent while polling one stage's futures
+struct Totals {
+    cpu_nanos: AtomicU64,
+    allocations: AtomicU64,
+    allocated: AtomicU64,
+}
+
+static TOTALS: [Totals; 3] = [const { Totals::new() }; 3];
+
+impl Totals {
+    const fn new() -> Self {
+        Self { cpu_nanos: AtomicU64::new(0), allocations: AtomicU64::new(0), allocated: AtomicU64::new(0) }
+    }
+}
+
+/// Counts every poll of `future` towards `stage`
+pub fn instrument<F: Future>(stage: Stage, future: F) -> Instrumented<F> {
+    Instrumented { stage, future: Box::pin(future) }
+}
+
+/// A future whose polls are measured, see `instrument`
+pub struct Instrumented<F> {
+    stage: Stage,
+    future: Pin<Box<F>>,
+}
+
+impl<F: Future> Future for Instrumented<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        let cpu = thread_cpu_nanos();
+        let (allocations, allocated) = allocator::thread_counts();
+        let poll = self.future.as_mut().poll(cx);
+
+        // Polls don't move between threads, so the thread's counters are the poll's
+        let totals = &TOTALS[self.stage as usize];
+        totals.cpu_nanos.fetch_add(thread_cpu_nanos().saturating_sub(cpu), Ordering::Relaxed);
+        let (now_allocations, now_allocated) = allocator::thread_counts();
+        totals.allocations.fetch_add(now_allocations - allocations, Ordering::Relaxed);
+        totals.allocated.fetch_add(now_allocated - allocated, Ordering::Relaxed);
+
+        poll
+    }
+}
+
+/// What getrusage tells of the process, all zero where there is no getrusage
+#[derive(Default)]
+struct ProcessUsage {
+    user: Duration,
+    system: Duration,
+    max_resident_bytes: u64,
+    major_faults: u64,
+    context_switches: u64,
+}
+
+impl ProcessUsage {
+    #[cfg(unix)]
+    fn now() -> Self {
+        // SAFETY: getrusage only writes the struct it is handed
+        let usage = unsafe {
+            let mut usage = std::mem::zeroed::<libc::rusage>();
+            libc::getrusage(libc::RUSAGE_SELF, &mut usage);
+            usage
+        };
+
+        Self {
+            user: timeval(usage.ru_utime),
+            system: timeval(usage.ru_stime),
+            // Linux reports KiB
+            max_resident_bytes: usage.ru_maxrss as u64 * 1024,
+            major_faults: usage.ru_majflt as u64,
+            context_switches: (usage.ru_nvcsw + usage.ru_nivcsw) as u64,
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn now() -> Self {
+        Self::default()
+    }
+}
+
+/// Resource usage of the process and its stages up to one point in time
+pub struct Snapshot {
+    usage: ProcessUsage,
+    stages: [(u64, u64, u64); 3],
+}
+
+impl Snapshot {
+    pub fn take() -> Self {
+        let usage = ProcessUsage::now();
+        let stages = STAGES.map(|stage| {
+            let totals = &TOTALS[stage as usize];
+            (
+                totals.cpu_nanos.load(Ordering::Relaxed),
+                totals.allocations.load(Ordering::Relaxed),
+                totals.allocated.load(Ordering::Relaxed),
+            )
+        });
+
+        Self { usage, stages }
+    }
+
+    /// Logs what was used since `self` was taken, overall and per stage
+    pub fn report(&self) {
+        let now = Self::take();
+        let user = now.usage.user.saturating_sub(self.usage.user);
+        let system = now.usage.system.saturating_sub(self.usage.system);
+        let cpu = (user + system).as_nanos().max(1) as f64;
+        info!(
+            "Used {:.2}s user and {:.2}s system CPU, {} peak resident, {} major page faults, {} context switches",
+            user.as_secs_f64(),
+            system.as_secs_f64(),
+            HumanBytes(now.usage.max_resident_bytes),
+            now.usage.major_faults - self.usage.major_faults,
+            now.usage.context_switches - self.usage.context_switches,
+        );
+
+        for (i, stage) in STAGES.into_iter().enumerate() {
+            let (cpu_nanos, allocations, allocated) = now.stages[i];
+            let (before_nanos, before_allocations, before_allocated) = self.stages[i];
+            let stage_nanos = cpu_nanos - before_nanos;
+            let mut line = format!(
+                "{:>6}: {:.1} ms CPU ({:.0}%)",
+                stage.name(),
+                stage_nanos as f64 / 1e6,
+                stage_nanos as f64 / cpu * 100.0,
+            );
+            if allocator::COUNTING {
+                line.push_str(&format!(
+                    ", {} allocations, {} allocated",
+                    allocations - before_allocations,
+                    HumanBytes(allocated - before_allocated),
+                ));
+            }
+            info!("{line}");
+        }
+    }
+}
+
+/// What the process used since it started, as recorded in metrics snapshots
+#[derive(Debug, Serialize)]
+pub struct Usage {
+    pub user_cpu_secs: f64,
+    pub system_cpu_secs: f64,
+    pub max_resident_bytes: u64,
+    pub stages: Vec<StageUsage>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StageUsage {
+    pub stage: &'static str,
+    pub cpu_secs: f64,
+    /// Only counted with the `alloc-stats` feature
+    pub allocations: Option<u64>,
+    pub allocated_bytes: Option<u64>,
+}
+
+impl Usage {
+    pub fn now() -> Self {
+        let now = Snapshot::take();
+
+        Self {
+            user_cpu_secs: now.usage.user.as_secs_f64(),
+            system_cpu_secs: now.usage.system.as_secs_f64(),
+            max_resident_bytes: now.usage.max_resident_bytes,
+            stages: STAGES.into_iter()
+                .zip(now.stages)
+                .map(|(stage, (cpu_nanos, allocations, allocated))| StageUsage {
+                    stage: stage.name(),
+                    cpu_secs: cpu_nanos as f64 / 1e9,
+                    allocations: allocator::COUNTING.then_some(allocations),
+                    allocated_bytes: allocator::COUNTING.then_some(allocated),
+                })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn timeval(time: libc::timeval) -> Duration {
+    Duration::new(time.tv_sec as u64, time.tv_usec as u32 * 1000)
+}
+
+/// CPU time the calling thread has used
+#[cfg(unix)]
+fn thread_cpu_nanos() -> u64 {
+    // SAFETY: clock_gettime only writes the struct it is handed
+    let time = unsafe {
+        let mut time = std::mem::zeroed::<libc::timespec>();
+        libc::clock_gettime(libc::CLOCK_THREAD_CPUTIME_ID, &mut time);
+        time
+    };
+
+    time.tv_sec as u64 * 1_000_000_000 + time.tv_nsec as u64
+}
+
+/// Without a per-thread CPU clock the stages are left at zero
+#[cfg(not(unix))]
+fn thread_cpu_nanos() -> u64 {
+    0
+}
+
+#[cfg(feature = "alloc-stats")]
+mod allocator {
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+
+    pub const COUNTING: bool = true;
+
+    thread_local! {
+        static ALLOCATIONS: Cell<u64> = const { Cell::new(0) };
+        static ALLOCATED: Cell<u64> = const { Cell::new(0) };
+    }
+
+    /// The system allocator, counting each thread's allocations
+    struct Counting;
+
+    #[global_allocator]
+    static GLOBAL: Counting = Counting;
+
+    // SAFETY: every call is passed on to the system allocator unchanged
+    unsafe impl GlobalAlloc for Counting {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            count(layout.size());
+            System.alloc(layout)
+        }
+
+        unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+            count(layout.size());
+            System.alloc_zeroed(layout)
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            count(new_size.saturating_sub(layout.size()));
+            System.realloc(ptr, layout, new_size)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+    }
+
+    fn count(bytes: usize) {
+        // Threads being torn down have no counters left, their allocations go uncounted
+        _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        _ = ALLOCATED.try_with(|count| count.set(count.get() + bytes as u64));
+    }
+
+    pub fn thread_counts() -> (u64, u64) {
+        (
+            ALLOCATIONS.try_with(Cell::get).unwrap_or_default(),
+            ALLOCATED.try_with(Cell::get).unwrap_or_default(),
+        )
+    }
+}
+
+#[cfg(not(feature = "alloc-stats"))]
+mod allocator {
+    pub const COUNTING: bool = false;
+
+    pub fn thread_counts() -> (u64, u64) {
+        (0, 0)
+    }
+}
@@ -0,0 +1,46 @@
+use serde::{Deserialize, Serialize};
+use crate::clients::Document;
+use crate::config::LlamaConfig;
+
+/// Payload field recording how a point's vector was made
+pub const PROVENANCE_FIELD: &str = "provenance";
+
+/// How far a vector's norm may be from 1 for it to count as normalized
+const NORM_TOLERANCE: f32 = 1e-3;
+
+/// What produced a vector, stored with its point so a collection that ended up holding
+/// vectors of different models or settings can be told apart from a consistent one
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Provenance {
+    pub model: Option<String>,
+    pub dimensions: usize,
+    pub normalized: bool,
+    pub prompt_prefix: String,
+    pub pipeline_version: String,
+}
+
+impl Provenance {
+    /// The provenance of `vector`, embedded through the backend `config` points at
+    pub fn of(config: &LlamaConfig, vector: &[f32]) -> Self {
+        let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+
+        Self {
+            model: config.model.clone(),
+            dimensions: vector.len(),
+            normalized: (norm - 1.0).abs() < NORM_TOLERANCE,
+            prompt_prefix: config.prompt_prefix.clone(),
+            pipeline_version: env!("CARGO_PKG_VERSION").to_string(),
+        }
+    }
+
+    /// The provenance recorded in a stored payload, if any
+    pub fn from_payload(payload: &serde_json::Map<String, serde_json::Value>) -> Option<Self> {
+        serde_json::from_value(payload.get(PROVENANCE_FIELD)?.clone()).ok()
+    }
+}
+
+/// Records in `document`'s metadata where `vector`, about to become its embedding, came from
+pub fn stamp(document: &mut Document, config: &LlamaConfig, vector: &[f32]) {
+    let provenance = serde_json::to_value(Provenance::of(config, vector)).expect("Provenance always serializes");
+    document.metadata.extra.insert(PROVENANCE_FIELD.to_string(), provenance);
+}
@@ -0,0 +1,93 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use crate::clients::vector_store::Filter;
+use crate::config::QueryLogConfig;
+
+/// Digits a word needs before they are masked, so short counts like "top 10" survive
+const MASKED_DIGITS: usize = 3;
+
+/// A served search as captured in the query log
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoggedQuery {
+    pub at: DateTime<Utc>,
+    /// Masked by `anonymize`; only kept with `serve.query_log.texts`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    /// Empty for searches answered by the query's words, as it couldn't be embedded
+    pub embedding: Vec<f32>,
+    pub limit: u64,
+    pub offset: u64,
+    /// As the caller sent it, before ACL groups were applied
+    #[serde(default, skip_serializing_if = "Filter::is_empty")]
+    pub filter: Filter,
+}
+
+/// Append-only JSONL file of the searches serve mode answers
+pub struct QueryLog {
+    /// Guards appends, so concurrent searches don't interleave their lines
+    path: Mutex<PathBuf>,
+    texts: bool,
+}
+
+impl QueryLog {
+    pub fn new(config: &QueryLogConfig) -> Self {
+        Self { path: Mutex::new(config.path.clone()), texts: config.texts }
+    }
+
+    pub async fn record(&self, text: &str, embedding: Vec<f32>, limit: u64, offset: u64, filter: Filter) -> Result<()> {
+        let query = LoggedQuery {
+            at: Utc::now(),
+            text: self.texts.then(|| anonymize(text)),
+            embedding,
+            limit,
+            offset,
+            filter,
+        };
+        let mut line = serde_json::to_vec(&query)?;
+        line.push(b'\n');
+
+        let path = self.path.lock().await;
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&*path)
+            .await?;
+        file.write_all(&line).await?;
+
+        Ok(())
+    }
+}
+
+/// Every query in the log at `path`, oldest first
+pub async fn read(path: &Path) -> Result<Vec<LoggedQuery>> {
+    let raw = tokio::fs::read_to_string(path).await
+        .with_context(|| format!("Failed to read the query log {}", path.display()))?;
+
+    raw.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(n, line)| serde_json::from_str(line).with_context(|| format!("Line {} of {} is not a logged query", n + 1, path.display())))
+        .collect()
+}
+
+/// `text` with words that look like email addresses replaced and the digits of longer
+/// numbers, like phone or account numbers, masked with `#`
+pub fn anonymize(text: &str) -> String {
+    text.split_inclusive(char::is_whitespace)
+        .map(|word| {
+            let trimmed = word.trim_end();
+            if trimmed.contains('@') && trimmed.contains('.') {
+                return format!("<email>{}", &word[trimmed.len()..]);
+            }
+            if word.chars().filter(char::is_ascii_digit).count() >= MASKED_DIGITS {
+                return word.chars().map(|c| if c.is_ascii_digit() { '#' } else { c }).collect();
+            }
+            word.to_string()
+        })
+        .collect()
+}
@@ -0,0 +1,93 @@
+use anyhow::Result;
+use tracing::warn;
+use crate::clients::llm::llama_cpp::LlamaCpp;
+use crate::clients::vector_store::{Filter, Hit};
+use crate::config::{CompressionConfig, Config};
+
+/// What the compression model answers for a hit without anything relevant in it
+const NOTHING_RELEVANT: &str = "NONE";
+
+/// The `[retrieval]` stages around the dense search, with clients for the models they call
+#[derive(Default)]
+pub struct Retrieval<'c> {
+    fetch_k: u64,
+    filter: Filter,
+    reranker: Option<LlamaCpp<'c>>,
+    compressor: Option<(LlamaCpp<'c>, CompressionConfig)>,
+}
+
+impl<'c> Retrieval<'c> {
+    /// Builds the model clients once, so a broken model table fails at startup rather than
+    /// per search and every search goes through the same connection pool
+    pub fn from_config(config: &'c Config) -> Result<Self> {
+        let retrieval = &config.retrieval;
+        let model = |name: &Option<String>| LlamaCpp::from_config(config.model(name.as_deref())?);
+
+        Ok(Self {
+            fetch_k: retrieval.fetch_k,
+            filter: retrieval.filter.clone(),
+            reranker: retrieval.rerank.enabled.then(|| model(&retrieval.rerank.model)).transpose()?,
+            compressor: retrieval.compression.enabled
+                .then(|| Ok::<_, anyhow::Error>((model(&retrieval.compression.model)?, retrieval.compression.clone())))
+                .transpose()?,
+        })
+    }
+
+    /// Candidates the dense search fetches when the stages after it need `needed`
+    pub fn fetch(&self, needed: u64) -> u64 {
+        self.fetch_k.max(needed)
+    }
+
+    /// The caller's `filter` with the configured conditions added; a field both name has to
+    /// meet both, so neither can widen the other
+    pub fn filter(&self, filter: &Filter) -> Filter {
+        let mut filter = filter.clone();
+        filter.and(&self.filter);
+        filter
+    }
+
+    /// Scores `hits` by the reranking model rather than by their vectors; they keep the
+    /// scores they had when the model can't be asked
+    pub async fn rerank(&self, query: &str, hits: &mut [Hit]) -> Result<()> {
+        let Some(llama) = &self.reranker else { return Ok(()) };
+        if hits.is_empty() {
+            return Ok(());
+        }
+
+        let documents: Vec<String> = hits.iter().map(|hit| hit.text.clone()).collect();
+        match llama.rerank(query, &documents).await {
+            Ok(scores) => hits.iter_mut().zip(scores).for_each(|(hit, score)| hit.score = score),
+            Err(e) => warn!("Reranking failed, keeping the vector scores: {e:#}"),
+        }
+
+        Ok(())
+    }
+
+    /// Cuts the text of each of `hits` down to what the compression model finds relevant to
+    /// `query`, leaving out hits it finds nothing in
+    pub async fn compress(&self, query: &str, hits: Vec<Hit>) -> Result<Vec<Hit>> {
+        let Some((llama, config)) = &self.compressor else { return Ok(hits) };
+
+        let mut kept = Vec::with_capacity(hits.len());
+        for mut hit in hits {
+            match llama.complete(&fill(&config.prompt, query, &hit.text), config.max_tokens).await {
+                Ok(answer) => match answer.trim() {
+                    "" | NOTHING_RELEVANT => continue,
+                    answer => hit.text = answer.to_string(),
+                },
+                Err(e) => warn!("Compressing {} failed, keeping all of it: {e:#}", hit.id),
+            }
+            kept.push(hit);
+        }
+
+        Ok(kept)
+    }
+}
+
+/// `prompt` with `{query}` and `{text}` filled in, neither of them searched for placeholders
+fn fill(prompt: &str, query: &str, text: &str) -> String {
+    prompt.split("{text}")
+        .map(|part| part.replace("{query}", query))
+        .collect::<Vec<_>>()
+        .join(text)
+}
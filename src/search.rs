@@ -0,0 +1,253 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::{Display, Formatter};
+
+use anyhow::{bail, Context, Result};
+use base64::Engine;
+use base64::prelude::BASE64_URL_SAFE_NO_PAD;
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+use crate::chunking::CHUNK_INDEX_FIELD;
+use crate::clients::vector_store::{Filter, Hit, VectorStore};
+use crate::feedback::Boosts;
+use crate::retrieval::Retrieval;
+
+/// Deepest result a page may reach, since every page asks the store for everything above it
+pub const MAX_DEPTH: u64 = 10_000;
+/// Metadata field of a merged hit counting the chunks it spans
+pub const MERGED_CHUNKS_FIELD: &str = "merged_chunks";
+
+/// Where the next page starts: how deep into the results it is, and the last hit before it
+///
+/// Handed out as an opaque token. Resuming after the last hit rather than at a bare offset
+/// keeps pages from repeating results when better matches were stored in between.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Cursor {
+    offset: u64,
+    score: f32,
+    id: String,
+}
+
+impl Cursor {
+    pub fn encode(&self) -> String {
+        BASE64_URL_SAFE_NO_PAD.encode(serde_json::to_vec(self).expect("a cursor always serializes"))
+    }
+
+    pub fn decode(token: &str) -> Result<Self> {
+        let json = BASE64_URL_SAFE_NO_PAD.decode(token).context("Malformed cursor")?;
+        serde_json::from_slice(&json).context("Malformed cursor")
+    }
+
+    /// Whether `hit` ranks below the hit the cursor was taken at
+    fn is_before(&self, hit: &Hit) -> bool {
+        rank(self.score, &self.id, hit.score, &hit.id) == Ordering::Less
+    }
+}
+
+/// A page that would reach past `MAX_DEPTH`
+#[derive(Debug)]
+pub struct TooDeep(u64);
+
+impl Display for TooDeep {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Pages end at result {MAX_DEPTH}, this one would reach {}", self.0)
+    }
+}
+
+impl std::error::Error for TooDeep {}
+
+/// One page of search results
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Page {
+    pub hits: Vec<Hit>,
+    /// Left out once the results run out, or when the page is partial
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+    /// Set when the deadline passed before every part of the store answered
+    pub partial: bool,
+    /// Set when the query couldn't be embedded, and an earlier embedding of it or its words
+    /// were searched with instead
+    #[serde(default)]
+    pub degraded: bool,
+}
+
+/// The `limit` hits passing `filter` after `cursor`, or after the first `offset` without one
+///
+/// Hits are the nearest to `vector`, or without one those matching the words of `query`.
+/// With a `deadline` the page holds whatever the store found by then. `boosts` reorder the
+/// hits the store found for the page, they don't bring in hits it didn't. The `retrieval`
+/// stages that need the `query` text are skipped without it.
+#[allow(clippy::too_many_arguments)]
+pub async fn page(
+    store: &(impl VectorStore + Sync),
+    retrieval: &Retrieval<'_>,
+    query: Option<&str>,
+    vector: Option<Vec<f32>>,
+    limit: u64,
+    offset: u64,
+    filter: &Filter,
+    cursor: Option<&Cursor>,
+    deadline: Option<Instant>,
+    boosts: Option<&Boosts>,
+) -> Result<Page> {
+    let offset = cursor.map_or(offset, |cursor| cursor.offset);
+    let depth = match offset.checked_add(limit) {
+        Some(depth) if depth <= MAX_DEPTH => depth,
+        _ => return Err(TooDeep(offset.saturating_add(limit)).into()),
+    };
+
+    // One more than the page needs tells whether there is another page after it
+    let filter = retrieval.filter(filter);
+    let fetch = retrieval.fetch(depth.saturating_add(1));
+    let (mut hits, partial) = match (vector, query, deadline) {
+        (Some(vector), _, Some(deadline)) => store.search_within(vector, fetch, &filter, deadline).await?,
+        (Some(vector), _, None) => (store.search(vector, fetch, &filter).await?, false),
+        (None, Some(query), Some(deadline)) => match tokio::time::timeout_at(deadline, store.search_text(query, fetch, &filter)).await {
+            Ok(hits) => (hits?, false),
+            Err(_) => (vec![], true),
+        },
+        (None, Some(query), None) => (store.search_text(query, fetch, &filter).await?, false),
+        (None, None, _) => bail!("A search needs a vector or a query"),
+    };
+    let more = hits.len() as u64 > depth;
+    if let Some(query) = query {
+        retrieval.rerank(query, &mut hits).await?;
+    }
+    if let Some(boosts) = boosts {
+        boosts.apply(&mut hits);
+    }
+    sort(&mut hits);
+
+    let start = match cursor {
+        Some(cursor) => hits.iter().position(|hit| cursor.is_before(hit)).unwrap_or(hits.len()),
+        None => (offset as usize).min(hits.len()),
+    };
+    let hits: Vec<Hit> = hits.into_iter().skip(start).take(limit as usize).collect();
+
+    let next_cursor = match hits.last() {
+        Some(last) if more && !partial && hits.len() as u64 == limit => Some(Cursor {
+            offset: (start + hits.len()) as u64,
+            score: last.score,
+            id: last.id.clone(),
+        }.encode()),
+        _ => None,
+    };
+    // After the cursor is taken, as the hits it drops still count towards the page
+    let hits = match query {
+        Some(query) => retrieval.compress(query, hits).await?,
+        None => hits,
+    };
+
+    Ok(Page { hits, next_cursor, partial, degraded: false })
+}
+
+/// The best `limit` of several result lists, such as one per partition, each point once
+pub fn merge(lists: Vec<Vec<Hit>>, limit: usize) -> Vec<Hit> {
+    let mut hits: Vec<Hit> = lists.into_iter().flatten().collect();
+    sort(&mut hits);
+
+    let mut seen = HashSet::new();
+    hits.retain(|hit| seen.insert(hit.id.clone()));
+    hits.truncate(limit);
+    hits
+}
+
+/// Joins hits that are consecutive chunks of one source into a single passage, with the
+/// score and in the place of the best of them, so merged passages rank where their best
+/// chunk did
+///
+/// `overlap` is `pipeline.chunk_overlap`, the text chunks repeat from the one before, which
+/// the passage holds only once. The merged hit keeps the first chunk's metadata, with
+/// `MERGED_CHUNKS_FIELD` telling how many it spans.
+pub fn merge_adjacent(hits: Vec<Hit>, overlap: usize) -> Vec<Hit> {
+    let mut chunks: BTreeMap<(&str, u64), usize> = BTreeMap::new();
+    for (at, hit) in hits.iter().enumerate() {
+        if let Some(index) = hit.metadata.extra.get(CHUNK_INDEX_FIELD).and_then(serde_json::Value::as_u64) {
+            chunks.entry((&hit.metadata.source, index)).or_insert(at);
+        }
+    }
+
+    // Runs of consecutive chunks of one source, as positions in `hits` in chunk order
+    let mut runs: Vec<Vec<usize>> = Vec::new();
+    let mut previous: Option<(&str, u64)> = None;
+    for (&(source, index), &at) in &chunks {
+        match (runs.last_mut(), previous) {
+            (Some(run), Some((last_source, last_index))) if last_source == source && last_index + 1 == index => run.push(at),
+            _ => runs.push(vec![at]),
+        }
+        previous = Some((source, index));
+    }
+
+    let mut merged: BTreeMap<usize, Hit> = BTreeMap::new();
+    let mut absorbed = HashSet::new();
+    for run in runs.into_iter().filter(|run| run.len() > 1) {
+        let best = *run.iter().min().expect("runs are never empty");
+        let mut passage = hits[run[0]].clone();
+        for &at in &run[1..] {
+            passage.text = join(passage.text, &hits[at].text, overlap);
+        }
+        passage.id = hits[best].id.clone();
+        passage.score = hits[best].score;
+        passage.metadata.extra.insert(MERGED_CHUNKS_FIELD.to_string(), run.len().into());
+        absorbed.extend(run.iter().copied());
+        merged.insert(best, passage);
+    }
+
+    hits.into_iter()
+        .enumerate()
+        .filter_map(|(at, hit)| match merged.remove(&at) {
+            Some(passage) => Some(passage),
+            None if absorbed.contains(&at) => None,
+            None => Some(hit),
+        })
+        .collect()
+}
+
+/// `next` appended to `passage`, leaving out the start it repeats from the passage's end
+///
+/// Only a repeat of at least half of `overlap` counts, a shorter one is most likely chance.
+fn join(mut passage: String, next: &str, overlap: usize) -> String {
+    let shared = next.char_indices()
+        .map(|(at, c)| at + c.len_utf8())
+        .take(overlap)
+        .skip((overlap / 2).saturating_sub(1))
+        .filter(|&end| passage.ends_with(&next[..end]))
+        .last();
+    match shared {
+        Some(end) => passage.push_str(&next[end..]),
+        None => {
+            passage.push(' ');
+            passage.push_str(next);
+        }
+    }
+
+    passage
+}
+
+/// Best first, ties broken by id so the same results always come in the same order
+pub fn sort(hits: &mut [Hit]) {
+    hits.sort_by(|a, b| rank(a.score, &a.id, b.score, &b.id));
+}
+
+fn rank(a_score: f32, a_id: &str, b_score: f32, b_id: &str) -> Ordering {
+    b_score.total_cmp(&a_score).then_with(|| a_id.cmp(b_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::clients::vector_store::sqlite::SqliteStore;
+    use crate::config::SqliteConfig;
+    use super::*;
+
+    #[tokio::test]
+    async fn pages_past_the_deepest_result_are_refused() {
+        // Never opened, as the page is refused before the store is asked
+        let store = SqliteStore::from_config(&SqliteConfig::default()).unwrap();
+        let (retrieval, filter) = (Retrieval::default(), Filter::default());
+
+        for (limit, offset) in [(10, u64::MAX), (u64::MAX, 1), (1, MAX_DEPTH)] {
+            let error = page(&store, &retrieval, None, Some(vec![1.0]), limit, offset, &filter, None, None, None).await.unwrap_err();
+            assert!(error.is::<TooDeep>(), "{limit} after {offset}: {error:#}");
+        }
+    }
+}
@@ -0,0 +1,55 @@
+use std::sync::{Mutex, OnceLock};
+
+use rand::distributions::{Distribution, Standard};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+/// `--seed`, fixed once at startup
+static SEED: OnceLock<Option<u64>> = OnceLock::new();
+/// Generator behind `random` and `uuid`, seeded on first use
+static SHARED: Mutex<Option<StdRng>> = Mutex::new(None);
+
+/// Makes every random choice of the process follow `seed`, or entropy without one
+///
+/// Only the first call counts. Choices still only repeat as far as the order they are
+/// made in does, which concurrent embedding can change between runs.
+pub fn set(seed: Option<u64>) {
+    _ = SEED.set(seed);
+}
+
+pub fn get() -> Option<u64> {
+    SEED.get().copied().flatten()
+}
+
+/// A generator of its own for `stream`, so one consumer drawing more numbers doesn't shift
+/// what another one gets
+pub fn rng(stream: &str) -> StdRng {
+    match get() {
+        Some(seed) => StdRng::from_seed(derive(seed, stream)),
+        None => StdRng::from_entropy(),
+    }
+}
+
+/// A value from the shared generator
+pub fn random<T>() -> T
+where
+    Standard: Distribution<T>,
+{
+    let mut shared = SHARED.lock().expect("seed lock poisoned");
+    shared.get_or_insert_with(|| rng("shared")).gen()
+}
+
+/// A v4 UUID, from the shared generator so seeded runs hand out the same ids
+pub fn uuid() -> Uuid {
+    uuid::Builder::from_random_bytes(random()).into_uuid()
+}
+
+fn derive(seed: u64, stream: &str) -> [u8; 32] {
+    Sha256::new()
+        .chain_update(seed.to_le_bytes())
+        .chain_update(stream.as_bytes())
+        .finalize()
+        .into()
+}
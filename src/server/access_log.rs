@@ -0,0 +1,128 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::body::Body;
+use axum::extract::{Request, State};
+use axum::middleware::Next;
+use axum::response::Response;
+use futures::StreamExt;
+use serde_json::Value;
+use tracing::{info, warn};
+use crate::config::AccessLogConfig;
+use crate::seed;
+
+/// Most of a sampled body kept for the log, so a large upload isn't held in memory for it;
+/// JSON cut off here is logged like a body that isn't JSON
+const MAX_CAPTURED: usize = 1 << 20;
+/// Routes whose bodies are never sampled, as they stream for as long as the upload lasts
+const STREAMED: &[&str] = &["/documents/stream"];
+
+/// The start of a body as the handler read it, and how long it was in all
+#[derive(Default)]
+struct Captured {
+    bytes: Vec<u8>,
+    total: usize,
+}
+
+/// Logs each request once answered, per `serve.access_log`
+///
+/// A sampled body is copied as the handler reads it, up to `MAX_CAPTURED` bytes.
+pub async fn log(State(config): State<Arc<AccessLogConfig>>, request: Request, next: Next) -> Response {
+    let started = Instant::now();
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+
+    let sampled = config.enabled
+        && config.body_sample_rate > 0.0
+        && !STREAMED.contains(&path.as_str())
+        && seed::random::<f64>() < config.body_sample_rate;
+    let captured = Arc::new(Mutex::new(Captured::default()));
+    let request = if sampled {
+        let (parts, body) = request.into_parts();
+        let tee = captured.clone();
+        let body = body.into_data_stream().inspect(move |chunk| {
+            if let Ok(chunk) = chunk {
+                let mut tee = tee.lock().expect("captured body lock poisoned");
+                let room = MAX_CAPTURED.saturating_sub(tee.bytes.len());
+                tee.bytes.extend_from_slice(&chunk[..chunk.len().min(room)]);
+                tee.total += chunk.len();
+            }
+        });
+        Request::from_parts(parts, Body::from_stream(body))
+    } else {
+        request
+    };
+
+    let response = next.run(request).await;
+    let elapsed = started.elapsed();
+    let status = response.status().as_u16();
+
+    let slow = config.slow_request_ms > 0 && elapsed >= Duration::from_millis(config.slow_request_ms);
+    if slow {
+        warn!("Slow request {method} {path} answered {status} in {elapsed:?}");
+    } else if config.enabled {
+        info!("{method} {path} answered {status} in {elapsed:?}");
+    }
+    let captured = std::mem::take(&mut *captured.lock().expect("captured body lock poisoned"));
+    if sampled && !captured.bytes.is_empty() {
+        let body = sample(&config, &captured.bytes);
+        match captured.total > captured.bytes.len() {
+            true => info!("Body of {method} {path}, the first {} of {} bytes: {body}", captured.bytes.len(), captured.total),
+            false => info!("Body of {method} {path}: {body}"),
+        }
+    }
+
+    response
+}
+
+/// The body as logged: redacted if it is JSON, cut to `max_body_bytes`
+fn sample(config: &AccessLogConfig, bytes: &[u8]) -> String {
+    let text = match serde_json::from_slice::<Value>(bytes) {
+        Ok(mut json) => {
+            redact(&mut json, &config.redact_fields);
+            json.to_string()
+        }
+        // NDJSON and other documents' lines are redacted one by one
+        Err(_) => String::from_utf8_lossy(bytes)
+            .lines()
+            .map(|line| match serde_json::from_str::<Value>(line) {
+                Ok(mut json) => {
+                    redact(&mut json, &config.redact_fields);
+                    json.to_string()
+                }
+                Err(_) if config.redact_fields.is_empty() => line.to_string(),
+                Err(_) => format!("[{} bytes]", line.len()),
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+    };
+
+    if text.len() <= config.max_body_bytes {
+        return text;
+    }
+    let mut end = config.max_body_bytes;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}... ({} bytes)", &text[..end], text.len())
+}
+
+fn redact(json: &mut Value, fields: &[String]) {
+    match json {
+        Value::Object(object) => {
+            for (key, value) in object.iter_mut() {
+                if fields.iter().any(|field| field == key) {
+                    let length = match &*value {
+                        Value::String(text) => text.len(),
+                        other => other.to_string().len(),
+                    };
+                    *value = Value::String(format!("[redacted, {length} bytes]"));
+                } else {
+                    redact(value, fields);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(|item| redact(item, fields)),
+        _ => {}
+    }
+}
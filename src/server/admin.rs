@@ -0,0 +1,64 @@
+use axum::extract::rejection::QueryRejection;
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::middleware;
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::Deserialize;
+use tracing::warn;
+use crate::control;
+use crate::history::{History, RunRecord};
+use crate::server::AppState;
+use crate::server::auth::{self, Keys};
+use crate::server::error::ApiError;
+
+/// Runs `GET /admin/runs` lists unless asked for another number
+const DEFAULT_RUNS: usize = 20;
+
+/// Runtime introspection and control, every route requiring `Authorization: Bearer <admin key>`
+pub fn router(key: &str) -> Router<AppState> {
+    Router::new()
+        .route("/state", get(state))
+        .route("/pause", post(pause))
+        .route("/resume", post(resume))
+        .route("/flush", post(flush))
+        .route("/runs", get(runs))
+        .layer(middleware::from_fn_with_state(Keys::bearer([key]), auth::authorize))
+}
+
+async fn state(State(app): State<AppState>) -> Json<control::State> {
+    Json(app.control.state())
+}
+
+async fn pause(State(app): State<AppState>) -> Json<control::State> {
+    app.control.pause();
+    Json(app.control.state())
+}
+
+async fn resume(State(app): State<AppState>) -> Json<control::State> {
+    app.control.resume();
+    Json(app.control.state())
+}
+
+/// Accepted rather than done: the upsert loop picks the request up between documents
+async fn flush(State(app): State<AppState>) -> StatusCode {
+    app.control.flush();
+    StatusCode::ACCEPTED
+}
+
+#[derive(Deserialize)]
+pub struct RunsQuery {
+    limit: Option<usize>,
+}
+
+/// The last `limit` runs of the history file, oldest first, as `history` lists them
+async fn runs(State(app): State<AppState>, query: Result<Query<RunsQuery>, QueryRejection>) -> Result<Json<Vec<RunRecord>>, ApiError> {
+    let Query(query) = query.map_err(|e| ApiError::BadRequest(e.body_text()))?;
+    let records = History::new(&app.config.history).list().await.map_err(|e| {
+        warn!("Failed to read the run history {}: {e:#}", app.config.history.display());
+        ApiError::HistoryUnavailable
+    })?;
+    let skip = records.len().saturating_sub(query.limit.unwrap_or(DEFAULT_RUNS));
+
+    Ok(Json(records.into_iter().skip(skip).collect()))
+}
@@ -0,0 +1,38 @@
+use std::sync::Arc;
+
+use axum::extract::{Request, State};
+use axum::http::header;
+use axum::middleware::Next;
+use axum::response::Response;
+use crate::server::error::ApiError;
+
+/// The `Authorization` values a group of routes accepts, one per key
+#[derive(Clone)]
+pub struct Keys(Arc<[String]>);
+
+impl Keys {
+    pub fn bearer<'k>(keys: impl IntoIterator<Item = &'k str>) -> Self {
+        Self(keys.into_iter().map(|key| format!("Bearer {key}")).collect())
+    }
+}
+
+/// Rejects requests without `Authorization: Bearer <key>` for one of the route's keys
+pub async fn authorize(State(keys): State<Keys>, request: Request, next: Next) -> Result<Response, ApiError> {
+    let given = request.headers()
+        .get(header::AUTHORIZATION)
+        .map(|value| value.as_bytes())
+        .unwrap_or_default();
+
+    // Every key is compared, so response times don't tell which one came closer either
+    let allowed = keys.0.iter().fold(false, |allowed, key| allowed | constant_time_eq(given, key.as_bytes()));
+    if !allowed {
+        return Err(ApiError::Unauthorized);
+    }
+
+    Ok(next.run(request).await)
+}
+
+/// Compares without returning early, so response times don't leak how much of the key matched
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
@@ -0,0 +1,166 @@
+use axum::body::{Body, Bytes};
+use axum::extract::rejection::QueryRejection;
+use axum::extract::{Query, State};
+use axum::http::{HeaderMap, HeaderName, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use futures::TryStreamExt;
+use serde::Deserialize;
+use tracing::{debug, warn};
+use crate::api::{Stored, Streamed, IDEMPOTENCY_KEY};
+use crate::clients::Document;
+use crate::clients::vector_store::VectorStore;
+use crate::dialect::{parse_document, DocumentFormat};
+use crate::multivector;
+use crate::provenance;
+use crate::server::{self, AppState};
+use crate::server::error::ApiError;
+use crate::server::idempotency::Claim;
+
+/// Documents of a stream embedded and stored together
+const STREAM_BATCH: usize = 64;
+const MAX_LINE_BYTES: usize = 16 * 1024 * 1024;
+
+/// Set on answers repeated for a retried `Idempotency-Key`
+pub const IDEMPOTENT_REPLAYED: HeaderName = HeaderName::from_static("idempotent-replayed");
+
+/// `POST /documents` with a JSON array of documents, embedded and stored before answering
+///
+/// Retries sent with the same `Idempotency-Key` within `serve.idempotency_ttl_secs` get the
+/// first answer again rather than storing the documents twice.
+pub async fn post(State(app): State<AppState>, headers: HeaderMap, body: Bytes) -> Result<Response, ApiError> {
+    writable(&app)?;
+    let key = headers.get(IDEMPOTENCY_KEY)
+        .map(|value| value.to_str().map_err(|_| ApiError::BadRequest("Idempotency-Key must be visible ASCII".to_string())))
+        .transpose()?;
+    let pending = match key {
+        Some(key) => match app.idempotency.claim(key, &body)? {
+            Claim::Replay(stored) => {
+                let mut response = (StatusCode::CREATED, Json(stored)).into_response();
+                response.headers_mut().insert(IDEMPOTENT_REPLAYED, HeaderValue::from_static("true"));
+                return Ok(response);
+            }
+            Claim::New(pending) => Some(pending),
+        },
+        None => None,
+    };
+
+    let documents: Vec<Document> = serde_json::from_slice(&body)
+        .map_err(|e| ApiError::BadRequest(format!("Body is not a JSON array of documents: {e}")))?;
+    let stored = Stored { stored: store(&app, documents).await? };
+
+    if let Some(pending) = pending {
+        pending.complete(stored.clone());
+    }
+    Ok((StatusCode::CREATED, Json(stored)).into_response())
+}
+
+#[derive(Deserialize)]
+pub struct StreamQuery {
+    #[serde(default)]
+    format: DocumentFormat,
+}
+
+/// `POST /documents/stream` with a document per line, stored in batches as they arrive
+///
+/// The next batch is only read once the last one is stored, so a client sending faster than
+/// documents are embedded is held back by the connection's flow control.
+pub async fn stream(
+    State(app): State<AppState>,
+    query: Result<Query<StreamQuery>, QueryRejection>,
+    body: Body,
+) -> Result<Json<Streamed>, ApiError> {
+    writable(&app)?;
+    let Query(query) = query.map_err(|e| ApiError::BadRequest(e.body_text()))?;
+    let mut chunks = body.into_data_stream();
+    let mut line: Vec<u8> = Vec::new();
+    let mut batch = Vec::with_capacity(STREAM_BATCH);
+    let mut streamed = Streamed { stored: 0, skipped: 0 };
+
+    loop {
+        let chunk = chunks.try_next().await
+            .map_err(|e| ApiError::BadRequest(format!("Reading the body failed: {e}")))?;
+        let Some(chunk) = chunk else {
+            break;
+        };
+
+        for piece in chunk.split_inclusive(|byte| *byte == b'\n') {
+            line.extend_from_slice(piece);
+            if line.len() > MAX_LINE_BYTES {
+                return Err(ApiError::BadRequest(format!("A line is longer than {MAX_LINE_BYTES} bytes")));
+            }
+            if line.ends_with(b"\n") {
+                parse_line(&std::mem::take(&mut line), query.format, &mut batch, &mut streamed);
+            }
+        }
+
+        if batch.len() >= STREAM_BATCH {
+            streamed.stored += store_streamed(&app, std::mem::take(&mut batch), streamed.stored).await?;
+        }
+    }
+
+    parse_line(&line, query.format, &mut batch, &mut streamed);
+    if !batch.is_empty() {
+        streamed.stored += store_streamed(&app, batch, streamed.stored).await?;
+    }
+
+    Ok(Json(streamed))
+}
+
+fn parse_line(line: &[u8], format: DocumentFormat, batch: &mut Vec<Document>, streamed: &mut Streamed) {
+    let Ok(text) = std::str::from_utf8(line) else {
+        streamed.skipped += 1;
+        return;
+    };
+    if text.trim().is_empty() {
+        return;
+    }
+
+    match parse_document(text, format) {
+        Ok(document) => batch.push(document),
+        Err(e) => {
+            debug!("Skipping a streamed line: {e}");
+            streamed.skipped += 1;
+        }
+    }
+}
+
+async fn store_streamed(app: &AppState, batch: Vec<Document>, stored: u64) -> Result<u64, ApiError> {
+    store(app, batch).await.inspect_err(|_| warn!("The stream broke off after {stored} stored documents"))
+}
+
+/// Refuses documents before anything is read or embedded when the server runs read-only
+fn writable(app: &AppState) -> Result<(), ApiError> {
+    match app.config.read_only {
+        true => Err(ApiError::ReadOnly),
+        false => Ok(()),
+    }
+}
+
+/// Embeds and stores the documents, in several batches when there are more than the buffer
+/// holds, so a failure may come after part of them were stored
+async fn store(app: &AppState, documents: Vec<Document>) -> Result<u64, ApiError> {
+    let count = documents.len() as u64;
+    let texts = documents.iter().map(|document| document.page_content.clone()).collect();
+    let vectors = server::embed(app, texts).await.map_err(|e| {
+        warn!("Embedding posted documents failed: {e:?}");
+        ApiError::EmbeddingUnavailable
+    })?;
+
+    let documents = documents.into_iter()
+        .zip(vectors)
+        .map(|(mut document, embeddings)| {
+            provenance::stamp(&mut document, &app.config.llama, multivector::head(&app.config, &embeddings));
+            Document { embeddings, ..document }
+        })
+        .collect();
+    let result = async {
+        app.store.ensure_collection().await?;
+        app.writer.write(documents).await
+    }.await;
+
+    result.map(|_| count).map_err(|e| {
+        warn!("Storing posted documents failed: {e:?}");
+        ApiError::StoreUnavailable
+    })
+}
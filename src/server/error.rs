@@ -0,0 +1,162 @@
+use std::fmt::{Display, Formatter};
+
+use axum::extract::Request;
+use axum::http::{HeaderName, HeaderValue, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde::Serialize;
+use tracing::Instrument;
+use crate::seed;
+
+/// Header a caller may set to pick the trace id, echoed back on every response
+pub const TRACE_ID_HEADER: HeaderName = HeaderName::from_static("x-trace-id");
+
+tokio::task_local! {
+    static TRACE_ID: String;
+}
+
+/// Why a request failed, as every route reports it
+#[derive(Debug)]
+pub enum ApiError {
+    /// The request itself is wrong; sending it again won't help
+    BadRequest(String),
+    Unauthorized,
+    NotFound,
+    MethodNotAllowed,
+    /// The `Idempotency-Key` came with a different body before
+    IdempotencyKeyReused,
+    /// The first request with the `Idempotency-Key` hasn't been answered yet
+    IdempotencyKeyInFlight,
+    EmbeddingUnavailable,
+    StoreUnavailable,
+    /// The feedback file couldn't be written
+    FeedbackUnavailable,
+    /// The server runs read-only and stores nothing
+    ReadOnly,
+    /// The run history file couldn't be read
+    HistoryUnavailable,
+}
+
+impl ApiError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized => StatusCode::UNAUTHORIZED,
+            ApiError::NotFound => StatusCode::NOT_FOUND,
+            ApiError::MethodNotAllowed => StatusCode::METHOD_NOT_ALLOWED,
+            ApiError::IdempotencyKeyReused => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::IdempotencyKeyInFlight => StatusCode::CONFLICT,
+            ApiError::EmbeddingUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::StoreUnavailable => StatusCode::BAD_GATEWAY,
+            ApiError::FeedbackUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::ReadOnly => StatusCode::FORBIDDEN,
+            ApiError::HistoryUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+
+    /// Stable name for clients to match on
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::Unauthorized => "unauthorized",
+            ApiError::NotFound => "not_found",
+            ApiError::MethodNotAllowed => "method_not_allowed",
+            ApiError::IdempotencyKeyReused => "idempotency_key_reused",
+            ApiError::IdempotencyKeyInFlight => "idempotency_key_in_flight",
+            ApiError::EmbeddingUnavailable => "embedding_unavailable",
+            ApiError::StoreUnavailable => "store_unavailable",
+            ApiError::FeedbackUnavailable => "feedback_unavailable",
+            ApiError::ReadOnly => "read_only",
+            ApiError::HistoryUnavailable => "history_unavailable",
+        }
+    }
+
+    /// Whether the same request may succeed later, once a backend is back or an earlier
+    /// attempt is done
+    pub fn retryable(&self) -> bool {
+        matches!(self, ApiError::IdempotencyKeyInFlight | ApiError::EmbeddingUnavailable | ApiError::StoreUnavailable | ApiError::FeedbackUnavailable | ApiError::HistoryUnavailable)
+    }
+}
+
+impl Display for ApiError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ApiError::BadRequest(message) => f.write_str(message),
+            ApiError::Unauthorized => f.write_str("Missing or wrong API key"),
+            ApiError::NotFound => f.write_str("No such route"),
+            ApiError::MethodNotAllowed => f.write_str("The route doesn't take this method"),
+            ApiError::IdempotencyKeyReused => f.write_str("The Idempotency-Key was used for a different request"),
+            ApiError::IdempotencyKeyInFlight => f.write_str("A request with this Idempotency-Key is still being handled"),
+            ApiError::EmbeddingUnavailable => f.write_str("The embedding backend is unavailable"),
+            ApiError::StoreUnavailable => f.write_str("The vector store is unavailable"),
+            ApiError::FeedbackUnavailable => f.write_str("Feedback can't be recorded right now"),
+            ApiError::ReadOnly => f.write_str("The server is read-only and doesn't store documents"),
+            ApiError::HistoryUnavailable => f.write_str("The run history can't be read right now"),
+        }
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+/// `{"error": {..}}`, the body of every failed response
+#[derive(Serialize)]
+struct Envelope {
+    error: Body,
+}
+
+#[derive(Serialize)]
+struct Body {
+    code: &'static str,
+    message: String,
+    retryable: bool,
+    /// Also in the `x-trace-id` header and the server's logs for the request
+    trace_id: String,
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let envelope = Envelope {
+            error: Body {
+                code: self.code(),
+                message: self.to_string(),
+                retryable: self.retryable(),
+                trace_id: trace_id(),
+            },
+        };
+
+        (self.status(), Json(envelope)).into_response()
+    }
+}
+
+/// The current request's trace id, empty outside of one
+pub fn trace_id() -> String {
+    TRACE_ID.try_with(String::clone).unwrap_or_default()
+}
+
+/// Gives each request a trace id, the caller's `x-trace-id` or a new one, for its logs,
+/// its error bodies and its response header
+pub async fn trace(request: Request, next: Next) -> Response {
+    let id = request.headers()
+        .get(&TRACE_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| !value.is_empty() && value.len() <= 128)
+        .map(str::to_string)
+        .unwrap_or_else(|| seed::uuid().to_string());
+    let span = tracing::info_span!("request", trace_id = %id);
+
+    let mut response = TRACE_ID.scope(id.clone(), next.run(request).instrument(span)).await;
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        response.headers_mut().insert(TRACE_ID_HEADER, value);
+    }
+
+    response
+}
+
+pub async fn not_found() -> ApiError {
+    ApiError::NotFound
+}
+
+pub async fn method_not_allowed() -> ApiError {
+    ApiError::MethodNotAllowed
+}
@@ -0,0 +1,105 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::clients::vector_store;
+use crate::config::FallbackConfig;
+
+/// Embeddings of recent search queries, searched with while llama.cpp is down, and when it
+/// last failed to embed one
+///
+/// Queries are looked up trimmed and lowercased, so a cached embedding may be of a query
+/// that differed in case; results from it are flagged degraded either way. Queries without
+/// one are searched for by their words.
+pub struct Fallback {
+    capacity: usize,
+    retry_after: Duration,
+    queries: Mutex<Queries>,
+    /// Until when searches skip embedding, set by a failure
+    down_until: Mutex<Option<Instant>>,
+}
+
+#[derive(Default)]
+struct Queries {
+    vectors: HashMap<String, Vec<f32>>,
+    /// Least recently used first
+    order: VecDeque<String>,
+}
+
+impl Fallback {
+    pub fn new(config: &FallbackConfig) -> Self {
+        Self {
+            capacity: config.cache_size.max(1),
+            retry_after: Duration::from_secs(config.retry_secs),
+            queries: Mutex::new(Queries::default()),
+            down_until: Mutex::new(None),
+        }
+    }
+
+    /// Whether a recent failure says not to try embedding yet
+    pub fn is_down(&self) -> bool {
+        self.down_until.lock().expect("fallback lock poisoned").is_some_and(|until| Instant::now() < until)
+    }
+
+    pub fn embedding_failed(&self) {
+        *self.down_until.lock().expect("fallback lock poisoned") = Some(Instant::now() + self.retry_after);
+    }
+
+    /// Keeps the embedding of `query`, which also says llama.cpp is back
+    pub fn embedded(&self, query: &str, vector: &[f32]) {
+        *self.down_until.lock().expect("fallback lock poisoned") = None;
+
+        let key = vector_store::keyword(query);
+        let mut queries = self.queries.lock().expect("fallback lock poisoned");
+        if queries.vectors.insert(key.clone(), vector.to_vec()).is_some() {
+            queries.order.retain(|known| *known != key);
+        }
+        queries.order.push_back(key);
+        while queries.order.len() > self.capacity {
+            if let Some(oldest) = queries.order.pop_front() {
+                queries.vectors.remove(&oldest);
+            }
+        }
+    }
+
+    pub fn cached(&self, query: &str) -> Option<Vec<f32>> {
+        let key = vector_store::keyword(query);
+        let mut queries = self.queries.lock().expect("fallback lock poisoned");
+        let vector = queries.vectors.get(&key)?.clone();
+        queries.order.retain(|known| *known != key);
+        queries.order.push_back(key);
+
+        Some(vector)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_least_recently_used_query_goes_first() {
+        let fallback = Fallback::new(&FallbackConfig { cache_size: 2, ..FallbackConfig::default() });
+        fallback.embedded("first", &[1.0]);
+        fallback.embedded("second", &[2.0]);
+        assert_eq!(fallback.cached(" FIRST "), Some(vec![1.0]));
+
+        fallback.embedded("third", &[3.0]);
+
+        assert_eq!(fallback.cached("first"), Some(vec![1.0]));
+        assert_eq!(fallback.cached("second"), None);
+        assert_eq!(fallback.cached("third"), Some(vec![3.0]));
+    }
+
+    #[test]
+    fn a_failure_holds_embedding_off_until_one_succeeds() {
+        let fallback = Fallback::new(&FallbackConfig { retry_secs: 60, ..FallbackConfig::default() });
+        assert!(!fallback.is_down());
+
+        fallback.embedding_failed();
+        assert!(fallback.is_down());
+
+        fallback.embedded("query", &[1.0]);
+        assert!(!fallback.is_down());
+    }
+}
@@ -0,0 +1,51 @@
+use std::net::SocketAddr;
+
+use axum::body::Bytes;
+use axum::extract::{ConnectInfo, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::Json;
+use serde::Deserialize;
+use tracing::warn;
+use crate::feedback::{Feedback, Vote};
+use crate::server::AppState;
+use crate::server::error::ApiError;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FeedbackRequest {
+    query: String,
+    point_id: String,
+    vote: Vote,
+}
+
+/// `POST /feedback` with `{"query", "point_id", "vote": "up" | "down"}`, appended to the
+/// config's feedback file with who voted, by `feedback.voter_header` or the peer address
+pub async fn post(
+    State(app): State<AppState>,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<(StatusCode, Json<Feedback>), ApiError> {
+    let request: FeedbackRequest = serde_json::from_slice(&body)
+        .map_err(|e| ApiError::BadRequest(format!("Body is not a vote: {e}")))?;
+    if request.query.trim().is_empty() || request.point_id.trim().is_empty() {
+        return Err(ApiError::BadRequest("A vote needs a query and a point_id".to_string()));
+    }
+
+    let voter = headers.get(&app.config.feedback.voter_header)
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim)
+        .filter(|voter| !voter.is_empty())
+        .map(str::to_string)
+        .or_else(|| peer.map(|ConnectInfo(peer)| peer.ip().to_string()));
+    let feedback = Feedback::new(request.query, request.point_id, request.vote, voter);
+    app.feedback.record(&feedback).await.map_err(|e| {
+        warn!("Recording feedback failed: {e:#}");
+        ApiError::FeedbackUnavailable
+    })?;
+    if let Some(boosts) = &app.boosts {
+        boosts.add(&feedback);
+    }
+
+    Ok((StatusCode::CREATED, Json(feedback)))
+}
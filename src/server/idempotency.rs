@@ -0,0 +1,91 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use sha2::{Digest, Sha256};
+use crate::server::error::ApiError;
+
+/// Longest `Idempotency-Key` accepted
+const MAX_KEY_LENGTH: usize = 255;
+
+/// Answers to requests sent with an `Idempotency-Key`, kept for `ttl` so a retry of one gets
+/// the same answer instead of being carried out again
+///
+/// Only successes are kept; a failed request can be retried under the same key.
+pub struct Idempotency<T> {
+    ttl: Duration,
+    entries: Mutex<HashMap<String, Entry<T>>>,
+}
+
+struct Entry<T> {
+    /// SHA-256 of the request body, which a retry has to repeat
+    fingerprint: [u8; 32],
+    expires: Instant,
+    /// `None` while the first request is still being carried out
+    answer: Option<T>,
+}
+
+/// What to do with a request under an `Idempotency-Key`
+pub enum Claim<'i, T: Clone> {
+    /// Carry it out, then hand the answer to the claim
+    New(Pending<'i, T>),
+    /// Answer with what the earlier request got
+    Replay(T),
+}
+
+impl<T: Clone> Idempotency<T> {
+    pub fn new(ttl: Duration) -> Self {
+        Self { ttl, entries: Mutex::new(HashMap::new()) }
+    }
+
+    /// Claims `key` for a request with `body`, unless it was answered already
+    pub fn claim(&self, key: &str, body: &[u8]) -> Result<Claim<'_, T>, ApiError> {
+        if key.is_empty() || key.len() > MAX_KEY_LENGTH {
+            return Err(ApiError::BadRequest(format!("Idempotency-Key must be 1 to {MAX_KEY_LENGTH} characters")));
+        }
+
+        let fingerprint: [u8; 32] = Sha256::digest(body).into();
+        let now = Instant::now();
+        let mut entries = self.entries.lock().expect("idempotency lock poisoned");
+        entries.retain(|_, entry| entry.expires > now);
+
+        match entries.get(key) {
+            Some(entry) if entry.fingerprint != fingerprint => Err(ApiError::IdempotencyKeyReused),
+            Some(Entry { answer: Some(answer), .. }) => Ok(Claim::Replay(answer.clone())),
+            Some(_) => Err(ApiError::IdempotencyKeyInFlight),
+            None => {
+                entries.insert(key.to_string(), Entry { fingerprint, expires: now + self.ttl, answer: None });
+                Ok(Claim::New(Pending { idempotency: self, key: Some(key.to_string()) }))
+            }
+        }
+    }
+}
+
+/// A claimed key, released again if dropped before `complete`, as when the request fails or
+/// the client goes away
+pub struct Pending<'i, T: Clone> {
+    idempotency: &'i Idempotency<T>,
+    key: Option<String>,
+}
+
+impl<T: Clone> Pending<'_, T> {
+    /// Keeps `answer` for retries, the TTL counting from now
+    pub fn complete(mut self, answer: T) {
+        let Some(key) = self.key.take() else {
+            return;
+        };
+        let mut entries = self.idempotency.entries.lock().expect("idempotency lock poisoned");
+        if let Some(entry) = entries.get_mut(&key) {
+            entry.expires = Instant::now() + self.idempotency.ttl;
+            entry.answer = Some(answer);
+        }
+    }
+}
+
+impl<T: Clone> Drop for Pending<'_, T> {
+    fn drop(&mut self) {
+        if let (Some(key), Ok(mut entries)) = (self.key.take(), self.idempotency.entries.lock()) {
+            entries.remove(&key);
+        }
+    }
+}
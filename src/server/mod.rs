@@ -0,0 +1,242 @@
+pub mod access_log;
+pub mod admin;
+pub mod auth;
+pub mod documents;
+pub mod error;
+pub mod fallback;
+pub mod feedback;
+pub mod idempotency;
+pub mod search;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use axum::{middleware, Router};
+use axum::routing::{get, post};
+use tracing::warn;
+use crate::api::Stored;
+use crate::clients::llm::llama_cpp::LlamaCpp;
+use crate::clients::vector_store::Store;
+use crate::config::Config;
+use crate::control::Control;
+use crate::feedback::{Boosts, FeedbackLog};
+use crate::multivector;
+use crate::query_log::QueryLog;
+use crate::retrieval::Retrieval;
+use crate::sink::Writer;
+use self::fallback::Fallback;
+use self::idempotency::Idempotency;
+
+/// Shared by every request handler
+#[derive(Clone)]
+pub struct AppState {
+    pub control: Arc<Control>,
+    pub config: Arc<Config>,
+    pub store: Arc<Store>,
+    /// A store of its own for writes, owned by a task taking one request's documents at a time
+    pub writer: Writer,
+    pub idempotency: Arc<Idempotency<Stored>>,
+    /// Set with `serve.query_log.enabled`
+    pub query_log: Option<Arc<QueryLog>>,
+    pub feedback: Arc<FeedbackLog>,
+    /// Set with `feedback.scoring`, and kept up with the votes the server records
+    pub boosts: Option<Arc<Boosts>>,
+    pub retrieval: Arc<Retrieval<'static>>,
+    /// Built once, so every embedding reuses the connections it keeps alive
+    pub llama: Arc<LlamaCpp<'static>>,
+    /// Set with `serve.fallback.enabled`
+    pub fallback: Option<Arc<Fallback>>,
+}
+
+impl AppState {
+    pub fn new(config: &Config, control: Arc<Control>) -> Result<Self> {
+        // The llama clients borrow their settings; the server keeps them until it exits anyway
+        let settings: &'static Config = Box::leak(Box::new(config.clone()));
+
+        Ok(Self {
+            control,
+            config: Arc::new(config.clone()),
+            store: Arc::new(Store::from_config(config)?),
+            writer: Writer::spawn(Store::from_config(config)?).0,
+            idempotency: Arc::new(Idempotency::new(Duration::from_secs(config.serve.idempotency_ttl_secs))),
+            query_log: config.serve.query_log.enabled.then(|| Arc::new(QueryLog::new(&config.serve.query_log))),
+            feedback: Arc::new(FeedbackLog::new(&config.feedback.path)),
+            boosts: Boosts::load(&config.feedback)?.map(Arc::new),
+            retrieval: Arc::new(Retrieval::from_config(settings)?),
+            llama: Arc::new(LlamaCpp::from_config(&settings.llama)?),
+            fallback: config.serve.fallback.enabled.then(|| Arc::new(Fallback::new(&config.serve.fallback))),
+        })
+    }
+}
+
+/// Every route the serve mode exposes, failures answered with `error::ApiError`, starting the `serve.warmup` searches alongside
+pub fn router(config: &Config, control: Arc<Control>) -> Result<Router> {
+    let state = AppState::new(config, control)?;
+    if !config.serve.warmup.is_empty() {
+        tokio::spawn(search::warm_up(state.clone()));
+    }
+
+    let mut router = Router::new()
+        .route("/search", get(search::search));
+
+    let write_keys: Vec<&str> = [&config.serve.write_api_key, &config.serve.admin_api_key]
+        .into_iter()
+        .flatten()
+        .map(|key| key.expose())
+        .collect();
+    if write_keys.is_empty() {
+        warn!("No serve.write_api_key or serve.admin_api_key configured, POST /documents and /feedback are disabled");
+    } else {
+        router = router.merge(Router::new()
+            .route("/documents", post(documents::post))
+            .route("/documents/stream", post(documents::stream))
+            .route("/feedback", post(feedback::post))
+            .route_layer(middleware::from_fn_with_state(auth::Keys::bearer(write_keys), auth::authorize)));
+    }
+
+    match &config.serve.admin_api_key {
+        Some(key) => router = router.nest("/admin", admin::router(key.expose())),
+        None => warn!("No serve.admin_api_key configured, the admin API is disabled"),
+    }
+
+    router = router
+        .fallback(error::not_found)
+        .method_not_allowed_fallback(error::method_not_allowed);
+    let access_log = &config.serve.access_log;
+    if access_log.enabled || access_log.slow_request_ms > 0 {
+        router = router.layer(middleware::from_fn_with_state(Arc::new(access_log.clone()), access_log::log));
+    }
+
+    // Outermost, so the trace id is on everything logged for the request
+    Ok(router
+        .layer(middleware::from_fn(error::trace))
+        .with_state(state))
+}
+
+/// Embeds each of `texts`
+pub async fn embed(app: &AppState, texts: Vec<String>) -> Result<Vec<Vec<f32>>> {
+    let mut vectors = Vec::with_capacity(texts.len());
+    for (i, text) in texts.iter().enumerate() {
+        let vector = multivector::embed(app.llama.as_ref(), &app.config, text).await?;
+        if vector.is_empty() {
+            anyhow::bail!("No embedding for text {i}");
+        }
+        vectors.push(vector);
+    }
+
+    Ok(vectors)
+}
+
+/// Embeds a search query like `embed` does documents, with `llama.query_prefix`
+pub async fn embed_query(app: &AppState, query: String) -> Result<Vec<f32>> {
+    let vector = app.llama.query_embedding(&query).await?;
+    if vector.is_empty() {
+        anyhow::bail!("No embedding for the query");
+    }
+
+    Ok(vector)
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::StatusCode;
+    use chrono::Utc;
+    use crate::config::StoreKind;
+    use crate::history::{History, RunRecord};
+    use crate::outcome::{Exit, RunSummary};
+    use crate::secret::Secret;
+    use super::*;
+
+    /// Serves a router over a throwaway SQLite store, returning its base URL
+    async fn serve(name: &str, configure: impl FnOnce(&mut Config)) -> String {
+        let dir = std::env::temp_dir().join(format!("rag-rs-server-{name}-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut config = Config { store: StoreKind::Sqlite, ..Config::default() };
+        config.sqlite.path = dir.join("index.sqlite");
+        config.feedback.path = dir.join("feedback.jsonl");
+        config.history = dir.join("history.jsonl");
+        configure(&mut config);
+
+        let router = router(&config, Arc::new(Control::default())).unwrap();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let service = router.into_make_service_with_connect_info::<std::net::SocketAddr>();
+        tokio::spawn(async move { axum::serve(listener, service).await.unwrap() });
+
+        format!("http://{address}")
+    }
+
+    fn write_key(config: &mut Config) {
+        config.serve.write_api_key = Some(Secret::new("secret"));
+    }
+
+    #[tokio::test]
+    async fn writing_needs_the_write_key() {
+        let url = serve("write-key", write_key).await;
+        let http = reqwest::Client::new();
+
+        for path in ["/documents", "/documents/stream", "/feedback"] {
+            let missing = http.post(format!("{url}{path}")).body("[]").send().await.unwrap();
+            assert_eq!(missing.status(), StatusCode::UNAUTHORIZED, "{path}");
+            let wrong = http.post(format!("{url}{path}")).bearer_auth("guess").body("[]").send().await.unwrap();
+            assert_eq!(wrong.status(), StatusCode::UNAUTHORIZED, "{path}");
+        }
+        let stored = http.post(format!("{url}/documents"))
+            .bearer_auth("secret")
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body("[]")
+            .send().await.unwrap();
+        assert_ne!(stored.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn an_unknown_stream_format_is_a_bad_request() {
+        let url = serve("stream-format", write_key).await;
+
+        let response = reqwest::Client::new().post(format!("{url}/documents/stream?format=csv"))
+            .bearer_auth("secret")
+            .body("")
+            .send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = serde_json::from_slice(&response.bytes().await.unwrap()).unwrap();
+        assert_eq!(body["error"]["code"], "bad_request");
+    }
+
+    #[tokio::test]
+    async fn without_a_key_documents_are_not_stored() {
+        let url = serve("no-key", |_| {}).await;
+
+        let response = reqwest::Client::new().post(format!("{url}/documents")).body("[]").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn the_admin_api_lists_the_last_runs() {
+        let history = std::env::temp_dir().join(format!("rag-rs-server-runs-{}", std::process::id())).join("history.jsonl");
+        let url = serve("runs", |config| config.serve.admin_api_key = Some(Secret::new("admin"))).await;
+        _ = std::fs::remove_file(&history);
+        for path in ["a", "b", "c"] {
+            let record = RunRecord {
+                id: 0,
+                started_at: Utc::now(),
+                finished_at: Utc::now(),
+                path: path.into(),
+                exit: Exit::Success,
+                error_rate: 0.0,
+                summary: RunSummary::default(),
+                config_hash: String::new(),
+            };
+            History::new(&history).append(record).await.unwrap();
+        }
+        let http = reqwest::Client::new();
+
+        let unauthorized = http.get(format!("{url}/admin/runs")).send().await.unwrap();
+        assert_eq!(unauthorized.status(), StatusCode::UNAUTHORIZED);
+        let response = http.get(format!("{url}/admin/runs?limit=2")).bearer_auth("admin").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let runs: Vec<serde_json::Value> = serde_json::from_slice(&response.bytes().await.unwrap()).unwrap();
+        let ids: Vec<u64> = runs.iter().map(|run| run["id"].as_u64().unwrap()).collect();
+        assert_eq!(ids, [2, 3]);
+    }
+}
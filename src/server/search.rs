@@ -0,0 +1,295 @@
+use anyhow::Result;
+use axum::extract::rejection::QueryRejection;
+use axum::extract::{Query, State};
+use axum::http::HeaderMap;
+use axum::Json;
+use serde::Deserialize;
+use tokio::time::Instant;
+use tracing::{info, warn};
+use crate::clients::llm::llama_cpp;
+use crate::clients::vector_store::{Filter, VectorStore};
+use crate::search::{self, Cursor, Page, TooDeep};
+use crate::server::{self, AppState};
+use crate::server::error::ApiError;
+
+const DEFAULT_LIMIT: u64 = 10;
+
+#[derive(Deserialize)]
+pub struct SearchQuery {
+    q: String,
+    #[serde(default = "default_limit")]
+    limit: u64,
+    #[serde(default)]
+    offset: u64,
+    /// `next_cursor` of the previous page; `offset` is ignored with one
+    cursor: Option<String>,
+    /// JSON object of payload fields to a value, or a list of values that each do
+    filter: Option<String>,
+    /// Comma-separated entities, any of which results mention
+    entity: Option<String>,
+    /// Comma-separated topics, any of which results are about
+    tag: Option<String>,
+}
+
+fn default_limit() -> u64 {
+    DEFAULT_LIMIT
+}
+
+/// `GET /search?q=<text>`, a page of the documents nearest to the text
+///
+/// The query's embedding counts against `serve.search_budget_ms` too, since the budget is
+/// about how long the caller waits; a query not embedded within it gets an empty page,
+/// flagged partial.
+pub async fn search(
+    State(app): State<AppState>,
+    headers: HeaderMap,
+    query: Result<Query<SearchQuery>, QueryRejection>,
+) -> Result<Json<Page>, ApiError> {
+    let deadline = app.config.serve.search_budget().map(|budget| Instant::now() + budget);
+    let Query(query) = query.map_err(|e| ApiError::BadRequest(e.body_text()))?;
+    let cursor = query.cursor.as_deref().map(Cursor::decode).transpose()
+        .map_err(|e| ApiError::BadRequest(format!("{e:#}")))?;
+    let mut filter: Filter = query.filter.as_deref().map(serde_json::from_str::<Filter>).transpose()
+        .map_err(|e| ApiError::BadRequest(format!("Malformed filter: {e}")))?
+        .unwrap_or_default()
+        .with_keywords(&list(query.entity.as_deref()), &list(query.tag.as_deref()));
+    let requested = app.query_log.is_some().then(|| filter.clone());
+    if app.config.serve.acl.enabled {
+        filter.within_groups(&groups(&headers, &app.config.serve.acl.groups_header));
+    }
+
+    let embedding = embed(&app, &query.q);
+    let (vector, degraded) = match deadline {
+        Some(deadline) => match tokio::time::timeout_at(deadline, embedding).await {
+            Ok(embedded) => embedded?,
+            Err(_) => {
+                warn!("Embedding a search query took up the whole search budget");
+                return Ok(Json(Page { hits: vec![], next_cursor: None, partial: true, degraded: false }));
+            }
+        },
+        None => embedding.await?,
+    };
+    if let (Some(log), Some(requested)) = (&app.query_log, requested) {
+        if let Err(e) = log.record(&query.q, vector.clone().unwrap_or_default(), query.limit, query.offset, requested).await {
+            warn!("Failed to log a search query: {e:#}");
+        }
+    }
+
+    let mut page = search::page(app.store.as_ref(), &app.retrieval, Some(&query.q), vector, query.limit, query.offset, &filter, cursor.as_ref(), deadline, app.boosts.as_deref()).await
+        .map_err(|e| {
+            if e.is::<TooDeep>() {
+                return ApiError::BadRequest(e.to_string());
+            }
+            warn!("Searching the vector store failed: {e:?}");
+            ApiError::StoreUnavailable
+        })?;
+    if app.config.serve.merge_adjacent {
+        page.hits = search::merge_adjacent(page.hits, app.config.pipeline.chunk_overlap);
+    }
+    page.degraded = degraded;
+
+    Ok(Json(page))
+}
+
+/// The query's embedding, or with `serve.fallback` while llama.cpp can't embed it an earlier
+/// one of the same query, or none, for the query's words to be searched for instead; the
+/// flag tells whether it's degraded
+async fn embed(app: &AppState, query: &str) -> Result<(Option<Vec<f32>>, bool), ApiError> {
+    let Some(fallback) = &app.fallback else {
+        return server::embed_query(app, query.to_string()).await.map(|vector| (Some(vector), false)).map_err(|e| {
+            warn!("Embedding a search query failed: {e:?}");
+            ApiError::EmbeddingUnavailable
+        });
+    };
+
+    if !fallback.is_down() {
+        match server::embed_query(app, query.to_string()).await {
+            Ok(vector) => {
+                fallback.embedded(query, &vector);
+                return Ok((Some(vector), false));
+            }
+            // A query llama.cpp refuses says nothing about whether it's up
+            Err(e) if !llama_cpp::is_unavailable(&e) => {
+                warn!("Embedding a search query failed: {e:?}");
+                return Err(ApiError::EmbeddingUnavailable);
+            }
+            Err(e) => {
+                warn!(
+                    "Embedding a search query failed, searching with cached embeddings or words for the next {}s: {e:?}",
+                    app.config.serve.fallback.retry_secs
+                );
+                fallback.embedding_failed();
+            }
+        }
+    }
+
+    Ok((fallback.cached(query), true))
+}
+
+fn list(values: Option<&str>) -> Vec<String> {
+    values.into_iter()
+        .flat_map(|values| values.split(','))
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+fn groups(headers: &HeaderMap, header: &str) -> Vec<String> {
+    headers.get_all(header)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|group| !group.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Runs each `serve.warmup` search once, one after another; failures are only logged, as
+/// serving doesn't depend on them
+pub async fn warm_up(app: AppState) {
+    for warmup in &app.config.serve.warmup {
+        let started = Instant::now();
+        let result = async {
+            let vector = server::embed_query(&app, warmup.query.clone()).await?;
+            // Serves as a fallback for the probe queries from the start
+            if let Some(fallback) = &app.fallback {
+                fallback.embedded(&warmup.query, &vector);
+            }
+            app.store.search(vector, warmup.limit, &warmup.filter).await
+        }.await;
+
+        match result {
+            Ok(hits) => info!("Warm-up search {:?} took {:?}, {} hits", warmup.query, started.elapsed(), hits.len()),
+            Err(e) => warn!("Warm-up search {:?} failed: {e:#}", warmup.query),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicU16, Ordering};
+
+    use axum::http::StatusCode;
+    use axum::routing::post;
+    use axum::Router;
+    use serde_json::json;
+    use crate::clients::{Document, Metadata};
+    use crate::clients::vector_store::sqlite::SqliteStore;
+    use crate::config::{Config, StoreKind};
+    use crate::control::Control;
+    use crate::sink::Sink;
+    use super::*;
+
+    /// A llama-server answering embedding requests with `status`, and its port
+    async fn llama(status: Arc<AtomicU16>) -> u16 {
+        let app = Router::new().route("/embedding", post(move || async move {
+            match StatusCode::from_u16(status.load(Ordering::Relaxed)).unwrap() {
+                StatusCode::OK => (StatusCode::OK, Json(json!({ "embedding": [0.6, 0.8] }))),
+                status => (status, Json(json!({ "error": { "code": status.as_u16(), "message": "refused" } }))),
+            }
+        }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        port
+    }
+
+    fn query(q: &str) -> SearchQuery {
+        SearchQuery { q: q.to_string(), limit: 10, offset: 0, cursor: None, filter: None, entity: None, tag: None }
+    }
+
+    fn app(port: u16) -> AppState {
+        let mut config = Config { store: StoreKind::Sqlite, ..Config::default() };
+        config.sqlite.path = std::env::temp_dir().join(format!("rag-rs-fallback-{}.sqlite", std::process::id()));
+        config.llama.port = port;
+        config.llama.busy_max_wait_secs = 0;
+
+        AppState::new(&config, Arc::new(Control::default())).unwrap()
+    }
+
+    #[tokio::test]
+    async fn only_an_unavailable_backend_trips_the_fallback() {
+        let status = Arc::new(AtomicU16::new(200));
+        let app = app(llama(status.clone()).await);
+        let fallback = app.fallback.clone().unwrap();
+        assert_eq!(embed(&app, "query").await.unwrap(), (Some(vec![0.6, 0.8]), false));
+
+        let tokens = app.llama.tokens_used();
+        status.store(400, Ordering::Relaxed);
+        assert!(embed(&app, "too long a query").await.is_err());
+        assert!(!fallback.is_down());
+        let e = app.llama.query_embedding("too long a query").await.unwrap_err();
+        assert_eq!(e.downcast_ref::<llama_cpp::ServerError>().map(|e| e.status), Some(400));
+        assert_eq!(app.llama.tokens_used(), tokens);
+
+        status.store(500, Ordering::Relaxed);
+        assert_eq!(embed(&app, "query").await.unwrap(), (Some(vec![0.6, 0.8]), true));
+        assert!(fallback.is_down());
+        assert_eq!(embed(&app, "never embedded").await.unwrap(), (None, true));
+    }
+
+    #[tokio::test]
+    async fn a_slow_embedding_is_cut_off_at_the_search_budget() {
+        let slow = Router::new().route("/embedding", post(|| async {
+            tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+            Json(json!({ "embedding": [0.6, 0.8] }))
+        }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move { axum::serve(listener, slow).await.unwrap() });
+        let mut config = (*app(port).config).clone();
+        config.serve.search_budget_ms = 100;
+        let app = AppState::new(&config, Arc::new(Control::default())).unwrap();
+
+        let started = Instant::now();
+        let Json(page) = search(State(app), HeaderMap::new(), Ok(Query(query("slow")))).await.unwrap();
+
+        assert!(started.elapsed() < std::time::Duration::from_secs(2), "{:?}", started.elapsed());
+        assert!(page.partial);
+        assert!(page.hits.is_empty());
+    }
+
+    #[tokio::test]
+    async fn an_unreachable_backend_trips_the_fallback() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+        let app = app(port);
+
+        assert_eq!(embed(&app, "query").await.unwrap(), (None, true));
+        assert!(app.fallback.clone().unwrap().is_down());
+
+        let mut config = (*app.config).clone();
+        config.serve.fallback.enabled = false;
+        let app = AppState::new(&config, Arc::new(Control::default())).unwrap();
+        assert!(embed(&app, "query").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn queries_never_embedded_are_searched_for_by_their_words() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+        let mut config = (*app(port).config).clone();
+        config.sqlite.path = std::env::temp_dir().join(format!("rag-rs-fallback-words-{}.sqlite", std::process::id()));
+        _ = std::fs::remove_file(&config.sqlite.path);
+        let app = AppState::new(&config, Arc::new(Control::default())).unwrap();
+        let mut store = SqliteStore::from_config(&config.sqlite).unwrap();
+        store.ensure_collection().await.unwrap();
+        for text in ["Rust borrow checker basics", "Baking sourdough bread", "The rust on old bikes"] {
+            let metadata = Metadata { source: format!("{text}.md"), ..Metadata::default() };
+            store.push(Document { page_content: text.to_string(), metadata, embeddings: vec![1.0, 0.0] }).await.unwrap();
+        }
+        store.flush().await.unwrap();
+
+        let Json(page) = search(State(app), HeaderMap::new(), Ok(Query(query("RUST checker")))).await.unwrap();
+
+        assert!(page.degraded);
+        let texts: Vec<&str> = page.hits.iter().map(|hit| hit.text.as_str()).collect();
+        assert_eq!(texts, ["Rust borrow checker basics", "The rust on old bikes"]);
+    }
+}
@@ -0,0 +1,140 @@
+use std::cmp::Ordering;
+
+use serde::Deserialize;
+
+/// How two vectors are compared
+///
+/// Vectors of different sizes don't compare: every function scores them as the metric's
+/// `worst` value, so they rank after any pair that does.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Metric {
+    #[default]
+    Cosine,
+    Dot,
+    /// A distance rather than a similarity, so lower is closer
+    Euclidean,
+}
+
+impl Metric {
+    pub fn compare(self, a: &[f32], b: &[f32]) -> f32 {
+        match self {
+            Metric::Cosine => cosine(a, b),
+            Metric::Dot => dot(a, b),
+            Metric::Euclidean => euclidean(a, b),
+        }
+    }
+
+    /// The score of vectors that can't be compared, further than any that can
+    pub fn worst(self) -> f32 {
+        match self {
+            Metric::Cosine => -1.0,
+            Metric::Dot => f32::NEG_INFINITY,
+            Metric::Euclidean => f32::INFINITY,
+        }
+    }
+
+    /// Orders values of this metric closest first
+    pub fn rank(self, a: f32, b: f32) -> Ordering {
+        match self {
+            Metric::Euclidean => a.total_cmp(&b),
+            Metric::Cosine | Metric::Dot => b.total_cmp(&a),
+        }
+    }
+}
+
+/// Cosine similarity, 0 for vectors without length
+pub fn cosine(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() {
+        return Metric::Cosine.worst();
+    }
+
+    let norm_a = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norm_b = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+
+    if norm_a == 0.0 || norm_b == 0.0 {
+        0.0
+    } else {
+        dot(a, b) / (norm_a * norm_b)
+    }
+}
+
+pub fn dot(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() {
+        return Metric::Dot.worst();
+    }
+
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+pub fn euclidean(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() {
+        return Metric::Euclidean.worst();
+    }
+
+    a.iter().zip(b).map(|(x, y)| (x - y) * (x - y)).sum::<f32>().sqrt()
+}
+
+/// Indices and values of the `k` candidates closest to `query`, closest first, ties in
+/// candidate order
+pub fn top_k<V: AsRef<[f32]>>(query: &[f32], candidates: &[V], k: usize, metric: Metric) -> Vec<(usize, f32)> {
+    let mut scored: Vec<(usize, f32)> = candidates.iter()
+        .enumerate()
+        .map(|(i, candidate)| (i, metric.compare(query, candidate.as_ref())))
+        .collect();
+    scored.sort_by(|a, b| metric.rank(a.1, b.1).then(a.0.cmp(&b.0)));
+    scored.truncate(k);
+    scored
+}
+
+/// Every pair of `vectors` compared, row `i` column `j` holding `i` against `j`
+pub fn pairwise<V: AsRef<[f32]>>(vectors: &[V], metric: Metric) -> Vec<Vec<f32>> {
+    let mut matrix = vec![vec![0.0; vectors.len()]; vectors.len()];
+    for i in 0..vectors.len() {
+        for j in i..vectors.len() {
+            let value = metric.compare(vectors[i].as_ref(), vectors[j].as_ref());
+            matrix[i][j] = value;
+            matrix[j][i] = value;
+        }
+    }
+
+    matrix
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const METRICS: [Metric; 3] = [Metric::Cosine, Metric::Dot, Metric::Euclidean];
+
+    #[test]
+    fn vectors_of_different_sizes_rank_last_whatever_the_metric() {
+        for metric in METRICS {
+            let unrelated = metric.compare(&[1.0, 0.0], &[0.0, 1.0]);
+            let mismatched = metric.compare(&[1.0, 0.0], &[1.0, 0.0, 0.0]);
+
+            assert_eq!(mismatched, metric.worst(), "{metric:?}");
+            assert_eq!(metric.rank(unrelated, mismatched), Ordering::Less, "{metric:?}");
+        }
+    }
+
+    #[test]
+    fn top_k_keeps_the_closest_in_order() {
+        let candidates = [vec![0.0, 1.0], vec![1.0, 0.0], vec![1.0, 1.0], vec![1.0]];
+
+        let cosine: Vec<usize> = top_k(&[1.0, 0.1], &candidates, 3, Metric::Cosine).into_iter().map(|(i, _)| i).collect();
+        assert_eq!(cosine, [1, 2, 0]);
+        let euclidean = top_k(&[1.0, 0.0], &candidates, 2, Metric::Euclidean);
+        assert_eq!(euclidean, [(1, 0.0), (2, 1.0)]);
+        assert_eq!(top_k(&[1.0, 0.0], &candidates, 10, Metric::Dot).last(), Some(&(3, f32::NEG_INFINITY)));
+    }
+
+    #[test]
+    fn pairwise_is_symmetric() {
+        let vectors = [[1.0, 0.0], [0.0, 2.0], [3.0, 4.0]];
+
+        let matrix = pairwise(&vectors, Metric::Dot);
+
+        assert_eq!(matrix, [[1.0, 0.0, 3.0], [0.0, 4.0, 8.0], [3.0, 8.0, 25.0]]);
+    }
+}
@@ -0,0 +1,709 @@
+use std::fmt::{Display, Formatter};
+use std::future::Future;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tracing::{error, info, warn};
+use crate::clients::Document;
+use crate::clients::vector_store::Locked;
+use crate::config::SpillConfig;
+
+/// Where embedded documents end up
+pub trait Sink {
+    /// Buffers `document`, writing a batch once the buffer is full
+    fn push(&mut self, document: Document) -> impl Future<Output = Result<()>> + Send;
+
+    /// Writes whatever is still buffered
+    fn flush(&mut self) -> impl Future<Output = Result<()>> + Send;
+
+    /// Documents waiting for the next write
+    fn buffered(&self) -> usize;
+
+    /// Documents kept on disk for want of a store that takes them, see `Spill`
+    fn spilled(&self) -> u64 {
+        0
+    }
+
+    /// Refuses a document the sink could never store, before it's buffered anywhere
+    fn check(&self, _document: &Document) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Context of a `push` error about the pushed document alone, which the sink turned down
+/// without buffering it; the documents buffered before are still there
+#[derive(Debug)]
+pub struct Rejected;
+
+impl Display for Rejected {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("The store can't take this document")
+    }
+}
+
+/// JSONL file embedded documents are appended to, vectors included, readable again as
+/// native documents
+pub struct Archive {
+    path: PathBuf,
+    buffer: Vec<u8>,
+    lines: usize,
+}
+
+impl Archive {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path, buffer: Vec::new(), lines: 0 }
+    }
+}
+
+impl Sink for Archive {
+    async fn push(&mut self, document: Document) -> Result<()> {
+        serde_json::to_writer(&mut self.buffer, &document)?;
+        self.buffer.push(b'\n');
+        self.lines += 1;
+
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        file.write_all(&self.buffer).await?;
+        self.buffer.clear();
+        self.lines = 0;
+
+        Ok(())
+    }
+
+    fn buffered(&self) -> usize {
+        self.lines
+    }
+}
+
+/// Writes every document to `sink` and a copy to `copy`, so embeddings are never kept in
+/// only one place
+///
+/// The copy is flushed whenever `sink` writes a batch, whether or not that batch made it.
+/// Only `sink` decides the outcome: a failed copy is logged and its lines are retried with
+/// the next batch.
+pub struct Tee<S, C> {
+    sink: S,
+    copy: C,
+}
+
+impl<S: Sink + Send, C: Sink + Send> Tee<S, C> {
+    pub fn new(sink: S, copy: C) -> Self {
+        Self { sink, copy }
+    }
+
+    async fn flush_copy(&mut self) {
+        if let Err(e) = self.copy.flush().await {
+            error!("Failed to copy {} documents, retrying with the next batch: {e:?}", self.copy.buffered());
+        }
+    }
+}
+
+impl<S: Sink + Send, C: Sink + Send> Sink for Tee<S, C> {
+    async fn push(&mut self, document: Document) -> Result<()> {
+        self.copy.push(document.clone()).await?;
+        let result = self.sink.push(document).await;
+        if self.sink.buffered() == 0 {
+            self.flush_copy().await;
+        }
+
+        result
+    }
+
+    async fn flush(&mut self) -> Result<()> {
+        let result = self.sink.flush().await;
+        self.flush_copy().await;
+
+        result
+    }
+
+    fn buffered(&self) -> usize {
+        self.sink.buffered()
+    }
+
+    fn spilled(&self) -> u64 {
+        self.sink.spilled()
+    }
+}
+
+/// Passes documents on to `sink` until it turns a batch down as `Locked`, then appends
+/// them to `SpillConfig::path` instead, retrying the whole file every `retry_secs`
+///
+/// Spilled documents count as written; those still on disk when the run ends are told by
+/// `spilled`. Without a config, or for other errors, it stays out of the way.
+pub struct Spill<S> {
+    sink: S,
+    config: Option<SpillConfig>,
+    /// The documents in `sink`'s buffer, which a refused batch takes with it
+    unsent: Vec<Document>,
+    file: Option<Archive>,
+    spilled: u64,
+    next_retry: Instant,
+}
+
+impl<S: Sink + Send> Spill<S> {
+    pub fn new(sink: S, config: Option<SpillConfig>) -> Self {
+        Self { sink, config, unsent: Vec::new(), file: None, spilled: 0, next_retry: Instant::now() }
+    }
+
+    fn retry_interval(&self) -> Duration {
+        Duration::from_secs(self.config.as_ref().map_or(0, |config| config.retry_secs))
+    }
+
+    /// Moves the documents the refused batch held to disk, along with all that follow
+    async fn start_spilling(&mut self, e: anyhow::Error) -> Result<()> {
+        let Some(config) = &self.config else { return Err(e) };
+        warn!(
+            "{e}, spilling documents to {} and retrying every {}s",
+            config.path.display(), config.retry_secs,
+        );
+        let mut file = Archive::new(config.path.clone());
+        for document in self.unsent.drain(..) {
+            file.push(document).await?;
+            self.spilled += 1;
+        }
+        file.flush().await?;
+        self.file = Some(file);
+        self.next_retry = Instant::now() + self.retry_interval();
+
+        Ok(())
+    }
+
+    /// Writes the spill file to `sink` if a retry is due, going back to writing directly
+    /// once all of it went through
+    async fn retry(&mut self, force: bool) -> Result<()> {
+        let Some(file) = &mut self.file else { return Ok(()) };
+        if !force && Instant::now() < self.next_retry {
+            return Ok(());
+        }
+        file.flush().await?;
+
+        match self.replay().await {
+            Ok(_) => {
+                info!("Stored the {} spilled documents, writing directly again", self.spilled);
+                if let Some(config) = &self.config {
+                    tokio::fs::remove_file(&config.path).await?;
+                }
+                self.file = None;
+                self.spilled = 0;
+            }
+            // Every document is still in the file, so nothing is lost by trying again later
+            Err(e) => {
+                info!("Still spilling: {e}");
+                self.next_retry = Instant::now() + self.retry_interval();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Hands everything in the spill file to `sink`; upserts replace points by id, so a
+    /// replay cut short can simply be started over
+    async fn replay(&mut self) -> Result<()> {
+        let Some(config) = &self.config else { return Ok(()) };
+        let mut lines = BufReader::new(tokio::fs::File::open(&config.path).await?).lines();
+        while let Some(line) = lines.next_line().await? {
+            self.sink.push(serde_json::from_str(&line)?).await?;
+        }
+
+        self.sink.flush().await
+    }
+}
+
+impl<S: Sink + Send> Sink for Spill<S> {
+    async fn push(&mut self, document: Document) -> Result<()> {
+        if let Some(file) = &mut self.file {
+            file.push(document).await?;
+            self.spilled += 1;
+            return self.retry(false).await;
+        }
+        if self.config.is_none() {
+            return self.sink.push(document).await;
+        }
+
+        self.unsent.push(document.clone());
+        match self.sink.push(document).await {
+            Ok(_) => {
+                if self.sink.buffered() == 0 {
+                    self.unsent.clear();
+                }
+                Ok(())
+            }
+            Err(e) if e.is::<Locked>() => self.start_spilling(e).await,
+            Err(e) if e.is::<Rejected>() => {
+                self.unsent.pop();
+                Err(e)
+            }
+            Err(e) => {
+                self.unsent.clear();
+                Err(e)
+            }
+        }
+    }
+
+    /// Gives the spill file one more try, leaving it in place if the store still refuses it
+    async fn flush(&mut self) -> Result<()> {
+        if self.file.is_none() {
+            match self.sink.flush().await {
+                Ok(_) => {
+                    self.unsent.clear();
+                    return Ok(());
+                }
+                Err(e) if e.is::<Locked>() => self.start_spilling(e).await?,
+                Err(e) => {
+                    self.unsent.clear();
+                    return Err(e);
+                }
+            }
+        }
+
+        self.retry(true).await
+    }
+
+    fn buffered(&self) -> usize {
+        match self.file {
+            Some(_) => 0,
+            None => self.sink.buffered(),
+        }
+    }
+
+    fn spilled(&self) -> u64 {
+        self.spilled
+    }
+}
+
+/// Writes a `Writer` queues before callers wait for room
+const WRITER_QUEUE: usize = 64;
+
+/// A batch for the task behind a `Writer`, and where its outcome goes
+struct Write {
+    documents: Vec<Document>,
+    done: oneshot::Sender<Result<()>>,
+}
+
+/// Cloneable handle on a sink owned by a task of its own, which takes one batch at a time
+///
+/// Each batch is pushed and flushed before the next one starts, so it never shares an upsert
+/// with another caller's documents. A caller that stops waiting doesn't cut its batch short,
+/// one the task has taken is always finished. Once every handle is dropped the task writes
+/// whatever is still queued and hands the sink back.
+#[derive(Clone)]
+pub struct Writer {
+    writes: mpsc::Sender<Write>,
+}
+
+impl Writer {
+    pub fn spawn<S: Sink + Send + 'static>(sink: S) -> (Self, JoinHandle<S>) {
+        let (writes, queue) = mpsc::channel(WRITER_QUEUE);
+        (Self { writes }, tokio::spawn(own(sink, queue)))
+    }
+
+    /// Pushes and flushes `documents`; after an error part of them may be stored already
+    pub async fn write(&self, documents: Vec<Document>) -> Result<()> {
+        let (done, outcome) = oneshot::channel();
+        self.writes.send(Write { documents, done }).await
+            .map_err(|_| anyhow!("The writer task is gone"))?;
+
+        outcome.await.map_err(|_| anyhow!("The writer task stopped before finishing the write"))?
+    }
+}
+
+async fn own<S: Sink + Send>(mut sink: S, mut queue: mpsc::Receiver<Write>) -> S {
+    while let Some(Write { documents, done }) = queue.recv().await {
+        let result = async {
+            for document in documents {
+                sink.push(document).await?;
+            }
+            sink.flush().await
+        }.await;
+
+        // What the failed batch left in the buffer goes now rather than with the next one
+        if result.is_err() && sink.buffered() > 0 {
+            if let Err(e) = sink.flush().await {
+                warn!("Failed to write the rest of a failed batch: {e:#}");
+            }
+        }
+        _ = done.send(result);
+    }
+
+    sink
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use anyhow::bail;
+    use shuttle::rand::Rng;
+    use super::*;
+
+    /// A point at which shuttle may switch to another task
+    pub(crate) async fn step() {
+        shuttle::future::yield_now().await;
+    }
+
+    /// A coin flip shuttle records with the schedule, so a failing run replays the same way
+    pub(crate) fn chance(probability: f64) -> bool {
+        shuttle::rand::thread_rng().gen_bool(probability)
+    }
+
+    /// `Recording` with a `step` around everything it does
+    #[derive(Default)]
+    pub(crate) struct Scheduled {
+        pub(crate) buffer: Vec<String>,
+        pub(crate) writes: Vec<Vec<String>>,
+    }
+
+    impl Sink for Scheduled {
+        async fn push(&mut self, document: Document) -> Result<()> {
+            step().await;
+            if document.page_content == "bad" {
+                bail!("bad document");
+            }
+            self.buffer.push(document.page_content);
+            step().await;
+
+            Ok(())
+        }
+
+        async fn flush(&mut self) -> Result<()> {
+            step().await;
+            let batch = std::mem::take(&mut self.buffer);
+            step().await;
+            if !batch.is_empty() {
+                self.writes.push(batch);
+            }
+
+            Ok(())
+        }
+
+        fn buffered(&self) -> usize {
+            self.buffer.len()
+        }
+    }
+
+    /// Keeps the text of every document of every write, failing on documents reading "bad"
+    #[derive(Default)]
+    struct Recording {
+        buffer: Vec<String>,
+        writes: Vec<Vec<String>>,
+    }
+
+    impl Sink for Recording {
+        async fn push(&mut self, document: Document) -> Result<()> {
+            // Gives other tasks the chance to get in between the documents of a batch
+            tokio::task::yield_now().await;
+            if document.page_content == "bad" {
+                bail!("bad document");
+            }
+            self.buffer.push(document.page_content);
+
+            Ok(())
+        }
+
+        async fn flush(&mut self) -> Result<()> {
+            tokio::task::yield_now().await;
+            if !self.buffer.is_empty() {
+                self.writes.push(std::mem::take(&mut self.buffer));
+            }
+
+            Ok(())
+        }
+
+        fn buffered(&self) -> usize {
+            self.buffer.len()
+        }
+    }
+
+    fn batch(caller: usize, size: usize) -> Vec<Document> {
+        (0..size)
+            .map(|i| Document { page_content: format!("{caller}/{i}"), ..Document::default() })
+            .collect()
+    }
+
+    fn caller(text: &str) -> &str {
+        text.split('/').next().unwrap()
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_batches_are_written_alone() {
+        for _ in 0..20 {
+            let (writer, task) = Writer::spawn(Recording::default());
+            let callers: Vec<_> = (0..16)
+                .map(|caller| {
+                    let writer = writer.clone();
+                    tokio::spawn(async move { writer.write(batch(caller, 5)).await })
+                })
+                .collect();
+            for caller in callers {
+                caller.await.unwrap().unwrap();
+            }
+            drop(writer);
+
+            let sink = task.await.unwrap();
+            assert_eq!(sink.writes.len(), 16);
+            for write in &sink.writes {
+                assert_eq!(write.len(), 5);
+                assert!(write.iter().all(|text| caller(text) == caller(&write[0])), "{write:?} mixes batches");
+            }
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn batches_of_callers_that_gave_up_are_finished() {
+        for _ in 0..20 {
+            let (writer, task) = Writer::spawn(Recording::default());
+            let callers: Vec<_> = (0..16)
+                .map(|caller| {
+                    let writer = writer.clone();
+                    tokio::spawn(async move { writer.write(batch(caller, 5)).await })
+                })
+                .collect();
+            // Some of the batches are queued by now, some are being written and some weren't sent
+            tokio::task::yield_now().await;
+            for caller in &callers {
+                caller.abort();
+            }
+            drop(writer);
+
+            let sink = task.await.unwrap();
+            assert!(sink.buffer.is_empty());
+            assert!(sink.writes.iter().all(|write| write.len() == 5), "{:?} has a partial batch", sink.writes);
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn queued_batches_are_written_after_the_last_handle_drops() {
+        let (writer, task) = Writer::spawn(Recording::default());
+        let pending = Arc::new(writer);
+        let callers: Vec<_> = (0..8)
+            .map(|caller| {
+                let writer = pending.clone();
+                tokio::spawn(async move { writer.write(batch(caller, 3)).await })
+            })
+            .collect();
+        drop(pending);
+        for caller in callers {
+            caller.await.unwrap().unwrap();
+        }
+
+        let sink = task.await.unwrap();
+        assert_eq!(sink.writes.iter().map(Vec::len).sum::<usize>(), 24);
+    }
+
+    #[tokio::test]
+    async fn a_failed_batch_leaves_nothing_for_the_next() {
+        let (writer, task) = Writer::spawn(Recording::default());
+        let failed = ["a", "bad", "c"].map(|text| Document { page_content: text.to_string(), ..Document::default() });
+
+        assert!(writer.write(failed.to_vec()).await.is_err());
+        writer.write(batch(1, 2)).await.unwrap();
+        drop(writer);
+
+        let sink = task.await.unwrap();
+        assert_eq!(sink.writes, vec![vec!["a".to_string()], vec!["1/0".to_string(), "1/1".to_string()]]);
+    }
+
+    #[tokio::test]
+    async fn writes_fail_once_the_task_is_gone() {
+        let (writer, task) = Writer::spawn(Recording::default());
+        task.abort();
+        _ = task.await;
+
+        assert!(writer.write(batch(0, 1)).await.is_err());
+    }
+
+    /// Stores documents in batches of two, refusing them as `Locked` while `locked` is set and
+    /// failing outright on documents reading "bad"; a refused batch is dropped, as Qdrant's is
+    struct Lockable {
+        locked: Arc<AtomicBool>,
+        /// Pushes taken before the lock comes back on, for replays that fail partway
+        lock_after: Option<usize>,
+        buffer: Vec<String>,
+        stored: Vec<String>,
+    }
+
+    impl Lockable {
+        fn new(locked: &Arc<AtomicBool>) -> Self {
+            Self { locked: locked.clone(), lock_after: None, buffer: Vec::new(), stored: Vec::new() }
+        }
+    }
+
+    impl Sink for Lockable {
+        async fn push(&mut self, document: Document) -> Result<()> {
+            if document.page_content == "bad" {
+                bail!("bad document");
+            }
+            if let Some(left) = &mut self.lock_after {
+                match left {
+                    0 => self.locked.store(true, Ordering::Relaxed),
+                    left => *left -= 1,
+                }
+            }
+            self.buffer.push(document.page_content);
+            match self.buffer.len() < 2 {
+                true => Ok(()),
+                false => self.flush().await,
+            }
+        }
+
+        async fn flush(&mut self) -> Result<()> {
+            let batch = std::mem::take(&mut self.buffer);
+            if self.locked.load(Ordering::Relaxed) {
+                return Err(anyhow!("write operations are locked").context(Locked("docs".to_string())));
+            }
+            self.stored.extend(batch);
+
+            Ok(())
+        }
+
+        fn buffered(&self) -> usize {
+            self.buffer.len()
+        }
+    }
+
+    fn spill_config(name: &str) -> SpillConfig {
+        let path = std::env::temp_dir().join(format!("rag-rs-spill-{name}-{}.jsonl", std::process::id()));
+        _ = std::fs::remove_file(&path);
+
+        SpillConfig { enabled: true, path, retry_secs: 3600 }
+    }
+
+    fn texts(texts: &[&str]) -> Vec<Document> {
+        texts.iter().map(|text| Document { page_content: text.to_string(), ..Document::default() }).collect()
+    }
+
+    fn spilled_lines(config: &SpillConfig) -> usize {
+        std::fs::read_to_string(&config.path).map_or(0, |spilled| spilled.lines().count())
+    }
+
+    #[tokio::test]
+    async fn a_locked_store_is_spilled_to_and_replayed_once_it_takes_writes() {
+        let locked = Arc::new(AtomicBool::new(true));
+        let config = spill_config("replay");
+        let mut spill = Spill::new(Lockable::new(&locked), Some(config.clone()));
+
+        for document in texts(&["a", "b", "c"]) {
+            spill.push(document).await.unwrap();
+        }
+        // The refused batch of a and b, and c after it
+        assert_eq!(spill.spilled(), 3);
+        assert!(spill.sink.stored.is_empty());
+
+        locked.store(false, Ordering::Relaxed);
+        spill.flush().await.unwrap();
+        assert_eq!(spill.spilled(), 0);
+        assert_eq!(spill.sink.stored, ["a", "b", "c"]);
+        assert!(!config.path.exists());
+
+        spill.push(texts(&["d"]).remove(0)).await.unwrap();
+        spill.flush().await.unwrap();
+        assert_eq!(spill.sink.stored, ["a", "b", "c", "d"]);
+    }
+
+    #[tokio::test]
+    async fn a_replay_cut_short_keeps_the_whole_file() {
+        let locked = Arc::new(AtomicBool::new(true));
+        let config = spill_config("partway");
+        let mut spill = Spill::new(Lockable::new(&locked), Some(config.clone()));
+        for document in texts(&["a", "b", "c", "d", "e"]) {
+            spill.push(document).await.unwrap();
+        }
+
+        // a and b go through, then the lock is back before c and d are
+        locked.store(false, Ordering::Relaxed);
+        spill.sink.lock_after = Some(3);
+        spill.flush().await.unwrap();
+        assert_eq!(spill.sink.stored, ["a", "b"]);
+        assert_eq!(spill.spilled(), 5);
+        assert_eq!(spilled_lines(&config), 5);
+
+        spill.sink.lock_after = None;
+        locked.store(false, Ordering::Relaxed);
+        spill.flush().await.unwrap();
+        assert_eq!(spill.sink.stored, ["a", "b", "a", "b", "c", "d", "e"]);
+        assert_eq!(spill.spilled(), 0);
+        assert!(!config.path.exists());
+    }
+
+    #[tokio::test]
+    async fn other_errors_are_not_spilled() {
+        let locked = Arc::new(AtomicBool::new(false));
+        let config = spill_config("errors");
+        let mut spill = Spill::new(Lockable::new(&locked), Some(config.clone()));
+
+        let error = spill.push(texts(&["bad"]).remove(0)).await.unwrap_err();
+        assert!(!error.is::<Locked>());
+        assert_eq!(spill.spilled(), 0);
+        assert!(!config.path.exists());
+
+        for document in texts(&["a", "b"]) {
+            spill.push(document).await.unwrap();
+        }
+        assert_eq!(spill.sink.stored, ["a", "b"]);
+    }
+
+    #[test]
+    fn writes_racing_shutdown_are_finished_whole_or_not_started() {
+        shuttle::check_random(|| shuttle::future::block_on(async {
+            // `Writer::spawn` without the tokio runtime, so shuttle schedules the task
+            let (writes, queue) = mpsc::channel(WRITER_QUEUE);
+            let writer = Writer { writes };
+            let task = shuttle::future::spawn(own(Scheduled::default(), queue));
+            let callers: Vec<_> = (0..6)
+                .map(|caller| {
+                    let writer = writer.clone();
+                    let mut documents = batch(caller, 3);
+                    if chance(0.2) {
+                        documents[1].page_content = "bad".to_string();
+                    }
+                    shuttle::future::spawn(async move {
+                        step().await;
+                        writer.write(documents).await
+                    })
+                })
+                .collect();
+            drop(writer);
+
+            step().await;
+            for caller in &callers {
+                if chance(0.3) {
+                    caller.abort();
+                }
+            }
+            let mut acknowledged = Vec::new();
+            for (caller, handle) in callers.into_iter().enumerate() {
+                if let Ok(Ok(())) = handle.await {
+                    acknowledged.push(caller.to_string());
+                }
+            }
+
+            let sink = task.await.unwrap();
+            assert!(sink.buffer.is_empty());
+            for write in &sink.writes {
+                assert!(write.iter().all(|text| caller(text) == caller(&write[0])), "{write:?} mixes batches");
+            }
+            for caller in acknowledged {
+                let written: Vec<&Vec<String>> = sink.writes.iter().filter(|write| self::caller(&write[0]) == caller).collect();
+                assert_eq!(written.len(), 1, "batch {caller} was written {} times", written.len());
+                assert_eq!(written[0].len(), 3, "batch {caller} was cut short");
+            }
+        }), 300);
+    }
+}
@@ -0,0 +1,102 @@
+use std::future::Future;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tracing::warn;
+use crate::clients::vector_store::Filter;
+use crate::config::SlowLogConfig;
+
+/// `[slow_log]`, fixed once at startup
+static LOG: OnceLock<SlowLog> = OnceLock::new();
+
+struct SlowLog {
+    threshold: Duration,
+    /// Guards appends, so concurrent entries don't interleave
+    path: Mutex<PathBuf>,
+}
+
+/// A store call as recorded in the slow log
+#[derive(Serialize)]
+#[serde(tag = "operation", rename_all = "snake_case")]
+pub enum Operation<'o> {
+    Upsert {
+        points: usize,
+        /// Whether the call waited for the store to apply the points
+        wait: bool,
+    },
+    Search {
+        limit: u64,
+        dimensions: usize,
+        filter: &'o Filter,
+    },
+}
+
+#[derive(Serialize)]
+struct Entry<'e> {
+    at: DateTime<Utc>,
+    store: &'static str,
+    collection: &'e str,
+    millis: u128,
+    failed: bool,
+    #[serde(flatten)]
+    operation: &'e Operation<'e>,
+}
+
+/// Starts logging store calls slower than `config.threshold_ms`; only the first call counts
+pub fn init(config: &SlowLogConfig) {
+    if config.threshold_ms == 0 {
+        return;
+    }
+
+    _ = LOG.set(SlowLog {
+        threshold: Duration::from_millis(config.threshold_ms),
+        path: Mutex::new(config.path.clone()),
+    });
+}
+
+/// Runs `call`, logging it with `operation` if it ran past the threshold, failed or not
+pub async fn timed<T>(store: &'static str, collection: &str, operation: Operation<'_>, call: impl Future<Output = Result<T>>) -> Result<T> {
+    let Some(log) = LOG.get() else {
+        return call.await;
+    };
+
+    let started = Instant::now();
+    let result = call.await;
+    let elapsed = started.elapsed();
+    if elapsed >= log.threshold {
+        let entry = Entry {
+            at: Utc::now(),
+            store,
+            collection,
+            millis: elapsed.as_millis(),
+            failed: result.is_err(),
+            operation: &operation,
+        };
+        if let Err(e) = log.append(&entry) {
+            warn!("Couldn't write the slow log: {e}");
+        }
+    }
+
+    result
+}
+
+impl SlowLog {
+    fn append(&self, entry: &Entry) -> Result<()> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+
+        let path = self.path.lock().expect("slow log lock poisoned");
+        std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&*path)?
+            .write_all(&line)?;
+
+        Ok(())
+    }
+}
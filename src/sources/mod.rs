@@ -0,0 +1,54 @@
+#[cfg(feature = "mongodb")]
+pub mod mongo;
+#[cfg(feature = "redis")]
+pub mod redis;
+#[cfg(feature = "sql")]
+pub mod sql;
+
+use anyhow::{anyhow, bail, Result};
+use tokio::sync::{mpsc, oneshot};
+use tokio_util::sync::CancellationToken;
+use crate::clients::Document;
+use crate::config::Source;
+use crate::loaders::{Loaded, Options, Selection};
+use crate::outcome::Exit;
+
+/// Reads every document of the source configured as `[sources.<name>]`
+#[cfg_attr(not(all(feature = "sql", feature = "mongodb")), allow(unused_variables))]
+pub async fn load(name: &str, source: &Source, options: &Options<'_>, selection: &mut Selection) -> Result<Loaded> {
+    match source {
+        #[cfg(feature = "sql")]
+        Source::Sql(sql) => sql::load(name, sql, options, selection).await,
+        #[cfg(feature = "mongodb")]
+        Source::Mongodb(mongo) => mongo::load(name, mongo, options, selection).await,
+        Source::Redis(_) => Err(anyhow!("[sources.{name}] is a stream, read it with --watch").context(Exit::ConfigError)),
+        #[allow(unreachable_patterns)]
+        _ => Err(unsupported(name, source)),
+    }
+}
+
+/// Documents a watched source reports
+pub struct Changes {
+    pub documents: Vec<Document>,
+    /// Told whether the run over the documents succeeded, for sources that acknowledge them
+    pub stored: Option<oneshot::Sender<bool>>,
+}
+
+/// Sends batches of changed documents until `cancel`, for sources that can report changes
+#[cfg_attr(not(all(feature = "mongodb", feature = "redis")), allow(unused_variables))]
+pub async fn watch(name: String, source: Source, cancel: CancellationToken, tx: mpsc::Sender<Changes>) -> Result<()> {
+    match source {
+        #[cfg(feature = "mongodb")]
+        Source::Mongodb(mongo) => mongo::watch(name, mongo, cancel, tx).await,
+        #[cfg(feature = "redis")]
+        Source::Redis(redis) => redis::watch(name, redis, cancel, tx).await,
+        Source::Sql(_) => bail!("[sources.{name}] can't be watched, only MongoDB and Redis sources report changes"),
+        #[allow(unreachable_patterns)]
+        _ => Err(unsupported(&name, &source)),
+    }
+}
+
+/// A source of a type this build left out
+fn unsupported(name: &str, source: &Source) -> anyhow::Error {
+    anyhow!("[sources.{name}] needs rag-rs built with the `{}` feature", source.feature()).context(Exit::ConfigError)
+}
@@ -0,0 +1,238 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
+use futures::TryStreamExt;
+use mongodb::bson::{doc, Bson, Document as BsonDocument};
+use mongodb::change_stream::event::OperationType;
+use mongodb::options::FullDocumentType;
+use mongodb::{Client, Collection};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info};
+use crate::clients::{Document, Metadata};
+use crate::config::MongoSource;
+use crate::loaders::{Loaded, Options, Selection};
+use crate::sources::Changes;
+use crate::outcome::Exit;
+use crate::secret::redact_url;
+
+const CONTENT_TYPE: &str = "text/plain";
+
+/// Changes are handed on once this long passes without another one, or a batch fills up
+const WATCH_QUIET: Duration = Duration::from_secs(5);
+
+/// Reads the documents matching the source's filter, with only the mapped fields projected
+pub async fn load(name: &str, source: &MongoSource, options: &Options<'_>, selection: &mut Selection) -> Result<Loaded> {
+    let collection = collection(name, source).await?;
+    let mut cursor = collection.find(filter(name, source)?)
+        .projection(projection(source))
+        .batch_size(source.batch_size as u32)
+        .await
+        .with_context(|| format!("Query of [sources.{name}] failed"))?;
+
+    let mut documents = Vec::new();
+    while let Some(record) = cursor.try_next().await
+        .with_context(|| format!("Reading [sources.{name}] failed after {} documents", documents.len()))?
+    {
+        if options.cancel.is_cancelled() || selection.is_full() {
+            break;
+        }
+        if let Some(document) = document(name, source, &record) {
+            if selection.admit() {
+                documents.push(document);
+            }
+        }
+    }
+
+    Ok(Loaded { documents, skipped: 0 })
+}
+
+/// Follows the collection's change stream until `cancel`, sending inserted, replaced and
+/// updated documents that match the filter on in batches
+///
+/// Deletions are only logged, since nothing yet removes points by source.
+pub async fn watch(name: String, source: MongoSource, cancel: CancellationToken, tx: mpsc::Sender<Changes>) -> Result<()> {
+    let collection = collection(&name, &source).await?;
+    let mut pipeline = vec![doc! { "$match": { "operationType": { "$in": ["insert", "replace", "update", "delete"] } } }];
+    let filter = filter(&name, &source)?;
+    if !filter.is_empty() {
+        // Deletions carry no document to match, so they bypass the filter
+        pipeline.push(doc! { "$match": { "$or": [{ "operationType": "delete" }, full_document(filter)] } });
+    }
+
+    let mut stream = collection.watch()
+        .pipeline(pipeline)
+        .full_document(FullDocumentType::UpdateLookup)
+        .await
+        .with_context(|| format!("Failed to watch [sources.{name}], change streams need a replica set"))?;
+    info!("Watching [sources.{name}] for changes");
+
+    let mut batch = Vec::new();
+    loop {
+        let next = tokio::select! {
+            next = tokio::time::timeout(WATCH_QUIET, stream.try_next()) => next,
+            _ = cancel.cancelled() => return Ok(()),
+        };
+        let quiet = match next {
+            Err(_) => true,
+            Ok(Err(e)) => return Err(e).with_context(|| format!("Watching [sources.{name}] failed")),
+            Ok(Ok(None)) => bail!("The change stream of [sources.{name}] ended"),
+            Ok(Ok(Some(event))) => {
+                match (event.operation_type, event.full_document) {
+                    (OperationType::Delete, _) => {
+                        debug!("Ignoring deletion of {:?} from [sources.{name}]", event.document_key);
+                    }
+                    (_, Some(record)) => batch.extend(document(&name, &source, &record)),
+                    _ => {}
+                }
+                false
+            }
+        };
+
+        if !batch.is_empty() && (quiet || batch.len() >= source.batch_size)
+            && tx.send(Changes { documents: std::mem::take(&mut batch), stored: None }).await.is_err()
+        {
+            return Ok(());
+        }
+    }
+}
+
+async fn collection(name: &str, source: &MongoSource) -> Result<Collection<BsonDocument>> {
+    let url = source.url.as_ref()
+        .ok_or_else(|| anyhow!("[sources.{name}] has no url").context(Exit::ConfigError))?;
+    let client = Client::with_uri_str(url.expose()).await
+        .with_context(|| format!("Failed to connect to {}", redact_url(url.expose())))?;
+
+    Ok(client.database(&source.database).collection(&source.collection))
+}
+
+fn filter(name: &str, source: &MongoSource) -> Result<BsonDocument> {
+    let Some(filter) = &source.filter else {
+        return Ok(BsonDocument::new());
+    };
+    let json: serde_json::Value = serde_json::from_str(filter)
+        .with_context(|| format!("[sources.{name}] filter is not JSON"))
+        .context(Exit::ConfigError)?;
+
+    match Bson::try_from(json) {
+        Ok(Bson::Document(filter)) => Ok(filter),
+        _ => Err(anyhow!("[sources.{name}] filter must be a JSON object").context(Exit::ConfigError)),
+    }
+}
+
+fn projection(source: &MongoSource) -> BsonDocument {
+    let mut projection = doc! { &source.content: 1 };
+    for field in source.source.iter().chain(&source.metadata) {
+        projection.insert(field, 1);
+    }
+    projection
+}
+
+/// The filter rewritten to match the `fullDocument` of change events
+fn full_document(filter: BsonDocument) -> BsonDocument {
+    filter.into_iter()
+        .map(|(key, value)| match (key.starts_with('$'), value) {
+            // `$and`, `$or` and `$nor` hold lists of filters
+            (true, Bson::Array(filters)) => (key, Bson::Array(filters.into_iter()
+                .map(|filter| match filter {
+                    Bson::Document(filter) => Bson::Document(full_document(filter)),
+                    other => other,
+                })
+                .collect())),
+            (true, value) => (key, value),
+            (false, value) => (format!("fullDocument.{key}"), value),
+        })
+        .collect()
+}
+
+/// The record as a document, or `None` when its content field is missing or empty
+fn document(name: &str, source: &MongoSource, record: &BsonDocument) -> Option<Document> {
+    let content = match field(record, &source.content)? {
+        Bson::String(text) => text.clone(),
+        other => other.clone().into_relaxed_extjson().to_string(),
+    };
+    if content.trim().is_empty() {
+        return None;
+    }
+
+    let origin = match source.source.as_ref().and_then(|path| field(record, path)) {
+        Some(Bson::String(origin)) => origin.clone(),
+        Some(other) => other.to_string(),
+        None => match record.get("_id") {
+            Some(Bson::ObjectId(id)) => format!("{name}/{}", id.to_hex()),
+            Some(Bson::String(id)) => format!("{name}/{id}"),
+            Some(other) => format!("{name}/{other}"),
+            None => name.to_string(),
+        },
+    };
+
+    let mut metadata = Metadata {
+        source: origin,
+        content_type: CONTENT_TYPE.to_string(),
+        ..Default::default()
+    };
+    for path in &source.metadata {
+        if let Some(value) = field(record, path) {
+            metadata.extra.insert(path.clone(), value.clone().into_relaxed_extjson());
+        }
+    }
+
+    Some(Document { page_content: content, metadata, embeddings: vec![] })
+}
+
+/// The value at a dotted path through nested documents
+fn field<'r>(record: &'r BsonDocument, path: &str) -> Option<&'r Bson> {
+    let mut parts = path.split('.');
+    let mut value = record.get(parts.next()?)?;
+    for part in parts {
+        value = value.as_document()?.get(part)?;
+    }
+    Some(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use mongodb::bson::oid::ObjectId;
+    use super::*;
+
+    fn source(origin: Option<&str>) -> MongoSource {
+        let mut source: MongoSource = toml::from_str(
+            "database = \"blog\"\ncollection = \"posts\"\ncontent = \"body.text\"\nmetadata = [\"lang\", \"meta.tags\", \"missing\"]"
+        ).unwrap();
+        source.source = origin.map(str::to_string);
+        source
+    }
+
+    #[test]
+    fn records_map_to_documents_through_dotted_paths() {
+        let id = ObjectId::new();
+        let record = doc! {
+            "_id": id,
+            "body": { "text": "Hello" },
+            "lang": "en",
+            "meta": { "tags": ["a", "b"], "slug": "hello" },
+        };
+
+        let mapped = document("blog", &source(None), &record).unwrap();
+
+        assert_eq!(mapped.page_content, "Hello");
+        assert_eq!(mapped.metadata.source, format!("blog/{}", id.to_hex()));
+        assert_eq!(mapped.metadata.content_type, CONTENT_TYPE);
+        assert_eq!(mapped.metadata.extra["lang"], "en");
+        assert_eq!(mapped.metadata.extra["meta.tags"], serde_json::json!(["a", "b"]));
+        assert!(!mapped.metadata.extra.contains_key("missing"));
+
+        let named = document("blog", &source(Some("meta.slug")), &record).unwrap();
+        assert_eq!(named.metadata.source, "hello");
+    }
+
+    #[test]
+    fn records_without_text_are_left_out() {
+        assert!(document("blog", &source(None), &doc! { "_id": 1, "body": { "text": "  " } }).is_none());
+        assert!(document("blog", &source(None), &doc! { "_id": 1, "title": "no body" }).is_none());
+
+        let numbered = document("blog", &source(None), &doc! { "_id": "post-1", "body": { "text": 42 } }).unwrap();
+        assert_eq!(numbered.page_content, "42");
+        assert_eq!(numbered.metadata.source, "blog/post-1");
+    }
+}
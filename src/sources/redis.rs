@@ -0,0 +1,119 @@
+use anyhow::{anyhow, Context, Result};
+use redis::aio::MultiplexedConnection;
+use redis::streams::{StreamId, StreamReadOptions, StreamReadReply};
+use redis::AsyncCommands;
+use tokio::sync::{mpsc, oneshot};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+use crate::clients::{Document, Metadata};
+use crate::config::RedisSource;
+use crate::outcome::Exit;
+use crate::secret::redact_url;
+use crate::sources::Changes;
+
+const CONTENT_TYPE: &str = "text/plain";
+
+/// How long one read waits for new entries before checking for shutdown again
+const BLOCK_MS: usize = 5000;
+
+/// Reads the stream through the source's consumer group, acknowledging a batch once the run
+/// over it succeeds
+///
+/// Every document of a successful run was either upserted or dead-lettered, so nothing is
+/// lost by acknowledging it. A failed run leaves its entries pending; they are read again
+/// when this consumer restarts, which starts with its own pending entries.
+pub async fn watch(name: String, source: RedisSource, cancel: CancellationToken, tx: mpsc::Sender<Changes>) -> Result<()> {
+    let url = source.url.as_ref()
+        .ok_or_else(|| anyhow!("[sources.{name}] has no url").context(Exit::ConfigError))?;
+    let mut connection = redis::Client::open(url.expose())
+        .context(Exit::ConfigError)?
+        .get_multiplexed_async_connection()
+        .await
+        .with_context(|| format!("Failed to connect to {}", redact_url(url.expose())))?;
+
+    ensure_group(&mut connection, &source).await
+        .with_context(|| format!("Failed to create consumer group {} on {}", source.group, source.stream))?;
+    info!("Reading stream {} as {} in group {}", source.stream, source.consumer, source.group);
+
+    // Pending entries of this consumer are replayed first, from after the last one read so a
+    // failed batch isn't retried in a loop; `>` then asks for entries never delivered
+    let mut from = "0".to_string();
+    let mut replaying = true;
+    loop {
+        let options = StreamReadOptions::default()
+            .group(&source.group, &source.consumer)
+            .count(source.batch_size)
+            .block(BLOCK_MS);
+        let (keys, ids) = ([&source.stream], [from.as_str()]);
+        let reply = tokio::select! {
+            reply = connection.xread_options::<_, _, StreamReadReply>(&keys, &ids, &options) => {
+                reply.with_context(|| format!("Reading stream {} failed", source.stream))?
+            }
+            _ = cancel.cancelled() => return Ok(()),
+        };
+
+        let entries: Vec<StreamId> = reply.keys.into_iter().flat_map(|key| key.ids).collect();
+        if entries.is_empty() {
+            if replaying {
+                replaying = false;
+                from = ">".to_string();
+            }
+            continue;
+        }
+        if replaying {
+            from = entries[entries.len() - 1].id.clone();
+        }
+
+        let ids: Vec<&str> = entries.iter().map(|entry| entry.id.as_str()).collect();
+        let documents: Vec<Document> = entries.iter().filter_map(|entry| document(&name, &source, entry)).collect();
+        if documents.is_empty() {
+            // Nothing to embed, so nothing to wait for before acknowledging
+            let _: usize = connection.xack(&source.stream, &source.group, &ids).await?;
+            continue;
+        }
+
+        let (stored, outcome) = oneshot::channel();
+        if tx.send(Changes { documents, stored: Some(stored) }).await.is_err() {
+            return Ok(());
+        }
+        match outcome.await {
+            Ok(true) => {
+                let _: usize = connection.xack(&source.stream, &source.group, &ids).await?;
+            }
+            _ => warn!("Leaving {} entries of {} pending after a failed run", ids.len(), source.stream),
+        }
+    }
+}
+
+async fn ensure_group(connection: &mut MultiplexedConnection, source: &RedisSource) -> Result<()> {
+    let created: redis::RedisResult<()> = connection.xgroup_create_mkstream(&source.stream, &source.group, "$").await;
+    match created {
+        Ok(()) => Ok(()),
+        Err(e) if e.code() == Some("BUSYGROUP") => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// The entry as a document, or `None` when its content field is missing or empty
+fn document(name: &str, source: &RedisSource, entry: &StreamId) -> Option<Document> {
+    let content: String = entry.get(&source.content)?;
+    if content.trim().is_empty() {
+        return None;
+    }
+
+    let origin = source.source.as_ref()
+        .and_then(|field| entry.get::<String>(field))
+        .unwrap_or_else(|| format!("{name}/{}", entry.id));
+    let mut metadata = Metadata {
+        source: origin,
+        content_type: CONTENT_TYPE.to_string(),
+        ..Default::default()
+    };
+    for field in &source.metadata {
+        if let Some(value) = entry.get::<String>(field) {
+            metadata.extra.insert(field.clone(), value.into());
+        }
+    }
+
+    Some(Document { page_content: content, metadata, embeddings: vec![] })
+}
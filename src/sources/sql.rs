@@ -0,0 +1,208 @@
+use anyhow::{anyhow, bail, Context, Result};
+use serde_json::Value;
+use sqlx::any::{AnyPoolOptions, AnyRow};
+use sqlx::{AnyPool, Row};
+use tracing::info;
+use crate::clients::{Document, Metadata};
+use crate::config::SqlSource;
+use crate::loaders::{Loaded, Options, Selection};
+use crate::outcome::Exit;
+use crate::secret::redact_url;
+
+const CONTENT_TYPE: &str = "text/plain";
+
+/// Last key of a page, bound into the next page's query
+enum Key {
+    Integer(i64),
+    Text(String),
+}
+
+/// Runs the source's query, a page of `batch_size` rows at a time when it has a `key`
+///
+/// Pages are fetched with `WHERE key > last ORDER BY key`, which stays fast on large tables
+/// where `OFFSET` would rescan everything before the page.
+pub async fn load(name: &str, source: &SqlSource, options: &Options<'_>, selection: &mut Selection) -> Result<Loaded> {
+    let url = source.url.as_ref()
+        .ok_or_else(|| anyhow!("[sources.{name}] has no url").context(Exit::ConfigError))?;
+    sqlx::any::install_default_drivers();
+    let pool = AnyPoolOptions::new()
+        .max_connections(1)
+        .connect(url.expose())
+        .await
+        .with_context(|| format!("Failed to connect to {}", redact_url(url.expose())))?;
+
+    let mut documents = Vec::new();
+    let mut last: Option<Key> = None;
+    let mut rows = 0;
+
+    loop {
+        let page = fetch(&pool, source, url.expose(), last.as_ref()).await
+            .with_context(|| format!("Query of [sources.{name}] failed after {rows} rows"))?;
+        rows += page.len();
+
+        for row in &page {
+            if options.cancel.is_cancelled() || selection.is_full() {
+                return Ok(Loaded { documents, skipped: 0 });
+            }
+            if let Some(document) = document(name, source, row)? {
+                if selection.admit() {
+                    documents.push(document);
+                }
+            }
+        }
+
+        let (Some(key), Some(row)) = (&source.key, page.last()) else {
+            break;
+        };
+        if page.len() < source.batch_size {
+            break;
+        }
+        last = Some(key_of(row, key)?);
+        info!("Read {rows} rows of [sources.{name}]");
+    }
+
+    pool.close().await;
+    Ok(Loaded { documents, skipped: 0 })
+}
+
+async fn fetch(pool: &AnyPool, source: &SqlSource, url: &str, after: Option<&Key>) -> Result<Vec<AnyRow>> {
+    let Some(key) = &source.key else {
+        return Ok(sqlx::query(&source.query).fetch_all(pool).await?);
+    };
+
+    // Postgres numbers its placeholders, MySQL and SQLite don't
+    let placeholder = if url.starts_with("postgres") { "$1" } else { "?" };
+    let filter = match after {
+        Some(_) => format!("WHERE {key} > {placeholder} "),
+        None => String::new(),
+    };
+    let sql = format!("SELECT * FROM ({}) AS page {filter}ORDER BY {key} LIMIT {}", source.query, source.batch_size);
+
+    let query = sqlx::query(&sql);
+    let query = match after {
+        Some(Key::Integer(value)) => query.bind(*value),
+        Some(Key::Text(value)) => query.bind(value.clone()),
+        None => query,
+    };
+
+    Ok(query.fetch_all(pool).await?)
+}
+
+/// The row as a document, or `None` when its content column is empty
+fn document(name: &str, source: &SqlSource, row: &AnyRow) -> Result<Option<Document>> {
+    let content = match value(row, &source.content)? {
+        Value::String(text) => text,
+        Value::Null => return Ok(None),
+        other => other.to_string(),
+    };
+    if content.trim().is_empty() {
+        return Ok(None);
+    }
+
+    let origin = match (&source.source, &source.key) {
+        (Some(column), _) => text(value(row, column)?),
+        (None, Some(key)) => format!("{name}/{}", text(value(row, key)?)),
+        (None, None) => name.to_string(),
+    };
+    let mut metadata = Metadata {
+        source: origin,
+        content_type: CONTENT_TYPE.to_string(),
+        ..Default::default()
+    };
+    for column in &source.metadata {
+        metadata.extra.insert(column.clone(), value(row, column)?);
+    }
+
+    Ok(Some(Document { page_content: content, metadata, embeddings: vec![] }))
+}
+
+fn key_of(row: &AnyRow, key: &str) -> Result<Key> {
+    match value(row, key)? {
+        Value::Number(number) => number.as_i64().map(Key::Integer)
+            .ok_or_else(|| anyhow!("Key column {key} must hold integers or text, found {number}")),
+        Value::String(text) => Ok(Key::Text(text)),
+        other => bail!("Key column {key} must hold integers or text, found {other}"),
+    }
+}
+
+/// A column's value, whatever type the driver reports it as
+fn value(row: &AnyRow, column: &str) -> Result<Value> {
+    row.try_column(column)
+        .with_context(|| format!("The query has no column {column:?}"))?;
+
+    if let Ok(value) = row.try_get::<Option<i64>, _>(column) {
+        return Ok(value.map_or(Value::Null, Value::from));
+    }
+    if let Ok(value) = row.try_get::<Option<f64>, _>(column) {
+        return Ok(value.map_or(Value::Null, Value::from));
+    }
+    if let Ok(value) = row.try_get::<Option<String>, _>(column) {
+        return Ok(value.map_or(Value::Null, Value::from));
+    }
+    if let Ok(value) = row.try_get::<Option<bool>, _>(column) {
+        return Ok(value.map_or(Value::Null, Value::from));
+    }
+
+    bail!("Column {column:?} has a type that can't be read as a number, text or boolean")
+}
+
+fn text(value: Value) -> String {
+    match value {
+        Value::String(text) => text,
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio_util::sync::CancellationToken;
+    use crate::config::LoadersConfig;
+    use crate::secret::Secret;
+    use super::*;
+
+    /// Rows written into the query, so an in-memory database needs no table
+    const ROWS: &str = "SELECT 1 AS id, 'first' AS body, 'en' AS lang \
+        UNION ALL SELECT 2, '   ', 'en' \
+        UNION ALL SELECT 3, 'third', NULL \
+        UNION ALL SELECT 4, 'fourth', 'de'";
+
+    fn source(key: Option<&str>, origin: Option<&str>) -> SqlSource {
+        toml::from_str::<SqlSource>(&format!("query = {ROWS:?}\ncontent = \"body\"\nmetadata = [\"lang\"]\nbatch_size = 2"))
+            .map(|source| SqlSource {
+                url: Some(Secret::new("sqlite::memory:")),
+                key: key.map(str::to_string),
+                source: origin.map(str::to_string),
+                ..source
+            })
+            .unwrap()
+    }
+
+    async fn documents(source: &SqlSource) -> Vec<Document> {
+        let cancel = CancellationToken::new();
+        let loaders = LoadersConfig::default();
+        let options = Options { format: Default::default(), strict: false, cancel: &cancel, loaders: &loaders };
+
+        load("posts", source, &options, &mut Selection::new(None, None)).await.unwrap().documents
+    }
+
+    #[tokio::test]
+    async fn pages_by_key_and_maps_columns() {
+        let documents = documents(&source(Some("id"), None)).await;
+
+        let texts: Vec<&str> = documents.iter().map(|document| document.page_content.as_str()).collect();
+        assert_eq!(texts, ["first", "third", "fourth"]);
+        let sources: Vec<&str> = documents.iter().map(|document| document.metadata.source.as_str()).collect();
+        assert_eq!(sources, ["posts/1", "posts/3", "posts/4"]);
+        assert_eq!(documents[0].metadata.extra["lang"], "en");
+        assert_eq!(documents[1].metadata.extra["lang"], Value::Null);
+        assert_eq!(documents[0].metadata.content_type, CONTENT_TYPE);
+    }
+
+    #[tokio::test]
+    async fn a_source_column_names_the_documents() {
+        let documents = documents(&source(None, Some("id"))).await;
+
+        let sources: Vec<&str> = documents.iter().map(|document| document.metadata.source.as_str()).collect();
+        assert_eq!(sources, ["1", "3", "4"]);
+    }
+}
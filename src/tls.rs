@@ -0,0 +1,19 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+use tokio_rustls::rustls::crypto::ring;
+
+/// TLS for connections this crate makes itself, servers checked against the system's CAs
+///
+/// The provider is picked here, as more than one is built into the dependency tree and
+/// rustls won't choose between them.
+pub fn client_config() -> Result<ClientConfig> {
+    let mut roots = RootCertStore::empty();
+    roots.add_parsable_certificates(rustls_native_certs::load_native_certs().certs);
+
+    Ok(ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()?
+        .with_root_certificates(roots)
+        .with_no_client_auth())
+}
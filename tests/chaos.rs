@@ -0,0 +1,146 @@
+#![cfg(feature = "chaos")]
+
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use rag_rs::chaos::ChaosConfig;
+use rag_rs::cli::IngestArgs;
+use rag_rs::commands::ingest;
+use rag_rs::config::{Config, StoreKind};
+use rag_rs::control::Control;
+use serde_json::{json, Value};
+
+const DOCUMENTS: usize = 40;
+
+/// A llama-server answering every embedding request, counting them
+async fn llama() -> (u16, Arc<AtomicUsize>) {
+    let requests = Arc::new(AtomicUsize::new(0));
+    let counted = requests.clone();
+    let app = Router::new()
+        .route("/health", get(|| async { Json(json!({ "status": "ok" })) }))
+        .route("/embedding", post(move || {
+            counted.fetch_add(1, Ordering::Relaxed);
+            async { Json(json!({ "embedding": [0.1, 0.2, 0.3, 0.4] })) }
+        }));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    (port, requests)
+}
+
+/// A directory of its own for each test, holding `DOCUMENTS` documents to ingest
+fn workspace(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("rag-rs-chaos-{name}-{}", std::process::id()));
+    _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let lines: Vec<String> = (0..DOCUMENTS)
+        .map(|i| json!({ "page_content": format!("document {i}"), "metadata": { "source": format!("s{i}"), "content_type": "text/plain", "language": "en" } }).to_string())
+        .collect();
+    std::fs::write(dir.join("documents.jsonl"), lines.join("\n")).unwrap();
+
+    dir
+}
+
+struct Run {
+    summary: Value,
+    /// `cause` of every dead letter, in the order they were written
+    causes: Vec<String>,
+    requests: usize,
+}
+
+async fn ingest(name: &str, chaos: ChaosConfig, tweak: impl FnOnce(&mut Config)) -> Run {
+    let dir = workspace(name);
+    let (port, requests) = llama().await;
+    let mut config = Config {
+        store: StoreKind::Sqlite,
+        history: dir.join("history.jsonl"),
+        dead_letter: dir.join("dead_letter.jsonl"),
+        chaos,
+        ..Config::default()
+    };
+    config.llama.port = port;
+    config.sqlite.path = dir.join("index.sqlite");
+    tweak(&mut config);
+    let args = IngestArgs {
+        path: dir.join("documents.jsonl"),
+        outcome: Some(dir.join("outcome.json")),
+        ..IngestArgs::default()
+    };
+
+    ingest::run(args, &config, &Control::default()).await.unwrap();
+
+    let outcome: Value = serde_json::from_slice(&std::fs::read(dir.join("outcome.json")).unwrap()).unwrap();
+    let causes = std::fs::read_to_string(dir.join("dead_letter.jsonl"))
+        .unwrap_or_default()
+        .lines()
+        .map(|line| serde_json::from_str::<Value>(line).unwrap()["cause"].as_str().unwrap().to_string())
+        .collect();
+    _ = std::fs::remove_dir_all(&dir);
+
+    Run { summary: outcome["summary"].clone(), causes, requests: requests.load(Ordering::Relaxed) }
+}
+
+fn count(run: &Run, field: &str) -> usize {
+    run.summary[field].as_u64().unwrap() as usize
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn busy_replies_are_retried_until_they_succeed() {
+    let chaos = ChaosConfig { seed: 7, busy_rate: 0.3, ..ChaosConfig::default() };
+    let run = ingest("busy", chaos, |_| {}).await;
+
+    assert_eq!(count(&run, "stored"), DOCUMENTS);
+    assert!(run.causes.is_empty(), "{:?} were dead lettered", run.causes);
+    // Injected 503s never reach the server, so it only sees the attempt that got through
+    assert_eq!(run.requests, DOCUMENTS);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn busy_replies_past_the_max_wait_are_dead_lettered() {
+    let chaos = ChaosConfig { seed: 7, busy_rate: 1.0, ..ChaosConfig::default() };
+    let run = ingest("busy-forever", chaos, |config| config.llama.busy_max_wait_secs = 1).await;
+
+    assert_eq!(count(&run, "stored"), 0);
+    assert_eq!(count(&run, "failed"), DOCUMENTS);
+    assert_eq!(run.causes, vec!["embed"; DOCUMENTS]);
+    assert_eq!(run.requests, 0);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn failures_and_malformed_replies_are_dead_lettered_with_their_cause() {
+    let chaos = ChaosConfig { seed: 11, embed_failure_rate: 0.25, malformed_rate: 0.25, ..ChaosConfig::default() };
+    let run = ingest("mixed", chaos, |_| {}).await;
+
+    let failed = run.causes.iter().filter(|cause| *cause == "embed").count();
+    let empty = run.causes.iter().filter(|cause| *cause == "empty").count();
+    assert!(failed > 0 && empty > 0, "{:?}", run.causes);
+    assert_eq!(failed + empty, run.causes.len());
+    assert_eq!(count(&run, "failed"), failed);
+    assert_eq!(count(&run, "empty"), empty);
+    assert_eq!(count(&run, "stored") + failed + empty, DOCUMENTS);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn the_same_seed_injects_the_same_faults() {
+    let chaos = ChaosConfig { seed: 42, embed_failure_rate: 0.5, ..ChaosConfig::default() };
+    let first = ingest("seeded-1", chaos.clone(), |_| {}).await;
+    let second = ingest("seeded-2", chaos, |_| {}).await;
+
+    assert!(!first.causes.is_empty());
+    assert_eq!(first.causes.len(), second.causes.len());
+    assert_eq!(count(&first, "stored"), count(&second, "stored"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn delayed_upserts_store_every_document() {
+    let chaos = ChaosConfig { seed: 3, upsert_delay_rate: 0.5, upsert_delay_ms: 10, ..ChaosConfig::default() };
+    let run = ingest("delayed", chaos, |config| config.sqlite.buffer_size = 4).await;
+
+    assert_eq!(count(&run, "stored"), DOCUMENTS);
+    assert!(run.causes.is_empty(), "{:?} were dead lettered", run.causes);
+}